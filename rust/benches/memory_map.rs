@@ -0,0 +1,79 @@
+use _rust_scatac_fragment_tools::aggregate_fragments::{
+    merge_fragment_files, rebgzip_fragment_file, MergeOptions,
+};
+use _rust_scatac_fragment_tools::fragment::TabixColumns;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const NUMBER_OF_FILES: usize = 200;
+const FRAGMENTS_PER_FILE: usize = 500;
+
+/// Writes `NUMBER_OF_FILES` small sorted BGZF compressed fragment files.
+///
+/// # Returns
+///
+/// The paths to the fragment files.
+fn create_fragment_files(folder: &Path) -> Vec<String> {
+    (0..NUMBER_OF_FILES)
+        .map(|file_index| {
+            let path_to_uncompressed = folder.join(format!("{}.fragments.tsv", file_index));
+            let mut writer = BufWriter::new(File::create(&path_to_uncompressed).unwrap());
+            for i in 0..FRAGMENTS_PER_FILE {
+                let start = 100 * i + file_index;
+                writeln!(
+                    writer,
+                    "chr1\t{}\t{}\tBARCODE-{}\t1",
+                    start,
+                    start + 150,
+                    file_index
+                )
+                .unwrap();
+            }
+            writer.flush().unwrap();
+            let path_to_fragments = format!("{}.gz", path_to_uncompressed.display());
+            rebgzip_fragment_file(
+                &path_to_uncompressed.display().to_string(),
+                &path_to_fragments,
+                0xff00,
+                1,
+                false,
+                &TabixColumns::default(),
+                false,
+            )
+            .unwrap();
+            path_to_fragments
+        })
+        .collect()
+}
+
+/// Compares memory-mapped with buffered reads of the input files, when merging many small files.
+fn bench_memory_map(c: &mut Criterion) {
+    let folder: PathBuf =
+        std::env::temp_dir().join(format!("memory_map_bench_{}", std::process::id()));
+    create_dir_all(&folder).unwrap();
+    let path_to_fragment_files = create_fragment_files(&folder);
+    let path_to_output_file = folder.join("merged.fragments.tsv.gz").display().to_string();
+
+    let mut group = c.benchmark_group("memory_map_inputs");
+    group.sample_size(10);
+    for memory_map_inputs in [false, true] {
+        let options = MergeOptions {
+            memory_map_inputs,
+            number_of_threads: 1,
+            ..Default::default()
+        };
+        group.bench_function(BenchmarkId::from_parameter(memory_map_inputs), |b| {
+            b.iter(|| {
+                merge_fragment_files(&path_to_fragment_files, &path_to_output_file, &options)
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+    remove_dir_all(&folder).unwrap();
+}
+
+criterion_group!(benches, bench_memory_map);
+criterion_main!(benches);
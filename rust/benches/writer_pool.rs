@@ -0,0 +1,105 @@
+use _rust_scatac_fragment_tools::aggregate_fragments::rebgzip_fragment_file;
+use _rust_scatac_fragment_tools::fragment::TabixColumns;
+use _rust_scatac_fragment_tools::split_fragments::{
+    split_fragments_by_cell_barcode, SplitOptions, WriterPoolStrategy,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const NUMBER_OF_CELL_TYPES: usize = 200;
+const BARCODES_PER_CELL_TYPE: usize = 10;
+const FRAGMENTS_PER_CONTIG: usize = 50_000;
+
+/// Writes an indexed fragments file with fragments of `NUMBER_OF_CELL_TYPES` cell types on two contigs.
+///
+/// # Returns
+///
+/// The path to the fragments file and a HashMap mapping its cell barcodes to cell types.
+fn create_fragments_file(folder: &Path) -> (String, HashMap<String, Vec<String>>) {
+    let number_of_barcodes = NUMBER_OF_CELL_TYPES * BARCODES_PER_CELL_TYPE;
+    let path_to_uncompressed = folder.join("fragments.tsv");
+    let mut writer = BufWriter::new(File::create(&path_to_uncompressed).unwrap());
+    for contig in ["chr1", "chr2"] {
+        for i in 0..FRAGMENTS_PER_CONTIG {
+            let start = 10 * i;
+            writeln!(
+                writer,
+                "{}\t{}\t{}\tBARCODE-{}\t1",
+                contig,
+                start,
+                start + 150,
+                // consecutive fragments belong to different cell types
+                i % number_of_barcodes
+            )
+            .unwrap();
+        }
+    }
+    writer.flush().unwrap();
+
+    let path_to_fragments = folder.join("fragments.tsv.gz").display().to_string();
+    rebgzip_fragment_file(
+        &path_to_uncompressed.display().to_string(),
+        &path_to_fragments,
+        0xff00,
+        4,
+        true,
+        &TabixColumns::default(),
+        false,
+    )
+    .unwrap();
+
+    let cell_barcode_to_cell_type = (0..number_of_barcodes)
+        .map(|i| {
+            (
+                format!("BARCODE-{}", i),
+                vec![format!("cell_type_{}", i % NUMBER_OF_CELL_TYPES)],
+            )
+        })
+        .collect();
+    (path_to_fragments, cell_barcode_to_cell_type)
+}
+
+/// Compares a shared thread pool for all output files with a thread pool per output file,
+/// when splitting into many cell types.
+fn bench_writer_pool_strategy(c: &mut Criterion) {
+    let folder: PathBuf =
+        std::env::temp_dir().join(format!("writer_pool_bench_{}", std::process::id()));
+    create_dir_all(&folder).unwrap();
+    let (path_to_fragments, cell_barcode_to_cell_type) = create_fragments_file(&folder);
+    let path_to_output_folder = folder.join("output").display().to_string();
+    create_dir_all(&path_to_output_folder).unwrap();
+
+    let mut group = c.benchmark_group("writer_pool_strategy");
+    group.sample_size(10);
+    for (name, writer_pool_strategy, number_of_threads) in [
+        ("shared", WriterPoolStrategy::Shared, 4),
+        ("per_writer", WriterPoolStrategy::PerWriter, 1),
+        ("per_writer", WriterPoolStrategy::PerWriter, 2),
+    ] {
+        let options = SplitOptions {
+            number_of_threads,
+            writer_pool_strategy,
+            ..Default::default()
+        };
+        group.bench_function(BenchmarkId::new(name, number_of_threads), |b| {
+            b.iter(|| {
+                split_fragments_by_cell_barcode(
+                    &path_to_fragments,
+                    &path_to_output_folder,
+                    cell_barcode_to_cell_type.clone(),
+                    HashMap::new(),
+                    &options,
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+    remove_dir_all(&folder).unwrap();
+}
+
+criterion_group!(benches, bench_writer_pool_strategy);
+criterion_main!(benches);
@@ -1,190 +1,1901 @@
-use bgzip::BGZFReader;
-use rust_htslib::bgzf::Writer;
+use crate::custom_errors::{FragmentFileErrorKind, FragmentToolsError, FragmentToolsResult};
+use crate::fragment::{
+    BarcodeRename, DuplicateCollapser, DuplicateHandling, Fragment, FragmentColumns,
+    FragmentFormat, ScorePredicate, TabixColumns, ZeroLengthHandling,
+};
+use crate::parquet_writer::{OutputCodec, ParquetFragmentWriter};
+use crate::sampling::FragmentSampler;
+use crate::summary::MergeSummary;
+use crate::tabix::{build_tabix_index, has_tabix_index, open_fragments_file, WHOLE_CONTIG};
+use itertools::Itertools;
+use rust_htslib::bgzf::{Reader, Writer};
+use rust_htslib::tbx::Read as TbxRead;
 use rust_htslib::tpool::ThreadPool;
-use core::fmt;
-use std::fs::File;
-/// Aggregates multiple fragment files into a single file
-/// This code is just a fancy implementation of the unix command `cat | sort -k1,1 -k2,2n -k3,3n | bgzip`
-/// And might not be super efficient.
-///
-/// It would be better to make an implementation that makes use of the bgzip blocks and the fact that the files are already sorted
-/// If someone wants and knows how to do that, please do!
-use std::io::{Read as IoRead, Write};
-
-fn read_fragments_file(file_name: &String, buffer: &mut String) {
-    let f = File::open(file_name).unwrap_or_else(|_| panic!("Could not open file {}", file_name));
-    let mut reader = BGZFReader::new(f)
-        .unwrap_or_else(|_| panic!("Could not create BGZF reader for file {}", file_name));
-    // Try to read file into buffer
-    match reader.read_to_string(buffer) {
-        Ok(_) => (),
-        Err(_) => {
-            println!("Could not read file {}, is it empty?", file_name);
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::fs::{canonicalize, remove_file, rename, File};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Reads fragments, one at a time, from a (BGZF or plain gzip compressed) fragment file.
+///
+/// Files consisting of multiple concatenated BGZF/gzip members (e.g. `cat a.gz b.gz > c.gz`)
+/// are read completely, htslib continues reading after the EOF block of each member.
+///
+/// # Fields
+///
+/// * `lines` - Lines of the file, read through htslib, from a memory map or from one contig
+///     of a tabix indexed file.
+/// * `path` - Path to the file, used in error messages.
+/// * `file_index` - Index of the file, set on each fragment that is read.
+/// * `format` - Layout of the lines of the file.
+/// * `sampler` - If set, fragments which are not kept by the sampler are skipped.
+/// * `line_number` - Number of lines read so far, used in error messages.
+/// * `sort_order_check` - If set, checks that the fragments of the file are sorted.
+struct FragmentFileReader<'a> {
+    lines: Box<dyn Iterator<Item = std::io::Result<String>>>,
+    line_number: usize,
+    path: &'a str,
+    file_index: usize,
+    format: &'a FragmentFormat,
+    sampler: Option<FragmentSampler>,
+    sort_order_check: Option<SortOrderCheck>,
+}
+
+impl<'a> FragmentFileReader<'a> {
+    /// Opens a fragment file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the file.
+    /// * `file_index` - Index of the file, set on each fragment that is read.
+    /// * `format` - Layout of the lines of the file.
+    /// * `read_buffer_size` - Size in bytes of the read buffer.
+    /// * `memory_map` - Whether to memory-map the file, see `memory_mapped_reader`.
+    ///     The file is read through htslib when it can not be memory-mapped.
+    /// * `contig` - If set, only the fragments of this contig are read, with the tabix index of the file.
+    fn open(
+        path: &'a str,
+        file_index: usize,
+        format: &'a FragmentFormat,
+        read_buffer_size: usize,
+        memory_map: bool,
+        contig: Option<&str>,
+    ) -> FragmentToolsResult<FragmentFileReader<'a>> {
+        let lines: Box<dyn Iterator<Item = std::io::Result<String>>> = match contig {
+            Some(contig) => contig_lines(path, contig)?,
+            None => {
+                let reader = match memory_map.then(|| memory_mapped_reader(path)).flatten() {
+                    Some(reader) => reader,
+                    None => Box::new(Reader::from_path(path).map_err(|_| {
+                        FragmentToolsError::InvalidFragmentFile(
+                            FragmentFileErrorKind::Unreadable,
+                            format!("Could not open file {}", path),
+                        )
+                    })?),
+                };
+                Box::new(BufReader::with_capacity(read_buffer_size, reader).lines())
+            }
+        };
+        Ok(FragmentFileReader {
+            lines,
+            line_number: 0,
+            path,
+            file_index,
+            format,
+            sampler: None,
+            sort_order_check: Some(SortOrderCheck::default()),
+        })
+    }
+
+    /// Sets the sampler of the reader, see `FragmentSampler`.
+    fn with_sampler(self, sampler: Option<FragmentSampler>) -> FragmentFileReader<'a> {
+        FragmentFileReader { sampler, ..self }
+    }
+
+    /// Disables the check that the fragments of the file are sorted, to read files which are not.
+    fn without_sort_order_check(self) -> FragmentFileReader<'a> {
+        FragmentFileReader {
+            sort_order_check: None,
+            ..self
         }
-    };
+    }
+
+    /// Returns the next fragment, or `None` at the end of the file. Empty lines and comment lines
+    /// (starting with `#`, e.g. the header of Cell Ranger fragment files) are skipped,
+    /// as are fragments which are not kept by the sampler.
+    ///
+    /// Errors on malformed lines mention their line number, counted from the start of the contig
+    /// when only one contig is read. Unless the check is disabled, a fragment which comes before the previous
+    /// fragment of the file (by contig, start and end) results in an error, as the merged output would not be sorted.
+    fn next_fragment(&mut self) -> FragmentToolsResult<Option<Fragment>> {
+        for line in self.lines.by_ref() {
+            let line = line.map_err(|e| {
+                FragmentToolsError::InvalidFragmentFile(
+                    FragmentFileErrorKind::Unreadable,
+                    format!("Could not read file {}: {}", self.path, e),
+                )
+            })?;
+            self.line_number += 1;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fragment =
+                Fragment::new_from_string_with_format(&line, self.format).map_err(|e| {
+                    FragmentToolsError::InvalidFragmentFile(
+                        FragmentFileErrorKind::Malformed,
+                        format!("{} (line {} of {})", e, self.line_number, self.path),
+                    )
+                })?;
+            if let Some(sort_order_check) = self.sort_order_check.as_mut() {
+                sort_order_check.check(&fragment, self.line_number, self.path)?;
+            }
+            if let Some(sampler) = self.sampler.as_mut() {
+                if !sampler.keep(&fragment.chrom) {
+                    continue;
+                }
+            }
+            fragment.file_index = self.file_index;
+            return Ok(Some(fragment));
+        }
+        Ok(None)
+    }
 }
 
-/// Struct representing a fragment, used for sorting
+/// Checks that the fragments of a file are sorted, one fragment at a time.
 ///
 /// # Fields
 ///
-/// * `chrom` - Chromosome name.
-/// * `start` - Start position.
-/// * `end` - End position.
-/// * `cell_barcode` - Cell barcode.
-/// * `score` - Optional score.
+/// * `contig` - Contig of the last fragment, empty before the first fragment.
+/// * `position` - Start and end of the last fragment.
+/// * `finished_contigs` - Contigs of which all fragments were seen, a fragment on one of them means
+///     that the file is not sorted.
+#[derive(Default)]
+struct SortOrderCheck {
+    contig: String,
+    position: (usize, usize),
+    finished_contigs: HashSet<String>,
+}
+
+impl SortOrderCheck {
+    /// Returns an error naming the file if a fragment comes before the previous fragment of the file:
+    /// on the same contig with a smaller (start, end), or on a contig of which all fragments were read.
+    /// Contigs can be in any order, as long as the fragments of each contig are consecutive.
+    ///
+    /// # Arguments
+    ///
+    /// * `fragment` - The next fragment of the file.
+    /// * `line_number` - Line number of the fragment, used in the error message.
+    /// * `path` - Path to the file, used in the error message.
+    fn check(
+        &mut self,
+        fragment: &Fragment,
+        line_number: usize,
+        path: &str,
+    ) -> FragmentToolsResult<()> {
+        let position = (fragment.start, fragment.end);
+        let previous_fragment =
+            |contig: &str, (start, end): (usize, usize)| format!("{}:{}-{}", contig, start, end);
+        let unsorted_after = if fragment.chrom == self.contig {
+            (position < self.position).then(|| previous_fragment(&self.contig, self.position))
+        } else {
+            let previous_contig = std::mem::replace(&mut self.contig, fragment.chrom.clone());
+            let unsorted_after = self
+                .finished_contigs
+                .contains(&fragment.chrom)
+                .then(|| previous_fragment(&previous_contig, self.position));
+            self.finished_contigs.insert(previous_contig);
+            unsorted_after
+        };
+        if let Some(previous_fragment) = unsorted_after {
+            return Err(FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unsorted,
+                format!(
+                    "Fragment {}:{}-{} (line {} of {}) comes after fragment {}, \
+                    the file should be sorted by contig, start and end (e.g. sort -k1,1 -k2,2n -k3,3n)",
+                    fragment.chrom,
+                    fragment.start,
+                    fragment.end,
+                    line_number,
+                    path,
+                    previous_fragment,
+                ),
+            ));
+        }
+        self.position = position;
+        Ok(())
+    }
+}
+
+/// Returns the lines of one contig of a tabix indexed fragment file.
+/// A contig which is not in the index has no lines.
+///
+/// # Arguments
+///
+/// * `path` - Path to the file.
+/// * `contig` - Name of the contig.
+fn contig_lines(
+    path: &str,
+    contig: &str,
+) -> FragmentToolsResult<Box<dyn Iterator<Item = std::io::Result<String>>>> {
+    let mut tbx_reader = open_fragments_file(path)?;
+    let Ok(contig_id) = tbx_reader.tid(contig) else {
+        return Ok(Box::new(std::iter::empty()));
+    };
+    tbx_reader.fetch(contig_id, 0, WHOLE_CONTIG).map_err(|_| {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Index,
+            format!(
+                "Could not fetch contig {} from fragments file {}",
+                contig, path
+            ),
+        )
+    })?;
+    let mut line: Vec<u8> = Vec::new();
+    Ok(Box::new(std::iter::from_fn(move || {
+        line.clear();
+        match tbx_reader.read(&mut line) {
+            Ok(true) => Some(
+                String::from_utf8(line.clone())
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            ),
+            Ok(false) => None,
+            Err(e) => Some(Err(std::io::Error::other(e))),
+        }
+    })))
+}
+
+/// Memory-maps a fragment file and returns a reader of its uncompressed content.
+///
+/// BGZF and gzip compressed files are decompressed member by member, other files are read as is.
+/// Returns `None` if the file can not be memory-mapped, e.g. because the OS does not support it.
+///
+/// # Arguments
+///
+/// * `path` - Path to the file.
+#[cfg(feature = "mmap")]
+fn memory_mapped_reader(path: &str) -> Option<Box<dyn Read>> {
+    let file = std::fs::File::open(path).ok()?;
+    // Safety: the file is only read, it should not be modified while merging.
+    let mmap = unsafe { memmap2::Mmap::map(&file) }.ok()?;
+    let is_compressed = mmap.starts_with(&[0x1f, 0x8b]);
+    let cursor = std::io::Cursor::new(mmap);
+    if is_compressed {
+        Some(Box::new(flate2::read::MultiGzDecoder::new(cursor)))
+    } else {
+        Some(Box::new(cursor))
+    }
+}
+
+/// Without the `mmap` feature, files are never memory-mapped.
+#[cfg(not(feature = "mmap"))]
+fn memory_mapped_reader(_path: &str) -> Option<Box<dyn Read>> {
+    None
+}
+
+/// Trade-off between memory usage and speed when merging fragment files.
+///
+/// # Variants
+///
+/// * `Low` - For nodes with little memory: at most 32 files are read at the same time
+///     (more files are merged in batches via temporary files), with 8 KiB read buffers.
+/// * `Fast` - At most 512 files are read at the same time, with 128 KiB read buffers
+///     (up to 64 MiB of buffers, on top of the decompression buffers of htslib).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MemoryMode {
+    Low,
+    Fast,
+}
+
+impl MemoryMode {
+    /// Parse a memory mode ("low" or "fast").
+    pub fn parse(s: &str) -> Result<MemoryMode, String> {
+        match s {
+            "low" => Ok(MemoryMode::Low),
+            "fast" => Ok(MemoryMode::Fast),
+            _ => Err(format!(
+                "Invalid memory mode {:?}, should be one of \"low\" or \"fast\"",
+                s
+            )),
+        }
+    }
 
-#[derive(PartialEq, Eq)]
-struct Fragment {
-    chrom: String,
-    start: usize,
-    end: usize,
-    cell_barcode: String,
-    score: Option<usize>,
+    /// Maximum number of files to read from at the same time.
+    pub fn max_open_files(&self) -> usize {
+        match self {
+            MemoryMode::Low => 32,
+            MemoryMode::Fast => 512,
+        }
+    }
+
+    /// Size in bytes of the read buffer of each file.
+    pub fn read_buffer_size(&self) -> usize {
+        match self {
+            MemoryMode::Low => 8 * 1024,
+            MemoryMode::Fast => 128 * 1024,
+        }
+    }
 }
 
-impl Fragment {
-    /// Create a new Fragment from a string.
+/// How the score column is normalized when merging files of which some lines lack a score.
+///
+/// # Variants
+///
+/// * `None` - Lines are written as read, with or without a score.
+/// * `Max(score)` - Missing scores are set to `score`, so all lines have 5 columns.
+/// * `Min` - Scores are dropped, so all lines have 4 columns.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColumnNormalization {
+    None,
+    Max(usize),
+    Min,
+}
+
+impl ColumnNormalization {
+    /// Parse a column normalization ("none", "max" or "min").
     ///
     /// # Arguments
     ///
-    /// * `s` - String to parse.
+    /// * `s` - The column normalization.
+    /// * `missing_score` - Score of lines without a score, used for "max".
+    pub fn parse(s: &str, missing_score: usize) -> Result<ColumnNormalization, String> {
+        match s {
+            "none" => Ok(ColumnNormalization::None),
+            "max" => Ok(ColumnNormalization::Max(missing_score)),
+            "min" => Ok(ColumnNormalization::Min),
+            _ => Err(format!(
+                "Invalid column normalization {:?}, should be one of \"none\", \"max\" or \"min\"",
+                s
+            )),
+        }
+    }
+
+    /// Returns this normalization for scores with `score_precision` decimals,
+    /// see `FragmentFormat::score_precision`.
+    fn with_score_precision(self, score_precision: Option<u32>) -> ColumnNormalization {
+        match self {
+            ColumnNormalization::Max(missing_score) => {
+                ColumnNormalization::Max(missing_score * 10_usize.pow(score_precision.unwrap_or(0)))
+            }
+            _ => self,
+        }
+    }
+
+    /// Normalizes the score of a fragment.
+    fn apply(&self, fragment: &mut Fragment) {
+        match self {
+            ColumnNormalization::None => {}
+            ColumnNormalization::Max(missing_score) => {
+                fragment.score.get_or_insert(*missing_score);
+            }
+            ColumnNormalization::Min => {
+                fragment.score = None;
+                fragment.score_pair = None;
+            }
+        }
+    }
+}
+
+/// Options for merging fragment files.
+///
+/// # Fields
+///
+/// * `format` - Layout of the lines of the input files, the output is always tab-separated.
+/// * `max_open_files` - Maximum number of files to read from at the same time, at least 2.
+/// * `read_buffer_size` - Size in bytes of the read buffer of each file.
+/// * `output_codec` - Codec of the output file, intermediate files are always BGZF compressed.
+/// * `add_fragment_ids` - Whether to add a column with a unique ID (`frag_1`, `frag_2`, ...)
+///     after the last column of each fragment, numbered in output order. Only for BGZF output.
+/// * `normalize_columns` - How the score column of the output is normalized.
+/// * `duplicate_handling` - How duplicate fragments (same chromosome, start, end and cell barcode) are written,
+///     applied after `normalize_columns`. Can not be combined with `source_labels`, as duplicates can come
+///     from different files.
+/// * `zero_length_handling` - How fragments with start equal to end are handled,
+///     dropped fragments are counted in the `MergeSummary`.
+/// * `source_labels` - If set, a label for each input file (e.g. the name of its sample), which is added
+///     as a column after the last column of each fragment from that file. Only for BGZF output and
+///     at most `max_open_files` files, as the origin of fragments is lost in intermediate files.
+/// * `barcode_rename` - If set, the cell barcodes are renamed in the output, or fragments of cell barcodes
+///     without a new name are dropped. Fragments are sorted and compared with their original cell barcode.
+/// * `memory_map_inputs` - Whether to memory-map the input files instead of reading them through htslib,
+///     which saves read system calls. Requires the `mmap` feature, files are read normally without it
+///     or when they can not be memory-mapped.
+/// * `input_weights` - If set, a weight between 0 and 1 for each input file: each fragment of the file
+///     is kept with this probability, e.g. to balance samples of different depths.
+/// * `sampling_seed` - Seed of the random number generator used with `input_weights`. Each contig of each
+///     input file gets its own random numbers, derived from this seed, see `sampling::derive_seed`.
+/// * `allow_duplicate_inputs` - Whether an input file may be listed more than once, which writes its
+///     fragments more than once. If not, this results in an error, otherwise in a warning.
+/// * `n_output_shards` - If set, the output is written in (at most) this many tabix indexed shards
+///     with about the same number of fragments instead of one file, see `write_shards`. Only for BGZF output.
+/// * `merge_contigs_in_parallel` - Whether the contigs are merged at the same time, each on its own thread,
+///     see `merge_fragment_files_by_contig`. The output is the same as when merging them one after the other,
+///     and is tabix indexed. Requires tabix indexed input files and BGZF output without fragment IDs.
+/// * `score_predicate` - If set, only fragments with a score satisfying this predicate are written
+///     (e.g. at least 2 to drop fragments supported by a single read). Checked before duplicates are collapsed
+///     and can not be combined with decimal scores (`FragmentFormat::score_precision`).
+/// * `missing_score_passes` - Whether fragments without a score are written when `score_predicate` is set.
+/// * `shift` - If set, the number of bp by which the start and end of each written fragment are moved,
+///     e.g. `(4, -5)` for the Tn5 insertion, see `Fragment::shifted`. The sizes of the contigs are not known,
+///     so positions are only clamped at 0. Duplicates are collapsed before the fragments are shifted.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+pub struct MergeOptions {
+    pub format: FragmentFormat,
+    pub max_open_files: usize,
+    pub read_buffer_size: usize,
+    pub output_codec: OutputCodec,
+    pub add_fragment_ids: bool,
+    pub normalize_columns: ColumnNormalization,
+    pub duplicate_handling: DuplicateHandling,
+    pub zero_length_handling: ZeroLengthHandling,
+    pub source_labels: Option<Vec<String>>,
+    pub barcode_rename: Option<BarcodeRename>,
+    pub memory_map_inputs: bool,
+    pub input_weights: Option<Vec<f64>>,
+    pub sampling_seed: u64,
+    pub allow_duplicate_inputs: bool,
+    pub n_output_shards: Option<usize>,
+    pub merge_contigs_in_parallel: bool,
+    pub score_predicate: Option<ScorePredicate>,
+    pub missing_score_passes: bool,
+    pub shift: Option<(i64, i64)>,
+    pub number_of_threads: u32,
+    pub verbose: bool,
+}
+
+impl MergeOptions {
+    /// Returns the default options, with `max_open_files` and `read_buffer_size` set by a memory mode.
     ///
-    /// # Example
+    /// # Arguments
     ///
-    /// ```rust
-    /// let fragment = Fragment::new_from_string("chr1\t100\t200\tAACATCGATGGATG-1\t10");
-    /// assert_eq!(fragment.chrom, "chr1");
-    /// assert_eq!(fragment.start, 100);
-    /// assert_eq!(fragment.end, 200);
-    /// assert_eq!(fragment.cell_barcode, "AACATCGATGGATG-1");
-    /// assert_eq!(fragment.score, Some(10));
-    /// ```
-    fn new_from_string(s: &str) -> Fragment {
-        let fields: Vec<&str> = s.split('\t').collect();
-        match fields.len() {
-            4 => Fragment {
-                chrom: fields[0].to_string(),
-                start: fields[1].parse::<usize>().unwrap(),
-                end: fields[2].parse::<usize>().unwrap(),
-                cell_barcode: fields[3].to_string(),
-                score: None,
-            },
-            5 => Fragment {
-                chrom: fields[0].to_string(),
-                start: fields[1].parse::<usize>().unwrap(),
-                end: fields[2].parse::<usize>().unwrap(),
-                cell_barcode: fields[3].to_string(),
-                score: Some(fields[4].parse::<usize>().unwrap()),
-            },
-            _ => panic!("Invalid number of fields in fragment file!"),
+    /// * `memory_mode` - The memory mode.
+    pub fn with_memory_mode(memory_mode: MemoryMode) -> MergeOptions {
+        MergeOptions {
+            max_open_files: memory_mode.max_open_files(),
+            read_buffer_size: memory_mode.read_buffer_size(),
+            ..Default::default()
         }
     }
 }
 
-impl Ord for Fragment {
-    fn cmp(&self, other: &Fragment) -> std::cmp::Ordering {
-        let self_chrom = &self.chrom;
-        let other_chrom = &other.chrom;
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            format: FragmentFormat::default(),
+            max_open_files: MemoryMode::Fast.max_open_files(),
+            read_buffer_size: MemoryMode::Fast.read_buffer_size(),
+            output_codec: OutputCodec::Bgzf,
+            add_fragment_ids: false,
+            normalize_columns: ColumnNormalization::None,
+            duplicate_handling: DuplicateHandling::Keep,
+            zero_length_handling: ZeroLengthHandling::Drop,
+            source_labels: None,
+            barcode_rename: None,
+            memory_map_inputs: false,
+            input_weights: None,
+            sampling_seed: 0,
+            allow_duplicate_inputs: false,
+            n_output_shards: None,
+            merge_contigs_in_parallel: false,
+            score_predicate: None,
+            missing_score_passes: false,
+            shift: None,
+            number_of_threads: 5,
+            verbose: false,
+        }
+    }
+}
 
-        let self_start = &self.start;
-        let other_start = &other.start;
+/// Aggregates multiple sorted fragment files into a single sorted file.
+///
+/// The files are merged with a k-way merge, so only one fragment per file is kept in memory.
+/// When there are more files than `max_open_files`, the files are first merged in batches of
+/// `max_open_files` into temporary files (next to the output file), which are merged in turn.
+/// A file of which the fragments are not sorted by start and end within each contig results in an
+/// `InvalidFragmentFile` error (of kind `Unsorted`) naming the file.
+/// The files are read from start to end, so they can also be plain gzip (instead of BGZF) compressed,
+/// unless `MergeOptions::merge_contigs_in_parallel` is set, which fetches the contigs with their tabix index.
+///
+/// # Arguments
+/// * `path_to_fragment_files` - Paths to the fragment files, each sorted by contig and position. The contigs
+///     are sorted lexicographically, or in the same order in all files which are tabix indexed, see `ContigOrder`.
+/// * `path_to_output_file` - Path to the output file.
+/// * `options` - Options, see `MergeOptions`.
+///
+/// # Returns
+///
+/// A `MergeSummary`.
 
-        let self_end = &self.end;
-        let other_end = &other.end;
+pub fn merge_fragment_files(
+    path_to_fragment_files: &[String],
+    path_to_output_file: &str,
+    options: &MergeOptions,
+) -> FragmentToolsResult<MergeSummary> {
+    if options.max_open_files < 2 {
+        return Err(FragmentToolsError::InvalidArgument(format!(
+            "max_open_files should be at least 2, got {}",
+            options.max_open_files
+        )));
+    }
+    if options.add_fragment_ids && options.output_codec != OutputCodec::Bgzf {
+        return Err(FragmentToolsError::InvalidArgument(
+            "Fragment IDs can only be added to bgzf output".to_string(),
+        ));
+    }
+    if let Some(source_labels) = &options.source_labels {
+        if source_labels.len() != path_to_fragment_files.len() {
+            return Err(FragmentToolsError::InvalidArgument(format!(
+                "Got {} source labels for {} fragment files",
+                source_labels.len(),
+                path_to_fragment_files.len()
+            )));
+        }
+        if options.output_codec != OutputCodec::Bgzf {
+            return Err(FragmentToolsError::InvalidArgument(
+                "Source labels can only be added to bgzf output".to_string(),
+            ));
+        }
+        if options.duplicate_handling != DuplicateHandling::Keep {
+            return Err(FragmentToolsError::InvalidArgument(
+                "Source labels can not be added when duplicates are collapsed".to_string(),
+            ));
+        }
+        if path_to_fragment_files.len() > options.max_open_files {
+            return Err(FragmentToolsError::InvalidArgument(format!(
+                "Source labels can only be added when merging at most max_open_files ({}) files",
+                options.max_open_files
+            )));
+        }
+    }
+    if let Some(input_weights) = &options.input_weights {
+        if input_weights.len() != path_to_fragment_files.len() {
+            return Err(FragmentToolsError::InvalidArgument(format!(
+                "Got {} input weights for {} fragment files",
+                input_weights.len(),
+                path_to_fragment_files.len()
+            )));
+        }
+        if let Some(input_weight) = input_weights
+            .iter()
+            .find(|input_weight| !(0.0..=1.0).contains(*input_weight))
+        {
+            return Err(FragmentToolsError::InvalidArgument(format!(
+                "Input weights should be between 0 and 1, got {}",
+                input_weight
+            )));
+        }
+    }
+    if options.format.score_precision.is_some() && options.output_codec != OutputCodec::Bgzf {
+        return Err(FragmentToolsError::InvalidArgument(
+            "Decimal scores (score_precision) can only be written to bgzf output".to_string(),
+        ));
+    }
+    if options.format.score_precision.is_some() && options.score_predicate.is_some() {
+        return Err(FragmentToolsError::InvalidArgument(
+            "score_predicate can not be used with decimal scores (score_precision)".to_string(),
+        ));
+    }
+    if let Some(n_output_shards) = options.n_output_shards {
+        if n_output_shards == 0 {
+            return Err(FragmentToolsError::InvalidArgument(
+                "n_output_shards should be at least 1".to_string(),
+            ));
+        }
+        if options.output_codec != OutputCodec::Bgzf {
+            return Err(FragmentToolsError::InvalidArgument(
+                "Shards can only be written to bgzf output".to_string(),
+            ));
+        }
+    }
+    if options.merge_contigs_in_parallel {
+        if options.output_codec != OutputCodec::Bgzf {
+            return Err(FragmentToolsError::InvalidArgument(
+                "Contigs can only be merged in parallel for bgzf output".to_string(),
+            ));
+        }
+        if options.add_fragment_ids {
+            return Err(FragmentToolsError::InvalidArgument(
+                "Fragment IDs can not be added when merging contigs in parallel".to_string(),
+            ));
+        }
+    }
+    check_duplicate_inputs(path_to_fragment_files, options.allow_duplicate_inputs)?;
+    if options.memory_map_inputs && !cfg!(feature = "mmap") {
+        println!(
+            "Warning: memory mapping the input files requires the mmap feature, reading them normally."
+        );
+    }
+    let tpool = create_thread_pool(options.number_of_threads)?;
 
-        let self_cell_barcode = &self.cell_barcode;
-        let other_cell_barcode = &other.cell_barcode;
+    let mut paths_to_intermediate_files: Vec<String> = Vec::new();
+    let mut number_of_zero_length_fragments: u64 = 0;
+    // shards are cut from the merged file, which is then removed
+    let path_to_merged_file = match options.n_output_shards {
+        Some(_) => {
+            let path_to_merged_file = format!("{}.merge_unsharded", path_to_output_file);
+            paths_to_intermediate_files.push(path_to_merged_file.clone());
+            path_to_merged_file
+        }
+        None => path_to_output_file.to_string(),
+    };
+    let contig_order = ContigOrder::of_files(path_to_fragment_files);
+    let result = if options.merge_contigs_in_parallel {
+        merge_fragment_files_by_contig(
+            path_to_fragment_files,
+            &path_to_merged_file,
+            options,
+            &contig_order,
+            &mut paths_to_intermediate_files,
+            &mut number_of_zero_length_fragments,
+        )
+    } else {
+        merge_fragment_files_in_batches(
+            path_to_fragment_files,
+            &path_to_merged_file,
+            options,
+            &contig_order,
+            None,
+            &tpool,
+            &mut paths_to_intermediate_files,
+            &mut number_of_zero_length_fragments,
+        )
+    }
+    .and_then(|contig_order| {
+        let shards = match options.n_output_shards {
+            Some(n_output_shards) => write_shards(
+                &path_to_merged_file,
+                path_to_output_file,
+                n_output_shards,
+                &tpool,
+                options.verbose,
+            )?,
+            None if options.merge_contigs_in_parallel => {
+                log(
+                    &format!("Indexing {}", path_to_output_file),
+                    options.verbose,
+                );
+                build_tabix_index(path_to_output_file, &TabixColumns::default())?;
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        Ok((contig_order, shards))
+    });
 
-        if self_chrom != other_chrom {
-            self_chrom.cmp(other_chrom)
-        } else if self_start != other_start {
-            return self_start.cmp(other_start);
-        } else if self_end != other_end {
-            return self_end.cmp(other_end);
-        } else {
-            return self_cell_barcode.cmp(other_cell_barcode);
+    // intermediate files are removed, also when merging failed
+    for path_to_intermediate_file in paths_to_intermediate_files {
+        let _ = remove_file(&path_to_intermediate_file);
+        let _ = remove_file(temporary_path(&path_to_intermediate_file));
+    }
+    let (contig_order, shards) = result?;
+    if number_of_zero_length_fragments > 0 {
+        println!(
+            "Warning: dropped {} zero-length fragment(s) (with start equal to end)",
+            number_of_zero_length_fragments
+        );
+    }
+    let (shard_paths, shard_ranges) = shards.into_iter().unzip();
+    Ok(MergeSummary {
+        contig_order,
+        zero_length_fragments: number_of_zero_length_fragments,
+        shard_paths,
+        shard_ranges,
+    })
+}
+
+/// A shard of which the contig and start can not be used within this fraction of the shard size
+/// from its ideal end is not ended at a contig boundary.
+const SHARD_CONTIG_BOUNDARY_TOLERANCE: f64 = 0.25;
+
+/// Contig and start of the first fragment and contig and end of the last fragment of a shard.
+type ShardRange = (String, u64, String, u64);
+
+/// Returns the path of a shard of the output file: `merged.tsv.gz` becomes `merged.shard_0.tsv.gz`.
+///
+/// # Arguments
+/// * `path_to_output_file` - Path to the output file.
+/// * `shard_index` - Index of the shard.
+fn shard_path(path_to_output_file: &str, shard_index: usize) -> String {
+    let file_name_start = path_to_output_file.rfind('/').map_or(0, |index| index + 1);
+    match path_to_output_file[file_name_start..].find('.') {
+        Some(extension_start) => {
+            let (stem, extension) = path_to_output_file.split_at(file_name_start + extension_start);
+            format!("{}.shard_{}{}", stem, shard_index, extension)
         }
+        None => format!("{}.shard_{}", path_to_output_file, shard_index),
     }
 }
 
-impl PartialOrd for Fragment {
-    fn partial_cmp(&self, other: &Fragment) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+/// Returns the contig and start of a line, which is not parsed completely.
+fn contig_and_position_of_line(line: &str, column: usize) -> (&str, u64) {
+    let mut fields = line.split('\t');
+    let contig = fields.next().unwrap_or_default();
+    let position = fields
+        .nth(column - 1)
+        .and_then(|position| position.parse::<u64>().ok())
+        .unwrap_or_default();
+    (contig, position)
+}
+
+/// Writes a merged (sorted) fragment file in shards with about the same number of fragments,
+/// e.g. for scatter-gather pipelines. Each shard is BGZF compressed and tabix indexed, and
+/// concatenating the shards gives the merged file.
+///
+/// A shard ends at a contig boundary when there is one within `SHARD_CONTIG_BOUNDARY_TOLERANCE`
+/// of the shard size from its ideal end, otherwise in the middle of a contig, but never between
+/// fragments with the same start. Fewer shards are written when there are fewer fragments than shards.
+///
+/// # Arguments
+/// * `path_to_merged_file` - Path to the merged file.
+/// * `path_to_output_file` - Path to the output file, from which the paths of the shards are derived
+///     (see `shard_path`).
+/// * `n_output_shards` - Number of shards.
+/// * `tpool` - Thread pool to use for writing.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// For each shard, its path and the contig and start of its first fragment and the contig and end
+/// of its last fragment.
+fn write_shards(
+    path_to_merged_file: &str,
+    path_to_output_file: &str,
+    n_output_shards: usize,
+    tpool: &ThreadPool,
+    verbose: bool,
+) -> FragmentToolsResult<Vec<(String, ShardRange)>> {
+    let read_error = |e: std::io::Error| {
+        FragmentToolsError::Io(format!(
+            "Could not read file {}: {}",
+            path_to_merged_file, e
+        ))
+    };
+    let open_merged_file = || {
+        Reader::from_path(path_to_merged_file)
+            .map(BufReader::new)
+            .map_err(|_| {
+                FragmentToolsError::Io(format!("Could not open file {}", path_to_merged_file))
+            })
+    };
+
+    // first pass: the number of fragments of each contig, from which the boundaries are chosen
+    let mut contig_boundaries: Vec<u64> = Vec::new();
+    let mut number_of_fragments: u64 = 0;
+    let mut previous_contig = String::new();
+    for line in open_merged_file()?.lines() {
+        let line = line.map_err(read_error)?;
+        let (contig, _) = contig_and_position_of_line(&line, 1);
+        if number_of_fragments > 0 && contig != previous_contig {
+            contig_boundaries.push(number_of_fragments);
+        }
+        if contig != previous_contig {
+            previous_contig = contig.to_string();
+        }
+        number_of_fragments += 1;
+    }
+    let shard_size = number_of_fragments as f64 / n_output_shards as f64;
+    let mut shard_ends: Vec<u64> = Vec::new();
+    for shard_index in 1..n_output_shards {
+        let ideal_end = (shard_index as f64 * shard_size).round() as u64;
+        let shard_end = contig_boundaries
+            .iter()
+            .copied()
+            .min_by_key(|contig_boundary| contig_boundary.abs_diff(ideal_end))
+            .filter(|contig_boundary| {
+                contig_boundary.abs_diff(ideal_end) as f64
+                    <= shard_size * SHARD_CONTIG_BOUNDARY_TOLERANCE
+            })
+            .unwrap_or(ideal_end);
+        if shard_end > shard_ends.last().copied().unwrap_or(0) && shard_end < number_of_fragments {
+            shard_ends.push(shard_end);
+        }
     }
+
+    // second pass: write the shards
+    let mut shards: Vec<(String, ShardRange)> = Vec::new();
+    let mut writer: Option<Writer> = None;
+    let mut previous_contig = String::new();
+    let mut previous_start: u64 = 0;
+    let mut previous_end: u64 = 0;
+    let finish_shard = |writer: Writer,
+                        shards: &mut Vec<(String, ShardRange)>,
+                        last_contig: &str,
+                        last_end: u64|
+     -> FragmentToolsResult<()> {
+        let (path_to_shard, (_, _, shard_last_contig, shard_last_end)) = shards.last_mut().unwrap();
+        *shard_last_contig = last_contig.to_string();
+        *shard_last_end = last_end;
+        finish_temporary_file(writer, path_to_shard).map_err(|e| {
+            FragmentToolsError::Io(format!("Could not write to file {}: {}", path_to_shard, e))
+        })?;
+        log(&format!("Indexing {}", path_to_shard), verbose);
+        build_tabix_index(path_to_shard, &TabixColumns::default())
+    };
+    for (line_index, line) in open_merged_file()?.lines().enumerate() {
+        let line = line.map_err(read_error)?;
+        let (contig, start) = contig_and_position_of_line(&line, 1);
+        // a shard is ended at the first fragment with another contig or start after its planned end
+        let starts_shard = match shards.len() {
+            0 => true,
+            number_of_shards => shard_ends
+                .get(number_of_shards - 1)
+                .is_some_and(|&shard_end| {
+                    line_index as u64 >= shard_end
+                        && (contig != previous_contig || start != previous_start)
+                }),
+        };
+        if starts_shard {
+            if let Some(writer) = writer.take() {
+                finish_shard(writer, &mut shards, &previous_contig, previous_end)?;
+            }
+            let path_to_shard = shard_path(path_to_output_file, shards.len());
+            log(&format!("Writing shard {}", path_to_shard), verbose);
+            writer = Some(create_writer(&path_to_shard, tpool)?);
+            shards.push((path_to_shard, (contig.to_string(), start, String::new(), 0)));
+        }
+        let writer = writer.as_mut().unwrap();
+        writer
+            .write_all(line.as_bytes())
+            .and_then(|_| writer.write_all(b"\n"))
+            .map_err(|e| {
+                FragmentToolsError::Io(format!(
+                    "Could not write to file {}: {}",
+                    shards.last().unwrap().0,
+                    e
+                ))
+            })?;
+        if contig != previous_contig {
+            previous_contig = contig.to_string();
+        }
+        previous_start = start;
+        previous_end = contig_and_position_of_line(&line, 2).1;
+    }
+    if let Some(writer) = writer.take() {
+        finish_shard(writer, &mut shards, &previous_contig, previous_end)?;
+    }
+    Ok(shards)
 }
 
-impl fmt::Display for Fragment {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.score {
-            Some(score) => write!(
-                f,
-                "{}\t{}\t{}\t{}\t{}",
-                self.chrom, self.start, self.end, self.cell_barcode, score
-            ),
-            None => write!(
-                f,
-                "{}\t{}\t{}\t{}",
-                self.chrom, self.start, self.end, self.cell_barcode
+/// Checks whether a file is listed more than once (e.g. by a wrong glob pattern), as its fragments
+/// would then be written more than once. Paths are compared after resolving them to absolute paths
+/// without symbolic links, paths of files which do not exist are compared as given.
+///
+/// # Arguments
+/// * `path_to_fragment_files` - Paths to the fragment files.
+/// * `allow_duplicate_inputs` - Whether to print a warning instead of returning an error.
+fn check_duplicate_inputs(
+    path_to_fragment_files: &[String],
+    allow_duplicate_inputs: bool,
+) -> FragmentToolsResult<()> {
+    let mut canonical_path_to_count: HashMap<PathBuf, usize> = HashMap::new();
+    for path_to_fragment_file in path_to_fragment_files {
+        let canonical_path = canonicalize(path_to_fragment_file)
+            .unwrap_or_else(|_| PathBuf::from(path_to_fragment_file));
+        *canonical_path_to_count.entry(canonical_path).or_default() += 1;
+    }
+    let duplicate_inputs: Vec<String> = canonical_path_to_count
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(canonical_path, count)| format!("{} ({} times)", canonical_path.display(), count))
+        .sorted()
+        .collect();
+    if duplicate_inputs.is_empty() {
+        return Ok(());
+    }
+    if !allow_duplicate_inputs {
+        return Err(FragmentToolsError::InvalidArgument(format!(
+            "Fragment files are listed more than once, which would write their fragments more than once: {} \
+            (set allow_duplicate_inputs to merge them anyway)",
+            duplicate_inputs.join(", ")
+        )));
+    }
+    println!(
+        "Warning: fragment files are listed more than once, their fragments are written more than once: {}",
+        duplicate_inputs.join(", ")
+    );
+    Ok(())
+}
+
+/// Merges fragment files in batches of at most `max_open_files`, until they can be merged at once.
+///
+/// # Arguments
+/// * `path_to_fragment_files` - Paths to the (sorted) fragment files.
+/// * `path_to_output_file` - Path to the output file.
+/// * `options` - Options, see `MergeOptions`.
+/// * `contig_order` - Order of the contigs in the output, see `ContigOrder`.
+/// * `contig` - If set, only the fragments of this contig are read from the (tabix indexed) input files.
+/// * `tpool` - Thread pool to use for writing.
+/// * `paths_to_intermediate_files` - Paths of the intermediate files are added here.
+/// * `number_of_zero_length_fragments` - Incremented for each dropped zero-length fragment.
+///
+/// # Returns
+///
+/// The contigs, in the order in which they were written.
+#[allow(clippy::too_many_arguments)]
+fn merge_fragment_files_in_batches(
+    path_to_fragment_files: &[String],
+    path_to_output_file: &str,
+    options: &MergeOptions,
+    contig_order: &ContigOrder,
+    mut contig: Option<&str>,
+    tpool: &ThreadPool,
+    paths_to_intermediate_files: &mut Vec<String>,
+    number_of_zero_length_fragments: &mut u64,
+) -> FragmentToolsResult<Vec<String>> {
+    let MergeOptions {
+        max_open_files,
+        verbose,
+        ..
+    } = *options;
+    let mut paths_to_merge: Vec<String> = path_to_fragment_files.to_vec();
+    // the input files are sampled when they are read, in the first merge
+    let mut input_weights = options.input_weights.as_deref();
+    let intermediate_format = intermediate_format(&options.format);
+    let mut level_format = &options.format;
+    let mut level: usize = 0;
+    while paths_to_merge.len() > max_open_files {
+        log(
+            &format!(
+                "Merging {} files in batches of {}",
+                paths_to_merge.len(),
+                max_open_files
             ),
+            verbose,
+        );
+        // batches are consecutive files, so ties are still written in the order of the files
+        let mut paths_to_merged_batches: Vec<String> = Vec::new();
+        for (batch_index, batch) in paths_to_merge.chunks(max_open_files).enumerate() {
+            let first_file_index = batch_index * max_open_files;
+            let path_to_merged_batch =
+                format!("{}.merge_{}_{}", path_to_output_file, level, batch_index);
+            paths_to_intermediate_files.push(path_to_merged_batch.clone());
+            merge_sorted_fragment_files(
+                batch,
+                &path_to_merged_batch,
+                level_format,
+                options,
+                contig_order,
+                input_weights.map(|input_weights| {
+                    (
+                        first_file_index,
+                        &input_weights[first_file_index..][..batch.len()],
+                    )
+                }),
+                contig,
+                false,
+                tpool,
+                number_of_zero_length_fragments,
+            )?;
+            paths_to_merged_batches.push(path_to_merged_batch);
         }
+        // intermediate files are always written in the standard layout, see `intermediate_format`
+        level_format = &intermediate_format;
+        input_weights = None;
+        contig = None;
+        paths_to_merge = paths_to_merged_batches;
+        level += 1;
     }
+    log(&format!("Merging {} files", paths_to_merge.len()), verbose);
+    merge_sorted_fragment_files(
+        &paths_to_merge,
+        path_to_output_file,
+        level_format,
+        options,
+        contig_order,
+        input_weights.map(|input_weights| (0, input_weights)),
+        contig,
+        true,
+        tpool,
+        number_of_zero_length_fragments,
+    )
 }
 
-/// Aggregates multiple fragment files into a single file.
+/// Returns the layout of the intermediate files of batched merges, see `intermediate_line`.
+///
+/// Intermediate files are written in the standard layout, but with the strand (if the input files have one)
+/// before the optional score, so fragments with a strand and without score are read back correctly.
+/// Score pairs are kept, so they are read back as pairs.
 ///
 /// # Arguments
-/// * `path_to_fragment_files` - Paths to the fragment files.
+///
+/// * `format` - Layout of the input files.
+fn intermediate_format(format: &FragmentFormat) -> FragmentFormat {
+    let default_columns = FragmentColumns::default();
+    FragmentFormat {
+        columns: match format.columns.strand {
+            Some(_) => FragmentColumns {
+                strand: Some(default_columns.score),
+                score: default_columns.score + 1,
+                ..default_columns
+            },
+            None => default_columns,
+        },
+        score_pair: format.score_pair,
+        ..FragmentFormat::default()
+    }
+}
+
+/// Returns a fragment as written to an intermediate file, see `intermediate_format`.
+fn intermediate_line(fragment: &Fragment) -> String {
+    let strand = match fragment.strand {
+        Some(strand) => strand,
+        None => return fragment.to_string(),
+    };
+    let mut line = format!(
+        "{}\t{}\t{}\t{}\t{}",
+        fragment.chrom, fragment.start, fragment.end, fragment.cell_barcode, strand
+    );
+    match (fragment.score, fragment.score_pair) {
+        (Some(_), Some((first, second))) => line.push_str(&format!("\t{},{}", first, second)),
+        (Some(score), None) => line.push_str(&format!("\t{}", score)),
+        (None, _) => {}
+    }
+    line
+}
+
+/// Order of the contigs in the merged output.
+///
+/// # Fields
+///
+/// * `ranks` - Position of each contig in the order. Contigs without rank come after all ranked contigs,
+///     in lexicographic order.
+#[derive(Default)]
+struct ContigOrder {
+    ranks: HashMap<String, usize>,
+}
+
+impl ContigOrder {
+    /// Returns the contig order of fragment files, taken from the tabix indexes of the files which have one,
+    /// as they list the contigs in the order of the file.
+    ///
+    /// The contig orders of the files are combined into one order in which each contig comes after the contigs
+    /// before it in any of the files, with ties in lexicographic order. So files which are all sorted
+    /// lexicographically, or all in the same other order (e.g. chr1, chr2, chr10), are merged in that order.
+    /// Files which are sorted in conflicting orders get an order in which some contig appears twice
+    /// in the output, which is an error when merging.
+    ///
+    /// # Arguments
+    ///
+    /// * `path_to_fragment_files` - Paths to the fragment files, files without (readable) index are skipped.
+    fn of_files(path_to_fragment_files: &[String]) -> ContigOrder {
+        let contigs_of_files: Vec<Vec<String>> = path_to_fragment_files
+            .iter()
+            .filter(|path| has_tabix_index(path))
+            .filter_map(|path| open_fragments_file(path).ok())
+            .map(|tbx_reader| tbx_reader.seqnames())
+            .collect();
+        let mut successors: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+        let mut number_of_predecessors: HashMap<&str, usize> = HashMap::new();
+        for contigs in &contigs_of_files {
+            for contig in contigs {
+                number_of_predecessors.entry(contig).or_default();
+            }
+            for (contig, next_contig) in contigs.iter().tuple_windows() {
+                if successors.entry(contig).or_default().insert(next_contig) {
+                    *number_of_predecessors.entry(next_contig).or_default() += 1;
+                }
+            }
+        }
+        let mut unranked_contigs: BTreeSet<&str> = number_of_predecessors.keys().copied().collect();
+        let mut ready_contigs: BTreeSet<&str> = number_of_predecessors
+            .iter()
+            .filter(|(_, &number_of_predecessors)| number_of_predecessors == 0)
+            .map(|(&contig, _)| contig)
+            .collect();
+        let mut ranks: HashMap<String, usize> = HashMap::new();
+        // when no contig is ready, the orders conflict and the lexicographically first contig is taken
+        while let Some(contig) = ready_contigs
+            .pop_first()
+            .or_else(|| unranked_contigs.first().copied())
+        {
+            unranked_contigs.remove(contig);
+            ranks.insert(contig.to_string(), ranks.len());
+            for &next_contig in successors.get(contig).into_iter().flatten() {
+                let number_of_predecessors = number_of_predecessors.get_mut(next_contig).unwrap();
+                *number_of_predecessors -= 1;
+                if *number_of_predecessors == 0 && unranked_contigs.contains(next_contig) {
+                    ready_contigs.insert(next_contig);
+                }
+            }
+        }
+        ContigOrder { ranks }
+    }
+
+    /// Returns the rank of a contig, `usize::MAX` for contigs which are not in the order.
+    ///
+    /// # Arguments
+    ///
+    /// * `contig` - Name of the contig.
+    fn rank(&self, contig: &str) -> usize {
+        self.ranks.get(contig).copied().unwrap_or(usize::MAX)
+    }
+}
+
+/// Next fragment of a file in a k-way merge, ordered by the rank of its contig (see `ContigOrder`)
+/// and then by all fields of the fragment.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct MergeEntry {
+    contig_rank: usize,
+    fragment: Fragment,
+}
+
+/// Contig order and number of dropped zero-length fragments of a merged contig.
+type MergedContig = (Vec<String>, u64);
+
+/// Merges tabix indexed fragment files contig by contig, with `number_of_threads` contigs at the same time.
+///
+/// Each contig is merged (in batches of at most `max_open_files` files, see `merge_fragment_files_in_batches`)
+/// into its own intermediate file, which are concatenated in the contig order, so the output
+/// is the same as when merging all contigs at once. As each contig of each input file gets its own random
+/// numbers, the sampled fragments are the same as well.
+///
+/// # Arguments
+/// * `path_to_fragment_files` - Paths to the (sorted and tabix indexed) fragment files.
+/// * `path_to_output_file` - Path to the output file.
+/// * `options` - Options, see `MergeOptions`.
+/// * `contig_order` - Order of the contigs in the output, see `ContigOrder`.
+/// * `paths_to_intermediate_files` - Paths of the intermediate files are added here.
+/// * `number_of_zero_length_fragments` - Incremented for each dropped zero-length fragment.
+///
+/// # Returns
+///
+/// The contigs, in the order in which they were written.
+fn merge_fragment_files_by_contig(
+    path_to_fragment_files: &[String],
+    path_to_output_file: &str,
+    options: &MergeOptions,
+    contig_order: &ContigOrder,
+    paths_to_intermediate_files: &mut Vec<String>,
+    number_of_zero_length_fragments: &mut u64,
+) -> FragmentToolsResult<Vec<String>> {
+    let mut contigs: Vec<String> = Vec::new();
+    for path_to_fragment_file in path_to_fragment_files {
+        contigs.extend(open_fragments_file(path_to_fragment_file)?.seqnames());
+    }
+    let contigs: Vec<String> = contigs
+        .into_iter()
+        .sorted_by_key(|contig| contig_order.rank(contig))
+        .dedup()
+        .collect();
+    let paths_to_contig_files: Vec<String> = (0..contigs.len())
+        .map(|contig_index| format!("{}.merge_contig_{}", path_to_output_file, contig_index))
+        .collect();
+    paths_to_intermediate_files.extend(paths_to_contig_files.iter().cloned());
+
+    // each thread takes the next contig that is not merged yet
+    let next_contig = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<FragmentToolsResult<MergedContig>>>> =
+        Mutex::new((0..contigs.len()).map(|_| None).collect());
+    let paths_to_batch_files: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..(options.number_of_threads as usize).min(contigs.len()) {
+            scope.spawn(|| loop {
+                let contig_index = next_contig.fetch_add(1, Ordering::SeqCst);
+                let Some(contig) = contigs.get(contig_index) else {
+                    break;
+                };
+                log(&format!("Merging contig {}", contig), options.verbose);
+                let mut paths_to_batch_files_of_contig: Vec<String> = Vec::new();
+                let mut number_of_zero_length_fragments_of_contig: u64 = 0;
+                let result = create_thread_pool(1).and_then(|tpool| {
+                    merge_fragment_files_in_batches(
+                        path_to_fragment_files,
+                        &paths_to_contig_files[contig_index],
+                        options,
+                        contig_order,
+                        Some(contig),
+                        &tpool,
+                        &mut paths_to_batch_files_of_contig,
+                        &mut number_of_zero_length_fragments_of_contig,
+                    )
+                });
+                paths_to_batch_files
+                    .lock()
+                    .unwrap()
+                    .extend(paths_to_batch_files_of_contig);
+                results.lock().unwrap()[contig_index] =
+                    Some(result.map(|contig_order| {
+                        (contig_order, number_of_zero_length_fragments_of_contig)
+                    }));
+            });
+        }
+    });
+    paths_to_intermediate_files.extend(paths_to_batch_files.into_inner().unwrap());
+
+    let mut contig_order: Vec<String> = Vec::new();
+    for result in results.into_inner().unwrap() {
+        let (contig_order_of_contig, number_of_zero_length_fragments_of_contig) =
+            result.unwrap()?;
+        contig_order.extend(contig_order_of_contig);
+        *number_of_zero_length_fragments += number_of_zero_length_fragments_of_contig;
+    }
+    log(
+        &format!("Concatenating {} contigs", contigs.len()),
+        options.verbose,
+    );
+    concatenate_bgzf_files(&paths_to_contig_files, path_to_output_file).map_err(|e| {
+        FragmentToolsError::Io(format!(
+            "Could not write to file {}: {}",
+            path_to_output_file, e
+        ))
+    })?;
+    Ok(contig_order)
+}
+
+/// Concatenates BGZF compressed files into one BGZF compressed file, by copying their compressed blocks
+/// without the EOF block at the end of each file, followed by one EOF block.
+///
+/// # Arguments
+/// * `paths` - Paths to the BGZF compressed files, which should all end with an EOF block.
+/// * `path_to_output_file` - Path to the output file, which is written to its temporary path first.
+fn concatenate_bgzf_files(paths: &[String], path_to_output_file: &str) -> std::io::Result<()> {
+    let path_to_temporary_file = temporary_path(path_to_output_file);
+    let mut output_file = File::create(&path_to_temporary_file)?;
+    for path in paths {
+        if !ends_with_bgzf_eof_block(path)? {
+            return Err(std::io::Error::other(format!(
+                "{} does not end with a BGZF EOF block, the file is truncated",
+                path
+            )));
+        }
+        let file = File::open(path)?;
+        let number_of_bytes = file.metadata()?.len() - BGZF_EOF_BLOCK.len() as u64;
+        std::io::copy(&mut file.take(number_of_bytes), &mut output_file)?;
+    }
+    output_file.write_all(&BGZF_EOF_BLOCK)?;
+    output_file.sync_all()?;
+    drop(output_file);
+    rename(path_to_temporary_file, path_to_output_file)
+}
+
+/// Merges sorted fragment files with a k-way merge and writes the result to a BGZF compressed
+/// or Parquet file.
+///
+/// Fragments which are equal in all but their file of origin are written in the order of the files.
+///
+/// # Arguments
+/// * `path_to_fragment_files` - Paths to the (sorted) fragment files.
+/// * `path_to_output_file` - Path to the output file.
+/// * `format` - Layout of the lines of the input files.
+/// * `options` - Options, see `MergeOptions`. The format is taken from `format` instead.
+/// * `contig_order` - Order of the contigs in the output, see `ContigOrder`.
+/// * `input_weights` - If the files are input files which are sampled, the index of the first file
+///     in the input files (used to seed its sampler) and the weights of the files, see `MergeOptions::input_weights`.
+/// * `contig` - If set, only the fragments of this contig are read from the (tabix indexed) files.
+/// * `is_final_merge` - Whether the output file is the final output. If not, the options which
+///     change the output (codec, fragment IDs, column normalization, source labels and shift) are not applied.
+/// * `tpool` - Thread pool to use for writing.
+/// * `number_of_zero_length_fragments` - Incremented for each dropped zero-length fragment. Zero-length
+///     fragments are handled in every merge, but can only be read from the input files.
+///
+/// # Returns
+///
+/// The contigs, in the order in which they were written.
+#[allow(clippy::too_many_arguments)]
+fn merge_sorted_fragment_files(
+    path_to_fragment_files: &[String],
+    path_to_output_file: &str,
+    format: &FragmentFormat,
+    options: &MergeOptions,
+    contig_order: &ContigOrder,
+    input_weights: Option<(usize, &[f64])>,
+    contig: Option<&str>,
+    is_final_merge: bool,
+    tpool: &ThreadPool,
+    number_of_zero_length_fragments: &mut u64,
+) -> FragmentToolsResult<Vec<String>> {
+    let (output_codec, add_fragment_ids, normalize_columns, duplicate_handling, source_labels) =
+        if is_final_merge {
+            (
+                options.output_codec,
+                options.add_fragment_ids,
+                options.normalize_columns,
+                options.duplicate_handling,
+                options.source_labels.as_deref(),
+            )
+        } else {
+            (
+                OutputCodec::Bgzf,
+                false,
+                ColumnNormalization::None,
+                DuplicateHandling::Keep,
+                None,
+            )
+        };
+    let barcode_rename = options.barcode_rename.as_ref().filter(|_| is_final_merge);
+    let shift = options.shift.filter(|_| is_final_merge);
+    // intermediate files keep decimal scores in their integer units, so they are read back exactly
+    let score_precision = options.format.score_precision.filter(|_| is_final_merge);
+    let normalize_columns = normalize_columns.with_score_precision(score_precision);
+    let read_buffer_size = options.read_buffer_size;
+    let mut readers: Vec<FragmentFileReader> = path_to_fragment_files
+        .iter()
+        .enumerate()
+        .map(|(file_index, path)| {
+            let sampler = input_weights.map(|(first_file_index, input_weights)| {
+                FragmentSampler::new(
+                    input_weights[file_index],
+                    options.sampling_seed,
+                    (first_file_index + file_index) as u64,
+                )
+            });
+            Ok(FragmentFileReader::open(
+                path,
+                file_index,
+                format,
+                read_buffer_size,
+                options.memory_map_inputs,
+                contig,
+            )?
+            .with_sampler(sampler))
+        })
+        .collect::<FragmentToolsResult<_>>()?;
+
+    // the heap contains the next fragment of each file, the file index refers to its reader
+    let merge_entry = |fragment: Fragment| {
+        Reverse(MergeEntry {
+            contig_rank: contig_order.rank(&fragment.chrom),
+            fragment,
+        })
+    };
+    let mut heap: BinaryHeap<Reverse<MergeEntry>> = BinaryHeap::new();
+    for reader in readers.iter_mut() {
+        if let Some(fragment) = reader.next_fragment()? {
+            heap.push(merge_entry(fragment));
+        }
+    }
+
+    // only one of the writers is used, depending on the output codec
+    let (mut writer, mut parquet_writer) = match output_codec {
+        OutputCodec::Bgzf => (Some(create_writer(path_to_output_file, tpool)?), None),
+        OutputCodec::Parquet => {
+            let mut parquet_writer =
+                ParquetFragmentWriter::new(path_to_output_file.to_string(), None);
+            // also write a file without fragments
+            parquet_writer.open()?;
+            (None, Some(parquet_writer))
+        }
+    };
+    let write_error = |e: std::io::Error| {
+        FragmentToolsError::Io(format!(
+            "Could not write to file {}: {}",
+            path_to_output_file, e
+        ))
+    };
+    let mut contig_order: Vec<String> = Vec::new();
+    let mut number_of_fragments: u64 = 0;
+    // Contigs of which the block in the output ended, with the file of their last fragment.
+    // A contig can only reappear when the files are sorted in different contig orders,
+    // which would make the output impossible to index.
+    let mut closed_contigs: HashMap<String, usize> = HashMap::new();
+    let mut last_file_index: usize = 0;
+    let mut write_fragment = |mut fragment: Fragment| -> FragmentToolsResult<()> {
+        if let Some(barcode_rename) = barcode_rename {
+            match barcode_rename.rename(&fragment.cell_barcode) {
+                Some(new_barcode) => fragment.cell_barcode = new_barcode.to_string(),
+                None => return Ok(()),
+            }
+        }
+        if let Some(shift) = shift {
+            fragment = fragment.shifted(shift, None);
+        }
+        if contig_order.last() != Some(&fragment.chrom) {
+            if let Some(&closing_file_index) = closed_contigs.get(&fragment.chrom) {
+                return Err(FragmentToolsError::InvalidFragmentFile(
+                    FragmentFileErrorKind::Unsorted,
+                    format!(
+                        "Contig {} appears again in the merged output after contig {}: \
+                    fragments of {} from {} come after fragments of {} from {}. \
+                    The files should all be sorted by contig lexicographically, \
+                    or in the same order when they are tabix indexed",
+                        fragment.chrom,
+                        contig_order.last().unwrap(),
+                        fragment.chrom,
+                        path_to_fragment_files[fragment.file_index],
+                        fragment.chrom,
+                        path_to_fragment_files[closing_file_index],
+                    ),
+                ));
+            }
+            if let Some(previous_contig) = contig_order.last() {
+                closed_contigs.insert(previous_contig.clone(), last_file_index);
+            }
+            contig_order.push(fragment.chrom.clone());
+        }
+        last_file_index = fragment.file_index;
+        number_of_fragments += 1;
+        if let Some(parquet_writer) = parquet_writer.as_mut() {
+            parquet_writer.write(&fragment)?;
+        } else if let Some(writer) = writer.as_mut() {
+            let mut line = if is_final_merge {
+                fragment.to_string_with_score_precision(score_precision)
+            } else {
+                intermediate_line(&fragment)
+            };
+            // the extra columns are only added here, so they do not affect the order
+            if let Some(source_labels) = source_labels {
+                line.push('\t');
+                line.push_str(&source_labels[fragment.file_index]);
+            }
+            if add_fragment_ids {
+                line.push_str(&format!("\tfrag_{}", number_of_fragments));
+            }
+            line.push('\n');
+            writer.write_all(line.as_bytes()).map_err(write_error)?;
+        }
+        Ok(())
+    };
+    // duplicates are consecutive, as fragments are popped in order of all their fields
+    let mut duplicate_collapser = DuplicateCollapser::new(duplicate_handling, score_precision);
+    while let Some(Reverse(MergeEntry { mut fragment, .. })) = heap.pop() {
+        let file_index = fragment.file_index;
+        if let Some(next_fragment) = readers[file_index].next_fragment()? {
+            heap.push(merge_entry(next_fragment));
+        }
+        if fragment.start == fragment.end {
+            match options.zero_length_handling {
+                ZeroLengthHandling::Keep => {}
+                ZeroLengthHandling::Drop => {
+                    *number_of_zero_length_fragments += 1;
+                    continue;
+                }
+                ZeroLengthHandling::Error => {
+                    return Err(FragmentToolsError::InvalidFragmentFile(
+                        FragmentFileErrorKind::InvalidCoordinates,
+                        format!(
+                            "Zero-length fragment {:?} in {}",
+                            fragment.to_string(),
+                            path_to_fragment_files[file_index]
+                        ),
+                    ))
+                }
+            }
+        }
+        // scores of intermediate files are unchanged, so filtering them again keeps the same fragments
+        if let Some(score_predicate) = &options.score_predicate {
+            let passes = match fragment.score {
+                Some(score) => score_predicate.matches(score),
+                None => options.missing_score_passes,
+            };
+            if !passes {
+                continue;
+            }
+        }
+        normalize_columns.apply(&mut fragment);
+        if let Some(fragment) = duplicate_collapser.push(fragment) {
+            write_fragment(fragment)?;
+        }
+    }
+    if let Some(fragment) = duplicate_collapser.finish() {
+        write_fragment(fragment)?;
+    }
+    if let Some(writer) = writer {
+        finish_temporary_file(writer, path_to_output_file).map_err(write_error)?;
+    }
+    if let Some(parquet_writer) = parquet_writer {
+        parquet_writer.finish()?;
+    }
+    Ok(contig_order)
+}
+
+/// Concatenates fragment files into a single BGZF compressed file, without sorting or parsing them.
+///
+/// Unlike concatenating the compressed files directly (`cat a.gz b.gz`), a file of which the last
+/// line lacks a trailing newline does not run into the first line of the next file:
+/// the missing newline is added. The output always ends with a newline (unless it is empty).
+///
+/// # Arguments
+/// * `path_to_fragment_files` - Paths to the fragment files (BGZF/gzip compressed or uncompressed),
+///     in the order in which they are written.
 /// * `path_to_output_file` - Path to the output file.
 /// * `number_of_threads` - Number of threads to use for writing.
 /// * `verbose` - Whether to print progress messages.
-
-pub fn merge_fragment_files(
+pub fn concatenate_fragment_files(
     path_to_fragment_files: &[String],
-    path_to_output_file: &String,
+    path_to_output_file: &str,
     number_of_threads: u32,
     verbose: bool,
-) {
-    // initialize writer
-    let tpool = ThreadPool::new(number_of_threads).unwrap_or_else(|_| {
-        panic!(
-            "Could not create thread pool with {} threads",
-            number_of_threads
+) -> FragmentToolsResult<()> {
+    let tpool = create_thread_pool(number_of_threads)?;
+    let mut writer = create_writer(path_to_output_file, &tpool)?;
+    let write_error = |e: std::io::Error| {
+        FragmentToolsError::Io(format!(
+            "Could not write to file {}: {}",
+            path_to_output_file, e
+        ))
+    };
+
+    let mut buffer = vec![0u8; 1 << 16];
+    for path_to_fragment_file in path_to_fragment_files {
+        log(&format!("Adding {}", path_to_fragment_file), verbose);
+        let mut reader = Reader::from_path(path_to_fragment_file).map_err(|_| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not open file {}", path_to_fragment_file),
+            )
+        })?;
+        let mut last_byte: Option<u8> = None;
+        loop {
+            let number_of_bytes = reader.read(&mut buffer).map_err(|e| {
+                FragmentToolsError::InvalidFragmentFile(
+                    FragmentFileErrorKind::Unreadable,
+                    format!("Could not read file {}: {}", path_to_fragment_file, e),
+                )
+            })?;
+            if number_of_bytes == 0 {
+                break;
+            }
+            writer
+                .write_all(&buffer[..number_of_bytes])
+                .map_err(write_error)?;
+            last_byte = Some(buffer[number_of_bytes - 1]);
+        }
+        // repair a missing trailing newline, so the next file starts on a new line
+        if last_byte.is_some_and(|last_byte| last_byte != b'\n') {
+            log(
+                &format!(
+                    "Adding missing trailing newline to {}",
+                    path_to_fragment_file
+                ),
+                verbose,
+            );
+            writer.write_all(b"\n").map_err(write_error)?;
+        }
+    }
+    finish_temporary_file(writer, path_to_output_file).map_err(write_error)
+}
+
+/// Maximum number of uncompressed bytes in a BGZF block.
+pub const MAX_BGZF_BLOCK_SIZE: usize = 0xff00;
+
+/// Recompresses a fragment file into a BGZF compressed file of which all blocks (except the last one)
+/// contain the same number of uncompressed bytes.
+///
+/// The content is copied unchanged, blocks can end in the middle of a line.
+///
+/// # Arguments
+/// * `path_to_input_file` - Path to the fragment file (BGZF/gzip compressed or uncompressed).
+/// * `path_to_output_file` - Path to the output file.
+/// * `block_size` - Number of uncompressed bytes per block, at most `MAX_BGZF_BLOCK_SIZE`.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `create_index` - Whether to create a tabix index for the output file.
+/// * `tabix_columns` - Column layout of the tabix index, as the content is copied unchanged
+///     this can be any layout with 0-based positions.
+/// * `verbose` - Whether to print progress messages.
+pub fn rebgzip_fragment_file(
+    path_to_input_file: &str,
+    path_to_output_file: &str,
+    block_size: usize,
+    number_of_threads: u32,
+    create_index: bool,
+    tabix_columns: &TabixColumns,
+    verbose: bool,
+) -> FragmentToolsResult<()> {
+    if block_size == 0 || block_size > MAX_BGZF_BLOCK_SIZE {
+        return Err(FragmentToolsError::InvalidArgument(format!(
+            "block_size should be between 1 and {}, got {}",
+            MAX_BGZF_BLOCK_SIZE, block_size
+        )));
+    }
+    let mut reader = Reader::from_path(path_to_input_file).map_err(|_| {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Unreadable,
+            format!("Could not open file {}", path_to_input_file),
         )
-    });
-    let mut writer = Writer::from_path(path_to_output_file)
-        .unwrap_or_else(|_| panic!("Could not open file {} for writing", path_to_output_file));
-    writer
-        .set_thread_pool(&tpool)
-        .unwrap_or_else(|_| panic!("Could not set thread pool for file {}", path_to_output_file));
-
-    // initialize buffer
-    let mut buffer = String::new();
-
-    // read all files into buffer
-    for path_to_fragment_file in path_to_fragment_files.iter() {
-        log(&format!("Reading file {}", path_to_fragment_file), verbose);
-        read_fragments_file(path_to_fragment_file, &mut buffer);
-    }
-
-    // split buffer and remove empty lines
-    let mut fragments: Vec<Fragment> = buffer
-        .split('\n')
-        .filter(|s| !s.is_empty())
-        .map(Fragment::new_from_string)
-        .collect();
+    })?;
+    let tpool = create_thread_pool(number_of_threads)?;
+    let mut writer = create_writer(path_to_output_file, &tpool)?;
+    let write_error = |e: std::io::Error| {
+        FragmentToolsError::Io(format!(
+            "Could not write to file {}: {}",
+            path_to_output_file, e
+        ))
+    };
+
+    log(
+        &format!(
+            "Recompressing {} with blocks of {} bytes",
+            path_to_input_file, block_size
+        ),
+        verbose,
+    );
+    let mut buffer = vec![0u8; block_size];
+    loop {
+        // fill the buffer completely, a read can return less than a block
+        let mut number_of_bytes = 0;
+        while number_of_bytes < block_size {
+            let number_of_bytes_read =
+                reader.read(&mut buffer[number_of_bytes..]).map_err(|e| {
+                    FragmentToolsError::InvalidFragmentFile(
+                        FragmentFileErrorKind::Unreadable,
+                        format!("Could not read file {}: {}", path_to_input_file, e),
+                    )
+                })?;
+            if number_of_bytes_read == 0 {
+                break;
+            }
+            number_of_bytes += number_of_bytes_read;
+        }
+        if number_of_bytes == 0 {
+            break;
+        }
+        // flushing ends the current block
+        writer
+            .write_all(&buffer[..number_of_bytes])
+            .and_then(|_| writer.flush())
+            .map_err(write_error)?;
+    }
+    finish_temporary_file(writer, path_to_output_file).map_err(write_error)?;
+
+    if create_index {
+        log(&format!("Indexing {}", path_to_output_file), verbose);
+        build_tabix_index(path_to_output_file, tabix_columns)?;
+    }
+    Ok(())
+}
 
-    // sort fragments
+/// Repairs the order of a nearly sorted fragment file (e.g. after small edits) in a single pass.
+///
+/// Fragments are read into a buffer of at most `window` fragments, from which the smallest fragment
+/// (see `Fragment::cmp`) is written each time the buffer is full. This sorts the file as long as no
+/// fragment is more than `window` positions away from its sorted position, which is much cheaper
+/// than a full sort. When a fragment is further out of order, an error is returned.
+///
+/// # Arguments
+/// * `path_to_input_file` - Path to the fragment file (BGZF/gzip compressed or uncompressed).
+/// * `path_to_output_file` - Path to the BGZF compressed output file.
+/// * `window` - Maximum number of fragments kept in the reordering buffer.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// The number of fragments which were out of order (smaller than the fragment before them).
+pub fn fix_local_sort(
+    path_to_input_file: &str,
+    path_to_output_file: &str,
+    window: usize,
+    number_of_threads: u32,
+    verbose: bool,
+) -> FragmentToolsResult<u64> {
+    let format = FragmentFormat::default();
+    let mut reader = FragmentFileReader::open(
+        path_to_input_file,
+        0,
+        &format,
+        MemoryMode::Fast.read_buffer_size(),
+        false,
+        None,
+    )?
+    .without_sort_order_check();
+    let tpool = create_thread_pool(number_of_threads)?;
+    let mut writer = create_writer(path_to_output_file, &tpool)?;
+    let write_error = |e: std::io::Error| {
+        FragmentToolsError::Io(format!(
+            "Could not write to file {}: {}",
+            path_to_output_file, e
+        ))
+    };
+
+    let mut buffer: BinaryHeap<Reverse<Fragment>> = BinaryHeap::with_capacity(window + 1);
+    let mut previous_fragment: Option<Fragment> = None;
+    let mut last_written_fragment: Option<Fragment> = None;
+    let mut number_of_unsorted_fragments: u64 = 0;
+    loop {
+        let fragment = reader.next_fragment()?;
+        if let Some(fragment) = fragment {
+            if previous_fragment
+                .as_ref()
+                .is_some_and(|previous_fragment| &fragment < previous_fragment)
+            {
+                number_of_unsorted_fragments += 1;
+            }
+            previous_fragment = Some(fragment.clone());
+            buffer.push(Reverse(fragment));
+            if buffer.len() <= window {
+                continue;
+            }
+        }
+        let Some(Reverse(smallest_fragment)) = buffer.pop() else {
+            break;
+        };
+        // a fragment which is smaller than the last written one arrived too late to be reordered
+        if let Some(last_written_fragment) = &last_written_fragment {
+            if &smallest_fragment < last_written_fragment {
+                return Err(FragmentToolsError::InvalidFragmentFile(
+                    FragmentFileErrorKind::Unsorted,
+                    format!(
+                        "Fragment {:?} of {} is more than {} positions out of order, \
+                        use a larger window or sort the file",
+                        smallest_fragment.to_string(),
+                        path_to_input_file,
+                        window
+                    ),
+                ));
+            }
+        }
+        writer
+            .write_all(smallest_fragment.to_string().as_bytes())
+            .and_then(|_| writer.write_all(b"\n"))
+            .map_err(write_error)?;
+        last_written_fragment = Some(smallest_fragment);
+    }
+    finish_temporary_file(writer, path_to_output_file).map_err(write_error)?;
+    log(
+        &format!(
+            "Reordered {} fragment(s) which were out of order in {}",
+            number_of_unsorted_fragments, path_to_input_file
+        ),
+        verbose,
+    );
+    Ok(number_of_unsorted_fragments)
+}
+
+/// Sorts fragments and writes them to a BGZF compressed file.
+///
+/// # Arguments
+/// * `fragments` - Fragments to write.
+/// * `path_to_output_file` - Path to the output file.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// The contigs, in the order in which they were written.
+pub(crate) fn sort_and_write_fragments(
+    mut fragments: Vec<Fragment>,
+    path_to_output_file: &String,
+    number_of_threads: u32,
+    verbose: bool,
+) -> FragmentToolsResult<Vec<String>> {
+    // sort fragments, the order of fragments is total so an unstable sort is deterministic
     log("Sorting fragments", verbose);
-    fragments.sort();
+    fragments.sort_unstable();
 
     // write fragments
     log("Writing fragments", verbose);
+    write_fragments(&fragments, path_to_output_file, number_of_threads)?;
+    Ok(fragments
+        .iter()
+        .map(|fragment| &fragment.chrom)
+        .dedup()
+        .cloned()
+        .collect())
+}
+
+/// Writes fragments, in the given order, to a BGZF compressed file.
+///
+/// # Arguments
+/// * `fragments` - Fragments to write.
+/// * `path_to_output_file` - Path to the output file.
+/// * `number_of_threads` - Number of threads to use for writing.
+pub(crate) fn write_fragments(
+    fragments: &[Fragment],
+    path_to_output_file: &String,
+    number_of_threads: u32,
+) -> FragmentToolsResult<()> {
+    // initialize writer
+    let tpool = create_thread_pool(number_of_threads)?;
+    let mut writer = create_writer(path_to_output_file, &tpool)?;
+    let write_error = |e: std::io::Error| {
+        FragmentToolsError::Io(format!(
+            "Could not write to file {}: {}",
+            path_to_output_file, e
+        ))
+    };
+
     for fragment in fragments {
-        writer.write_all(fragment.to_string().as_bytes()).unwrap();
-        writer.write_all(b"\n").unwrap();
+        writer
+            .write_all(fragment.to_string().as_bytes())
+            .map_err(write_error)?;
+        writer.write_all(b"\n").map_err(write_error)?;
+    }
+    finish_temporary_file(writer, path_to_output_file).map_err(write_error)
+}
+
+/// Creates a thread pool for writing BGZF compressed files.
+///
+/// Prints a warning when the number of threads is more than twice the number of available cores,
+/// as the threads then mostly compete for the same cores.
+///
+/// # Arguments
+/// * `number_of_threads` - Number of threads.
+pub(crate) fn create_thread_pool(number_of_threads: u32) -> FragmentToolsResult<ThreadPool> {
+    if let Ok(available_cores) = std::thread::available_parallelism() {
+        if number_of_threads as usize > 2 * available_cores.get() {
+            println!(
+                "Warning: number_of_threads ({}) is more than twice the number of available cores ({}), \
+                consider using at most {} threads.",
+                number_of_threads, available_cores, available_cores
+            );
+        }
+    }
+    ThreadPool::new(number_of_threads).map_err(|_| {
+        FragmentToolsError::InvalidArgument(format!(
+            "Could not create thread pool with {} threads",
+            number_of_threads
+        ))
+    })
+}
+
+/// Opens a BGZF writer for the temporary path of a file, see `finish_temporary_file`.
+///
+/// # Arguments
+/// * `path_to_output_file` - Final path of the file.
+/// * `tpool` - Thread pool to use for writing.
+pub(crate) fn create_writer(
+    path_to_output_file: &str,
+    tpool: &ThreadPool,
+) -> FragmentToolsResult<Writer> {
+    let mut writer = Writer::from_path(temporary_path(path_to_output_file)).map_err(|_| {
+        FragmentToolsError::Io(format!(
+            "Could not open file {} for writing",
+            path_to_output_file
+        ))
+    })?;
+    writer.set_thread_pool(tpool).map_err(|_| {
+        FragmentToolsError::Io(format!(
+            "Could not set thread pool for file {}",
+            path_to_output_file
+        ))
+    })?;
+    Ok(writer)
+}
+
+/// Returns the temporary path a file is written to, before it is moved to its final path.
+///
+/// # Arguments
+/// * `path` - Final path of the file.
+pub(crate) fn temporary_path(path: &str) -> String {
+    format!("{}.tmp", path)
+}
+
+/// The empty block which ends every BGZF file, see section 4.1.2 of the SAM specification.
+const BGZF_EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Flushes and closes a BGZF writer of a temporary file (which writes the EOF block),
+/// and moves the file to its final path.
+///
+/// Closing the writer can not return an error, so the EOF block is checked before the file is moved:
+/// a file of which the last blocks were not written would otherwise look complete to tools which
+/// read it as plain gzip (e.g. `zcat`), as each block is a complete gzip member.
+///
+/// # Arguments
+/// * `writer` - Writer of the temporary file, see `temporary_path`.
+/// * `path` - Final path of the file.
+pub(crate) fn finish_temporary_file(mut writer: Writer, path: &str) -> std::io::Result<()> {
+    writer.flush()?;
+    drop(writer);
+    let path_to_temporary_file = temporary_path(path);
+    if !ends_with_bgzf_eof_block(&path_to_temporary_file)? {
+        return Err(std::io::Error::other(format!(
+            "{} does not end with a BGZF EOF block, the file is truncated",
+            path
+        )));
+    }
+    rename(path_to_temporary_file, path)
+}
+
+/// Returns whether a file ends with the BGZF EOF block, see `BGZF_EOF_BLOCK`.
+///
+/// # Arguments
+/// * `path` - Path to the file.
+fn ends_with_bgzf_eof_block(path: &str) -> std::io::Result<bool> {
+    let mut file = File::open(path)?;
+    if file.metadata()?.len() < BGZF_EOF_BLOCK.len() as u64 {
+        return Ok(false);
     }
-    writer.flush().unwrap();
+    file.seek(SeekFrom::End(-(BGZF_EOF_BLOCK.len() as i64)))?;
+    let mut last_block = [0u8; BGZF_EOF_BLOCK.len()];
+    file.read_exact(&mut last_block)?;
+    Ok(last_block == BGZF_EOF_BLOCK)
 }
 
 fn log(message: &str, verbose: bool) {
@@ -0,0 +1,127 @@
+use crate::coverage::BinnedCoverage;
+use crate::custom_errors::{FragmentToolsError, FragmentToolsResult};
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Format in which results are returned to Python.
+///
+/// # Variants
+///
+/// * `Dict` - Python dictionaries (and lists).
+/// * `Arrow` - An Arrow IPC stream, which Polars (`polars.read_ipc_stream`) and PyArrow
+///     (`pyarrow.ipc.open_stream`) read without converting each value to a Python object.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ResultFormat {
+    Dict,
+    Arrow,
+}
+
+impl ResultFormat {
+    /// Parse a result format ("dict" or "arrow").
+    pub(crate) fn parse(s: &str) -> Result<ResultFormat, String> {
+        match s {
+            "dict" => Ok(ResultFormat::Dict),
+            "arrow" => Ok(ResultFormat::Arrow),
+            _ => Err(format!(
+                "Invalid result format {:?}, should be one of \"dict\" or \"arrow\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Returns the result of `binned_coverage` as a record batch with one row per bin, in the order of the bins,
+/// and columns `chrom`, `start`, `end` and a column with the counts of each cell type (sorted by name).
+///
+/// # Arguments
+///
+/// * `binned_coverage` - Result of `coverage::binned_coverage`.
+/// * `chromsizes` - A HashMap mapping contig names to contig sizes, as used for the bin layout.
+/// * `bin_size` - Size of the bins in bp.
+pub(crate) fn binned_coverage_record_batch(
+    binned_coverage: &BinnedCoverage,
+    chromsizes: &HashMap<String, u64>,
+    bin_size: u64,
+) -> FragmentToolsResult<RecordBatch> {
+    let (_, cell_type_to_bins) = binned_coverage;
+    let mut chrom: Vec<&str> = Vec::new();
+    let mut start: Vec<u64> = Vec::new();
+    let mut end: Vec<u64> = Vec::new();
+    // the bins of the contigs are one after the other, with the contigs sorted by name
+    for contig in chromsizes.keys().sorted() {
+        let contig_size = chromsizes[contig];
+        for bin_start in (0..contig_size).step_by(bin_size as usize) {
+            chrom.push(contig);
+            start.push(bin_start);
+            end.push((bin_start + bin_size).min(contig_size));
+        }
+    }
+    let mut fields = vec![
+        Field::new("chrom", DataType::Utf8, false),
+        Field::new("start", DataType::UInt64, false),
+        Field::new("end", DataType::UInt64, false),
+    ];
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(chrom)),
+        Arc::new(UInt64Array::from(start)),
+        Arc::new(UInt64Array::from(end)),
+    ];
+    for (cell_type, bins) in cell_type_to_bins
+        .iter()
+        .sorted_by_key(|(cell_type, _)| *cell_type)
+    {
+        fields.push(Field::new(cell_type, DataType::Float64, false));
+        columns.push(Arc::new(Float64Array::from(bins.clone())));
+    }
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(arrow_error)
+}
+
+/// Returns the result of `barcodes::fragments_per_mb` as a record batch with columns `cell_barcode`
+/// and `fragments_per_mb`, sorted by cell barcode.
+///
+/// # Arguments
+///
+/// * `cell_barcode_to_fragments_per_mb` - Result of `barcodes::fragments_per_mb`.
+pub(crate) fn fragments_per_mb_record_batch(
+    cell_barcode_to_fragments_per_mb: &HashMap<String, f64>,
+) -> FragmentToolsResult<RecordBatch> {
+    let (cell_barcodes, fragments_per_mb): (Vec<&str>, Vec<f64>) = cell_barcode_to_fragments_per_mb
+        .iter()
+        .sorted_by_key(|(cell_barcode, _)| *cell_barcode)
+        .map(|(cell_barcode, fragments_per_mb)| (cell_barcode.as_str(), *fragments_per_mb))
+        .unzip();
+    RecordBatch::try_from_iter([
+        (
+            "cell_barcode",
+            Arc::new(StringArray::from(cell_barcodes)) as ArrayRef,
+        ),
+        (
+            "fragments_per_mb",
+            Arc::new(Float64Array::from(fragments_per_mb)) as ArrayRef,
+        ),
+    ])
+    .map_err(arrow_error)
+}
+
+/// Serializes a record batch to an Arrow IPC stream.
+///
+/// # Arguments
+///
+/// * `record_batch` - The record batch.
+pub(crate) fn to_ipc_stream(record_batch: &RecordBatch) -> FragmentToolsResult<Vec<u8>> {
+    let mut ipc_stream: Vec<u8> = Vec::new();
+    let mut writer =
+        StreamWriter::try_new(&mut ipc_stream, &record_batch.schema()).map_err(arrow_error)?;
+    writer.write(record_batch).map_err(arrow_error)?;
+    writer.finish().map_err(arrow_error)?;
+    drop(writer);
+    Ok(ipc_stream)
+}
+
+fn arrow_error(e: ArrowError) -> FragmentToolsError {
+    FragmentToolsError::Io(format!("Could not create Arrow result: {}", e))
+}
@@ -0,0 +1,257 @@
+use crate::aggregate_fragments::{create_thread_pool, create_writer, finish_temporary_file};
+use crate::custom_errors::{FragmentFileErrorKind, FragmentToolsError, FragmentToolsResult};
+use crate::fragment::TabixColumns;
+use crate::tabix::{
+    build_tabix_index, cell_barcode_of_read, contigs_to_process, for_each_fragment_in_contig,
+    open_fragments_file, TabixIndex, WHOLE_CONTIG,
+};
+use rust_htslib::bgzf::Reader;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+
+/// Compares the cell barcodes of two fragment files.
+///
+/// Each file is read once, contig by contig, collecting its distinct cell barcodes.
+///
+/// # Arguments
+///
+/// * `path_to_fragments_a` - Path to the first (tabix indexed) fragments file.
+/// * `path_to_fragments_b` - Path to the second (tabix indexed) fragments file.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// The number of distinct cell barcodes only in the first file, in both files and only in the second file.
+pub fn compare_barcode_sets(
+    path_to_fragments_a: &str,
+    path_to_fragments_b: &str,
+    verbose: bool,
+) -> FragmentToolsResult<(u64, u64, u64)> {
+    let cell_barcodes_a = read_cell_barcodes(path_to_fragments_a, verbose)?;
+    let cell_barcodes_b = read_cell_barcodes(path_to_fragments_b, verbose)?;
+    let shared = cell_barcodes_a.intersection(&cell_barcodes_b).count() as u64;
+    Ok((
+        cell_barcodes_a.len() as u64 - shared,
+        shared,
+        cell_barcodes_b.len() as u64 - shared,
+    ))
+}
+
+/// Returns the distinct cell barcodes of a fragments file.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the (tabix indexed) fragments file.
+/// * `verbose` - Whether to print progress messages.
+fn read_cell_barcodes(
+    path_to_fragments: &str,
+    verbose: bool,
+) -> FragmentToolsResult<HashSet<String>> {
+    let mut cell_barcodes: HashSet<String> = HashSet::new();
+    let mut tbx_reader = open_fragments_file(path_to_fragments)?;
+    for contig in tbx_reader.seqnames() {
+        log(
+            &format!(
+                "Reading cell barcodes of contig {} of {}",
+                contig, path_to_fragments
+            ),
+            verbose,
+        );
+        for_each_fragment_in_contig(
+            &mut tbx_reader,
+            path_to_fragments,
+            &contig,
+            WHOLE_CONTIG,
+            |read| {
+                let cell_barcode = cell_barcode_of_read(read, path_to_fragments)?;
+                // only allocate for barcodes which were not seen yet
+                if !cell_barcodes.contains(cell_barcode) {
+                    cell_barcodes.insert(cell_barcode.to_string());
+                }
+                Ok(())
+            },
+        )?;
+    }
+    Ok(cell_barcodes)
+}
+
+/// Computes the number of fragments per Mb of the genome for each cell barcode, a library complexity metric.
+///
+/// Only the fragments on contigs of `chromsizes` are counted.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the (tabix indexed) fragments file.
+/// * `chromsizes` - A HashMap mapping contig names to contig sizes, of which the total is the genome size.
+/// * `genome_size_override` - If set, the (effective) genome size in bp to use instead of the total of `chromsizes`.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// A HashMap mapping cell barcodes to their number of fragments divided by the genome size in Mb.
+pub fn fragments_per_mb(
+    path_to_fragments: &str,
+    chromsizes: &HashMap<String, u64>,
+    genome_size_override: Option<u64>,
+    verbose: bool,
+) -> FragmentToolsResult<HashMap<String, f64>> {
+    let genome_size = genome_size_override.unwrap_or_else(|| chromsizes.values().sum());
+    if genome_size == 0 {
+        return Err(FragmentToolsError::InvalidArgument(
+            "The genome size should be larger than 0".to_string(),
+        ));
+    }
+    let genome_size_in_mb = genome_size as f64 / 1_000_000.0;
+    Ok(
+        count_fragments_per_cell_barcode(path_to_fragments, chromsizes, verbose)?
+            .into_iter()
+            .map(|(cell_barcode, number_of_fragments)| {
+                (cell_barcode, number_of_fragments as f64 / genome_size_in_mb)
+            })
+            .collect(),
+    )
+}
+
+/// Returns the number of fragments of each cell barcode of a fragments file,
+/// on the contigs of chromsizes.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the (tabix indexed) fragments file.
+/// * `chromsizes` - A HashMap mapping contig names to contig sizes.
+/// * `verbose` - Whether to print progress messages.
+fn count_fragments_per_cell_barcode(
+    path_to_fragments: &str,
+    chromsizes: &HashMap<String, u64>,
+    verbose: bool,
+) -> FragmentToolsResult<HashMap<String, u64>> {
+    let mut cell_barcode_to_count: HashMap<String, u64> = HashMap::new();
+    let mut tbx_reader = open_fragments_file(path_to_fragments)?;
+    for contig in contigs_to_process(&tbx_reader.seqnames(), chromsizes, verbose) {
+        log(
+            &format!(
+                "Counting fragments of contig {} of {}",
+                contig, path_to_fragments
+            ),
+            verbose,
+        );
+        for_each_fragment_in_contig(
+            &mut tbx_reader,
+            path_to_fragments,
+            contig,
+            chromsizes[contig],
+            |read| {
+                let cell_barcode = cell_barcode_of_read(read, path_to_fragments)?;
+                // only allocate for barcodes which were not seen yet
+                match cell_barcode_to_count.get_mut(cell_barcode) {
+                    Some(count) => *count += 1,
+                    None => {
+                        cell_barcode_to_count.insert(cell_barcode.to_string(), 1);
+                    }
+                }
+                Ok(())
+            },
+        )?;
+    }
+    Ok(cell_barcode_to_count)
+}
+
+/// Writes the fragments of a set of cell barcodes to a new BGZF compressed fragment file.
+///
+/// With a tabix index, the input is read contig by contig through the index (in the order of the index),
+/// otherwise it is read from start to end, skipping lines starting with `#`. The fragments are written
+/// in the order in which they are read, so the output is sorted when the input is.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file (BGZF compressed or uncompressed).
+/// * `path_to_output_file` - Path to the output file.
+/// * `cell_barcodes` - The cell barcodes of which the fragments are written.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `create_index` - Whether to create a tabix index for the output file.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// The number of fragments written.
+pub fn subset_fragments(
+    path_to_fragments: &str,
+    path_to_output_file: &str,
+    cell_barcodes: &HashSet<String>,
+    number_of_threads: u32,
+    create_index: bool,
+    verbose: bool,
+) -> FragmentToolsResult<u64> {
+    let tpool = create_thread_pool(number_of_threads)?;
+    let mut writer = create_writer(path_to_output_file, &tpool)?;
+    let write_error = |e: std::io::Error| {
+        FragmentToolsError::Io(format!(
+            "Could not write to file {}: {}",
+            path_to_output_file, e
+        ))
+    };
+    let mut number_of_fragments: u64 = 0;
+    let mut write_if_selected = |read: &[u8]| -> FragmentToolsResult<()> {
+        if cell_barcodes.contains(cell_barcode_of_read(read, path_to_fragments)?) {
+            writer
+                .write_all(read)
+                .and_then(|_| writer.write_all(b"\n"))
+                .map_err(write_error)?;
+            number_of_fragments += 1;
+        }
+        Ok(())
+    };
+
+    if TabixIndex::load(path_to_fragments).is_some() {
+        let mut tbx_reader = open_fragments_file(path_to_fragments)?;
+        for contig in tbx_reader.seqnames() {
+            log(&format!("Subsetting contig {}", contig), verbose);
+            for_each_fragment_in_contig(
+                &mut tbx_reader,
+                path_to_fragments,
+                &contig,
+                WHOLE_CONTIG,
+                &mut write_if_selected,
+            )?;
+        }
+    } else {
+        log(
+            &format!(
+                "No tabix index found for {}, reading the whole file",
+                path_to_fragments
+            ),
+            verbose,
+        );
+        let reader = Reader::from_path(path_to_fragments).map_err(|_| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not open file {}", path_to_fragments),
+            )
+        })?;
+        for line in BufReader::new(reader).lines() {
+            let line = line.map_err(|e| {
+                FragmentToolsError::InvalidFragmentFile(
+                    FragmentFileErrorKind::Unreadable,
+                    format!("Could not read file {}: {}", path_to_fragments, e),
+                )
+            })?;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            write_if_selected(line.as_bytes())?;
+        }
+    }
+    finish_temporary_file(writer, path_to_output_file).map_err(write_error)?;
+
+    if create_index {
+        log(&format!("Indexing {}", path_to_output_file), verbose);
+        build_tabix_index(path_to_output_file, &TabixColumns::default())?;
+    }
+    Ok(number_of_fragments)
+}
+
+fn log(message: &str, verbose: bool) {
+    if verbose {
+        println!("{}", message);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::aggregate_fragments::{sort_and_write_fragments, write_fragments};
+use crate::custom_errors::{FragmentFileErrorKind, FragmentToolsError, FragmentToolsResult};
+use crate::fragment::{
+    ContigNameMode, DuplicateCollapser, DuplicateHandling, Fragment, FragmentFormat, TabixColumns,
+};
+use crate::tabix::build_tabix_index;
+use rust_htslib::bgzf::Reader;
+use std::io::{BufRead, BufReader};
+
+/// Converts a BEDPE file to a fragment file.
+///
+/// For each read pair the fragment spans from the smallest start to the largest end of both reads.
+/// Pairs for which both reads are on different chromosomes are dropped.
+///
+/// # Arguments
+///
+/// * `path_to_bedpe` - Path to the BEDPE file (plain text, gzip or BGZF compressed).
+/// * `path_to_output_file` - Path to the output fragment file.
+/// * `barcode_column` - 0-based index of the column containing the cell barcode.
+/// * `score_column` - 0-based index of the column containing the score, if any.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+pub fn bedpe_to_fragments(
+    path_to_bedpe: &String,
+    path_to_output_file: &String,
+    barcode_column: usize,
+    score_column: Option<usize>,
+    number_of_threads: u32,
+    verbose: bool,
+) -> FragmentToolsResult<()> {
+    let reader = Reader::from_path(path_to_bedpe).map_err(|_| {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Unreadable,
+            format!("Could not open file {}", path_to_bedpe),
+        )
+    })?;
+
+    let mut fragments: Vec<Fragment> = Vec::new();
+    let mut number_of_interchromosomal_pairs: usize = 0;
+
+    log(&format!("Reading file {}", path_to_bedpe), verbose);
+    for (line_number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.map_err(|e| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!(
+                    "Could not read line {} of {}: {}",
+                    line_number + 1,
+                    path_to_bedpe,
+                    e
+                ),
+            )
+        })?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let get_field = |column: usize| -> FragmentToolsResult<&str> {
+            fields.get(column).copied().ok_or_else(|| {
+                FragmentToolsError::InvalidFragmentFile(
+                    FragmentFileErrorKind::Malformed,
+                    format!(
+                        "Line {} of {} has no column {}",
+                        line_number + 1,
+                        path_to_bedpe,
+                        column + 1
+                    ),
+                )
+            })
+        };
+        let parse_position = |column: usize| -> FragmentToolsResult<usize> {
+            get_field(column)?.parse::<usize>().map_err(|_| {
+                FragmentToolsError::InvalidFragmentFile(
+                    FragmentFileErrorKind::Malformed,
+                    format!(
+                        "Invalid position in column {} on line {} of {}",
+                        column + 1,
+                        line_number + 1,
+                        path_to_bedpe
+                    ),
+                )
+            })
+        };
+        if get_field(0)? != get_field(3)? {
+            number_of_interchromosomal_pairs += 1;
+            continue;
+        }
+        fragments.push(Fragment {
+            chrom: get_field(0)?.to_string(),
+            start: parse_position(1)?.min(parse_position(4)?),
+            end: parse_position(2)?.max(parse_position(5)?),
+            cell_barcode: get_field(barcode_column)?.to_string(),
+            score: score_column.map(parse_position).transpose()?,
+            score_pair: None,
+            strand: None,
+            file_index: 0,
+        });
+    }
+
+    if number_of_interchromosomal_pairs > 0 {
+        println!(
+            "Warning: dropped {} interchromosomal read pairs from {}",
+            number_of_interchromosomal_pairs, path_to_bedpe
+        );
+    }
+
+    sort_and_write_fragments(fragments, path_to_output_file, number_of_threads, verbose)?;
+    Ok(())
+}
+
+/// Writes a fragment file in canonical form, e.g. to standardize fragment files from different sources
+/// before they are used in a pipeline.
+///
+/// The following transforms are applied, in this order:
+/// 1. Empty lines and comment lines (starting with `#`) are dropped.
+/// 2. Columns after the score column are dropped, so each fragment has 5 columns.
+/// 3. Contig names are normalized with `contig_name_mode`.
+/// 4. Fragments without a score get score 1.
+/// 5. Fragments are sorted by contig (lexicographically), start, end and cell barcode.
+/// 6. Duplicates (fragments with the same contig, start, end and cell barcode) are written once,
+///     with the sum of their scores as score.
+/// 7. The output is BGZF compressed and tabix indexed.
+///
+/// All fragments are kept in memory, the input file does not need to be sorted.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragment file (plain text, gzip or BGZF compressed).
+/// * `path_to_output_file` - Path to the output fragment file.
+/// * `contig_name_mode` - How contig names are normalized.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// The number of written fragments.
+pub fn canonicalize_fragments(
+    path_to_fragments: &str,
+    path_to_output_file: &String,
+    contig_name_mode: ContigNameMode,
+    number_of_threads: u32,
+    verbose: bool,
+) -> FragmentToolsResult<u64> {
+    let reader = Reader::from_path(path_to_fragments).map_err(|_| {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Unreadable,
+            format!("Could not open file {}", path_to_fragments),
+        )
+    })?;
+    let format = FragmentFormat::default();
+
+    log(&format!("Reading file {}", path_to_fragments), verbose);
+    let mut fragments: Vec<Fragment> = Vec::new();
+    for line in BufReader::new(reader).lines() {
+        let line = line.map_err(|e| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not read file {}: {}", path_to_fragments, e),
+            )
+        })?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fragment = Fragment::new_from_string_with_format(&line, &format).map_err(|e| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Malformed,
+                format!("{} ({})", e, path_to_fragments),
+            )
+        })?;
+        if contig_name_mode != ContigNameMode::Keep {
+            fragment.chrom = contig_name_mode.apply(&fragment.chrom).into_owned();
+        }
+        fragment.score.get_or_insert(1);
+        fragments.push(fragment);
+    }
+
+    log("Sorting fragments", verbose);
+    fragments.sort_unstable();
+    let mut duplicate_collapser =
+        DuplicateCollapser::new(DuplicateHandling::CollapseSumScore, None);
+    let mut canonical_fragments: Vec<Fragment> = Vec::with_capacity(fragments.len());
+    for fragment in fragments {
+        canonical_fragments.extend(duplicate_collapser.push(fragment));
+    }
+    canonical_fragments.extend(duplicate_collapser.finish());
+
+    log(&format!("Writing {}", path_to_output_file), verbose);
+    write_fragments(&canonical_fragments, path_to_output_file, number_of_threads)?;
+    log(&format!("Indexing {}", path_to_output_file), verbose);
+    build_tabix_index(path_to_output_file, &TabixColumns::default())?;
+    Ok(canonical_fragments.len() as u64)
+}
+
+fn log(message: &str, verbose: bool) {
+    if verbose {
+        println!("{}", message);
+    }
+}
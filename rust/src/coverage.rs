@@ -0,0 +1,496 @@
+use crate::custom_errors::{FragmentFileErrorKind, FragmentToolsError, FragmentToolsResult};
+use crate::fragment::{Fragment, FragmentColumns, FragmentFormat};
+use crate::regions::{overlaps_region, read_bed_regions, regions_of_contig, ContigToRegions};
+use crate::tabix::{
+    contigs_to_process, for_each_fragment_in_contig, open_fragments_file, WHOLE_CONTIG,
+};
+use itertools::Itertools;
+use std::collections::HashMap;
+
+/// Bitset with one bit per genomic bin, marking which bins are covered by fragments.
+///
+/// # Fields
+///
+/// * `words` - The bits, 64 bins per word.
+struct BinSet {
+    words: Vec<u64>,
+}
+
+impl BinSet {
+    fn new(number_of_bins: usize) -> BinSet {
+        BinSet {
+            words: vec![0; number_of_bins.div_ceil(64)],
+        }
+    }
+
+    /// Marks the bins from `first_bin` up to and including `last_bin` as covered.
+    fn insert_range(&mut self, first_bin: usize, last_bin: usize) {
+        for bin in first_bin..=last_bin {
+            self.words[bin / 64] |= 1 << (bin % 64);
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.words.iter().map(|word| word.count_ones() as u64).sum()
+    }
+
+    fn intersection_len(&self, other: &BinSet) -> u64 {
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .map(|(a, b)| (a & b).count_ones() as u64)
+            .sum()
+    }
+
+    fn union_len(&self, other: &BinSet) -> u64 {
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .map(|(a, b)| (a | b).count_ones() as u64)
+            .sum()
+    }
+}
+
+/// Computes the pairwise Jaccard similarity of the genomic bins covered by each cell type.
+///
+/// The genome (as given by chromsizes) is divided in bins of `bin_size` bp,
+/// a bin is covered by a cell type when at least one fragment of that cell type overlaps it.
+/// The fragments file is read once.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `cell_barcode_to_cell_type` - A HashMap mapping cell barcodes to cell types.
+/// * `chromsizes` - A HashMap mapping contig names to contig sizes, used for the bin layout.
+/// * `bin_size` - Size of the bins in bp.
+/// * `path_to_blacklist` - If set, path to a BED file with blacklist regions (plain or gzip compressed),
+///     fragments overlapping any of these regions are not used.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// The cell types (sorted by name) and the Jaccard matrix, in the same order as the cell types.
+/// The Jaccard similarity of two cell types without any covered bins is NaN.
+pub fn celltype_coverage_jaccard(
+    path_to_fragments: &String,
+    cell_barcode_to_cell_type: HashMap<String, Vec<String>>,
+    chromsizes: HashMap<String, u64>,
+    bin_size: u64,
+    path_to_blacklist: Option<&str>,
+    verbose: bool,
+) -> FragmentToolsResult<(Vec<String>, Vec<Vec<f64>>)> {
+    if bin_size == 0 {
+        return Err(FragmentToolsError::InvalidArgument(
+            "bin_size must be larger than 0".to_string(),
+        ));
+    }
+    let contig_to_blacklist = read_blacklist(path_to_blacklist)?;
+    let mut number_of_blacklisted_fragments: u64 = 0;
+
+    let mut tbx_reader = open_fragments_file(path_to_fragments)?;
+
+    let (contig_to_first_bin, number_of_bins) = bin_layout(&chromsizes, bin_size);
+
+    let cell_types: Vec<String> = cell_barcode_to_cell_type
+        .values()
+        .flatten()
+        .unique()
+        .sorted()
+        .cloned()
+        .collect();
+    let mut cell_type_to_bins: HashMap<&String, BinSet> = cell_types
+        .iter()
+        .map(|cell_type| (cell_type, BinSet::new(number_of_bins)))
+        .collect();
+
+    for contig in contigs_to_process(&tbx_reader.seqnames(), &chromsizes, verbose) {
+        log(&format!("Processing contig {}", contig), verbose);
+        let contig_size = chromsizes[contig];
+        if contig_size == 0 {
+            continue;
+        }
+        let first_bin = contig_to_first_bin[contig];
+        let last_bin_of_contig = ((contig_size - 1) / bin_size) as usize;
+        let blacklist = regions_of_contig(&contig_to_blacklist, contig);
+        for_each_fragment_in_contig(
+            &mut tbx_reader,
+            path_to_fragments,
+            contig,
+            contig_size,
+            |read| {
+                let line = std::str::from_utf8(read).map_err(|_| {
+                    FragmentToolsError::InvalidFragmentFile(
+                        FragmentFileErrorKind::Malformed,
+                        format!("Fragment in {} is not valid UTF-8", path_to_fragments),
+                    )
+                })?;
+                let fragment =
+                    Fragment::new_from_string_with_format(line, &FragmentFormat::default())
+                        .map_err(|e| {
+                            FragmentToolsError::InvalidFragmentFile(
+                                FragmentFileErrorKind::Malformed,
+                                format!("{} ({})", e, path_to_fragments),
+                            )
+                        })?;
+                if let Some(cell_types) = cell_barcode_to_cell_type.get(&fragment.cell_barcode) {
+                    if overlaps_region(blacklist, fragment.start, fragment.end) {
+                        number_of_blacklisted_fragments += 1;
+                        return Ok(());
+                    }
+                    // fragments are half-open, the last covered base is end - 1
+                    let start_bin = (fragment.start as u64 / bin_size) as usize;
+                    let end_bin = (fragment.end.max(fragment.start + 1) as u64 - 1) / bin_size;
+                    let end_bin = (end_bin as usize).min(last_bin_of_contig);
+                    if start_bin > end_bin {
+                        return Ok(());
+                    }
+                    for cell_type in cell_types {
+                        cell_type_to_bins
+                            .get_mut(cell_type)
+                            .unwrap()
+                            .insert_range(first_bin + start_bin, first_bin + end_bin);
+                    }
+                }
+                Ok(())
+            },
+        )?;
+    }
+    log_blacklisted_fragments(number_of_blacklisted_fragments, path_to_blacklist, verbose);
+
+    log("Computing Jaccard similarities", verbose);
+    let jaccard: Vec<Vec<f64>> = cell_types
+        .iter()
+        .map(|cell_type_a| {
+            cell_types
+                .iter()
+                .map(|cell_type_b| {
+                    let bins_a = &cell_type_to_bins[cell_type_a];
+                    let bins_b = &cell_type_to_bins[cell_type_b];
+                    let union = bins_a.union_len(bins_b);
+                    if union == 0 {
+                        f64::NAN
+                    } else {
+                        bins_a.intersection_len(bins_b) as f64 / union as f64
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    for cell_type in cell_types.iter() {
+        log(
+            &format!(
+                "{}: {} bins covered",
+                cell_type,
+                cell_type_to_bins[cell_type].len()
+            ),
+            verbose,
+        );
+    }
+    Ok((cell_types, jaccard))
+}
+
+/// How fragments which overlap more than one bin are counted, see `binned_coverage`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BinAssignment {
+    /// Weight of a fragment is split over the bins it overlaps, by the number of bp in each bin.
+    Split,
+    /// Whole weight of a fragment is added to the bin of its start position.
+    Start,
+}
+
+impl BinAssignment {
+    /// Parse a bin assignment ("split" or "start").
+    pub fn parse(s: &str) -> Result<BinAssignment, String> {
+        match s {
+            "split" => Ok(BinAssignment::Split),
+            "start" => Ok(BinAssignment::Start),
+            _ => Err(format!(
+                "Invalid bin assignment {:?}, should be one of \"split\" or \"start\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Index of the first bin of each contig and counts of all bins of each cell type, see `binned_coverage`.
+pub type BinnedCoverage = (HashMap<String, usize>, HashMap<String, Vec<f64>>);
+
+/// Counts the fragments of each cell type in genomic bins.
+///
+/// The genome (as given by chromsizes) is divided in bins of `bin_size` bp, with the bins of all contigs
+/// one after the other (contigs sorted by name). The fragments file is read once, contig by contig,
+/// and the weight of each fragment of a cell type is added to the bins it overlaps.
+/// Fragments are clipped to the end of their contig.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `cell_barcode_to_cell_type` - A HashMap mapping cell barcodes to cell types.
+/// * `chromsizes` - A HashMap mapping contig names to contig sizes, used for the bin layout.
+/// * `bin_size` - Size of the bins in bp.
+/// * `bin_assignment` - How fragments which overlap more than one bin are counted.
+/// * `use_scores` - Whether the weight of a fragment is its score (1 for fragments without score)
+///     instead of 1.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// A HashMap mapping contig names to the index of their first bin and a HashMap mapping
+/// cell types to the counts of all bins.
+pub fn binned_coverage(
+    path_to_fragments: &str,
+    cell_barcode_to_cell_type: HashMap<String, Vec<String>>,
+    chromsizes: HashMap<String, u64>,
+    bin_size: u64,
+    bin_assignment: BinAssignment,
+    use_scores: bool,
+    verbose: bool,
+) -> FragmentToolsResult<BinnedCoverage> {
+    if bin_size == 0 {
+        return Err(FragmentToolsError::InvalidArgument(
+            "bin_size must be larger than 0".to_string(),
+        ));
+    }
+    let mut tbx_reader = open_fragments_file(path_to_fragments)?;
+
+    let (contig_to_first_bin, number_of_bins) = bin_layout(&chromsizes, bin_size);
+    let mut cell_type_to_bins: HashMap<&String, Vec<f64>> = cell_barcode_to_cell_type
+        .values()
+        .flatten()
+        .map(|cell_type| (cell_type, vec![0.0; number_of_bins]))
+        .collect();
+
+    for contig in contigs_to_process(&tbx_reader.seqnames(), &chromsizes, verbose) {
+        log(&format!("Processing contig {}", contig), verbose);
+        let contig_size = chromsizes[contig];
+        let first_bin = contig_to_first_bin[contig];
+        for_each_fragment_in_contig(
+            &mut tbx_reader,
+            path_to_fragments,
+            contig,
+            contig_size,
+            |read| {
+                let line = std::str::from_utf8(read).map_err(|_| {
+                    FragmentToolsError::InvalidFragmentFile(
+                        FragmentFileErrorKind::Malformed,
+                        format!("Fragment in {} is not valid UTF-8", path_to_fragments),
+                    )
+                })?;
+                let fragment =
+                    Fragment::new_from_string_with_format(line, &FragmentFormat::default())
+                        .map_err(|e| {
+                            FragmentToolsError::InvalidFragmentFile(
+                                FragmentFileErrorKind::Malformed,
+                                format!("{} ({})", e, path_to_fragments),
+                            )
+                        })?;
+                let Some(cell_types) = cell_barcode_to_cell_type.get(&fragment.cell_barcode) else {
+                    return Ok(());
+                };
+                let start = fragment.start as u64;
+                if start >= contig_size {
+                    return Ok(());
+                }
+                let end = (fragment.end as u64).min(contig_size);
+                let weight = if use_scores {
+                    fragment.score.unwrap_or(1) as f64
+                } else {
+                    1.0
+                };
+                // fragments are half-open, the last covered base is end - 1
+                let start_bin = start / bin_size;
+                let end_bin = match bin_assignment {
+                    BinAssignment::Split => (end.max(start + 1) - 1) / bin_size,
+                    BinAssignment::Start => start_bin,
+                };
+                for bin in start_bin..=end_bin {
+                    let bin_weight = if start_bin == end_bin {
+                        weight
+                    } else {
+                        let overlap = end.min((bin + 1) * bin_size) - start.max(bin * bin_size);
+                        weight * overlap as f64 / (end - start) as f64
+                    };
+                    for cell_type in cell_types {
+                        cell_type_to_bins.get_mut(cell_type).unwrap()[first_bin + bin as usize] +=
+                            bin_weight;
+                    }
+                }
+                Ok(())
+            },
+        )?;
+    }
+
+    Ok((
+        contig_to_first_bin
+            .into_iter()
+            .map(|(contig, first_bin)| (contig.to_string(), first_bin))
+            .collect(),
+        cell_type_to_bins
+            .into_iter()
+            .map(|(cell_type, bins)| (cell_type.to_string(), bins))
+            .collect(),
+    ))
+}
+
+/// Lays out the bins of all contigs one after the other, with the contigs sorted by name.
+///
+/// Returns a HashMap mapping contig names to the index of their first bin and the total number of bins.
+fn bin_layout(
+    chromsizes: &HashMap<String, u64>,
+    bin_size: u64,
+) -> (HashMap<&String, usize>, usize) {
+    let mut contig_to_first_bin: HashMap<&String, usize> = HashMap::new();
+    let mut number_of_bins: usize = 0;
+    for contig in chromsizes.keys().sorted() {
+        contig_to_first_bin.insert(contig, number_of_bins);
+        number_of_bins += chromsizes[contig].div_ceil(bin_size) as usize;
+    }
+    (contig_to_first_bin, number_of_bins)
+}
+
+/// Computes the fraction of reads in peaks (FRiP) for each cell type.
+///
+/// For each cell type, the fragments of its cell barcodes are counted,
+/// together with the fragments that overlap at least one peak. The fragments file is read once,
+/// contig by contig. A fragment of a cell barcode with several cell types counts for each of them.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `path_to_peaks` - Path to a BED file with peaks (plain or gzip compressed).
+/// * `cell_barcode_to_cell_type` - A HashMap mapping cell barcodes to cell types.
+/// * `strand_column` - If set, column (0-based) of the strand of the fragments.
+/// * `tn5_shift` - Whether to shift the fragments for the Tn5 insertion before overlapping them with the peaks,
+///     by strand if `strand_column` is set, see `Fragment::tn5_shifted`.
+/// * `path_to_blacklist` - If set, path to a BED file with blacklist regions (plain or gzip compressed),
+///     fragments overlapping any of these regions (before shifting) are not counted.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// A HashMap mapping cell types to their FRiP. The FRiP of a cell type without fragments is NaN.
+pub fn frip_per_celltype(
+    path_to_fragments: &str,
+    path_to_peaks: &str,
+    cell_barcode_to_cell_type: HashMap<String, Vec<String>>,
+    strand_column: Option<usize>,
+    tn5_shift: bool,
+    path_to_blacklist: Option<&str>,
+    verbose: bool,
+) -> FragmentToolsResult<HashMap<String, f64>> {
+    let contig_to_peaks = read_bed_regions(path_to_peaks, "peaks")?;
+    let contig_to_blacklist = read_blacklist(path_to_blacklist)?;
+    let mut number_of_blacklisted_fragments: u64 = 0;
+    let format = FragmentFormat::default().with_columns(
+        FragmentColumns::new(0, 1, 2, 3, 4, strand_column)
+            .map_err(FragmentToolsError::InvalidArgument)?,
+    );
+    let mut tbx_reader = open_fragments_file(path_to_fragments)?;
+
+    // number of fragments and number of fragments in peaks per cell type
+    let mut cell_type_to_counts: HashMap<&String, (u64, u64)> = cell_barcode_to_cell_type
+        .values()
+        .flatten()
+        .map(|cell_type| (cell_type, (0, 0)))
+        .collect();
+
+    for contig in tbx_reader.seqnames() {
+        log(&format!("Processing contig {}", contig), verbose);
+        let peaks = regions_of_contig(&contig_to_peaks, &contig);
+        let blacklist = regions_of_contig(&contig_to_blacklist, &contig);
+        for_each_fragment_in_contig(
+            &mut tbx_reader,
+            path_to_fragments,
+            &contig,
+            WHOLE_CONTIG,
+            |read| {
+                let line = std::str::from_utf8(read).map_err(|_| {
+                    FragmentToolsError::InvalidFragmentFile(
+                        FragmentFileErrorKind::Malformed,
+                        format!("Fragment in {} is not valid UTF-8", path_to_fragments),
+                    )
+                })?;
+                let fragment =
+                    Fragment::new_from_string_with_format(line, &format).map_err(|e| {
+                        FragmentToolsError::InvalidFragmentFile(
+                            FragmentFileErrorKind::Malformed,
+                            format!("{} ({})", e, path_to_fragments),
+                        )
+                    })?;
+                if !cell_barcode_to_cell_type.contains_key(&fragment.cell_barcode) {
+                    return Ok(());
+                }
+                if overlaps_region(blacklist, fragment.start, fragment.end) {
+                    number_of_blacklisted_fragments += 1;
+                    return Ok(());
+                }
+                let fragment = if tn5_shift {
+                    fragment.tn5_shifted()
+                } else {
+                    fragment
+                };
+                if let Some(cell_types) = cell_barcode_to_cell_type.get(&fragment.cell_barcode) {
+                    let in_peak = overlaps_region(peaks, fragment.start, fragment.end);
+                    for cell_type in cell_types {
+                        let counts = cell_type_to_counts.get_mut(cell_type).unwrap();
+                        counts.0 += 1;
+                        counts.1 += in_peak as u64;
+                    }
+                }
+                Ok(())
+            },
+        )?;
+    }
+    log_blacklisted_fragments(number_of_blacklisted_fragments, path_to_blacklist, verbose);
+
+    Ok(cell_type_to_counts
+        .into_iter()
+        .map(
+            |(cell_type, (number_of_fragments, number_of_fragments_in_peaks))| {
+                log(
+                    &format!(
+                        "{}: {} of {} fragments in peaks",
+                        cell_type, number_of_fragments_in_peaks, number_of_fragments
+                    ),
+                    verbose,
+                );
+                let frip = if number_of_fragments == 0 {
+                    f64::NAN
+                } else {
+                    number_of_fragments_in_peaks as f64 / number_of_fragments as f64
+                };
+                (cell_type.to_string(), frip)
+            },
+        )
+        .collect())
+}
+
+/// Reads the blacklist regions, if a blacklist is given.
+fn read_blacklist(path_to_blacklist: Option<&str>) -> FragmentToolsResult<ContigToRegions> {
+    match path_to_blacklist {
+        Some(path_to_blacklist) => read_bed_regions(path_to_blacklist, "blacklist"),
+        None => Ok(ContigToRegions::new()),
+    }
+}
+
+fn log_blacklisted_fragments(
+    number_of_blacklisted_fragments: u64,
+    path_to_blacklist: Option<&str>,
+    verbose: bool,
+) {
+    if let Some(path_to_blacklist) = path_to_blacklist {
+        log(
+            &format!(
+                "Dropped {} fragment(s) overlapping blacklist regions of {}",
+                number_of_blacklisted_fragments, path_to_blacklist
+            ),
+            verbose,
+        );
+    }
+}
+
+fn log(message: &str, verbose: bool) {
+    if verbose {
+        println!("{}", message);
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt;
+
+#[cfg(feature = "python")]
+use pyo3::create_exception;
+#[cfg(feature = "python")]
+use pyo3::exceptions::{PyIOError, PyRuntimeError, PyValueError};
+#[cfg(feature = "python")]
+use pyo3::{PyErr, Python};
+
+#[cfg(feature = "python")]
+create_exception!(
+    _rust_scatac_fragment_tools,
+    InvalidFragmentFileError,
+    PyValueError,
+    "Raised when a fragment file can not be opened, read or parsed, with a `kind` attribute telling why."
+);
+
+/// Why a fragment file is invalid.
+///
+/// # Variants
+///
+/// * `Unreadable` - The file can not be opened or decompressed, e.g. because it does not exist
+///     or is not BGZF compressed.
+/// * `Index` - The tabix index is missing or does not match the file.
+/// * `Malformed` - A line can not be parsed, e.g. because it has too few columns or a non-numeric position.
+/// * `InvalidCoordinates` - A fragment starts after its end or lies outside its contig.
+/// * `Unsorted` - The fragments are not sorted by contig and start position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentFileErrorKind {
+    Unreadable,
+    Index,
+    Malformed,
+    InvalidCoordinates,
+    Unsorted,
+}
+
+impl FragmentFileErrorKind {
+    /// Returns the name of the kind, as set on the `kind` attribute of the raised exception in Python.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FragmentFileErrorKind::Unreadable => "unreadable",
+            FragmentFileErrorKind::Index => "index",
+            FragmentFileErrorKind::Malformed => "malformed",
+            FragmentFileErrorKind::InvalidCoordinates => "invalid_coordinates",
+            FragmentFileErrorKind::Unsorted => "unsorted",
+        }
+    }
+}
+
+/// Errors returned by the fragment tools.
+///
+/// When called from Python, each variant is raised as the exception mentioned below,
+/// with a `kind` attribute set to the value of `FragmentToolsError::kind`.
+///
+/// # Variants
+///
+/// * `InvalidFragmentFile` - A fragment file can not be opened, read or parsed (`InvalidFragmentFileError`).
+/// * `InvalidArgument` - An argument has an invalid value (`ValueError`).
+/// * `Io` - An output file can not be written (`IOError`).
+/// * `Callback` - A user provided callback failed (the original exception, without `kind` attribute).
+#[derive(Debug)]
+pub enum FragmentToolsError {
+    InvalidFragmentFile(FragmentFileErrorKind, String),
+    InvalidArgument(String),
+    Io(String),
+    Callback(Box<dyn std::error::Error + Send + Sync>),
+}
+
+pub type FragmentToolsResult<T> = Result<T, FragmentToolsError>;
+
+impl FragmentToolsError {
+    /// Returns a machine-readable code of the error: the name of the `FragmentFileErrorKind`
+    /// for invalid fragment files, `"invalid_argument"`, `"io"` or `"callback"` otherwise.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FragmentToolsError::InvalidFragmentFile(kind, _) => kind.as_str(),
+            FragmentToolsError::InvalidArgument(_) => "invalid_argument",
+            FragmentToolsError::Io(_) => "io",
+            FragmentToolsError::Callback(_) => "callback",
+        }
+    }
+}
+
+impl fmt::Display for FragmentToolsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FragmentToolsError::InvalidFragmentFile(_, message)
+            | FragmentToolsError::InvalidArgument(message)
+            | FragmentToolsError::Io(message) => write!(f, "{}", message),
+            FragmentToolsError::Callback(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for FragmentToolsError {}
+
+#[cfg(feature = "python")]
+impl From<FragmentToolsError> for PyErr {
+    fn from(error: FragmentToolsError) -> PyErr {
+        let kind = error.kind();
+        let py_error = match error {
+            FragmentToolsError::InvalidFragmentFile(_, message) => {
+                InvalidFragmentFileError::new_err(message)
+            }
+            FragmentToolsError::InvalidArgument(message) => PyValueError::new_err(message),
+            FragmentToolsError::Io(message) => PyIOError::new_err(message),
+            // the exception raised by the callback is passed on unchanged
+            FragmentToolsError::Callback(error) => {
+                return match error.downcast::<PyErr>() {
+                    Ok(error) => *error,
+                    Err(error) => PyRuntimeError::new_err(error.to_string()),
+                }
+            }
+        };
+        Python::with_gil(|py| {
+            // setting an attribute on a freshly created exception only fails when out of memory
+            let _ = py_error.value(py).setattr("kind", kind);
+        });
+        py_error
+    }
+}
@@ -0,0 +1,177 @@
+use crate::custom_errors::{FragmentFileErrorKind, FragmentToolsError, FragmentToolsResult};
+use crate::summary::FragmentFileStats;
+use crate::tabix::{has_tabix_index, open_fragments_file, unreadable_index_message, TabixIndex};
+use itertools::Itertools;
+use rust_htslib::bgzf::Reader;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader};
+
+/// Returns the contigs, the number of records per contig and whether a fragment file is sorted.
+///
+/// When the file has a tabix index, everything is read from the index, without reading the fragments.
+/// Otherwise the file is read from start to end, if `allow_scan` is set. This includes files with an index
+/// which can not be loaded (e.g. a corrupt one), for which a warning is printed.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `allow_scan` - Whether to read the whole file when it has no tabix index,
+///     instead of returning an error.
+/// * `verbose` - Whether to print progress messages.
+pub fn fragment_file_stats(
+    path_to_fragments: &str,
+    allow_scan: bool,
+    verbose: bool,
+) -> FragmentToolsResult<FragmentFileStats> {
+    match TabixIndex::load(path_to_fragments) {
+        Some(tabix_index) => {
+            log(
+                &format!("Reading statistics of {} from its index", path_to_fragments),
+                verbose,
+            );
+            fragment_file_stats_from_index(path_to_fragments, &tabix_index)
+        }
+        None if allow_scan && has_tabix_index(path_to_fragments) => {
+            println!(
+                "Warning: {}, reading the whole file instead",
+                unreadable_index_message(path_to_fragments)
+            );
+            fragment_file_stats_from_scan(path_to_fragments)
+        }
+        None if allow_scan => {
+            log(
+                &format!(
+                    "No tabix index found for {}, reading the whole file",
+                    path_to_fragments
+                ),
+                verbose,
+            );
+            fragment_file_stats_from_scan(path_to_fragments)
+        }
+        None if has_tabix_index(path_to_fragments) => Err(FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Index,
+            format!(
+                "Could not load the tabix index of {}: {}, or allow a scan to read the whole file instead",
+                path_to_fragments,
+                unreadable_index_message(path_to_fragments)
+            ),
+        )),
+        None => Err(FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Index,
+            format!(
+                "Could not load the tabix index of {}, allow a scan to read the whole file instead",
+                path_to_fragments
+            ),
+        )),
+    }
+}
+
+/// Reads the statistics of a fragment file from its tabix index.
+///
+/// That the fragments of each contig are in one block is checked with the file offsets of the contigs
+/// recorded in the index, see `TabixIndex::contig_offsets`.
+fn fragment_file_stats_from_index(
+    path_to_fragments: &str,
+    tabix_index: &TabixIndex,
+) -> FragmentToolsResult<FragmentFileStats> {
+    let tbx_reader = open_fragments_file(path_to_fragments)?;
+    let contigs = tbx_reader.seqnames();
+    let mut records_per_contig: HashMap<String, u64> = HashMap::new();
+    let mut contig_offsets: Vec<(u64, u64)> = Vec::new();
+    for contig in contigs.iter() {
+        let Ok(contig_id) = tbx_reader.tid(contig) else {
+            continue;
+        };
+        if let Some(number_of_records) = tabix_index.number_of_records(contig_id) {
+            records_per_contig.insert(contig.clone(), number_of_records);
+        }
+        if let Some(offsets) = tabix_index.contig_offsets(contig_id) {
+            contig_offsets.push(offsets);
+        }
+    }
+    contig_offsets.sort();
+    let sorted = contig_offsets
+        .iter()
+        .tuple_windows()
+        .all(|((_, previous_end), (start, _))| start >= previous_end);
+    Ok(FragmentFileStats {
+        contigs,
+        records_per_contig,
+        indexed: true,
+        sorted,
+    })
+}
+
+/// Reads the statistics of a fragment file (BGZF compressed or uncompressed) from start to end.
+///
+/// Lines starting with `#` are skipped.
+fn fragment_file_stats_from_scan(
+    path_to_fragments: &str,
+) -> FragmentToolsResult<FragmentFileStats> {
+    let reader = Reader::from_path(path_to_fragments).map_err(|_| {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Unreadable,
+            format!("Could not open file {}", path_to_fragments),
+        )
+    })?;
+    let mut stats = FragmentFileStats {
+        contigs: Vec::new(),
+        records_per_contig: HashMap::new(),
+        indexed: false,
+        sorted: true,
+    };
+    let mut finished_contigs: HashSet<String> = HashSet::new();
+    let mut previous_contig: Option<String> = None;
+    let mut previous_start: usize = 0;
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.map_err(|e| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not read file {}: {}", path_to_fragments, e),
+            )
+        })?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut columns = line.split('\t');
+        let contig = columns.next().unwrap_or_default();
+        let start: usize = columns
+            .next()
+            .and_then(|start| start.parse().ok())
+            .ok_or_else(|| {
+                FragmentToolsError::InvalidFragmentFile(
+                    FragmentFileErrorKind::Malformed,
+                    format!(
+                        "Invalid start position (line {} of {})",
+                        i + 1,
+                        path_to_fragments
+                    ),
+                )
+            })?;
+        if previous_contig.as_deref() == Some(contig) {
+            stats.sorted &= start >= previous_start;
+        } else {
+            if let Some(previous_contig) = previous_contig.take() {
+                finished_contigs.insert(previous_contig);
+            }
+            if finished_contigs.contains(contig) {
+                stats.sorted = false;
+            } else {
+                stats.contigs.push(contig.to_string());
+            }
+            previous_contig = Some(contig.to_string());
+        }
+        previous_start = start;
+        *stats
+            .records_per_contig
+            .entry(contig.to_string())
+            .or_default() += 1;
+    }
+    Ok(stats)
+}
+
+fn log(message: &str, verbose: bool) {
+    if verbose {
+        println!("{}", message);
+    }
+}
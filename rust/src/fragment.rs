@@ -0,0 +1,1160 @@
+use core::fmt;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+/// Layout of the lines of a fragment file.
+///
+/// # Fields
+///
+/// * `delimiter` - Column delimiter, can be multiple characters.
+/// * `strip_quotes` - Whether to strip surrounding double or single quotes from the cell barcode.
+/// * `barcode_tag` - If set, the cell barcode column contains a SAM-style tag (e.g. `CB:Z:AACG`)
+///     with this name, of which the value is used as cell barcode.
+/// * `columns` - Columns of the fields of a fragment.
+/// * `score_precision` - If set, scores are decimal numbers (e.g. weights), kept with this many decimals.
+///     They are stored as integers in units of `10^-score_precision` (so `Fragment::score` of `1.25`
+///     with a precision of 2 is 125), which keeps sums of scores exact, see `format_score`.
+/// * `score_pair` - If set, the score column can also contain a comma separated pair of integers
+///     (e.g. `3,5`), of which this value is used as score. Both values are kept in `Fragment::score_pair`.
+#[derive(Clone)]
+pub struct FragmentFormat {
+    pub delimiter: String,
+    pub strip_quotes: bool,
+    pub barcode_tag: Option<String>,
+    pub columns: FragmentColumns,
+    pub score_precision: Option<u32>,
+    pub score_pair: Option<ScorePairValue>,
+}
+
+impl Default for FragmentFormat {
+    fn default() -> FragmentFormat {
+        FragmentFormat {
+            delimiter: "\t".to_string(),
+            strip_quotes: false,
+            barcode_tag: None,
+            columns: FragmentColumns::default(),
+            score_precision: None,
+            score_pair: None,
+        }
+    }
+}
+
+/// Which value of a score pair (e.g. `3,5`) is used as score, see `FragmentFormat::score_pair`.
+///
+/// # Variants
+///
+/// * `First` - The first value (e.g. the number of duplicates) is used as score.
+/// * `Second` - The second value (e.g. the total number of reads) is used as score.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ScorePairValue {
+    First,
+    Second,
+}
+
+impl ScorePairValue {
+    /// Parse a score pair value ("first" or "second").
+    pub fn parse(s: &str) -> Result<ScorePairValue, String> {
+        match s {
+            "first" => Ok(ScorePairValue::First),
+            "second" => Ok(ScorePairValue::Second),
+            _ => Err(format!(
+                "Invalid score pair value {:?}, should be one of \"first\" or \"second\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Maximum of `FragmentFormat::score_precision`.
+///
+/// Decimal scores are parsed as `f64` and rounded to the precision, which is exact for
+/// scores up to about 10^9 with 6 decimals.
+pub const MAX_SCORE_PRECISION: u32 = 6;
+
+/// Formats a score as it is written, see `FragmentFormat::score_precision`.
+///
+/// # Arguments
+///
+/// * `score` - The score, in units of `10^-score_precision` if a precision is given.
+/// * `score_precision` - Number of decimals of the score, if scores are decimal numbers.
+pub fn format_score(score: usize, score_precision: Option<u32>) -> String {
+    match score_precision {
+        None | Some(0) => score.to_string(),
+        Some(score_precision) => {
+            let unit = 10_usize.pow(score_precision);
+            format!(
+                "{}.{:0width$}",
+                score / unit,
+                score % unit,
+                width = score_precision as usize
+            )
+        }
+    }
+}
+
+/// Parses a decimal score into units of `10^-score_precision`, rounding extra decimals.
+///
+/// Returns None for scores which are not a number, negative or too large to be stored exactly.
+fn parse_decimal_score(field: &str, score_precision: u32) -> Option<usize> {
+    let score = field.parse::<f64>().ok()?;
+    let scaled_score = (score * 10_f64.powi(score_precision as i32)).round();
+    // f64 represents integers exactly up to 2^53
+    (score >= 0.0 && scaled_score <= (1_u64 << 53) as f64).then_some(scaled_score as usize)
+}
+
+/// Column indices (0-based) of the fields of a fragment in a line.
+///
+/// Columns which are not used for any field are ignored, also when they come after the last
+/// field (e.g. a 6th column of a fragment file). The score is optional:
+/// it is missing when a line has no column at its index.
+///
+/// # Fields
+///
+/// * `chrom` - Column of the chromosome name.
+/// * `start` - Column of the start position.
+/// * `end` - Column of the end position.
+/// * `barcode` - Column of the cell barcode.
+/// * `score` - Column of the (optional) score.
+/// * `strand` - If set, column of the strand (`+`, `-` or `.`), which every line should have.
+#[derive(Clone, PartialEq, Eq)]
+pub struct FragmentColumns {
+    pub chrom: usize,
+    pub start: usize,
+    pub end: usize,
+    pub barcode: usize,
+    pub score: usize,
+    pub strand: Option<usize>,
+}
+
+impl Default for FragmentColumns {
+    fn default() -> FragmentColumns {
+        FragmentColumns {
+            chrom: 0,
+            start: 1,
+            end: 2,
+            barcode: 3,
+            score: 4,
+            strand: None,
+        }
+    }
+}
+
+impl FragmentColumns {
+    /// Create new FragmentColumns, returns an error if two fields are read from the same column.
+    ///
+    /// # Arguments
+    ///
+    /// * `chrom` - Column of the chromosome name.
+    /// * `start` - Column of the start position.
+    /// * `end` - Column of the end position.
+    /// * `barcode` - Column of the cell barcode.
+    /// * `score` - Column of the (optional) score.
+    /// * `strand` - Column of the strand, if the lines have one.
+    pub fn new(
+        chrom: usize,
+        start: usize,
+        end: usize,
+        barcode: usize,
+        score: usize,
+        strand: Option<usize>,
+    ) -> Result<FragmentColumns, String> {
+        let columns: Vec<usize> = [chrom, start, end, barcode, score]
+            .into_iter()
+            .chain(strand)
+            .collect();
+        if columns.iter().collect::<HashSet<_>>().len() != columns.len() {
+            return Err(match strand {
+                Some(strand) => format!(
+                    "Columns should be distinct, got chrom {}, start {}, end {}, barcode {}, score {} and strand {}",
+                    chrom, start, end, barcode, score, strand
+                ),
+                None => format!(
+                    "Columns should be distinct, got chrom {}, start {}, end {}, barcode {} and score {}",
+                    chrom, start, end, barcode, score
+                ),
+            });
+        }
+        Ok(FragmentColumns {
+            chrom,
+            start,
+            end,
+            barcode,
+            score,
+            strand,
+        })
+    }
+
+    /// Returns the minimum number of fields of a line: all fields of a fragment except the score.
+    fn minimum_number_of_fields(&self) -> usize {
+        [self.chrom, self.start, self.end, self.barcode]
+            .into_iter()
+            .chain(self.strand)
+            .max()
+            .unwrap()
+            + 1
+    }
+}
+
+/// Column layout of a tabix index, see `tabix_conf_t` of htslib.
+///
+/// Positions are always read as 0-based half-open intervals (like the `bed` preset of tabix),
+/// as in fragment files, but the contig and positions can be in any column.
+///
+/// # Fields
+///
+/// * `seq_col` - Column (0-based) of the contig name.
+/// * `begin_col` - Column (0-based) of the start position.
+/// * `end_col` - Column (0-based) of the end position.
+/// * `comment_char` - Lines starting with this character are skipped.
+/// * `skip_lines` - Number of lines to skip at the start of the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TabixColumns {
+    pub seq_col: usize,
+    pub begin_col: usize,
+    pub end_col: usize,
+    pub comment_char: char,
+    pub skip_lines: usize,
+}
+
+impl Default for TabixColumns {
+    fn default() -> TabixColumns {
+        TabixColumns {
+            seq_col: 0,
+            begin_col: 1,
+            end_col: 2,
+            comment_char: '#',
+            skip_lines: 0,
+        }
+    }
+}
+
+impl TabixColumns {
+    /// Create new TabixColumns, returns an error if two positions are read from the same column
+    /// or if the comment character is not ASCII.
+    ///
+    /// # Arguments
+    ///
+    /// * `seq_col` - Column (0-based) of the contig name.
+    /// * `begin_col` - Column (0-based) of the start position.
+    /// * `end_col` - Column (0-based) of the end position.
+    /// * `comment_char` - Lines starting with this character are skipped.
+    /// * `skip_lines` - Number of lines to skip at the start of the file.
+    pub fn new(
+        seq_col: usize,
+        begin_col: usize,
+        end_col: usize,
+        comment_char: char,
+        skip_lines: usize,
+    ) -> Result<TabixColumns, String> {
+        if seq_col == begin_col || seq_col == end_col || begin_col == end_col {
+            return Err(format!(
+                "Tabix columns should be distinct, got seq_col {}, begin_col {} and end_col {}",
+                seq_col, begin_col, end_col
+            ));
+        }
+        if !comment_char.is_ascii() {
+            return Err(format!(
+                "Invalid tabix comment character {:?}, should be ASCII",
+                comment_char
+            ));
+        }
+        Ok(TabixColumns {
+            seq_col,
+            begin_col,
+            end_col,
+            comment_char,
+            skip_lines,
+        })
+    }
+
+    /// Returns the TabixColumns of a tabix preset, only `bed` is supported as the other presets
+    /// of tabix (`gff`, `sam` and `vcf`) use 1-based positions.
+    pub fn from_preset(preset: &str) -> Result<TabixColumns, String> {
+        match preset {
+            "bed" => Ok(TabixColumns::default()),
+            _ => Err(format!(
+                "Invalid tabix preset {:?}, should be \"bed\" (use explicit columns for other layouts)",
+                preset
+            )),
+        }
+    }
+
+    /// Checks that a (non-comment) line has a contig and integer start and end positions
+    /// in the columns of the index.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line, without trailing newline.
+    pub fn check_line(&self, line: &str) -> Result<(), String> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.get(self.seq_col).is_none_or(|seq| seq.is_empty()) {
+            return Err(format!(
+                "Line {:?} has no contig in tabix column {}",
+                line, self.seq_col
+            ));
+        }
+        for (description, column) in [("start", self.begin_col), ("end", self.end_col)] {
+            if fields
+                .get(column)
+                .and_then(|position| position.parse::<u64>().ok())
+                .is_none()
+            {
+                return Err(format!(
+                    "Line {:?} has no integer {} position in tabix column {}",
+                    line, description, column
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FragmentFormat {
+    /// Create a new FragmentFormat, returns an error if the delimiter is empty
+    /// or if the barcode tag is not a valid SAM tag name.
+    ///
+    /// # Arguments
+    ///
+    /// * `delimiter` - Column delimiter, can be multiple characters.
+    /// * `strip_quotes` - Whether to strip surrounding quotes from the cell barcode.
+    /// * `barcode_tag` - Name of the SAM-style tag containing the cell barcode, e.g. `CB`.
+    pub fn new(
+        delimiter: &str,
+        strip_quotes: bool,
+        barcode_tag: Option<&str>,
+    ) -> Result<FragmentFormat, String> {
+        if delimiter.is_empty() {
+            return Err("Delimiter can not be empty".to_string());
+        }
+        if let Some(barcode_tag) = barcode_tag {
+            let mut characters = barcode_tag.chars();
+            let is_valid_tag = barcode_tag.len() == 2
+                && characters.next().is_some_and(|c| c.is_ascii_alphabetic())
+                && characters.next().is_some_and(|c| c.is_ascii_alphanumeric());
+            if !is_valid_tag {
+                return Err(format!(
+                    "Invalid barcode tag {:?}, expected two characters like \"CB\"",
+                    barcode_tag
+                ));
+            }
+        }
+        Ok(FragmentFormat {
+            delimiter: delimiter.to_string(),
+            strip_quotes,
+            barcode_tag: barcode_tag.map(str::to_string),
+            columns: FragmentColumns::default(),
+            score_precision: None,
+            score_pair: None,
+        })
+    }
+
+    /// Returns this format with decimal scores, kept with `score_precision` decimals,
+    /// or with integer scores when `score_precision` is None.
+    /// Returns an error if the precision is larger than `MAX_SCORE_PRECISION`.
+    ///
+    /// # Arguments
+    ///
+    /// * `score_precision` - Number of decimals of the scores.
+    pub fn with_score_precision(
+        self,
+        score_precision: Option<u32>,
+    ) -> Result<FragmentFormat, String> {
+        if let Some(score_precision) = score_precision {
+            if score_precision > MAX_SCORE_PRECISION {
+                return Err(format!(
+                    "Invalid score precision {}, should be at most {}",
+                    score_precision, MAX_SCORE_PRECISION
+                ));
+            }
+        }
+        Ok(FragmentFormat {
+            score_precision,
+            ..self
+        })
+    }
+
+    /// Returns this format with score columns which can contain a pair of integers (e.g. `3,5`),
+    /// of which `score_pair` is used as score, or with single scores when `score_pair` is None.
+    /// Returns an error if the scores are decimal numbers, see `with_score_precision`.
+    ///
+    /// # Arguments
+    ///
+    /// * `score_pair` - Which value of a score pair is used as score.
+    pub fn with_score_pair(
+        self,
+        score_pair: Option<ScorePairValue>,
+    ) -> Result<FragmentFormat, String> {
+        if score_pair.is_some() && self.score_precision.is_some() {
+            return Err(
+                "Score pairs can not be used with decimal scores (score_precision)".to_string(),
+            );
+        }
+        Ok(FragmentFormat { score_pair, ..self })
+    }
+
+    /// Returns this format with the fields of a fragment read from other columns.
+    ///
+    /// # Arguments
+    ///
+    /// * `columns` - Columns of the fields of a fragment.
+    pub fn with_columns(self, columns: FragmentColumns) -> FragmentFormat {
+        FragmentFormat { columns, ..self }
+    }
+
+    /// Get the cell barcode from the cell barcode column,
+    /// by stripping quotes and/or extracting the value of the barcode tag.
+    ///
+    /// The column can contain multiple whitespace separated tags (e.g. `CB:Z:AACG UB:Z:TTGA`).
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - Content of the cell barcode column.
+    pub fn cell_barcode<'a>(&self, field: &'a str) -> Result<&'a str, String> {
+        let mut cell_barcode = field;
+        if self.strip_quotes {
+            for quote in ['"', '\''] {
+                if let Some(unquoted) = cell_barcode
+                    .strip_prefix(quote)
+                    .and_then(|cell_barcode| cell_barcode.strip_suffix(quote))
+                {
+                    cell_barcode = unquoted;
+                    break;
+                }
+            }
+        }
+        if let Some(barcode_tag) = &self.barcode_tag {
+            cell_barcode = cell_barcode
+                .split_whitespace()
+                .find_map(|token| {
+                    token
+                        .strip_prefix(barcode_tag.as_str())
+                        .and_then(|token| token.strip_prefix(":Z:"))
+                })
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| {
+                    format!(
+                        "Cell barcode column {:?} does not contain a {}:Z:<barcode> tag",
+                        field, barcode_tag
+                    )
+                })?;
+        }
+        Ok(cell_barcode)
+    }
+
+    /// Split a line into its fields.
+    ///
+    /// A multi-character delimiter which overlaps with itself in the line (e.g. `|||` for `||`)
+    /// can be split in more than one way, this is reported as an error.
+    fn split<'a>(&self, s: &'a str) -> Result<Vec<&'a str>, String> {
+        if self.delimiter.chars().count() > 1 {
+            let number_of_delimiters = s.matches(self.delimiter.as_str()).count();
+            let number_of_overlapping_delimiters = (0..s.len())
+                .filter(|&i| s.is_char_boundary(i) && s[i..].starts_with(self.delimiter.as_str()))
+                .count();
+            if number_of_delimiters != number_of_overlapping_delimiters {
+                return Err(format!(
+                    "Delimiter {:?} can not be split unambiguously in line {:?}",
+                    self.delimiter, s
+                ));
+            }
+        }
+        Ok(s.split(self.delimiter.as_str()).collect())
+    }
+}
+
+/// Strand of a fragment.
+///
+/// # Variants
+///
+/// * `Forward` - `+`
+/// * `Reverse` - `-`
+/// * `Unknown` - `.`
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Strand {
+    Forward,
+    Reverse,
+    Unknown,
+}
+
+impl Strand {
+    /// Parse a strand ("+", "-" or ".").
+    pub fn parse(s: &str) -> Result<Strand, String> {
+        match s {
+            "+" => Ok(Strand::Forward),
+            "-" => Ok(Strand::Reverse),
+            "." => Ok(Strand::Unknown),
+            _ => Err(format!(
+                "Invalid strand {:?}, should be one of \"+\", \"-\" or \".\"",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Strand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Strand::Forward => write!(f, "+"),
+            Strand::Reverse => write!(f, "-"),
+            Strand::Unknown => write!(f, "."),
+        }
+    }
+}
+
+/// Struct representing a fragment, used for sorting
+///
+/// # Fields
+///
+/// * `chrom` - Chromosome name.
+/// * `start` - Start position.
+/// * `end` - End position.
+/// * `cell_barcode` - Cell barcode.
+/// * `score` - Optional score.
+/// * `score_pair` - Both values of the score, if the score column contains a pair (e.g. `3,5`),
+///     which is written instead of `score`, see `FragmentFormat::score_pair`.
+///     Cleared when the score is changed.
+/// * `strand` - Strand, if the fragment file has a strand column.
+/// * `file_index` - Index of the file the fragment was read from,
+///     used to order otherwise identical fragments from different files deterministically.
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Fragment {
+    pub chrom: String,
+    pub start: usize,
+    pub end: usize,
+    pub cell_barcode: String,
+    pub score: Option<usize>,
+    pub score_pair: Option<(usize, usize)>,
+    pub strand: Option<Strand>,
+    pub file_index: usize,
+}
+
+impl Fragment {
+    /// Create a new Fragment from a tab separated string, or an error describing the string when it can not be parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - String to parse.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use _rust_scatac_fragment_tools::fragment::Fragment;
+    ///
+    /// let fragment = Fragment::new_from_string("chr1\t100\t200\tAACATCGATGGATG-1\t10").unwrap();
+    /// assert_eq!(fragment.chrom, "chr1");
+    /// assert_eq!(fragment.start, 100);
+    /// assert_eq!(fragment.end, 200);
+    /// assert_eq!(fragment.cell_barcode, "AACATCGATGGATG-1");
+    /// assert_eq!(fragment.score, Some(10));
+    /// assert!(Fragment::new_from_string("chr1\tx\t200\tAACATCGATGGATG-1").is_err());
+    /// ```
+    pub fn new_from_string(s: &str) -> Result<Fragment, String> {
+        Fragment::new_from_string_with_format(s, &FragmentFormat::default())
+    }
+
+    /// Create a new Fragment from a string with a custom delimiter, quoted cell barcode and/or column layout.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - String to parse.
+    /// * `format` - Layout of the string.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use _rust_scatac_fragment_tools::fragment::{Fragment, FragmentFormat};
+    ///
+    /// let format = FragmentFormat::new("||", true, None).unwrap();
+    /// let fragment =
+    ///     Fragment::new_from_string_with_format("chr1||100||200||\"AACATCGATGGATG-1\"", &format)
+    ///         .unwrap();
+    /// assert_eq!(fragment.cell_barcode, "AACATCGATGGATG-1");
+    /// ```
+    pub fn new_from_string_with_format(
+        s: &str,
+        format: &FragmentFormat,
+    ) -> Result<Fragment, String> {
+        let fields = format.split(s)?;
+        let columns = &format.columns;
+        // columns after the last field of a fragment are ignored
+        let minimum_number_of_fields = columns.minimum_number_of_fields();
+        if fields.len() < minimum_number_of_fields {
+            return Err(format!(
+                "Invalid number of fields in fragment file: expected at least {}, got {} in line {:?}",
+                minimum_number_of_fields,
+                fields.len(),
+                s
+            ));
+        }
+        let parse_position = |field: &str| -> Result<usize, String> {
+            field
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid number {:?} in line {:?}", field, s))
+        };
+        let parse_score = |field: &str| -> Result<usize, String> {
+            match format.score_precision {
+                None => parse_position(field),
+                Some(score_precision) => parse_decimal_score(field, score_precision)
+                    .ok_or_else(|| format!("Invalid score {:?} in line {:?}", field, s)),
+            }
+        };
+        let cell_barcode = format.cell_barcode(fields[columns.barcode])?;
+        let score_pair = match (format.score_pair, fields.get(columns.score)) {
+            (Some(_), Some(field)) if field.contains(',') => {
+                let score_pair = field.split_once(',').and_then(|(first, second)| {
+                    Some((first.parse::<usize>().ok()?, second.parse::<usize>().ok()?))
+                });
+                Some(score_pair.ok_or_else(|| {
+                    format!(
+                        "Invalid score pair {:?} in line {:?}, expected two integers separated by a comma",
+                        field, s
+                    )
+                })?)
+            }
+            _ => None,
+        };
+        let score = match (format.score_pair, score_pair) {
+            (Some(ScorePairValue::First), Some((first, _))) => Some(first),
+            (Some(ScorePairValue::Second), Some((_, second))) => Some(second),
+            _ => fields
+                .get(columns.score)
+                .map(|score| parse_score(score))
+                .transpose()?,
+        };
+        Ok(Fragment {
+            chrom: fields[columns.chrom].to_string(),
+            start: parse_position(fields[columns.start])?,
+            end: parse_position(fields[columns.end])?,
+            cell_barcode: cell_barcode.to_string(),
+            score,
+            score_pair,
+            strand: columns
+                .strand
+                .map(|strand| {
+                    Strand::parse(fields[strand]).map_err(|e| format!("{} in line {:?}", e, s))
+                })
+                .transpose()?,
+            file_index: 0,
+        })
+    }
+
+    /// Returns this fragment shifted for the Tn5 insertion, so its ends are the centers of the cut sites.
+    ///
+    /// Tn5 inserts its adapters 9 bp apart, so reads on the forward strand start 4 bp before
+    /// and reads on the reverse strand end 5 bp after the center of the cut site.
+    /// A fragment with a forward or reverse strand is a single read and is moved +4 or -5 bp as a whole,
+    /// a fragment without (known) strand spans both cut sites: its start is moved +4 and its end -5 bp.
+    /// Positions are clamped at 0 and an end before the start is set to the start.
+    pub fn tn5_shifted(&self) -> Fragment {
+        let (start, end) = match self.strand {
+            Some(Strand::Forward) => (self.start + 4, self.end + 4),
+            Some(Strand::Reverse) => (self.start.saturating_sub(5), self.end.saturating_sub(5)),
+            Some(Strand::Unknown) | None => (self.start + 4, self.end.saturating_sub(5)),
+        };
+        Fragment {
+            start,
+            end: end.max(start),
+            ..self.clone()
+        }
+    }
+
+    /// Returns this fragment with its start and end shifted, e.g. by `(4, -5)` for the Tn5 insertion
+    /// (see `tn5_shifted` to shift reads by strand).
+    ///
+    /// Positions are clamped between 0 and the contig size, and an end before the start is set to the start.
+    ///
+    /// # Arguments
+    ///
+    /// * `shift` - Number of bp by which the start and the end are moved.
+    /// * `contig_size` - If set, the size of the contig of the fragment.
+    pub fn shifted(&self, shift: (i64, i64), contig_size: Option<u64>) -> Fragment {
+        let max_position = contig_size.map_or(i64::MAX, |contig_size| {
+            contig_size.min(i64::MAX as u64) as i64
+        });
+        let shift_position = |position: usize, shift: i64| {
+            (position as i64)
+                .saturating_add(shift)
+                .clamp(0, max_position) as usize
+        };
+        let start = shift_position(self.start, shift.0);
+        let end = shift_position(self.end, shift.1);
+        Fragment {
+            start,
+            end: end.max(start),
+            ..self.clone()
+        }
+    }
+}
+
+impl Ord for Fragment {
+    fn cmp(&self, other: &Fragment) -> std::cmp::Ordering {
+        let self_chrom = &self.chrom;
+        let other_chrom = &other.chrom;
+
+        let self_start = &self.start;
+        let other_start = &other.start;
+
+        let self_end = &self.end;
+        let other_end = &other.end;
+
+        let self_cell_barcode = &self.cell_barcode;
+        let other_cell_barcode = &other.cell_barcode;
+
+        // Fragments are compared on all fields, so the order of fragments
+        // that only differ in their file of origin or score is deterministic.
+        if self_chrom != other_chrom {
+            self_chrom.cmp(other_chrom)
+        } else if self_start != other_start {
+            self_start.cmp(other_start)
+        } else if self_end != other_end {
+            self_end.cmp(other_end)
+        } else if self_cell_barcode != other_cell_barcode {
+            self_cell_barcode.cmp(other_cell_barcode)
+        } else if self.file_index != other.file_index {
+            self.file_index.cmp(&other.file_index)
+        } else if self.score != other.score {
+            self.score.cmp(&other.score)
+        } else if self.score_pair != other.score_pair {
+            self.score_pair.cmp(&other.score_pair)
+        } else {
+            self.strand.cmp(&other.strand)
+        }
+    }
+}
+
+impl PartialOrd for Fragment {
+    fn partial_cmp(&self, other: &Fragment) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Fragment {
+    /// Returns the fragment as written by `Display`, with the score formatted with `score_precision` decimals,
+    /// see `FragmentFormat::score_precision`.
+    ///
+    /// # Arguments
+    ///
+    /// * `score_precision` - Number of decimals of the score, if scores are decimal numbers.
+    pub fn to_string_with_score_precision(&self, score_precision: Option<u32>) -> String {
+        let mut line = format!(
+            "{}\t{}\t{}\t{}",
+            self.chrom, self.start, self.end, self.cell_barcode
+        );
+        match (self.score, self.score_pair) {
+            (Some(_), Some((first, second))) => line.push_str(&format!("\t{},{}", first, second)),
+            (Some(score), None) => {
+                line.push('\t');
+                line.push_str(&format_score(score, score_precision));
+            }
+            (None, _) => {}
+        }
+        if let Some(strand) = self.strand {
+            line.push('\t');
+            line.push_str(&strand.to_string());
+        }
+        line
+    }
+}
+
+impl fmt::Display for Fragment {
+    /// Writes the fragment in the standard column order, the strand (if any) is written after the score.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.score, self.score_pair) {
+            (Some(_), Some((first, second))) => write!(
+                f,
+                "{}\t{}\t{}\t{}\t{},{}",
+                self.chrom, self.start, self.end, self.cell_barcode, first, second
+            ),
+            (Some(score), None) => write!(
+                f,
+                "{}\t{}\t{}\t{}\t{}",
+                self.chrom, self.start, self.end, self.cell_barcode, score
+            ),
+            (None, _) => write!(
+                f,
+                "{}\t{}\t{}\t{}",
+                self.chrom, self.start, self.end, self.cell_barcode
+            ),
+        }?;
+        match self.strand {
+            Some(strand) => write!(f, "\t{}", strand),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Condition on the score of a fragment.
+///
+/// # Variants
+///
+/// * `Range` - Score lies in an (inclusive) range.
+/// * `Set` - Score is one of a set of values.
+pub enum ScorePredicate {
+    Range(RangeInclusive<usize>),
+    Set(HashSet<usize>),
+}
+
+impl ScorePredicate {
+    /// Parse a score predicate, returns an error if it is not valid.
+    ///
+    /// Supported are ranges with an exclusive (`2..10`) or inclusive (`2..=10`) end,
+    /// where the start or the end can be left out (`5..`, `..10`),
+    /// and comma separated sets of values (`1,2,5` or just `3`).
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - String to parse.
+    pub fn parse(s: &str) -> Result<ScorePredicate, String> {
+        let parse_score = |score: &str| -> Result<usize, String> {
+            score.trim().parse::<usize>().map_err(|_| {
+                format!(
+                    "Invalid score {:?} in score predicate {:?}, scores should be non-negative integers",
+                    score, s
+                )
+            })
+        };
+        match s.split_once("..") {
+            Some((start, end)) => {
+                let start = if start.trim().is_empty() {
+                    0
+                } else {
+                    parse_score(start)?
+                };
+                let end = match end.strip_prefix('=') {
+                    Some(inclusive_end) => parse_score(inclusive_end)?,
+                    None if end.trim().is_empty() => usize::MAX,
+                    None => parse_score(end)?
+                        .checked_sub(1)
+                        .ok_or_else(|| format!("Score predicate {:?} is an empty range", s))?,
+                };
+                if start > end {
+                    return Err(format!("Score predicate {:?} is an empty range", s));
+                }
+                Ok(ScorePredicate::Range(start..=end))
+            }
+            None => Ok(ScorePredicate::Set(
+                s.split(',').map(parse_score).collect::<Result<_, _>>()?,
+            )),
+        }
+    }
+
+    /// Returns the predicate given as a string or as a minimum score (`min_score..`), if any.
+    /// Returns an error if the string is not valid or if both are given.
+    ///
+    /// # Arguments
+    ///
+    /// * `score_predicate` - If set, the predicate as string, see `ScorePredicate::parse`.
+    /// * `min_score` - If set, the minimum score.
+    pub fn from_predicate_or_min_score(
+        score_predicate: Option<&str>,
+        min_score: Option<usize>,
+    ) -> Result<Option<ScorePredicate>, String> {
+        match (score_predicate, min_score) {
+            (Some(_), Some(_)) => {
+                Err("score_predicate and min_score can not be given together".to_string())
+            }
+            (Some(score_predicate), None) => ScorePredicate::parse(score_predicate).map(Some),
+            (None, Some(min_score)) => Ok(Some(ScorePredicate::Range(min_score..=usize::MAX))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Whether a score satisfies the predicate.
+    pub fn matches(&self, score: usize) -> bool {
+        match self {
+            ScorePredicate::Range(range) => range.contains(&score),
+            ScorePredicate::Set(set) => set.contains(&score),
+        }
+    }
+}
+
+/// How zero-length fragments (with start equal to end) are handled.
+///
+/// These fragments do not cover any base and usually come from bugs in upstream tools.
+///
+/// # Variants
+///
+/// * `Keep` - Zero-length fragments are written like other fragments.
+/// * `Drop` - Zero-length fragments are not written, but counted.
+/// * `Error` - A zero-length fragment results in an error.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ZeroLengthHandling {
+    Keep,
+    Drop,
+    Error,
+}
+
+impl ZeroLengthHandling {
+    /// Parse a zero-length handling ("keep", "drop" or "error").
+    pub fn parse(s: &str) -> Result<ZeroLengthHandling, String> {
+        match s {
+            "keep" => Ok(ZeroLengthHandling::Keep),
+            "drop" => Ok(ZeroLengthHandling::Drop),
+            "error" => Ok(ZeroLengthHandling::Error),
+            _ => Err(format!(
+                "Invalid zero-length handling {:?}, should be one of \"keep\", \"drop\" or \"error\"",
+                s
+            )),
+        }
+    }
+}
+
+/// How contig names are normalized, e.g. to compare names of files which use different naming conventions.
+///
+/// # Variants
+///
+/// * `Keep` - Contig names are used as is.
+/// * `AddChr` - A `chr` prefix is added to contig names without one (`1` becomes `chr1`).
+/// * `StripChr` - The `chr` prefix is removed from contig names with one (`chr1` becomes `1`).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ContigNameMode {
+    Keep,
+    AddChr,
+    StripChr,
+}
+
+impl ContigNameMode {
+    /// Parse a contig name mode ("keep", "add_chr" or "strip_chr").
+    pub fn parse(s: &str) -> Result<ContigNameMode, String> {
+        match s {
+            "keep" => Ok(ContigNameMode::Keep),
+            "add_chr" => Ok(ContigNameMode::AddChr),
+            "strip_chr" => Ok(ContigNameMode::StripChr),
+            _ => Err(format!(
+                "Invalid contig name mode {:?}, should be one of \"keep\", \"add_chr\" or \"strip_chr\"",
+                s
+            )),
+        }
+    }
+
+    /// Returns the normalized name of a contig.
+    ///
+    /// # Arguments
+    ///
+    /// * `contig` - Name of the contig.
+    pub fn apply<'a>(&self, contig: &'a str) -> Cow<'a, str> {
+        match self {
+            ContigNameMode::AddChr if !contig.starts_with("chr") => {
+                Cow::Owned(format!("chr{}", contig))
+            }
+            ContigNameMode::StripChr => Cow::Borrowed(contig.strip_prefix("chr").unwrap_or(contig)),
+            _ => Cow::Borrowed(contig),
+        }
+    }
+}
+
+/// How records with the same chromosome, start, end and cell barcode are written.
+///
+/// Only consecutive records are compared, so duplicates are only all found in sorted input.
+///
+/// # Variants
+///
+/// * `Keep` - Every record is written.
+/// * `CollapseSumScore` - Duplicates are written once, with the sum of their scores as score.
+///     Missing scores are left out of the sum, the score stays missing when all scores are missing.
+/// * `CollapseSumReads` - Duplicates are written once, with the sum of their scores as score,
+///     where a missing score counts as one read (e.g. when merging resequenced libraries of the same sample).
+///     Fragments without duplicates are written unchanged.
+/// * `CollapseCount` - Duplicates are written once, with the number of duplicates as score.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateHandling {
+    Keep,
+    CollapseSumScore,
+    CollapseSumReads,
+    CollapseCount,
+}
+
+impl DuplicateHandling {
+    /// Parse a duplicate handling ("keep", "collapse_sum_score", "collapse_sum_reads" or "collapse_count").
+    pub fn parse(s: &str) -> Result<DuplicateHandling, String> {
+        match s {
+            "keep" => Ok(DuplicateHandling::Keep),
+            "collapse_sum_score" => Ok(DuplicateHandling::CollapseSumScore),
+            "collapse_sum_reads" => Ok(DuplicateHandling::CollapseSumReads),
+            "collapse_count" => Ok(DuplicateHandling::CollapseCount),
+            _ => Err(format!(
+                "Invalid duplicate handling {:?}, should be one of \"keep\", \
+                \"collapse_sum_score\", \"collapse_sum_reads\" or \"collapse_count\"",
+                s
+            )),
+        }
+    }
+
+    /// Returns the duplicate handling given as a string, or `CollapseSumReads` if `collapse_duplicates` is set.
+    /// Returns an error if the string is not valid or if both are given.
+    ///
+    /// # Arguments
+    ///
+    /// * `duplicate_handling` - The duplicate handling as string, see `DuplicateHandling::parse`.
+    /// * `collapse_duplicates` - Whether duplicates are collapsed by summing their reads.
+    pub fn from_handling_or_collapse_duplicates(
+        duplicate_handling: &str,
+        collapse_duplicates: bool,
+    ) -> Result<DuplicateHandling, String> {
+        match (
+            DuplicateHandling::parse(duplicate_handling)?,
+            collapse_duplicates,
+        ) {
+            (duplicate_handling, false) => Ok(duplicate_handling),
+            (DuplicateHandling::Keep, true) => Ok(DuplicateHandling::CollapseSumReads),
+            _ => Err(
+                "duplicate_handling and collapse_duplicates can not be given together".to_string(),
+            ),
+        }
+    }
+}
+
+/// Collapses consecutive duplicate fragments of a stream of fragments.
+///
+/// Each fragment is passed to `push`, which returns the fragments that are complete,
+/// the last fragment is returned by `finish`.
+///
+/// # Fields
+///
+/// * `duplicate_handling` - How duplicates are collapsed.
+/// * `count_unit` - Score of a single fragment when duplicates are counted,
+///     `10^score_precision` for decimal scores, so counts are written as whole numbers.
+/// * `pending` - The last fragment, with the duplicates seen so far collapsed into it.
+pub struct DuplicateCollapser {
+    duplicate_handling: DuplicateHandling,
+    count_unit: usize,
+    pending: Option<Fragment>,
+}
+
+impl DuplicateCollapser {
+    /// Create a new DuplicateCollapser.
+    ///
+    /// # Arguments
+    ///
+    /// * `duplicate_handling` - How duplicates are collapsed.
+    /// * `score_precision` - Number of decimals of the scores, see `FragmentFormat::score_precision`.
+    pub fn new(
+        duplicate_handling: DuplicateHandling,
+        score_precision: Option<u32>,
+    ) -> DuplicateCollapser {
+        DuplicateCollapser {
+            duplicate_handling,
+            count_unit: 10_usize.pow(score_precision.unwrap_or(0)),
+            pending: None,
+        }
+    }
+
+    /// Adds the next fragment of the stream.
+    ///
+    /// Returns the previous fragment (with all its duplicates collapsed) if the given fragment
+    /// is not a duplicate of it, or the given fragment itself when duplicates are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `fragment` - The next fragment.
+    pub fn push(&mut self, mut fragment: Fragment) -> Option<Fragment> {
+        match (self.duplicate_handling, self.pending.as_mut()) {
+            (DuplicateHandling::Keep, _) => return Some(fragment),
+            (duplicate_handling, Some(pending))
+                if pending.chrom == fragment.chrom
+                    && pending.start == fragment.start
+                    && pending.end == fragment.end
+                    && pending.cell_barcode == fragment.cell_barcode =>
+            {
+                pending.score = match (duplicate_handling, pending.score, fragment.score) {
+                    (DuplicateHandling::CollapseSumScore, Some(score), Some(other_score)) => {
+                        Some(score + other_score)
+                    }
+                    (DuplicateHandling::CollapseSumScore, score, other_score) => {
+                        score.or(other_score)
+                    }
+                    (DuplicateHandling::CollapseSumReads, score, other_score) => Some(
+                        score.unwrap_or(self.count_unit) + other_score.unwrap_or(self.count_unit),
+                    ),
+                    (_, score, _) => score.map(|count| count + self.count_unit),
+                };
+                pending.score_pair = None;
+                return None;
+            }
+            _ => {}
+        }
+        if self.duplicate_handling == DuplicateHandling::CollapseCount {
+            fragment.score = Some(self.count_unit);
+            fragment.score_pair = None;
+        }
+        self.pending.replace(fragment)
+    }
+
+    /// Returns the last fragment of the stream, if any.
+    pub fn finish(&mut self) -> Option<Fragment> {
+        self.pending.take()
+    }
+}
+
+/// Renaming of the cell barcodes of written fragments, e.g. to sample-specific IDs.
+///
+/// Only the written cell barcodes change: cell barcodes are looked up and fragments are sorted
+/// and compared with their original cell barcode.
+///
+/// # Fields
+///
+/// * `new_barcodes` - A HashMap mapping cell barcodes to their new names.
+/// * `drop_missing` - Whether fragments of cell barcodes which are not in `new_barcodes` are dropped,
+///     instead of being written with their original cell barcode.
+pub struct BarcodeRename {
+    pub new_barcodes: HashMap<String, String>,
+    pub drop_missing: bool,
+}
+
+impl BarcodeRename {
+    /// Returns the cell barcode to write for a cell barcode, or `None` if its fragments are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `cell_barcode` - The original cell barcode.
+    pub fn rename<'a>(&'a self, cell_barcode: &'a str) -> Option<&'a str> {
+        match self.new_barcodes.get(cell_barcode) {
+            Some(new_barcode) => Some(new_barcode),
+            None if self.drop_missing => None,
+            None => Some(cell_barcode),
+        }
+    }
+}
+
+/// Transformation of the cell barcodes of a fragments file before they are looked up in an annotation,
+/// e.g. from `AACGATG-1` to `sample1:AACGATG` when the annotation uses other cell barcodes than the file.
+///
+/// The prefix and suffix are stripped first (when the cell barcode has them), then the sample prefix is added.
+///
+/// # Fields
+///
+/// * `strip_prefix` - If set, prefix to remove from the cell barcodes.
+/// * `strip_suffix` - If set, suffix to remove from the cell barcodes (e.g. `-1`).
+/// * `add_prefix` - If set, prefix to add to the cell barcodes (e.g. `sample1:`).
+pub struct BarcodeTransform {
+    pub strip_prefix: Option<String>,
+    pub strip_suffix: Option<String>,
+    pub add_prefix: Option<String>,
+}
+
+impl BarcodeTransform {
+    /// Returns the transformed cell barcode.
+    ///
+    /// # Arguments
+    ///
+    /// * `cell_barcode` - The cell barcode of the fragments file.
+    pub fn apply<'a>(&self, cell_barcode: &'a str) -> Cow<'a, str> {
+        let mut cell_barcode = cell_barcode;
+        if let Some(strip_prefix) = &self.strip_prefix {
+            cell_barcode = cell_barcode
+                .strip_prefix(strip_prefix.as_str())
+                .unwrap_or(cell_barcode);
+        }
+        if let Some(strip_suffix) = &self.strip_suffix {
+            cell_barcode = cell_barcode
+                .strip_suffix(strip_suffix.as_str())
+                .unwrap_or(cell_barcode);
+        }
+        match &self.add_prefix {
+            Some(add_prefix) => Cow::Owned(format!("{}{}", add_prefix, cell_barcode)),
+            None => Cow::Borrowed(cell_barcode),
+        }
+    }
+}
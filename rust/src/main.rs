@@ -0,0 +1,688 @@
+use _rust_scatac_fragment_tools::aggregate_fragments::{
+    merge_fragment_files, ColumnNormalization, MemoryMode, MergeOptions,
+};
+use _rust_scatac_fragment_tools::custom_errors::{FragmentToolsError, FragmentToolsResult};
+use _rust_scatac_fragment_tools::fragment::{
+    BarcodeTransform, ContigNameMode, DuplicateHandling, FragmentColumns, FragmentFormat,
+    ScorePairValue, ScorePredicate, ZeroLengthHandling,
+};
+use _rust_scatac_fragment_tools::parquet_writer::OutputCodec;
+use _rust_scatac_fragment_tools::split_fragments::{
+    split_fragments_by_cell_barcode, CellTypeAssignment, LengthHistogramBins, SplitGroupBy,
+    SplitOptions, WriterPoolStrategy,
+};
+use _rust_scatac_fragment_tools::validate::validate_fragment_file;
+use clap::{Args, Parser, Subcommand};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::process::ExitCode;
+
+/// Tools for working with scATAC-seq fragment files, without Python.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+// Parsed once per run, so the size of the largest subcommand does not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+enum Command {
+    /// Split a fragment file by cell type.
+    Split {
+        /// Path to the (tabix-indexed) fragments file.
+        #[arg(short = 'f', long)]
+        fragments: String,
+        /// Path to a TSV file with a header, mapping cell barcodes to cell types.
+        #[arg(short = 'a', long)]
+        annotation: String,
+        /// Path to a TSV file without header, with chromosome names and sizes.
+        /// If not set, all contigs of the fragments file are used.
+        #[arg(short = 'c', long)]
+        chromsizes: Option<String>,
+        /// Path to the output folder, one file per cell type is written here.
+        #[arg(short = 'o', long)]
+        output_folder: String,
+        /// Only use the rows of the annotation of this sample.
+        #[arg(long)]
+        sample: Option<String>,
+        /// Name of the sample column of the annotation, used with --sample.
+        #[arg(long, default_value = "sample")]
+        sample_column: String,
+        /// Name of the cell type column of the annotation.
+        #[arg(long, default_value = "cell_type")]
+        cell_type_column: String,
+        /// Name of the cell barcode column of the annotation.
+        #[arg(long, default_value = "cell_barcode")]
+        cell_barcode_column: String,
+        /// Number of threads to use for writing, in total or per file (see --writer-pool-strategy).
+        #[arg(short = 't', long, default_value_t = 5)]
+        threads: u32,
+        /// Whether the files per cell type share one thread pool ("shared")
+        /// or each get their own ("per_writer").
+        #[arg(long, default_value = "shared")]
+        writer_pool_strategy: String,
+        /// Write the files per cell type for genome browsers: in BGZF blocks of 16 KiB,
+        /// at compression level 6, each with a tabix index.
+        #[arg(long)]
+        browser_optimized: bool,
+        /// Create a tabix index for each file per cell type.
+        #[arg(long)]
+        create_index: bool,
+        /// Remove this prefix from the cell barcodes of the fragments file before looking them up in the annotation.
+        #[arg(long)]
+        strip_barcode_prefix: Option<String>,
+        /// Remove this suffix (e.g. "-1") from the cell barcodes of the fragments file
+        /// before looking them up in the annotation.
+        #[arg(long)]
+        strip_barcode_suffix: Option<String>,
+        /// Add this prefix (e.g. "sample1:") to the cell barcodes of the fragments file
+        /// before looking them up in the annotation, after stripping.
+        #[arg(long)]
+        add_sample_prefix: Option<String>,
+        /// How the contig names of the chromsizes, --contig-order and --blacklist are normalized before
+        /// they are compared with the contigs of the fragments file: "keep", "add_chr" or "strip_chr".
+        #[arg(long, default_value = "keep")]
+        contig_name_mode: String,
+        /// Move the start and end of each written fragment by START,END bp, e.g. "4,-5" for the Tn5 insertion,
+        /// clamped between 0 and the contig size.
+        #[arg(long, value_parser = parse_shift, allow_hyphen_values = true)]
+        shift: Option<(i64, i64)>,
+        /// Print the SHA-256 checksum of the uncompressed content of each output file.
+        #[arg(long)]
+        checksums: bool,
+        /// Print a table with the number of fragments written per cell type.
+        #[arg(long)]
+        print_counts: bool,
+        /// Write the fragments of cell barcodes which are not in the annotation to this file.
+        #[arg(long)]
+        unassigned_output: Option<String>,
+        /// Print a table with the number of fragments written per cell type and length bin of this many bp.
+        #[arg(long)]
+        length_histogram_bin_size: Option<u64>,
+        /// Count fragments of at least this length in the last bin of --length-histogram-bin-size.
+        #[arg(long, default_value_t = 1000)]
+        length_histogram_max_length: u64,
+        /// Only write fragments with a score satisfying this predicate, e.g. "2..10" or "1,2,5".
+        #[arg(long)]
+        score_predicate: Option<String>,
+        /// Only write fragments with at least this score (short for --score-predicate "MIN..").
+        #[arg(long, conflicts_with = "score_predicate")]
+        min_score: Option<usize>,
+        /// Write fragments without a score when --score-predicate or --min-score is set.
+        #[arg(long)]
+        missing_score_passes: bool,
+        /// Name of the SAM-style tag (e.g. CB) in the cell barcode column containing the cell barcode.
+        #[arg(long)]
+        barcode_tag: Option<String>,
+        /// How fragments of a cell barcode which maps to several cell types are written:
+        /// "all", "first" or "error".
+        #[arg(long, default_value = "all")]
+        assignment: String,
+        /// Write the files per cell type into this tar archive instead.
+        #[arg(long)]
+        tar_archive: Option<String>,
+        /// Path to a TSV file with the number of fragments written per cell type so far,
+        /// updated after each contig.
+        #[arg(long)]
+        counts_file: Option<String>,
+        /// Codec of the files per cell type: "bgzf" or "parquet".
+        #[arg(long, default_value = "bgzf")]
+        output_codec: String,
+        /// Extension of the files per cell type (without leading dot), e.g. "fragments.tsv.bgz".
+        /// Defaults to the extension of --output-codec.
+        #[arg(long)]
+        output_extension: Option<String>,
+        /// How fragments with the same chromosome, start, end and cell barcode are written:
+        /// "keep", "collapse_sum_score" (sum their scores), "collapse_sum_reads" (sum their scores,
+        /// a missing score counts as one read) or "collapse_count" (count them).
+        #[arg(long, default_value = "keep")]
+        duplicate_handling: String,
+        /// How fragments which start where they end are handled: "keep", "drop" or "error".
+        #[arg(long, default_value = "drop")]
+        zero_length: String,
+        /// Comma-separated contigs in the order in which they are written, e.g. "chr2,chr1".
+        /// Only these contigs are written. Defaults to the sorted contigs of the chromsizes.
+        #[arg(long, value_delimiter = ',')]
+        contig_order: Option<Vec<String>>,
+        /// Path to a BED file with blacklist regions, fragments overlapping them are not written.
+        #[arg(long)]
+        blacklist: Option<String>,
+        /// Write one file per "cell_type" or one file per "barcode" (the annotation is then a whitelist).
+        #[arg(long, default_value = "cell_type")]
+        group_by: String,
+        /// Maximum number of files to write at the same time. When grouping by barcode, the fragments
+        /// file is read once per batch of this many barcodes (default 1000). When grouping by cell type,
+        /// there is no limit by default.
+        #[arg(long)]
+        max_open_files: Option<usize>,
+        /// Read the scores as decimal numbers and write them with this many decimals (at most 6).
+        #[arg(long)]
+        score_precision: Option<u32>,
+        /// Allow comma separated score pairs (e.g. "3,5") and use their "first" or "second" value as score.
+        #[arg(long)]
+        score_pair: Option<String>,
+        /// Number of cell types the annotation should have, warns when the number of cell types differs more than 50%.
+        #[arg(long)]
+        expected_cell_types: Option<usize>,
+        /// Print progress messages.
+        #[arg(short = 'v', long)]
+        verbose: bool,
+    },
+    /// Merge sorted fragment files into a single sorted fragment file.
+    Aggregate {
+        /// Paths to the fragment files, each sorted by contig and position. The contigs are sorted
+        /// lexicographically, or in the same order in all files which are tabix indexed.
+        #[arg(required = true)]
+        fragments: Vec<String>,
+        /// Path to the output file.
+        #[arg(short = 'o', long)]
+        output: String,
+        /// Number of threads to use for writing.
+        #[arg(short = 't', long, default_value_t = 5)]
+        threads: u32,
+        /// Maximum number of files to read from at the same time, defaults to the preset of --memory-mode.
+        #[arg(long)]
+        max_open_files: Option<usize>,
+        /// Presets for the memory usage: "low" (32 open files, 8 KiB read buffers)
+        /// or "fast" (512 open files, 128 KiB read buffers).
+        #[arg(long, default_value = "fast")]
+        memory_mode: String,
+        /// Codec of the output file: "bgzf" or "parquet".
+        #[arg(long, default_value = "bgzf")]
+        output_codec: String,
+        /// Add a column with a unique ID (frag_1, frag_2, ...) after the last column of each fragment.
+        #[arg(long)]
+        add_fragment_ids: bool,
+        /// How the score column is normalized when some lines lack a score: "none" (write lines as read),
+        /// "max" (set missing scores to --missing-score) or "min" (drop the scores).
+        #[arg(long, default_value = "none")]
+        normalize_columns: String,
+        /// Score of lines without a score, used with --normalize-columns max.
+        #[arg(long, default_value_t = 0)]
+        missing_score: usize,
+        /// How fragments with the same chromosome, start, end and cell barcode are written:
+        /// "keep", "collapse_sum_score" (sum their scores), "collapse_sum_reads" (sum their scores,
+        /// a missing score counts as one read) or "collapse_count" (count them).
+        #[arg(long, default_value = "keep")]
+        duplicate_handling: String,
+        /// How fragments which start where they end are handled: "keep", "drop" or "error".
+        #[arg(long, default_value = "drop")]
+        zero_length: String,
+        /// Memory-map the input files (requires the mmap feature, otherwise they are read normally).
+        #[arg(long)]
+        memory_map_inputs: bool,
+        /// Allow a fragment file to be listed more than once (its fragments are then written more than once).
+        #[arg(long)]
+        allow_duplicate_inputs: bool,
+        /// Write the output in this many tabix indexed shards (e.g. merged.shard_0.tsv.gz)
+        /// with about the same number of fragments, instead of one file.
+        #[arg(long)]
+        n_output_shards: Option<usize>,
+        /// Merge --threads contigs at the same time (requires tabix indexed fragment files),
+        /// the output is the same and is tabix indexed.
+        #[arg(long)]
+        merge_contigs_in_parallel: bool,
+        /// Only write fragments with at least this score.
+        #[arg(long)]
+        min_score: Option<usize>,
+        /// Write fragments without a score when --min-score is set.
+        #[arg(long)]
+        missing_score_passes: bool,
+        /// Write duplicate fragments once with the sum of their scores, a missing score counts as one read
+        /// (short for --duplicate-handling collapse_sum_reads).
+        #[arg(long)]
+        collapse_duplicates: bool,
+        /// Move the start and end of each written fragment by START,END bp, e.g. "4,-5" for the Tn5 insertion,
+        /// clamped at 0.
+        #[arg(long, value_parser = parse_shift, allow_hyphen_values = true)]
+        shift: Option<(i64, i64)>,
+        #[command(flatten)]
+        format: FormatArgs,
+        /// Print progress messages.
+        #[arg(short = 'v', long)]
+        verbose: bool,
+    },
+    /// Validate a fragment file and print the number of fragments per contig.
+    Validate {
+        /// Path to the fragments file.
+        fragments: String,
+        /// Path to a TSV file without header, with chromosome names and sizes.
+        #[arg(short = 'c', long)]
+        chromsizes: Option<String>,
+        #[command(flatten)]
+        format: FormatArgs,
+        /// Number of contigs to validate at the same time, more than one requires a tabix index.
+        #[arg(short = 't', long, default_value_t = 1)]
+        threads: usize,
+        /// Number of bp a fragment may start before the previous fragments of its contig,
+        /// reported with a warning instead of an error.
+        #[arg(long, default_value_t = 0)]
+        unsorted_tolerance: u64,
+        /// Print progress messages.
+        #[arg(short = 'v', long)]
+        verbose: bool,
+    },
+}
+
+/// Layout of the lines of input fragment files, output files are always written in the standard layout.
+#[derive(Args)]
+struct FormatArgs {
+    /// Column delimiter of the input files, can be multiple characters.
+    #[arg(long, default_value = "\t")]
+    delimiter: String,
+    /// Strip surrounding quotes from the cell barcodes.
+    #[arg(long)]
+    strip_quotes: bool,
+    /// Name of the SAM-style tag (e.g. CB) in the cell barcode column containing the cell barcode.
+    #[arg(long)]
+    barcode_tag: Option<String>,
+    /// Column (0-based) of the chromosome name.
+    #[arg(long, default_value_t = 0)]
+    chrom_column: usize,
+    /// Column (0-based) of the start position.
+    #[arg(long, default_value_t = 1)]
+    start_column: usize,
+    /// Column (0-based) of the end position.
+    #[arg(long, default_value_t = 2)]
+    end_column: usize,
+    /// Column (0-based) of the cell barcode.
+    #[arg(long, default_value_t = 3)]
+    barcode_column: usize,
+    /// Column (0-based) of the (optional) score.
+    #[arg(long, default_value_t = 4)]
+    score_column: usize,
+    /// Column (0-based) of the strand ("+", "-" or "."), if the fragments have one.
+    #[arg(long)]
+    strand_column: Option<usize>,
+    /// Read the scores as decimal numbers and write them with this many decimals (at most 6).
+    #[arg(long)]
+    score_precision: Option<u32>,
+    /// Allow comma separated score pairs (e.g. "3,5") and use their "first" or "second" value as score.
+    #[arg(long)]
+    score_pair: Option<String>,
+}
+
+impl FormatArgs {
+    fn fragment_format(&self) -> FragmentToolsResult<FragmentFormat> {
+        let columns = FragmentColumns::new(
+            self.chrom_column,
+            self.start_column,
+            self.end_column,
+            self.barcode_column,
+            self.score_column,
+            self.strand_column,
+        )
+        .map_err(FragmentToolsError::InvalidArgument)?;
+        let format = FragmentFormat::new(
+            &self.delimiter,
+            self.strip_quotes,
+            self.barcode_tag.as_deref(),
+        )
+        .map_err(FragmentToolsError::InvalidArgument)?;
+        format
+            .with_columns(columns)
+            .with_score_precision(self.score_precision)
+            .and_then(|format| {
+                format.with_score_pair(
+                    self.score_pair
+                        .as_deref()
+                        .map(ScorePairValue::parse)
+                        .transpose()?,
+                )
+            })
+            .map_err(FragmentToolsError::InvalidArgument)
+    }
+}
+
+/// Parses a shift of the start and end of fragments, given as "START,END" (e.g. "4,-5").
+fn parse_shift(s: &str) -> Result<(i64, i64), String> {
+    s.split(',')
+        .map(|shift| shift.trim().parse::<i64>().ok())
+        .collect::<Option<Vec<i64>>>()
+        .and_then(|shifts| shifts.into_iter().collect_tuple())
+        .ok_or_else(|| format!("Invalid shift {:?}, should be START,END (e.g. \"4,-5\")", s))
+}
+
+/// Reads a chromsizes file: a TSV file without header, with chromosome names and sizes.
+///
+/// # Arguments
+///
+/// * `path_to_chromsizes` - Path to the chromsizes file.
+fn read_chromsizes(path_to_chromsizes: &str) -> FragmentToolsResult<HashMap<String, u64>> {
+    let content = read_to_string(path_to_chromsizes).map_err(|e| {
+        FragmentToolsError::InvalidArgument(format!(
+            "Could not read chromsizes file {}: {}",
+            path_to_chromsizes, e
+        ))
+    })?;
+    let mut chromsizes: HashMap<String, u64> = HashMap::new();
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let (chromosome, size) = line
+            .split('\t')
+            .next_tuple()
+            .and_then(|(chromosome, size)| Some((chromosome, size.parse::<u64>().ok()?)))
+            .ok_or_else(|| {
+                FragmentToolsError::InvalidArgument(format!(
+                    "Invalid line in chromsizes file {}: {:?}",
+                    path_to_chromsizes, line
+                ))
+            })?;
+        if chromsizes.insert(chromosome.to_string(), size).is_some() {
+            return Err(FragmentToolsError::InvalidArgument(format!(
+                "Duplicates in {}, for chromosome {}",
+                path_to_chromsizes, chromosome
+            )));
+        }
+    }
+    Ok(chromsizes)
+}
+
+/// Reads an annotation file: a TSV file with a header, with (at least) a cell type and a cell barcode column.
+///
+/// # Arguments
+///
+/// * `path_to_annotation` - Path to the annotation file.
+/// * `cell_type_column` - Name of the cell type column.
+/// * `cell_barcode_column` - Name of the cell barcode column.
+/// * `sample` - If set, the name of the sample column and the sample of which the rows are used.
+///
+/// # Returns
+///
+/// A HashMap mapping cell barcodes to cell types.
+fn read_annotation(
+    path_to_annotation: &str,
+    cell_type_column: &str,
+    cell_barcode_column: &str,
+    sample: Option<(&str, &str)>,
+) -> FragmentToolsResult<HashMap<String, Vec<String>>> {
+    let content = read_to_string(path_to_annotation).map_err(|e| {
+        FragmentToolsError::InvalidArgument(format!(
+            "Could not read annotation file {}: {}",
+            path_to_annotation, e
+        ))
+    })?;
+    let mut lines = content.lines().filter(|line| !line.is_empty());
+    let header: Vec<&str> = lines.next().unwrap_or_default().split('\t').collect();
+    let column_index = |column: &str| {
+        header
+            .iter()
+            .position(|name| *name == column)
+            .ok_or_else(|| {
+                FragmentToolsError::InvalidArgument(format!(
+                    "Annotation file {} does not have a column {}",
+                    path_to_annotation, column
+                ))
+            })
+    };
+    let cell_type_index = column_index(cell_type_column)?;
+    let cell_barcode_index = column_index(cell_barcode_column)?;
+    let sample = sample
+        .map(|(sample_column, sample)| Ok((column_index(sample_column)?, sample)))
+        .transpose()?;
+
+    let mut cell_barcode_to_cell_type: HashMap<String, Vec<String>> = HashMap::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if let Some((sample_index, sample)) = sample {
+            if fields.get(sample_index) != Some(&sample) {
+                continue;
+            }
+        }
+        match (fields.get(cell_type_index), fields.get(cell_barcode_index)) {
+            (Some(cell_type), Some(cell_barcode)) => cell_barcode_to_cell_type
+                .entry(cell_barcode.to_string())
+                .or_default()
+                .push(cell_type.to_string()),
+            _ => {
+                return Err(FragmentToolsError::InvalidArgument(format!(
+                    "Invalid line in annotation file {}: {:?}",
+                    path_to_annotation, line
+                )))
+            }
+        }
+    }
+    Ok(cell_barcode_to_cell_type)
+}
+
+fn run(cli: Cli) -> FragmentToolsResult<()> {
+    match cli.command {
+        Command::Split {
+            fragments,
+            annotation,
+            chromsizes,
+            output_folder,
+            sample,
+            sample_column,
+            cell_type_column,
+            cell_barcode_column,
+            threads,
+            writer_pool_strategy,
+            browser_optimized,
+            create_index,
+            strip_barcode_prefix,
+            strip_barcode_suffix,
+            add_sample_prefix,
+            contig_name_mode,
+            shift,
+            checksums,
+            print_counts,
+            length_histogram_bin_size,
+            length_histogram_max_length,
+            unassigned_output,
+            score_predicate,
+            min_score,
+            missing_score_passes,
+            barcode_tag,
+            assignment,
+            tar_archive,
+            counts_file,
+            output_codec,
+            output_extension,
+            duplicate_handling,
+            zero_length,
+            contig_order,
+            blacklist,
+            group_by,
+            max_open_files,
+            score_precision,
+            score_pair,
+            expected_cell_types,
+            verbose,
+        } => {
+            let score_predicate =
+                ScorePredicate::from_predicate_or_min_score(score_predicate.as_deref(), min_score)
+                    .map_err(FragmentToolsError::InvalidArgument)?;
+            let barcode_transform = (strip_barcode_prefix.is_some()
+                || strip_barcode_suffix.is_some()
+                || add_sample_prefix.is_some())
+            .then_some(BarcodeTransform {
+                strip_prefix: strip_barcode_prefix,
+                strip_suffix: strip_barcode_suffix,
+                add_prefix: add_sample_prefix,
+            });
+            let options = SplitOptions {
+                number_of_threads: threads,
+                writer_pool_strategy: WriterPoolStrategy::parse(&writer_pool_strategy)
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                browser_optimized,
+                create_index,
+                barcode_transform: barcode_transform.as_ref(),
+                contig_name_mode: ContigNameMode::parse(&contig_name_mode)
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                shift,
+                compute_checksums: checksums,
+                score_predicate: score_predicate.as_ref(),
+                missing_score_passes,
+                barcode_tag: barcode_tag.as_deref(),
+                assignment: CellTypeAssignment::parse(&assignment)
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                path_to_tar_archive: tar_archive.as_deref(),
+                path_to_counts_file: counts_file.as_deref(),
+                output_codec: OutputCodec::parse(&output_codec)
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                output_extension: output_extension.as_deref(),
+                duplicate_handling: DuplicateHandling::parse(&duplicate_handling)
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                zero_length_handling: ZeroLengthHandling::parse(&zero_length)
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                contig_order: contig_order.as_deref(),
+                path_to_blacklist: blacklist.as_deref(),
+                group_by: SplitGroupBy::parse(&group_by)
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                max_open_files,
+                score_precision,
+                score_pair: score_pair
+                    .as_deref()
+                    .map(ScorePairValue::parse)
+                    .transpose()
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                expected_cell_types,
+                length_histogram: length_histogram_bin_size.map(|bin_size| LengthHistogramBins {
+                    bin_size,
+                    max_length: length_histogram_max_length,
+                }),
+                path_to_unassigned_output: unassigned_output.as_deref(),
+                verbose,
+                ..Default::default()
+            };
+            let summary = split_fragments_by_cell_barcode(
+                &fragments,
+                &output_folder,
+                read_annotation(
+                    &annotation,
+                    &cell_type_column,
+                    &cell_barcode_column,
+                    sample
+                        .as_deref()
+                        .map(|sample| (sample_column.as_str(), sample)),
+                )?,
+                chromsizes
+                    .map(|chromsizes| read_chromsizes(&chromsizes))
+                    .transpose()?
+                    .unwrap_or_default(),
+                &options,
+            )?;
+            if let Some(checksums) = summary.checksums {
+                for (cell_type, checksum) in checksums.iter().sorted() {
+                    println!("{}\t{}", cell_type, checksum);
+                }
+            }
+            if print_counts {
+                println!("cell_type\tfragments");
+                for (cell_type, number_of_fragments) in
+                    summary.fragments_per_cell_type.iter().sorted()
+                {
+                    println!("{}\t{}", cell_type, number_of_fragments);
+                }
+            }
+            if let (Some(bin_size), Some(length_histograms)) =
+                (length_histogram_bin_size, summary.length_histograms)
+            {
+                // the last bin counts the fragments of at least the maximum length
+                println!("cell_type\tmin_length\tfragments");
+                for (cell_type, histogram) in length_histograms.iter().sorted() {
+                    for (bin, number_of_fragments) in histogram.iter().enumerate() {
+                        println!(
+                            "{}\t{}\t{}",
+                            cell_type,
+                            bin as u64 * bin_size,
+                            number_of_fragments
+                        );
+                    }
+                }
+            }
+        }
+        Command::Aggregate {
+            fragments,
+            output,
+            threads,
+            max_open_files,
+            memory_mode,
+            output_codec,
+            add_fragment_ids,
+            normalize_columns,
+            missing_score,
+            duplicate_handling,
+            zero_length,
+            memory_map_inputs,
+            allow_duplicate_inputs,
+            n_output_shards,
+            merge_contigs_in_parallel,
+            min_score,
+            missing_score_passes,
+            collapse_duplicates,
+            shift,
+            format,
+            verbose,
+        } => {
+            let memory_mode =
+                MemoryMode::parse(&memory_mode).map_err(FragmentToolsError::InvalidArgument)?;
+            let options = MergeOptions {
+                format: format.fragment_format()?,
+                max_open_files: max_open_files.unwrap_or(memory_mode.max_open_files()),
+                output_codec: OutputCodec::parse(&output_codec)
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                add_fragment_ids,
+                normalize_columns: ColumnNormalization::parse(&normalize_columns, missing_score)
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                duplicate_handling: DuplicateHandling::from_handling_or_collapse_duplicates(
+                    &duplicate_handling,
+                    collapse_duplicates,
+                )
+                .map_err(FragmentToolsError::InvalidArgument)?,
+                zero_length_handling: ZeroLengthHandling::parse(&zero_length)
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                memory_map_inputs,
+                allow_duplicate_inputs,
+                n_output_shards,
+                merge_contigs_in_parallel,
+                score_predicate: ScorePredicate::from_predicate_or_min_score(None, min_score)
+                    .map_err(FragmentToolsError::InvalidArgument)?,
+                missing_score_passes,
+                shift,
+                number_of_threads: threads,
+                verbose,
+                ..MergeOptions::with_memory_mode(memory_mode)
+            };
+            merge_fragment_files(&fragments, &output, &options)?;
+        }
+        Command::Validate {
+            fragments,
+            chromsizes,
+            format,
+            threads,
+            unsorted_tolerance,
+            verbose,
+        } => {
+            let format = format.fragment_format()?;
+            let chromsizes = chromsizes
+                .map(|chromsizes| read_chromsizes(&chromsizes))
+                .transpose()?;
+            let report = validate_fragment_file(
+                &fragments,
+                &format,
+                chromsizes.as_ref(),
+                unsorted_tolerance,
+                threads,
+                verbose,
+            )?;
+            for contig in report.contig_order.iter() {
+                println!("{}\t{}", contig, report.fragments_per_contig[contig]);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
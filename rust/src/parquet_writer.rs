@@ -0,0 +1,204 @@
+use crate::aggregate_fragments::temporary_path;
+use crate::custom_errors::{FragmentToolsError, FragmentToolsResult};
+use crate::fragment::Fragment;
+use arrow_array::builder::{ArrayBuilder, StringBuilder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::fs::{rename, File};
+use std::sync::Arc;
+
+/// Number of fragments which are buffered before they are passed to the Parquet writer.
+const BATCH_SIZE: usize = 65_536;
+
+/// Codec of output fragment files.
+///
+/// # Variants
+///
+/// * `Bgzf` - BGZF compressed TSV file (`.fragments.tsv.gz`).
+/// * `Parquet` - Parquet file (`.fragments.parquet`) with columns `chrom`, `start`, `end`,
+///     `barcode`, `score` and, for files per cell type, `cell_type`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OutputCodec {
+    Bgzf,
+    Parquet,
+}
+
+impl OutputCodec {
+    /// Parse an output codec ("bgzf" or "parquet").
+    pub fn parse(s: &str) -> Result<OutputCodec, String> {
+        match s {
+            "bgzf" => Ok(OutputCodec::Bgzf),
+            "parquet" => Ok(OutputCodec::Parquet),
+            _ => Err(format!(
+                "Invalid output codec {:?}, should be one of \"bgzf\" or \"parquet\"",
+                s
+            )),
+        }
+    }
+
+    /// Returns the file extension of fragment files written with this codec.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputCodec::Bgzf => "fragments.tsv.gz",
+            OutputCodec::Parquet => "fragments.parquet",
+        }
+    }
+}
+
+/// A lazy Parquet writer for fragments, that only opens the file when the first fragment is written.
+///
+/// Fragments have to be written sorted by contig, each row group only contains fragments of one contig.
+/// Like `LazyBgzfWriter`, the file is written to a temporary path and only moved to its final path by `finish`.
+///
+/// # Fields
+///
+/// * `writer` - The Parquet writer.
+/// * `path` - The (final) path to the file.
+/// * `cell_type` - If set, the value of the `cell_type` column.
+/// * `contig` - Contig of the current row group.
+/// * `chrom`, `start`, `end`, `barcode`, `score` - Buffered columns, not yet passed to the writer.
+pub(crate) struct ParquetFragmentWriter {
+    writer: Option<ArrowWriter<File>>,
+    pub path: String,
+    cell_type: Option<String>,
+    contig: Option<String>,
+    chrom: StringBuilder,
+    start: UInt64Builder,
+    end: UInt64Builder,
+    barcode: StringBuilder,
+    score: UInt64Builder,
+}
+
+impl ParquetFragmentWriter {
+    /// Creates a new ParquetFragmentWriter.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the file.
+    /// * `cell_type` - If set, a `cell_type` column with this value is written.
+    pub fn new(path: String, cell_type: Option<String>) -> ParquetFragmentWriter {
+        ParquetFragmentWriter {
+            writer: None,
+            path,
+            cell_type,
+            contig: None,
+            chrom: StringBuilder::new(),
+            start: UInt64Builder::new(),
+            end: UInt64Builder::new(),
+            barcode: StringBuilder::new(),
+            score: UInt64Builder::new(),
+        }
+    }
+
+    /// Whether the file has been opened yet.
+    pub fn written(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    fn schema(&self) -> SchemaRef {
+        let mut fields = vec![
+            Field::new("chrom", DataType::Utf8, false),
+            Field::new("start", DataType::UInt64, false),
+            Field::new("end", DataType::UInt64, false),
+            Field::new("barcode", DataType::Utf8, false),
+            Field::new("score", DataType::UInt64, true),
+        ];
+        if self.cell_type.is_some() {
+            fields.push(Field::new("cell_type", DataType::Utf8, false));
+        }
+        Arc::new(Schema::new(fields))
+    }
+
+    fn write_error(&self, e: impl std::fmt::Display) -> FragmentToolsError {
+        FragmentToolsError::Io(format!("Could not write to file {}: {}", self.path, e))
+    }
+
+    /// Opens the file, if it has not been opened yet.
+    pub fn open(&mut self) -> FragmentToolsResult<()> {
+        if self.writer.is_none() {
+            let file = File::create(temporary_path(&self.path)).map_err(|_| {
+                FragmentToolsError::Io(format!("Could not open file {} for writing", self.path))
+            })?;
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build();
+            let writer = ArrowWriter::try_new(file, self.schema(), Some(properties))
+                .map_err(|e| self.write_error(e))?;
+            self.writer = Some(writer);
+        }
+        Ok(())
+    }
+
+    /// Opens the file, if it has not been opened yet, and writes a fragment to it.
+    ///
+    /// When the fragment is on another contig than the previous one, a new row group is started.
+    ///
+    /// # Arguments
+    ///
+    /// * `fragment` - The fragment to write.
+    pub fn write(&mut self, fragment: &Fragment) -> FragmentToolsResult<()> {
+        self.open()?;
+        if self.contig.as_ref() != Some(&fragment.chrom) {
+            self.finish_row_group()?;
+            self.contig = Some(fragment.chrom.clone());
+        }
+        self.chrom.append_value(&fragment.chrom);
+        self.start.append_value(fragment.start as u64);
+        self.end.append_value(fragment.end as u64);
+        self.barcode.append_value(&fragment.cell_barcode);
+        self.score
+            .append_option(fragment.score.map(|score| score as u64));
+        if self.chrom.len() >= BATCH_SIZE {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Passes the buffered fragments to the Parquet writer.
+    fn write_batch(&mut self) -> FragmentToolsResult<()> {
+        let number_of_fragments = self.chrom.len();
+        if number_of_fragments == 0 {
+            return Ok(());
+        }
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(self.chrom.finish()),
+            Arc::new(self.start.finish()),
+            Arc::new(self.end.finish()),
+            Arc::new(self.barcode.finish()),
+            Arc::new(self.score.finish()),
+        ];
+        if let Some(cell_type) = &self.cell_type {
+            columns.push(Arc::new(StringArray::from(vec![
+                cell_type.as_str();
+                number_of_fragments
+            ])));
+        }
+        let batch =
+            RecordBatch::try_new(self.schema(), columns).map_err(|e| self.write_error(e))?;
+        let result = self.writer.as_mut().unwrap().write(&batch);
+        result.map_err(|e| self.write_error(e))
+    }
+
+    /// Writes the buffered fragments and closes the current row group.
+    fn finish_row_group(&mut self) -> FragmentToolsResult<()> {
+        self.write_batch()?;
+        if let Some(writer) = self.writer.as_mut() {
+            let result = writer.flush();
+            result.map_err(|e| self.write_error(e))?;
+        }
+        Ok(())
+    }
+
+    /// Closes the file, if it was opened, and moves it to its final path.
+    pub fn finish(mut self) -> FragmentToolsResult<()> {
+        self.write_batch()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close().map_err(|e| self.write_error(e))?;
+            rename(temporary_path(&self.path), &self.path).map_err(|e| self.write_error(e))?;
+        }
+        Ok(())
+    }
+}
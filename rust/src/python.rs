@@ -0,0 +1,1912 @@
+use crate::arrow_output::{self, ResultFormat};
+use crate::custom_errors::{FragmentToolsError, FragmentToolsResult, InvalidFragmentFileError};
+use crate::fragment::{
+    BarcodeRename, BarcodeTransform, ContigNameMode, DuplicateHandling, Fragment, FragmentColumns,
+    FragmentFormat, ScorePairValue, ScorePredicate, TabixColumns, ZeroLengthHandling,
+};
+use crate::parquet_writer::OutputCodec;
+use crate::summary::{
+    FragmentFileStats, MergeSummary, SplitCompletenessReport, SplitSizeEstimate, SplitSummary,
+    ValidationReport,
+};
+use crate::{
+    aggregate_fragments, barcodes, convert_fragments, coverage, file_stats, split_fragments,
+    validate,
+};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::collections::{HashMap, HashSet};
+
+/// Invert a HashMap mapping cell types to cell barcodes,
+/// into a HashMap mapping cell barcodes to cell types.
+fn invert_cell_type_to_cell_barcodes(
+    cell_type_to_cell_barcodes: &HashMap<String, Vec<String>>,
+) -> HashMap<String, Vec<String>> {
+    let mut cell_barcode_to_cell_type: HashMap<String, Vec<String>> = HashMap::new();
+    for (cell_type, cell_barcodes) in cell_type_to_cell_barcodes.iter() {
+        for cell_barcode in cell_barcodes.iter() {
+            cell_barcode_to_cell_type
+                .entry(cell_barcode.to_string())
+                .or_default()
+                .push(cell_type.to_string());
+        }
+    }
+    cell_barcode_to_cell_type
+}
+
+/// Converts the message of an invalid argument into a ValueError, with `kind` `"invalid_argument"`.
+fn invalid_argument(message: String) -> PyErr {
+    FragmentToolsError::InvalidArgument(message).into()
+}
+
+/// Split fragments by cell barcode.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `path_to_output_folder` - Path to the output folder,
+///    one file per cell type will be written here and the cell type name will be used as the filename,
+///    with spaces and slashes replaced by underscores. Cell types which get the same filename are an error.
+///    If there are no fragments for a cell type, no file will be written for that cell type.
+/// * `cell_type_to_cell_barcodes` - A HashMap mapping cell types to cell barcodes.
+///    Should be empty when `cell_barcodes` and `cell_types` or `path_to_barcode_tsv` are given.
+/// * `chromsizes` - A HashMap mapping chromosome names to chromosome sizes.
+///    If empty, all contigs of the fragments file (as listed in its index) are processed.
+/// * `verbose` - Whether to print progress messages.
+/// * `compute_checksums` - Whether to compute a SHA-256 checksum of the uncompressed content of each output file.
+///    The checksum does not depend on how the file was compressed,
+///    so it can be used to verify that two runs produced identical fragments.
+/// * `fragment_filter` - Optional Python callable, called for each fragment of a selected cell barcode
+///    with `(chrom, start, end, cell_barcode, score)`. Only fragments for which it returns True are written.
+///    Calling Python for every fragment is slow, so this should only be used when no built-in option
+///    does the same filtering.
+/// * `score_predicate` - If set, only fragments with a score satisfying this predicate are written.
+///    Either a range with an exclusive (`"2..10"`) or inclusive (`"2..=10"`) end, of which the start
+///    or end can be left out (`"5.."`), or a comma separated set of scores (`"1,2,5"`).
+/// * `missing_score_passes` - Whether fragments without a score are written when `score_predicate` is set.
+/// * `barcode_tag` - If set, the cell barcode column contains a SAM-style tag with this name
+///    (e.g. `"CB"` for `CB:Z:AACATCGATGGATG-1`), of which the value is used to look up the cell type.
+///    The fragments are written unchanged.
+/// * `strand_column` - If set, column (0-based) of the strand (`+`, `-` or `.`), for fragment files with
+///    a strand column after the score. Fragments are written with their strand. Not included in Parquet output.
+/// * `assignment` - How fragments of a cell barcode which maps to several cell types are written:
+///    `"all"` writes them to every cell type, `"first"` only to the first cell type (sorted by name)
+///    and `"error"` raises a ValueError before splitting.
+/// * `path_to_tar_archive` - If set, the files per cell type are written into this (uncompressed) tar archive,
+///    with members named like the files (`{cell_type}.fragments.tsv.gz`), instead of being kept as separate files.
+///    The output folder is then only used to write the files temporarily.
+/// * `output_codec` - Codec of the files per cell type: `"bgzf"` writes BGZF compressed TSV files
+///    (`{cell_type}.fragments.tsv.gz`), `"parquet"` writes Parquet files (`{cell_type}.fragments.parquet`)
+///    with columns `chrom`, `start`, `end`, `barcode`, `score` and `cell_type`, with one or more
+///    row groups per contig. Checksums can only be computed for `"bgzf"`.
+/// * `output_extension` - If set, the extension of the files per cell type (without leading dot),
+///    instead of the default extension of `output_codec`, e.g. `"fragments.tsv.bgz"`.
+///    Only the file names change, not the content.
+/// * `barcode_rename` - If set, a dictionary mapping cell barcodes to new names, e.g. sample-specific IDs,
+///    which are written instead of the original cell barcodes. Cell barcodes without a new name are written
+///    unchanged, or their fragments are dropped if `drop_unrenamed_barcodes` is set.
+/// * `drop_unrenamed_barcodes` - Whether fragments of cell barcodes which are not in `barcode_rename` are dropped.
+///    Cell types are looked up with the original cell barcodes.
+/// * `file_contigs` - If set, the contigs of the fragments file, which are then not read from its index.
+///    This saves time for files with many contigs when the contigs are already known.
+///    The contigs are trusted: fetching a contig which is not in the index raises an `InvalidFragmentFileError`.
+/// * `number_of_threads` - Number of threads to use for writing, in total or per file depending on
+///    `writer_pool_strategy`.
+/// * `comment_char` - Lines starting with this character are header lines, None if there are none.
+///    If the index was created with another comment character, tabix lists header lines as records
+///    on contigs starting with this character: these are skipped (with a warning).
+///    Raises an `InvalidFragmentFileError` if a header line is still read as a fragment.
+/// * `duplicate_handling` - How fragments with the same chromosome, start, end and cell barcode are written:
+///    `"keep"` writes all of them, `"collapse_sum_score"` writes them once with the sum of their scores,
+///    `"collapse_sum_reads"` does the same but counts a missing score as one read
+///    and `"collapse_count"` writes them once with their number as score.
+///    Duplicates are collapsed per cell type, collapsed fragments are written with the standard columns.
+/// * `split_regex` - If set, a regular expression with at least one capture group, e.g. `"-(sample[A-Z])$"`.
+///    The fragments are split by the value of its first group in the cell barcode instead of by cell type,
+///    `cell_type_to_cell_barcodes` should then be empty. This splits fragment files of several samples,
+///    of which the sample is encoded in the cell barcodes, without listing the cell barcodes.
+///    Fragments of cell barcodes which do not match are not written.
+///    Raises a ValueError if the regular expression is invalid or has no capture group.
+/// * `writer_pool_strategy` - How the files per cell type get threads to compress their output:
+///    `"shared"` uses one pool of `number_of_threads` threads for all files, `"per_writer"` gives each file
+///    its own pool of `number_of_threads` threads. Separate pools avoid that files wait on each other's
+///    compression jobs, but start a pool per cell type with fragments, so use few threads per file.
+/// * `path_to_counts_file` - If set, a TSV file (columns `cell_type` and `fragments`) with the number of fragments
+///    written per cell type so far. It is replaced after each contig, so it can be followed during long runs,
+///    and is never read partially written.
+/// * `browser_optimized` - Write the files per cell type for genome browsers (IGV, UCSC): in BGZF blocks
+///    of 16 KiB (instead of ~64 KiB), so a browser decompresses less data per displayed region,
+///    at compression level 6, and each with a tabix index (`.tbi`). Requires `output_codec="bgzf"`.
+/// * `cell_barcodes`, `cell_types` - If set, parallel lists of cell barcodes and their cell types,
+///    e.g. `list(adata.obs_names)` and `list(adata.obs["cell_type"])`, used instead of
+///    `cell_type_to_cell_barcodes`, which avoids building a dictionary per cell type in Python.
+///    Repeated pairs of a cell barcode and cell type are only used once.
+/// * `zero_length` - How fragments which start where they end are handled: `"keep"` writes them,
+///    `"drop"` skips them (their number is reported in the summary) and `"error"` raises an
+///    `InvalidFragmentFileError`.
+/// * `contig_order` - If set, the contigs in the order in which they are processed and written.
+///    Only these contigs are written (when they are in the fragments file). A contig which is
+///    not in `chromsizes` raises a `ValueError`.
+/// * `path_to_blacklist` - If set, path to a BED file with blacklist regions (plain or gzip compressed),
+///    e.g. the ENCODE blacklist. Fragments overlapping any of these regions are not written.
+/// * `group_by` - `"cell_type"` writes one file per cell type, `"barcode"` one file per cell barcode,
+///    named after the (sanitized) cell barcode. When grouping by barcode, the cell barcodes are only used
+///    as whitelist (their cell types are ignored) and they take the place of the cell types in the summary.
+/// * `max_open_files` - Maximum number of files to write at the same time, as they are kept open while splitting.
+///    When grouping by barcode, the fragments file is read once for each batch of this many (by default 1000)
+///    cell barcodes, which can not be combined with `path_to_unassigned_output`, `path_to_counts_file` or
+///    `return_partial_on_error`. When grouping by cell type, there is no limit by default and more cell types
+///    raise a `ValueError`. Raise the limit of open files of the process (`ulimit -n`) before raising this number.
+/// * `score_precision` - If set, scores are decimal numbers (e.g. weights), written with this many decimals
+///    (at most 6), also when duplicates are summed with `duplicate_handling="collapse_sum_score"`.
+///    `fragment_filter` gets these scores as integers in units of `10**-score_precision`.
+///    Can not be combined with `score_predicate` or `output_codec="parquet"`.
+/// * `score_pair` - If set, the score column can also contain a comma separated pair of integers (e.g. `3,5`),
+///    of which the `"first"` or `"second"` value is used as score by `score_predicate`, `fragment_filter`,
+///    `duplicate_handling` and in Parquet output. Pairs are written unchanged, unless duplicates are collapsed.
+///    Can not be combined with `score_precision`.
+/// * `expected_cell_types` - If set, the number of cell types the annotation should have. A warning is printed
+///    when the number of cell types differs more than 50% from it, e.g. when sample labels were given instead
+///    of cell types.
+/// * `return_partial_on_error` - Whether to return a partial `SplitSummary` instead of raising when splitting stops
+///    while processing the contigs, e.g. on Ctrl-C (which is checked before each contig and every million fragments),
+///    an exception raised by `fragment_filter` or a malformed fragment. The files written so far are valid, but
+///    get a `.partial` suffix: they have the fragments of `completed_contigs` and possibly some of the contig on which
+///    splitting stopped. They are not indexed or archived. Splitting can be resumed for the remaining contigs
+///    with `contig_order`.
+/// * `create_index` - Whether to create a tabix index (`.tbi`) for each file per cell type, so it can be queried
+///    by region right away (e.g. by pycisTopic). Requires `output_codec="bgzf"`.
+/// * `strip_barcode_prefix`, `strip_barcode_suffix`, `add_sample_prefix` - If set, the cell barcodes of the fragments
+///    file are transformed before they are looked up in the annotation: the prefix and suffix are removed (when
+///    present), then the sample prefix is added. E.g. `strip_barcode_suffix="-1"` and `add_sample_prefix="sample1:"`
+///    look up `AACGATG-1` as `sample1:AACGATG`. The fragments are written with their original cell barcode,
+///    `barcode_rename` (with the transformed cell barcodes as keys) can change them.
+/// * `min_score` - If set, only fragments with at least this score are written, e.g. `2` to drop fragments supported
+///    by a single read. Fragments without score are dropped, unless `missing_score_passes` is set.
+///    Short for `score_predicate=f"{min_score}.."`, with which it can not be combined.
+/// * `contig_name_mode` - How the contig names of `chromsizes`, `contig_order` and the blacklist are normalized
+///    before they are compared with the contigs of the fragments file: `"keep"` uses them as is, `"add_chr"` adds
+///    a `chr` prefix to contig names without one and `"strip_chr"` removes the `chr` prefix.
+///    A warning is printed when none of the contigs of `chromsizes` are in the fragments file.
+/// * `shift` - If set, a tuple with the number of bp by which the start and end of each written fragment
+///    are moved, e.g. `(4, -5)` for the Tn5 insertion. Positions are clamped between 0 and the contig size.
+///    Shifted fragments are written with the standard columns.
+/// * `progress_callback` - Optional Python callable, called after each contig is written with
+///    `(cell_type, contig, fragments_written)` for each cell type (also when no fragments were written),
+///    e.g. to update a progress bar. An exception raised by it stops splitting, like one of `fragment_filter`.
+/// * `length_histogram_bin_size` - If set, a histogram of the lengths (end - start) of the written fragments
+///    is counted per cell type in bins of this many bp, e.g. `10` to check the nucleosome banding.
+/// * `length_histogram_max_length` - Fragments of at least this length are counted in the last bin
+///    of the length histograms. Should be a multiple of `length_histogram_bin_size`.
+/// * `path_to_barcode_tsv` - Instead of `cell_type_to_cell_barcodes`, path to a (gzip compressed) TSV file
+///    with two columns, the cell barcode and the cell type, without header. It is read line by line,
+///    so large barcode tables don't have to be loaded in Python first. Lines starting with `#` are skipped.
+/// * `path_to_unassigned_output` - If set, the fragments of cell barcodes without cell type are written as read
+///    to this (BGZF compressed) file, e.g. to check that the cell barcodes of the annotation match those of the
+///    fragments file. The file is only created when there are such fragments.
+///
+/// # Returns
+///
+/// A `SplitSummary` with attributes:
+/// * `contig_order` - The contigs in the order in which they were written: the given `contig_order`,
+///    otherwise the contigs of chromsizes which are in the fragments file, sorted lexicographically
+///    (chr1, chr10, chr2).
+/// * `checksums` - If `compute_checksums` is set, a dictionary mapping cell types to the (hex encoded) checksums
+///    of their output files, otherwise None.
+/// * `distinct_barcodes` - A dictionary mapping cell types to the number of distinct cell barcodes of the
+///    fragments written for them. Cell types without fragments are not included.
+/// * `truncated_file_names` - A dictionary mapping cell types longer than 200 bytes to the name of their
+///    output files (without extension): the first 200 bytes of the cell type, followed by `_` and 8 hex
+///    characters of its SHA-256 hash, which keeps the file names of similar long cell types unique.
+/// * `zero_length_fragments` - The number of dropped zero-length fragments of the annotated cell barcodes.
+/// * `blacklisted_fragments` - The number of dropped fragments of the annotated cell barcodes
+///    which overlap a blacklist region.
+/// * `completed_contigs` - The contigs of which all fragments were written.
+/// * `written_fragments_per_contig` - A dictionary mapping the completed contigs to the number of fragments
+///    written for them (a fragment written for several cell types is counted for each of them).
+/// * `fragments_per_cell_type` - A dictionary mapping all cell types to the number of fragments written to their
+///    output file (0 when no file was written), fragments of cell barcodes with several cell types count for each.
+/// * `error` - If a partial summary was returned (see `return_partial_on_error`), the message of the error
+///    on which splitting stopped, otherwise None.
+/// * `output_files` - A list of `(cell_type, path)` tuples of the files which were written, sorted by cell type.
+///    Cell types without fragments have no file and are not included. Use these paths instead of deriving
+///    the file names from the cell types, as the cell types are sanitized (and long ones truncated) for them.
+/// * `length_histograms` - If `length_histogram_bin_size` is set, a dictionary mapping all cell types to a list with
+///    the number of written fragments per length bin: `[0, bin_size)`, `[bin_size, 2 * bin_size)`, ...
+///    up to `length_histogram_max_length`, followed by the number of longer fragments. Otherwise None.
+/// * `unassigned_fragments` - The number of fragments written to `path_to_unassigned_output` (0 when it is not set).
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+///     path_to_fragments="fragments.tsv.gz",
+///     path_to_output_folder="fragments_by_cell_type",
+///     cell_type_to_cell_barcodes={
+///         "cell_type_1": ["AACATCGATGGATG-1", "AACATCGATGGTTG-1"],
+///         "cell_type_2": ["TTGATCGATGGATG-1", "AACATCGCTAGATG-1"]
+///     },
+///     chromsizes={
+///         "chr1": 248956422,
+///         "chr2": 242193529
+///     },
+///     verbose=True
+/// )
+/// ```
+
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragments,
+    path_to_output_folder,
+    cell_type_to_cell_barcodes,
+    chromsizes,
+    verbose,
+    compute_checksums = false,
+    fragment_filter = None,
+    score_predicate = None,
+    missing_score_passes = false,
+    barcode_tag = None,
+    assignment = "all",
+    path_to_tar_archive = None,
+    output_codec = "bgzf",
+    file_contigs = None,
+    number_of_threads = 5,
+    comment_char = Some('#'),
+    duplicate_handling = "keep",
+    output_extension = None,
+    barcode_rename = None,
+    drop_unrenamed_barcodes = false,
+    split_regex = None,
+    writer_pool_strategy = "shared",
+    strand_column = None,
+    path_to_counts_file = None,
+    browser_optimized = false,
+    cell_barcodes = None,
+    cell_types = None,
+    zero_length = "drop",
+    contig_order = None,
+    path_to_blacklist = None,
+    group_by = "cell_type",
+    max_open_files = None,
+    score_precision = None,
+    score_pair = None,
+    expected_cell_types = None,
+    return_partial_on_error = false,
+    create_index = false,
+    strip_barcode_prefix = None,
+    strip_barcode_suffix = None,
+    add_sample_prefix = None,
+    min_score = None,
+    contig_name_mode = "keep",
+    shift = None,
+    progress_callback = None,
+    length_histogram_bin_size = None,
+    length_histogram_max_length = 1000,
+    path_to_barcode_tsv = None,
+    path_to_unassigned_output = None
+))]
+#[allow(clippy::too_many_arguments)]
+fn split_fragments_by_cell_barcode(
+    py: Python<'_>,
+    path_to_fragments: String,
+    path_to_output_folder: String,
+    cell_type_to_cell_barcodes: HashMap<String, Vec<String>>,
+    chromsizes: HashMap<String, u64>,
+    verbose: bool,
+    compute_checksums: bool,
+    fragment_filter: Option<PyObject>,
+    score_predicate: Option<String>,
+    missing_score_passes: bool,
+    barcode_tag: Option<String>,
+    assignment: &str,
+    path_to_tar_archive: Option<String>,
+    output_codec: &str,
+    file_contigs: Option<Vec<String>>,
+    number_of_threads: u32,
+    comment_char: Option<char>,
+    duplicate_handling: &str,
+    output_extension: Option<String>,
+    barcode_rename: Option<HashMap<String, String>>,
+    drop_unrenamed_barcodes: bool,
+    split_regex: Option<String>,
+    writer_pool_strategy: &str,
+    strand_column: Option<usize>,
+    path_to_counts_file: Option<String>,
+    browser_optimized: bool,
+    cell_barcodes: Option<Vec<String>>,
+    cell_types: Option<Vec<String>>,
+    zero_length: &str,
+    contig_order: Option<Vec<String>>,
+    path_to_blacklist: Option<String>,
+    group_by: &str,
+    max_open_files: Option<usize>,
+    score_precision: Option<u32>,
+    score_pair: Option<&str>,
+    expected_cell_types: Option<usize>,
+    return_partial_on_error: bool,
+    create_index: bool,
+    strip_barcode_prefix: Option<String>,
+    strip_barcode_suffix: Option<String>,
+    add_sample_prefix: Option<String>,
+    min_score: Option<usize>,
+    contig_name_mode: &str,
+    shift: Option<(i64, i64)>,
+    progress_callback: Option<PyObject>,
+    length_histogram_bin_size: Option<u64>,
+    length_histogram_max_length: u64,
+    path_to_barcode_tsv: Option<String>,
+    path_to_unassigned_output: Option<String>,
+) -> PyResult<SplitSummary> {
+    let assignment =
+        split_fragments::CellTypeAssignment::parse(assignment).map_err(invalid_argument)?;
+    let output_codec = OutputCodec::parse(output_codec).map_err(invalid_argument)?;
+    let writer_pool_strategy = split_fragments::WriterPoolStrategy::parse(writer_pool_strategy)
+        .map_err(invalid_argument)?;
+    let duplicate_handling =
+        DuplicateHandling::parse(duplicate_handling).map_err(invalid_argument)?;
+    let zero_length_handling = ZeroLengthHandling::parse(zero_length).map_err(invalid_argument)?;
+    let score_predicate =
+        ScorePredicate::from_predicate_or_min_score(score_predicate.as_deref(), min_score)
+            .map_err(invalid_argument)?;
+    if path_to_barcode_tsv.is_some() && !cell_type_to_cell_barcodes.is_empty() {
+        return Err(invalid_argument(
+            "cell_type_to_cell_barcodes should be empty when path_to_barcode_tsv is given"
+                .to_string(),
+        ));
+    }
+    let cell_barcode_to_cell_type = match (cell_barcodes, cell_types) {
+        (None, None) => invert_cell_type_to_cell_barcodes(&cell_type_to_cell_barcodes),
+        (Some(_), Some(_)) if path_to_barcode_tsv.is_some() => {
+            return Err(invalid_argument(
+                "cell_barcodes and cell_types can not be combined with path_to_barcode_tsv"
+                    .to_string(),
+            ))
+        }
+        (Some(cell_barcodes), Some(cell_types)) => {
+            if !cell_type_to_cell_barcodes.is_empty() {
+                return Err(invalid_argument(
+                    "cell_type_to_cell_barcodes should be empty when cell_barcodes and cell_types are given"
+                        .to_string(),
+                ));
+            }
+            split_fragments::cell_barcode_to_cell_type_from_lists(cell_barcodes, cell_types)?
+        }
+        _ => {
+            return Err(invalid_argument(
+                "cell_barcodes and cell_types should be given together".to_string(),
+            ))
+        }
+    };
+    let barcode_rename = barcode_rename.map(|new_barcodes| BarcodeRename {
+        new_barcodes,
+        drop_missing: drop_unrenamed_barcodes,
+    });
+    let barcode_transform = (strip_barcode_prefix.is_some()
+        || strip_barcode_suffix.is_some()
+        || add_sample_prefix.is_some())
+    .then_some(BarcodeTransform {
+        strip_prefix: strip_barcode_prefix,
+        strip_suffix: strip_barcode_suffix,
+        add_prefix: add_sample_prefix,
+    });
+    // The GIL is released while splitting and only re-acquired to call the fragment filter and progress callback.
+    let fragment_filter = fragment_filter.map(|fragment_filter| {
+        move |fragment: &Fragment| -> FragmentToolsResult<bool> {
+            Python::with_gil(|py| -> PyResult<bool> {
+                fragment_filter
+                    .call1(
+                        py,
+                        (
+                            fragment.chrom.as_str(),
+                            fragment.start,
+                            fragment.end,
+                            fragment.cell_barcode.as_str(),
+                            fragment.score,
+                        ),
+                    )?
+                    .is_true(py)
+            })
+            .map_err(|e| FragmentToolsError::Callback(Box::new(e)))
+        }
+    });
+    let progress_callback = progress_callback.map(|progress_callback| {
+        move |cell_type: &str, contig: &str, fragments_written: u64| -> FragmentToolsResult<()> {
+            Python::with_gil(|py| {
+                progress_callback
+                    .call1(py, (cell_type, contig, fragments_written))
+                    .map(|_| ())
+            })
+            .map_err(|e| FragmentToolsError::Callback(Box::new(e)))
+        }
+    });
+    // Signal handlers (e.g. of Ctrl-C) only run when the GIL is held, so they are run from the interrupt check.
+    let interrupt_check = || -> FragmentToolsResult<()> {
+        Python::with_gil(|py| py.check_signals())
+            .map_err(|e| FragmentToolsError::Callback(Box::new(e)))
+    };
+    let options = split_fragments::SplitOptions {
+        compute_checksums,
+        fragment_filter: fragment_filter
+            .as_ref()
+            .map(|fragment_filter| fragment_filter as &split_fragments::FragmentFilter),
+        score_predicate: score_predicate.as_ref(),
+        missing_score_passes,
+        barcode_tag: barcode_tag.as_deref(),
+        strand_column,
+        assignment,
+        path_to_tar_archive: path_to_tar_archive.as_deref(),
+        output_codec,
+        output_extension: output_extension.as_deref(),
+        file_contigs: file_contigs.as_deref(),
+        number_of_threads,
+        writer_pool_strategy,
+        comment_char,
+        duplicate_handling,
+        zero_length_handling,
+        barcode_rename: barcode_rename.as_ref(),
+        split_regex: split_regex.as_deref(),
+        path_to_counts_file: path_to_counts_file.as_deref(),
+        browser_optimized,
+        contig_order: contig_order.as_deref(),
+        path_to_blacklist: path_to_blacklist.as_deref(),
+        group_by: split_fragments::SplitGroupBy::parse(group_by).map_err(invalid_argument)?,
+        max_open_files,
+        score_precision,
+        score_pair: score_pair
+            .map(ScorePairValue::parse)
+            .transpose()
+            .map_err(invalid_argument)?,
+        expected_cell_types,
+        interrupt_check: Some(&interrupt_check),
+        return_partial_on_error,
+        create_index,
+        barcode_transform: barcode_transform.as_ref(),
+        contig_name_mode: ContigNameMode::parse(contig_name_mode).map_err(invalid_argument)?,
+        shift,
+        progress_callback: progress_callback
+            .as_ref()
+            .map(|progress_callback| progress_callback as &split_fragments::ProgressCallback),
+        length_histogram: length_histogram_bin_size.map(|bin_size| {
+            split_fragments::LengthHistogramBins {
+                bin_size,
+                max_length: length_histogram_max_length,
+            }
+        }),
+        path_to_unassigned_output: path_to_unassigned_output.as_deref(),
+        verbose,
+    };
+    py.allow_threads(|| match &path_to_barcode_tsv {
+        Some(path_to_barcode_tsv) => split_fragments::split_fragments_by_cell_barcode_from_file(
+            &path_to_fragments,
+            &path_to_output_folder,
+            path_to_barcode_tsv,
+            chromsizes,
+            &options,
+        ),
+        None => split_fragments::split_fragments_by_cell_barcode(
+            &path_to_fragments,
+            &path_to_output_folder,
+            cell_barcode_to_cell_type,
+            chromsizes,
+            &options,
+        ),
+    })
+    .map_err(Into::into)
+}
+
+/// Estimate the output of splitting fragments by cell barcode, without writing anything.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the (tabix indexed) fragments file.
+/// * `cell_type_to_cell_barcodes` - A HashMap mapping cell types to cell barcodes.
+/// * `bytes_per_fragment` - Average number of compressed bytes per fragment, used to estimate the file sizes.
+///    If None, the average of the fragments file itself is used.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// A dictionary mapping each cell type to a `SplitSizeEstimate` with attributes:
+/// * `number_of_fragments` - Exact number of fragments that would be written for the cell type.
+/// * `estimated_bytes` - Estimated size of its BGZF compressed output file.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// estimates = _rust_scatac_fragment_tools.estimate_split_sizes(
+///     path_to_fragments="fragments.tsv.gz",
+///     cell_type_to_cell_barcodes={
+///         "cell_type_1": ["AACATCGATGGATG-1", "AACATCGATGGTTG-1"],
+///         "cell_type_2": ["TTGATCGATGGATG-1", "AACATCGCTAGATG-1"]
+///     }
+/// )
+/// estimates["cell_type_1"].estimated_bytes
+/// ```
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragments,
+    cell_type_to_cell_barcodes,
+    bytes_per_fragment = None,
+    verbose = false
+))]
+fn estimate_split_sizes(
+    py: Python<'_>,
+    path_to_fragments: String,
+    cell_type_to_cell_barcodes: HashMap<String, Vec<String>>,
+    bytes_per_fragment: Option<f64>,
+    verbose: bool,
+) -> PyResult<HashMap<String, SplitSizeEstimate>> {
+    let cell_barcode_to_cell_type = invert_cell_type_to_cell_barcodes(&cell_type_to_cell_barcodes);
+    py.allow_threads(|| {
+        split_fragments::estimate_split_sizes(
+            &path_to_fragments,
+            &cell_barcode_to_cell_type,
+            bytes_per_fragment,
+            verbose,
+        )
+    })
+    .map_err(Into::into)
+}
+
+/// A fragment as a tuple of (chrom, start, end, cell_barcode, score).
+type FragmentTuple = (String, usize, usize, String, Option<usize>);
+
+/// Fragments passed from Python, either as a list of tuples or as the bytes of a TSV file.
+#[derive(FromPyObject)]
+enum InMemoryFragments<'a> {
+    Bytes(&'a PyBytes),
+    Tuples(Vec<FragmentTuple>),
+}
+
+/// Split in-memory fragments by cell barcode.
+///
+/// # Arguments
+///
+/// * `fragments` - Either a list of (chrom, start, end, cell_barcode, score) tuples,
+///    where score can be None, or the (uncompressed) bytes of a fragments TSV file.
+/// * `cell_type_to_cell_barcodes` - A HashMap mapping cell types to cell barcodes.
+/// * `path_to_output_folder` - Path to the output folder.
+///    If set, one file per cell type will be written here and None is returned.
+///    If not set, the fragments per cell type are returned.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+/// * `delimiter` - Column delimiter when fragments are passed as bytes, can be multiple characters.
+/// * `strip_quotes` - Whether to strip surrounding quotes from the cell barcodes
+///    when fragments are passed as bytes.
+/// * `barcode_tag` - If set and fragments are passed as bytes, the cell barcode column contains
+///    a SAM-style tag with this name (e.g. `"CB"` for `CB:Z:AACATCGATGGATG-1`),
+///    of which the value is used as cell barcode.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// _rust_scatac_fragment_tools.split_fragments_in_memory(
+///     fragments=[
+///         ("chr1", 10066, 10279, "AACATCGATGGATG-1", 2),
+///         ("chr1", 10079, 10316, "TTGATCGATGGATG-1", None)
+///     ],
+///     cell_type_to_cell_barcodes={
+///         "cell_type_1": ["AACATCGATGGATG-1", "AACATCGATGGTTG-1"],
+///         "cell_type_2": ["TTGATCGATGGATG-1", "AACATCGCTAGATG-1"]
+///     }
+/// )
+/// ```
+
+#[pyfunction]
+#[pyo3(signature = (
+    fragments,
+    cell_type_to_cell_barcodes,
+    path_to_output_folder = None,
+    number_of_threads = 5,
+    verbose = false,
+    delimiter = "\t",
+    strip_quotes = false,
+    barcode_tag = None
+))]
+#[allow(clippy::too_many_arguments)]
+fn split_fragments_in_memory(
+    py: Python<'_>,
+    fragments: InMemoryFragments,
+    cell_type_to_cell_barcodes: HashMap<String, Vec<String>>,
+    path_to_output_folder: Option<String>,
+    number_of_threads: u32,
+    verbose: bool,
+    delimiter: &str,
+    strip_quotes: bool,
+    barcode_tag: Option<String>,
+) -> PyResult<Option<HashMap<String, Vec<FragmentTuple>>>> {
+    let format = FragmentFormat::new(delimiter, strip_quotes, barcode_tag.as_deref())
+        .map_err(invalid_argument)?;
+    let fragments: Vec<Fragment> = match fragments {
+        InMemoryFragments::Bytes(bytes) => std::str::from_utf8(bytes.as_bytes())
+            .map_err(|_| InvalidFragmentFileError::new_err("Fragments are not valid UTF-8"))?
+            .split('\n')
+            .filter(|s| !s.is_empty() && !s.starts_with('#'))
+            .map(|s| Fragment::new_from_string_with_format(s, &format))
+            .collect::<Result<Vec<Fragment>, String>>()
+            .map_err(InvalidFragmentFileError::new_err)?,
+        InMemoryFragments::Tuples(tuples) => tuples
+            .into_iter()
+            .map(|(chrom, start, end, cell_barcode, score)| Fragment {
+                chrom,
+                start,
+                end,
+                cell_barcode,
+                score,
+                score_pair: None,
+                strand: None,
+                file_index: 0,
+            })
+            .collect(),
+    };
+    let cell_barcode_to_cell_type = invert_cell_type_to_cell_barcodes(&cell_type_to_cell_barcodes);
+    let cell_type_to_fragments = py.allow_threads(|| {
+        split_fragments::split_fragments_in_memory(
+            fragments,
+            path_to_output_folder.as_ref(),
+            cell_barcode_to_cell_type,
+            number_of_threads,
+            verbose,
+        )
+    })?;
+    if path_to_output_folder.is_some() {
+        return Ok(None);
+    }
+    Ok(Some(
+        cell_type_to_fragments
+            .into_iter()
+            .map(|(cell_type, fragments)| {
+                (
+                    cell_type,
+                    fragments
+                        .into_iter()
+                        .map(|fragment| {
+                            (
+                                fragment.chrom,
+                                fragment.start,
+                                fragment.end,
+                                fragment.cell_barcode,
+                                fragment.score,
+                            )
+                        })
+                        .collect(),
+                )
+            })
+            .collect(),
+    ))
+}
+
+/// Merge fragment files.
+///
+/// # Arguments
+///
+/// * `path_to_fragment_files` - Paths to the fragment files,
+///    each sorted by contig and position, like the output of `split_fragments_by_cell_barcode`.
+///    The contigs are sorted lexicographically, or in the same order (e.g. chr1, chr2, chr10) in all files
+///    which are tabix indexed, as the order is taken from their indexes.
+///    A file which is not sorted by start and end within each contig raises an `InvalidFragmentFileError`
+///    (with kind `unsorted`) naming the file.
+///    Files can be BGZF or plain gzip compressed, plain gzip files can only be merged from start to end
+///    (not with `merge_contigs_in_parallel`).
+/// * `path_to_output_file` - Path to the output file.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+/// * `delimiter` - Column delimiter of the input files, can be multiple characters.
+///    The output file is always tab-separated.
+/// * `strip_quotes` - Whether to strip surrounding quotes from the cell barcodes.
+/// * `barcode_tag` - If set, the cell barcode column contains a SAM-style tag with this name
+///    (e.g. `"CB"` for `CB:Z:AACATCGATGGATG-1`), of which the value is used as cell barcode.
+/// * `max_open_files` - Maximum number of files to read from at the same time, defaults to the preset of `memory_mode`.
+///    When merging more files, they are first merged in batches into temporary files next to the output file.
+/// * `memory_mode` - Presets for the memory usage: `"low"` reads at most 32 files at the same time with 8 KiB
+///    read buffers, `"fast"` reads at most 512 files at the same time with 128 KiB read buffers.
+///    Both modes write the same output.
+/// * `output_codec` - Codec of the output file: `"bgzf"` writes a BGZF compressed TSV file,
+///    `"parquet"` writes a Parquet file with columns `chrom`, `start`, `end`, `barcode` and `score`,
+///    with one or more row groups per contig.
+/// * `add_fragment_ids` - Whether to add a column with a unique ID (`frag_1`, `frag_2`, ...) after the last
+///    column of each fragment, increasing in output order. Only supported for `"bgzf"` output.
+/// * `normalize_columns` - How the score column is normalized when some lines lack a score:
+///    `"none"` writes lines as read (with 4 or 5 columns), `"max"` sets missing scores to `missing_score`
+///    (all lines have 5 columns) and `"min"` drops the scores (all lines have 4 columns).
+/// * `missing_score` - Score of lines without a score, used with `normalize_columns="max"`.
+/// * `chrom_column`, `start_column`, `end_column`, `barcode_column`, `score_column` - Columns (0-based)
+///    of the fields of a fragment in the input files, which should be distinct. Other columns are ignored.
+///    The output is always written with the standard column order.
+/// * `strand_column` - If set, column (0-based) of the strand (`+`, `-` or `.`), which is written after the score.
+/// * `source_labels` - If set, a label for each fragment file (e.g. the name of its sample), in the same order,
+///    which is added as a column after the last column of each fragment from that file (before the fragment ID).
+///    Only supported for `"bgzf"` output and when merging at most `max_open_files` files.
+/// * `duplicate_handling` - How fragments with the same chromosome, start, end and cell barcode are written:
+///    `"keep"` writes all of them, `"collapse_sum_score"` writes them once with the sum of their scores,
+///    `"collapse_sum_reads"` does the same but counts a missing score as one read
+///    and `"collapse_count"` writes them once with their number as score.
+///    Duplicates are collapsed after `normalize_columns` and can not be combined with `source_labels`.
+/// * `barcode_rename` - If set, a dictionary mapping cell barcodes to new names, e.g. sample-specific IDs,
+///    which are written instead of the original cell barcodes. Cell barcodes without a new name are written
+///    unchanged, or their fragments are dropped if `drop_unrenamed_barcodes` is set.
+/// * `drop_unrenamed_barcodes` - Whether fragments of cell barcodes which are not in `barcode_rename` are dropped.
+///    Fragments are sorted with the original cell barcodes.
+/// * `memory_map_inputs` - Whether to memory-map the fragment files instead of reading them through htslib.
+///    Only available when the extension was built with the `mmap` feature, otherwise (or when a file can not
+///    be memory-mapped) the files are read normally. Both write the same output.
+/// * `zero_length` - How fragments which start where they end are handled: `"keep"` writes them,
+///    `"drop"` skips them (their number is reported in the summary) and `"error"` raises an
+///    `InvalidFragmentFileError`.
+/// * `score_precision` - If set, scores are decimal numbers (e.g. weights), written with this many decimals
+///    (at most 6), also when duplicates are summed with `duplicate_handling="collapse_sum_score"`.
+///    Requires `output_codec="bgzf"`.
+/// * `input_weights` - If set, a weight between 0 and 1 for each file of `path_to_fragment_files`:
+///    each fragment of the file is kept with this probability, e.g. to balance samples of different depths.
+/// * `sampling_seed` - Seed of the random number generator used with `input_weights`,
+///    the same seed keeps the same fragments. The random numbers of each contig of each file are derived from
+///    the seed, the index of the file and the name of the contig, so the kept fragments of a contig do not depend
+///    on `number_of_threads`, `max_open_files` or the other contigs in the file.
+/// * `allow_duplicate_inputs` - Whether a file may be listed more than once in `path_to_fragment_files`,
+///    which writes its fragments more than once. If not, this raises a `ValueError`, otherwise it prints a warning.
+/// * `n_output_shards` - If set, the output is written in (at most) this many tabix indexed shards with about
+///    the same number of fragments, e.g. `merged.shard_0.tsv.gz`, `merged.shard_1.tsv.gz`, ... for
+///    `path_to_output_file="merged.tsv.gz"`. Shards end at a contig boundary where possible.
+///    Concatenating the shards gives the merged file. Requires `output_codec="bgzf"`.
+/// * `score_pair` - If set, the score column can also contain a comma separated pair of integers (e.g. `3,5`),
+///    of which the `"first"` or `"second"` value is used as score, e.g. to sum with
+///    `duplicate_handling="collapse_sum_score"`. Pairs are written unchanged, unless their score is changed.
+///    Can not be combined with `score_precision`.
+/// * `merge_contigs_in_parallel` - Whether to merge `number_of_threads` contigs at the same time, each into
+///    a temporary file next to the output file. The temporary files are concatenated into the output file,
+///    which is the same as when merging the contigs one after the other, and is tabix indexed.
+///    Requires tabix indexed fragment files and `output_codec="bgzf"`, and can not be combined with `add_fragment_ids`.
+/// * `min_score` - If set, only fragments with at least this score are written, e.g. `2` to drop fragments supported
+///    by a single read. Checked before duplicates are collapsed. Can not be combined with `score_precision`.
+/// * `missing_score_passes` - Whether fragments without a score are written when `min_score` is set.
+/// * `collapse_duplicates` - Whether duplicate fragments (e.g. from resequenced libraries of the same sample)
+///    are written once with the sum of their scores, counting a missing score as one read.
+///    Short for `duplicate_handling="collapse_sum_reads"`, with which it can not be combined.
+/// * `shift` - If set, a tuple with the number of bp by which the start and end of each written fragment
+///    are moved, e.g. `(4, -5)` for the Tn5 insertion. Positions are clamped at 0, as the contig sizes
+///    are not known. Duplicates are collapsed before the fragments are shifted.
+///
+/// # Returns
+///
+/// A `MergeSummary` with attributes:
+/// * `contig_order` - The contigs in the order in which they were written.
+/// * `zero_length_fragments` - The number of dropped zero-length fragments.
+/// * `shard_paths` - The paths of the shards, empty if the output is not sharded.
+/// * `shard_ranges` - For each shard, a tuple with the contig and start of its first fragment and
+///    the contig and end of its last fragment.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// _rust_scatac_fragment_tools.merge_fragment_files(
+///     path_to_fragment_files=[
+///         "fragments_by_cell_type/cell_type_1.fragments.tsv.gz",
+///         "fragments_by_cell_type/cell_type_2.fragments.tsv.gz"
+///     ],
+///     path_to_output_file="merged_fragments.tsv.gz",
+///     number_of_threads=5,
+///     verbose=True
+/// )
+/// ```
+
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragment_files,
+    path_to_output_file,
+    number_of_threads,
+    verbose,
+    delimiter = "\t",
+    strip_quotes = false,
+    barcode_tag = None,
+    max_open_files = None,
+    output_codec = "bgzf",
+    add_fragment_ids = false,
+    memory_mode = "fast",
+    normalize_columns = "none",
+    missing_score = 0,
+    chrom_column = 0,
+    start_column = 1,
+    end_column = 2,
+    barcode_column = 3,
+    score_column = 4,
+    strand_column = None,
+    source_labels = None,
+    duplicate_handling = "keep",
+    barcode_rename = None,
+    drop_unrenamed_barcodes = false,
+    memory_map_inputs = false,
+    zero_length = "drop",
+    score_precision = None,
+    input_weights = None,
+    sampling_seed = 0,
+    allow_duplicate_inputs = false,
+    n_output_shards = None,
+    score_pair = None,
+    merge_contigs_in_parallel = false,
+    min_score = None,
+    missing_score_passes = false,
+    collapse_duplicates = false,
+    shift = None
+))]
+#[allow(clippy::too_many_arguments)]
+fn merge_fragment_files(
+    path_to_fragment_files: Vec<String>,
+    path_to_output_file: String,
+    number_of_threads: u32,
+    verbose: bool,
+    delimiter: &str,
+    strip_quotes: bool,
+    barcode_tag: Option<String>,
+    max_open_files: Option<usize>,
+    output_codec: &str,
+    add_fragment_ids: bool,
+    memory_mode: &str,
+    normalize_columns: &str,
+    missing_score: usize,
+    chrom_column: usize,
+    start_column: usize,
+    end_column: usize,
+    barcode_column: usize,
+    score_column: usize,
+    strand_column: Option<usize>,
+    source_labels: Option<Vec<String>>,
+    duplicate_handling: &str,
+    barcode_rename: Option<HashMap<String, String>>,
+    drop_unrenamed_barcodes: bool,
+    memory_map_inputs: bool,
+    zero_length: &str,
+    score_precision: Option<u32>,
+    input_weights: Option<Vec<f64>>,
+    sampling_seed: u64,
+    allow_duplicate_inputs: bool,
+    n_output_shards: Option<usize>,
+    score_pair: Option<&str>,
+    merge_contigs_in_parallel: bool,
+    min_score: Option<usize>,
+    missing_score_passes: bool,
+    collapse_duplicates: bool,
+    shift: Option<(i64, i64)>,
+) -> PyResult<MergeSummary> {
+    let columns = FragmentColumns::new(
+        chrom_column,
+        start_column,
+        end_column,
+        barcode_column,
+        score_column,
+        strand_column,
+    )
+    .map_err(invalid_argument)?;
+    let format = FragmentFormat::new(delimiter, strip_quotes, barcode_tag.as_deref())
+        .map_err(invalid_argument)?
+        .with_columns(columns)
+        .with_score_precision(score_precision)
+        .and_then(|format| {
+            format.with_score_pair(score_pair.map(ScorePairValue::parse).transpose()?)
+        })
+        .map_err(invalid_argument)?;
+    let memory_mode =
+        aggregate_fragments::MemoryMode::parse(memory_mode).map_err(invalid_argument)?;
+    let options = aggregate_fragments::MergeOptions {
+        format,
+        max_open_files: max_open_files.unwrap_or(memory_mode.max_open_files()),
+        output_codec: OutputCodec::parse(output_codec).map_err(invalid_argument)?,
+        add_fragment_ids,
+        normalize_columns: aggregate_fragments::ColumnNormalization::parse(
+            normalize_columns,
+            missing_score,
+        )
+        .map_err(invalid_argument)?,
+        duplicate_handling: DuplicateHandling::from_handling_or_collapse_duplicates(
+            duplicate_handling,
+            collapse_duplicates,
+        )
+        .map_err(invalid_argument)?,
+        zero_length_handling: ZeroLengthHandling::parse(zero_length).map_err(invalid_argument)?,
+        source_labels,
+        barcode_rename: barcode_rename.map(|new_barcodes| BarcodeRename {
+            new_barcodes,
+            drop_missing: drop_unrenamed_barcodes,
+        }),
+        memory_map_inputs,
+        input_weights,
+        sampling_seed,
+        allow_duplicate_inputs,
+        n_output_shards,
+        merge_contigs_in_parallel,
+        score_predicate: ScorePredicate::from_predicate_or_min_score(None, min_score)
+            .map_err(invalid_argument)?,
+        missing_score_passes,
+        shift,
+        number_of_threads,
+        verbose,
+        ..aggregate_fragments::MergeOptions::with_memory_mode(memory_mode)
+    };
+    aggregate_fragments::merge_fragment_files(
+        &path_to_fragment_files,
+        &path_to_output_file,
+        &options,
+    )
+    .map_err(Into::into)
+}
+
+/// Concatenate fragment files, without sorting them.
+///
+/// Use this instead of concatenating compressed files with `cat`: a file of which the last line lacks
+/// a trailing newline would then run into the first line of the next file, here the newline is added.
+///
+/// # Arguments
+///
+/// * `path_to_fragment_files` - Paths to the fragment files, in the order in which they are written.
+///    For a sorted output, e.g. files with the fragments of different contigs, in sorted order.
+/// * `path_to_output_file` - Path to the output file.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// _rust_scatac_fragment_tools.concatenate_fragment_files(
+///     path_to_fragment_files=["chr1.fragments.tsv.gz", "chr2.fragments.tsv.gz"],
+///     path_to_output_file="fragments.tsv.gz"
+/// )
+/// ```
+
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragment_files,
+    path_to_output_file,
+    number_of_threads = 5,
+    verbose = false
+))]
+fn concatenate_fragment_files(
+    py: Python<'_>,
+    path_to_fragment_files: Vec<String>,
+    path_to_output_file: String,
+    number_of_threads: u32,
+    verbose: bool,
+) -> PyResult<()> {
+    py.allow_threads(|| {
+        aggregate_fragments::concatenate_fragment_files(
+            &path_to_fragment_files,
+            &path_to_output_file,
+            number_of_threads,
+            verbose,
+        )
+    })
+    .map_err(Into::into)
+}
+
+/// Recompress a fragment file with BGZF blocks of a uniform size.
+///
+/// Fragment files written by different tools have very different block sizes, which affects how precise
+/// a tabix query can seek and how fast the file is read. All blocks of the output file, except the last one,
+/// contain `block_size` uncompressed bytes. The content of the file is not changed.
+///
+/// # Arguments
+///
+/// * `path_to_input_file` - Path to the fragment file (BGZF/gzip compressed or uncompressed).
+/// * `path_to_output_file` - Path to the output file.
+/// * `block_size` - Number of uncompressed bytes per block, at most 65280 (the maximum of BGZF).
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `create_index` - Whether to create a tabix index (`.tbi`) for the output file,
+///    which requires the fragments to be sorted by contig and position.
+/// * `verbose` - Whether to print progress messages.
+/// * `tabix_preset` - Tabix preset of the index, only `"bed"` (the layout of fragment files) is supported.
+/// * `tabix_columns` - Column layout of the index for files with non-standard layouts, as a tuple of
+///    `(seq_col, begin_col, end_col, comment_char, skip_lines)` with 0-based columns and 0-based
+///    positions. Can not be combined with `tabix_preset`. Defaults to the `"bed"` preset.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// _rust_scatac_fragment_tools.rebgzip(
+///     path_to_input_file="fragments.tsv.gz",
+///     path_to_output_file="fragments.rebgzipped.tsv.gz",
+///     block_size=65280,
+///     create_index=True
+/// )
+/// ```
+
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_input_file,
+    path_to_output_file,
+    block_size = aggregate_fragments::MAX_BGZF_BLOCK_SIZE,
+    number_of_threads = 5,
+    create_index = false,
+    verbose = false,
+    tabix_preset = None,
+    tabix_columns = None
+))]
+#[allow(clippy::too_many_arguments)]
+fn rebgzip(
+    py: Python<'_>,
+    path_to_input_file: String,
+    path_to_output_file: String,
+    block_size: usize,
+    number_of_threads: u32,
+    create_index: bool,
+    verbose: bool,
+    tabix_preset: Option<&str>,
+    tabix_columns: Option<(usize, usize, usize, char, usize)>,
+) -> PyResult<()> {
+    let tabix_columns = match (tabix_preset, tabix_columns) {
+        (None, None) => TabixColumns::default(),
+        (Some(tabix_preset), None) => {
+            TabixColumns::from_preset(tabix_preset).map_err(invalid_argument)?
+        }
+        (None, Some((seq_col, begin_col, end_col, comment_char, skip_lines))) => {
+            TabixColumns::new(seq_col, begin_col, end_col, comment_char, skip_lines)
+                .map_err(invalid_argument)?
+        }
+        (Some(_), Some(_)) => {
+            return Err(invalid_argument(
+                "tabix_preset and tabix_columns can not be given together".to_string(),
+            ))
+        }
+    };
+    py.allow_threads(|| {
+        aggregate_fragments::rebgzip_fragment_file(
+            &path_to_input_file,
+            &path_to_output_file,
+            block_size,
+            number_of_threads,
+            create_index,
+            &tabix_columns,
+            verbose,
+        )
+    })
+    .map_err(Into::into)
+}
+
+/// Repair the sort order of a nearly sorted fragment file, e.g. after inserting a few fragments.
+///
+/// A buffer of `window` fragments is used to reorder the fragments in a single pass, which is much
+/// cheaper than sorting the whole file. Fragments further than `window` positions from their sorted
+/// position raise an `InvalidFragmentFileError` (with kind `unsorted`).
+///
+/// # Arguments
+///
+/// * `path_to_input_file` - Path to the fragment file (BGZF/gzip compressed or uncompressed).
+/// * `path_to_output_file` - Path to the BGZF compressed output file.
+/// * `window` - Maximum number of fragments kept in the reordering buffer.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// The number of fragments which were out of order.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// _rust_scatac_fragment_tools.fix_local_sort(
+///     path_to_input_file="edited.fragments.tsv.gz",
+///     path_to_output_file="sorted.fragments.tsv.gz",
+///     window=1000
+/// )
+/// ```
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_input_file,
+    path_to_output_file,
+    window,
+    number_of_threads = 1,
+    verbose = false
+))]
+fn fix_local_sort(
+    py: Python<'_>,
+    path_to_input_file: String,
+    path_to_output_file: String,
+    window: usize,
+    number_of_threads: u32,
+    verbose: bool,
+) -> PyResult<u64> {
+    py.allow_threads(|| {
+        aggregate_fragments::fix_local_sort(
+            &path_to_input_file,
+            &path_to_output_file,
+            window,
+            number_of_threads,
+            verbose,
+        )
+    })
+    .map_err(Into::into)
+}
+
+/// Convert a BEDPE file to a fragment file.
+///
+/// # Arguments
+///
+/// * `path_to_bedpe` - Path to the BEDPE file.
+/// * `path_to_output_file` - Path to the output fragment file.
+/// * `barcode_column` - 0-based index of the column containing the cell barcode.
+///    Defaults to the name column of the BEDPE file.
+/// * `score_column` - 0-based index of the column containing the score.
+///    If not set, no score column is written.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// _rust_scatac_fragment_tools.bedpe_to_fragments(
+///     path_to_bedpe="reads.bedpe.gz",
+///     path_to_output_file="fragments.tsv.gz",
+///     barcode_column=6,
+///     score_column=None,
+///     number_of_threads=5,
+///     verbose=True
+/// )
+/// ```
+
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_bedpe,
+    path_to_output_file,
+    barcode_column = 6,
+    score_column = None,
+    number_of_threads = 5,
+    verbose = false
+))]
+fn bedpe_to_fragments(
+    py: Python<'_>,
+    path_to_bedpe: String,
+    path_to_output_file: String,
+    barcode_column: usize,
+    score_column: Option<usize>,
+    number_of_threads: u32,
+    verbose: bool,
+) -> PyResult<()> {
+    py.allow_threads(|| {
+        convert_fragments::bedpe_to_fragments(
+            &path_to_bedpe,
+            &path_to_output_file,
+            barcode_column,
+            score_column,
+            number_of_threads,
+            verbose,
+        )
+    })
+    .map_err(Into::into)
+}
+
+/// Write a fragment file in canonical form, e.g. to standardize fragment files from different sources
+/// before they are used in a pipeline.
+///
+/// The following transforms are applied, in this order:
+/// 1. Empty lines and comment lines (starting with `#`) are dropped.
+/// 2. Columns after the score column are dropped, so each fragment has 5 columns.
+/// 3. Contig names are normalized with `contig_name_mode`.
+/// 4. Fragments without a score get score 1.
+/// 5. Fragments are sorted by contig (lexicographically), start, end and cell barcode.
+/// 6. Duplicates (fragments with the same contig, start, end and cell barcode) are written once,
+///    with the sum of their scores as score.
+/// 7. The output is BGZF compressed and tabix indexed.
+///
+/// All fragments are kept in memory, the input file does not need to be sorted.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragment file (plain text, gzip or BGZF compressed).
+/// * `path_to_output_file` - Path to the output fragment file.
+/// * `contig_name_mode` - How contig names are normalized: `"keep"` uses them as is, `"add_chr"` adds
+///    a `chr` prefix to contig names without one and `"strip_chr"` removes the `chr` prefix.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// The number of written fragments.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// _rust_scatac_fragment_tools.canonicalize_fragments(
+///     path_to_fragments="fragments.tsv.gz",
+///     path_to_output_file="fragments.canonical.tsv.gz",
+///     contig_name_mode="add_chr"
+/// )
+/// ```
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragments,
+    path_to_output_file,
+    contig_name_mode = "keep",
+    number_of_threads = 5,
+    verbose = false
+))]
+fn canonicalize_fragments(
+    py: Python<'_>,
+    path_to_fragments: String,
+    path_to_output_file: String,
+    contig_name_mode: &str,
+    number_of_threads: u32,
+    verbose: bool,
+) -> PyResult<u64> {
+    let contig_name_mode = ContigNameMode::parse(contig_name_mode).map_err(invalid_argument)?;
+    py.allow_threads(|| {
+        convert_fragments::canonicalize_fragments(
+            &path_to_fragments,
+            &path_to_output_file,
+            contig_name_mode,
+            number_of_threads,
+            verbose,
+        )
+    })
+    .map_err(Into::into)
+}
+
+/// Compute the number of fragments per Mb of the genome for each cell barcode, for library complexity QC.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the (tabix indexed) fragments file.
+/// * `chromsizes` - A HashMap mapping chromosome names to chromosome sizes. Only fragments on these
+///    chromosomes are counted, and their total size is the genome size.
+/// * `genome_size_override` - If set, the (effective) genome size in bp to use instead of the total of `chromsizes`.
+/// * `verbose` - Whether to print progress messages.
+/// * `output_format` - `"dict"` returns a dictionary, `"arrow"` returns the same values as an Arrow IPC stream
+///    (`bytes`) with columns `cell_barcode` and `fragments_per_mb` (sorted by cell barcode), which can be read
+///    with `polars.read_ipc_stream` or `pyarrow.ipc.open_stream`, e.g. for many cell barcodes.
+///
+/// # Returns
+///
+/// A dictionary mapping cell barcodes to their number of fragments divided by the genome size in Mb,
+/// or an Arrow IPC stream with these values.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// cell_barcode_to_fragments_per_mb = _rust_scatac_fragment_tools.fragments_per_mb(
+///     path_to_fragments="fragments.tsv.gz",
+///     chromsizes={"chr1": 248956422, "chr2": 242193529}
+/// )
+/// ```
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragments,
+    chromsizes,
+    genome_size_override = None,
+    verbose = false,
+    output_format = "dict"
+))]
+fn fragments_per_mb(
+    py: Python<'_>,
+    path_to_fragments: String,
+    chromsizes: HashMap<String, u64>,
+    genome_size_override: Option<u64>,
+    verbose: bool,
+    output_format: &str,
+) -> PyResult<PyObject> {
+    let output_format = ResultFormat::parse(output_format).map_err(invalid_argument)?;
+    let cell_barcode_to_fragments_per_mb = py.allow_threads(|| {
+        barcodes::fragments_per_mb(
+            &path_to_fragments,
+            &chromsizes,
+            genome_size_override,
+            verbose,
+        )
+    })?;
+    match output_format {
+        ResultFormat::Dict => Ok(cell_barcode_to_fragments_per_mb.into_py(py)),
+        ResultFormat::Arrow => {
+            let ipc_stream =
+                arrow_output::fragments_per_mb_record_batch(&cell_barcode_to_fragments_per_mb)
+                    .and_then(|record_batch| arrow_output::to_ipc_stream(&record_batch))?;
+            Ok(PyBytes::new(py, &ipc_stream).into_py(py))
+        }
+    }
+}
+
+/// Compare the cell barcodes of two fragment files, e.g. of two samples or of two processing versions.
+///
+/// # Arguments
+///
+/// * `path_to_fragments_a` - Path to the first (tabix indexed) fragments file.
+/// * `path_to_fragments_b` - Path to the second (tabix indexed) fragments file.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// A tuple with the number of distinct cell barcodes only in the first file, in both files
+/// and only in the second file.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// only_a, shared, only_b = _rust_scatac_fragment_tools.compare_barcode_sets(
+///     path_to_fragments_a="sample_1.fragments.tsv.gz",
+///     path_to_fragments_b="sample_2.fragments.tsv.gz"
+/// )
+/// ```
+#[pyfunction]
+#[pyo3(signature = (path_to_fragments_a, path_to_fragments_b, verbose = false))]
+fn compare_barcode_sets(
+    py: Python<'_>,
+    path_to_fragments_a: String,
+    path_to_fragments_b: String,
+    verbose: bool,
+) -> PyResult<(u64, u64, u64)> {
+    py.allow_threads(|| {
+        barcodes::compare_barcode_sets(&path_to_fragments_a, &path_to_fragments_b, verbose)
+    })
+    .map_err(Into::into)
+}
+
+/// Write the fragments of a list of cell barcodes to a new fragment file.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file (BGZF compressed or uncompressed).
+///    With a tabix index, it is read through the index, otherwise it is read from start to end.
+/// * `path_to_output_file` - Path to the BGZF compressed output file.
+/// * `cell_barcodes` - The cell barcodes of which the fragments are written.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `create_index` - Whether to create a tabix index for the output file.
+///    The fragments are written in the order of the input, so this requires a sorted input file.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// The number of fragments written.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// _rust_scatac_fragment_tools.subset_fragments(
+///     path_to_fragments="fragments.tsv.gz",
+///     path_to_output_file="subset.fragments.tsv.gz",
+///     cell_barcodes=["AACATCGATGGATG-1", "AACATCGATGGTTG-1"],
+///     create_index=True
+/// )
+/// ```
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragments,
+    path_to_output_file,
+    cell_barcodes,
+    number_of_threads = 1,
+    create_index = false,
+    verbose = false
+))]
+fn subset_fragments(
+    py: Python<'_>,
+    path_to_fragments: String,
+    path_to_output_file: String,
+    cell_barcodes: Vec<String>,
+    number_of_threads: u32,
+    create_index: bool,
+    verbose: bool,
+) -> PyResult<u64> {
+    let cell_barcodes: HashSet<String> = cell_barcodes.into_iter().collect();
+    py.allow_threads(|| {
+        barcodes::subset_fragments(
+            &path_to_fragments,
+            &path_to_output_file,
+            &cell_barcodes,
+            number_of_threads,
+            create_index,
+            verbose,
+        )
+    })
+    .map_err(Into::into)
+}
+
+/// Get the contigs and number of records per contig of a fragment file, without reading its fragments.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `allow_scan` - Whether to read the whole file when it has no tabix index.
+///    If not set, an `InvalidFragmentFileError` is raised for files without tabix index.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// A `FragmentFileStats` with attributes:
+/// * `contigs` - The contigs in the order of the index header, or in the order in which they appear
+///    in the file when scanned.
+/// * `records_per_contig` - A dictionary mapping contigs to their number of records, as recorded in the index
+///    (or counted when scanned).
+/// * `indexed` - Whether the statistics were read from a tabix index.
+/// * `sorted` - Whether the fragments of each contig are in one block and sorted by start position.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// stats = _rust_scatac_fragment_tools.fragment_file_stats(
+///     path_to_fragments="fragments.tsv.gz"
+/// )
+/// stats.records_per_contig["chr1"]
+/// ```
+#[pyfunction]
+#[pyo3(signature = (path_to_fragments, allow_scan = false, verbose = false))]
+fn fragment_file_stats(
+    py: Python<'_>,
+    path_to_fragments: String,
+    allow_scan: bool,
+    verbose: bool,
+) -> PyResult<FragmentFileStats> {
+    py.allow_threads(|| file_stats::fragment_file_stats(&path_to_fragments, allow_scan, verbose))
+        .map_err(Into::into)
+}
+
+/// Compute the pairwise Jaccard similarity of the genomic bins covered by each cell type.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `cell_type_to_cell_barcodes` - A HashMap mapping cell types to cell barcodes.
+/// * `chromsizes` - A HashMap mapping chromosome names to chromosome sizes.
+/// * `bin_size` - Size of the genomic bins in bp.
+/// * `verbose` - Whether to print progress messages.
+/// * `path_to_blacklist` - If set, path to a BED file with blacklist regions (plain or gzip compressed).
+///    Fragments overlapping any of these regions are not used.
+///
+/// # Returns
+///
+/// A tuple of the cell types (sorted by name) and the Jaccard matrix (list of rows),
+/// in the same order as the cell types.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// cell_types, jaccard = _rust_scatac_fragment_tools.celltype_coverage_jaccard(
+///     path_to_fragments="fragments.tsv.gz",
+///     cell_type_to_cell_barcodes={
+///         "cell_type_1": ["AACATCGATGGATG-1", "AACATCGATGGTTG-1"],
+///         "cell_type_2": ["TTGATCGATGGATG-1", "TTGATCGATGGTTG-1"]
+///     },
+///     chromsizes={"chr1": 248956422, "chr2": 242193529},
+///     bin_size=500,
+///     verbose=True
+/// )
+/// ```
+
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragments,
+    cell_type_to_cell_barcodes,
+    chromsizes,
+    bin_size,
+    verbose = false,
+    path_to_blacklist = None
+))]
+fn celltype_coverage_jaccard(
+    py: Python<'_>,
+    path_to_fragments: String,
+    cell_type_to_cell_barcodes: HashMap<String, Vec<String>>,
+    chromsizes: HashMap<String, u64>,
+    bin_size: u64,
+    verbose: bool,
+    path_to_blacklist: Option<String>,
+) -> PyResult<(Vec<String>, Vec<Vec<f64>>)> {
+    let cell_barcode_to_cell_type = invert_cell_type_to_cell_barcodes(&cell_type_to_cell_barcodes);
+    py.allow_threads(|| {
+        coverage::celltype_coverage_jaccard(
+            &path_to_fragments,
+            cell_barcode_to_cell_type,
+            chromsizes,
+            bin_size,
+            path_to_blacklist.as_deref(),
+            verbose,
+        )
+    })
+    .map_err(Into::into)
+}
+
+/// Count the fragments of each cell type in genome-wide bins, e.g. for low-resolution accessibility heatmaps.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `chromsizes` - A HashMap mapping chromosome names to chromosome sizes.
+///    The bins of all chromosomes are laid out one after the other, with the chromosomes sorted by name.
+/// * `bin_size` - Size of the genomic bins in bp.
+/// * `cell_type_to_cell_barcodes` - A HashMap mapping cell types to cell barcodes.
+/// * `bin_assignment` - How fragments overlapping more than one bin are counted: `"split"` divides their
+///    weight over the bins by the number of bp in each bin, `"start"` adds it to the bin of their start.
+/// * `use_scores` - Whether the weight of a fragment is its score (1 for fragments without score) instead of 1.
+/// * `verbose` - Whether to print progress messages.
+/// * `output_format` - `"dict"` returns dictionaries, `"arrow"` returns the counts as an Arrow IPC stream
+///    (`bytes`) with one row per bin (in the same order) and columns `chrom`, `start`, `end` and one column
+///    per cell type (sorted by name), which can be read with `polars.read_ipc_stream` or
+///    `pyarrow.ipc.open_stream` without converting each count to a Python float.
+///
+/// # Returns
+///
+/// A tuple of a dictionary mapping chromosome names to the index of their first bin
+/// and a dictionary mapping cell types to the counts of all bins, or an Arrow IPC stream with the counts.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// chrom_to_first_bin, cell_type_to_bins = _rust_scatac_fragment_tools.binned_coverage(
+///     path_to_fragments="fragments.tsv.gz",
+///     chromsizes={"chr1": 248956422, "chr2": 242193529},
+///     bin_size=100000,
+///     cell_type_to_cell_barcodes={
+///         "cell_type_1": ["AACATCGATGGATG-1", "AACATCGATGGTTG-1"],
+///         "cell_type_2": ["TTGATCGATGGATG-1", "TTGATCGATGGTTG-1"]
+///     }
+/// )
+/// ```
+
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragments,
+    chromsizes,
+    bin_size,
+    cell_type_to_cell_barcodes,
+    bin_assignment = "split",
+    use_scores = false,
+    verbose = false,
+    output_format = "dict"
+))]
+#[allow(clippy::too_many_arguments)]
+fn binned_coverage(
+    py: Python<'_>,
+    path_to_fragments: String,
+    chromsizes: HashMap<String, u64>,
+    bin_size: u64,
+    cell_type_to_cell_barcodes: HashMap<String, Vec<String>>,
+    bin_assignment: &str,
+    use_scores: bool,
+    verbose: bool,
+    output_format: &str,
+) -> PyResult<PyObject> {
+    let bin_assignment =
+        coverage::BinAssignment::parse(bin_assignment).map_err(invalid_argument)?;
+    let output_format = ResultFormat::parse(output_format).map_err(invalid_argument)?;
+    let cell_barcode_to_cell_type = invert_cell_type_to_cell_barcodes(&cell_type_to_cell_barcodes);
+    let binned_coverage = py.allow_threads(|| {
+        coverage::binned_coverage(
+            &path_to_fragments,
+            cell_barcode_to_cell_type,
+            chromsizes.clone(),
+            bin_size,
+            bin_assignment,
+            use_scores,
+            verbose,
+        )
+    })?;
+    match output_format {
+        ResultFormat::Dict => Ok(binned_coverage.into_py(py)),
+        ResultFormat::Arrow => {
+            let ipc_stream = py.allow_threads(|| {
+                arrow_output::binned_coverage_record_batch(&binned_coverage, &chromsizes, bin_size)
+                    .and_then(|record_batch| arrow_output::to_ipc_stream(&record_batch))
+            })?;
+            Ok(PyBytes::new(py, &ipc_stream).into_py(py))
+        }
+    }
+}
+
+/// Compute the fraction of reads in peaks (FRiP) for each cell type.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `path_to_peaks` - Path to a BED file with peaks (plain or gzip compressed).
+///    Overlapping peaks are merged.
+/// * `cell_type_to_cell_barcodes` - A HashMap mapping cell types to cell barcodes.
+/// * `strand_column` - If set, column (0-based) of the strand (`+`, `-` or `.`) of the fragments.
+/// * `tn5_shift` - Whether to shift the fragments to the centers of the Tn5 cut sites before overlapping
+///    them with the peaks. Fragments on the `+` strand are moved 4 bp to the right, fragments on the `-` strand
+///    5 bp to the left, and fragments without strand (or `.`) get their start moved +4 and their end -5 bp.
+/// * `verbose` - Whether to print progress messages.
+/// * `path_to_blacklist` - If set, path to a BED file with blacklist regions (plain or gzip compressed).
+///    Fragments overlapping any of these regions (before shifting) are not counted.
+///
+/// # Returns
+///
+/// A dictionary mapping cell types to the fraction of their fragments overlapping at least one peak.
+/// The FRiP of a cell type without fragments is NaN.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// frip = _rust_scatac_fragment_tools.frip_per_celltype(
+///     path_to_fragments="fragments.tsv.gz",
+///     path_to_peaks="peaks.bed",
+///     cell_type_to_cell_barcodes={
+///         "cell_type_1": ["AACATCGATGGATG-1", "AACATCGATGGTTG-1"],
+///         "cell_type_2": ["TTGATCGATGGATG-1", "TTGATCGATGGTTG-1"]
+///     }
+/// )
+/// ```
+
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragments,
+    path_to_peaks,
+    cell_type_to_cell_barcodes,
+    strand_column = None,
+    tn5_shift = false,
+    verbose = false,
+    path_to_blacklist = None
+))]
+#[allow(clippy::too_many_arguments)]
+fn frip_per_celltype(
+    py: Python<'_>,
+    path_to_fragments: String,
+    path_to_peaks: String,
+    cell_type_to_cell_barcodes: HashMap<String, Vec<String>>,
+    strand_column: Option<usize>,
+    tn5_shift: bool,
+    verbose: bool,
+    path_to_blacklist: Option<String>,
+) -> PyResult<HashMap<String, f64>> {
+    let cell_barcode_to_cell_type = invert_cell_type_to_cell_barcodes(&cell_type_to_cell_barcodes);
+    py.allow_threads(|| {
+        coverage::frip_per_celltype(
+            &path_to_fragments,
+            &path_to_peaks,
+            cell_barcode_to_cell_type,
+            strand_column,
+            tn5_shift,
+            path_to_blacklist.as_deref(),
+            verbose,
+        )
+    })
+    .map_err(Into::into)
+}
+
+/// Validate a fragment file.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `chromsizes` - Optional HashMap mapping chromosome names to chromosome sizes.
+///    If set, fragments on other chromosomes or beyond the end of their chromosome are invalid.
+/// * `verbose` - Whether to print progress messages.
+/// * `delimiter` - Column delimiter, can be multiple characters.
+/// * `strip_quotes` - Whether to strip surrounding quotes from the cell barcodes.
+/// * `barcode_tag` - If set, the cell barcode column contains a SAM-style tag with this name
+///    (e.g. `"CB"` for `CB:Z:AACATCGATGGATG-1`), of which the value is used as cell barcode.
+/// * `number_of_threads` - Number of contigs to validate at the same time. With more than one thread,
+///    the file needs a tabix index: the fragments of each contig are read through the index and
+///    problems are reported by their position on the contig instead of their line number.
+///    Without index, the file is validated with one thread.
+/// * `chrom_column`, `start_column`, `end_column`, `barcode_column`, `score_column` - Columns (0-based)
+///    of the fields of a fragment, which should be distinct. Other columns are ignored.
+/// * `strand_column` - If set, column (0-based) of the strand, which should be `+`, `-` or `.` on every line.
+/// * `unsorted_tolerance` - Number of bp a fragment may start before the largest start of the previous
+///    fragments of its contig, which is then reported with a warning instead of an error. Fragments with
+///    equal starts are always sorted, whatever the order of their ends. Use this only for small, known
+///    regressions (e.g. from rounded coordinates): tabix queries and merging assume properly sorted files.
+///
+/// # Returns
+///
+/// A `ValidationReport` with attributes:
+/// * `number_of_fragments` - The number of fragments in the file.
+/// * `contig_order` - The contigs in the order in which they appear in the file.
+/// * `fragments_per_contig` - A dictionary mapping contigs to their number of fragments.
+///
+/// Raises an `InvalidFragmentFileError` (mentioning the line number) if a line can not be parsed,
+/// a fragment starts after its end, the fragments of a contig are not in one block
+/// or not sorted by start position (beyond `unsorted_tolerance`).
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// report = _rust_scatac_fragment_tools.validate_fragment_file(
+///     path_to_fragments="fragments.tsv.gz",
+///     chromsizes={"chr1": 248956422, "chr2": 242193529}
+/// )
+/// ```
+
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragments,
+    chromsizes = None,
+    verbose = false,
+    delimiter = "\t",
+    strip_quotes = false,
+    barcode_tag = None,
+    number_of_threads = 1,
+    chrom_column = 0,
+    start_column = 1,
+    end_column = 2,
+    barcode_column = 3,
+    score_column = 4,
+    strand_column = None,
+    unsorted_tolerance = 0
+))]
+#[allow(clippy::too_many_arguments)]
+fn validate_fragment_file(
+    py: Python<'_>,
+    path_to_fragments: String,
+    chromsizes: Option<HashMap<String, u64>>,
+    verbose: bool,
+    delimiter: &str,
+    strip_quotes: bool,
+    barcode_tag: Option<String>,
+    number_of_threads: usize,
+    chrom_column: usize,
+    start_column: usize,
+    end_column: usize,
+    barcode_column: usize,
+    score_column: usize,
+    strand_column: Option<usize>,
+    unsorted_tolerance: u64,
+) -> PyResult<ValidationReport> {
+    let columns = FragmentColumns::new(
+        chrom_column,
+        start_column,
+        end_column,
+        barcode_column,
+        score_column,
+        strand_column,
+    )
+    .map_err(invalid_argument)?;
+    let format = FragmentFormat::new(delimiter, strip_quotes, barcode_tag.as_deref())
+        .map_err(invalid_argument)?
+        .with_columns(columns);
+    py.allow_threads(|| {
+        validate::validate_fragment_file(
+            &path_to_fragments,
+            &format,
+            chromsizes.as_ref(),
+            unsorted_tolerance,
+            number_of_threads,
+            verbose,
+        )
+    })
+    .map_err(Into::into)
+}
+
+/// Verify that the output files of a split contain exactly the fragments which should have been written.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file that was split.
+/// * `paths_to_output_files` - Paths to the output files of the split.
+/// * `cell_type_to_cell_barcodes` - A HashMap mapping cell types to cell barcodes, as used for the split.
+///    Each fragment of one of these cell barcodes is expected once per cell type of its cell barcode.
+/// * `verbose` - Whether to print progress messages.
+///
+/// Only splits which write the fragments unchanged can be verified: the lines of the output files
+/// are compared with the lines of the fragments file.
+///
+/// # Returns
+///
+/// A `SplitCompletenessReport` with attributes:
+/// * `expected_fragments` - The number of fragments the output files should contain together.
+/// * `missing_fragments` - The number of expected fragments which are not in the output files.
+/// * `extra_fragments` - The number of fragments in the output files which were not expected.
+/// * `missing_examples` - Up to 10 of the missing fragments (lines), sorted.
+/// * `extra_examples` - Up to 10 of the extra fragments (lines), sorted.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// report = _rust_scatac_fragment_tools.verify_split_completeness(
+///     path_to_fragments="fragments.tsv.gz",
+///     paths_to_output_files=["split/cell_type_1.fragments.tsv.gz", "split/cell_type_2.fragments.tsv.gz"],
+///     cell_type_to_cell_barcodes={
+///         "cell_type_1": ["AACATCGATGGATG-1", "AACATCGATGGTTG-1"],
+///         "cell_type_2": ["TTGATCGATGGATG-1", "TTGATCGATGGTTG-1"]
+///     }
+/// )
+/// assert report.missing_fragments == 0 and report.extra_fragments == 0
+/// ```
+
+#[pyfunction]
+#[pyo3(signature = (
+    path_to_fragments,
+    paths_to_output_files,
+    cell_type_to_cell_barcodes,
+    verbose = false
+))]
+fn verify_split_completeness(
+    py: Python<'_>,
+    path_to_fragments: String,
+    paths_to_output_files: Vec<String>,
+    cell_type_to_cell_barcodes: HashMap<String, Vec<String>>,
+    verbose: bool,
+) -> PyResult<SplitCompletenessReport> {
+    let cell_barcode_to_cell_type = invert_cell_type_to_cell_barcodes(&cell_type_to_cell_barcodes);
+    py.allow_threads(|| {
+        validate::verify_split_completeness(
+            &path_to_fragments,
+            &paths_to_output_files,
+            &cell_barcode_to_cell_type,
+            verbose,
+        )
+    })
+    .map_err(Into::into)
+}
+
+/// Check a fragment file quickly, without parsing all its lines, e.g. as a health check of huge files.
+///
+/// The lines are counted and the contig and start of every `sample_stride`-th line are read to spot-check
+/// the sort order. Use `validate_fragment_file` to check every fragment.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file (BGZF compressed or uncompressed).
+/// * `sample_stride` - Check the sort order of every `sample_stride`-th line, at least 1.
+///
+/// # Returns
+///
+/// A tuple with the number of lines (including comment lines), whether the file is BGZF compressed,
+/// whether it has a tabix index and whether the sampled lines are sorted.
+///
+/// # Example
+///
+/// ```python
+/// from scatac_fragment_tools import _rust_scatac_fragment_tools
+/// number_of_lines, is_bgzf, has_index, appears_sorted = _rust_scatac_fragment_tools.quick_check(
+///     path_to_fragments="fragments.tsv.gz",
+///     sample_stride=1000
+/// )
+/// ```
+#[pyfunction]
+#[pyo3(signature = (path_to_fragments, sample_stride = 1000))]
+fn quick_check(
+    py: Python<'_>,
+    path_to_fragments: String,
+    sample_stride: usize,
+) -> PyResult<(u64, bool, bool, bool)> {
+    py.allow_threads(|| validate::quick_check(&path_to_fragments, sample_stride))
+        .map_err(Into::into)
+}
+
+#[pymodule]
+fn _rust_scatac_fragment_tools(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    // set version dunder
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    // add exceptions
+    m.add(
+        "InvalidFragmentFileError",
+        py.get_type::<InvalidFragmentFileError>(),
+    )?;
+    // add classes
+    m.add_class::<SplitSummary>()?;
+    m.add_class::<SplitSizeEstimate>()?;
+    m.add_class::<MergeSummary>()?;
+    m.add_class::<ValidationReport>()?;
+    m.add_class::<SplitCompletenessReport>()?;
+    m.add_class::<FragmentFileStats>()?;
+    // add functions
+    m.add_function(wrap_pyfunction!(split_fragments_by_cell_barcode, m)?)?;
+    m.add_function(wrap_pyfunction!(estimate_split_sizes, m)?)?;
+    m.add_function(wrap_pyfunction!(split_fragments_in_memory, m)?)?;
+    m.add_function(wrap_pyfunction!(merge_fragment_files, m)?)?;
+    m.add_function(wrap_pyfunction!(concatenate_fragment_files, m)?)?;
+    m.add_function(wrap_pyfunction!(rebgzip, m)?)?;
+    m.add_function(wrap_pyfunction!(fix_local_sort, m)?)?;
+    m.add_function(wrap_pyfunction!(bedpe_to_fragments, m)?)?;
+    m.add_function(wrap_pyfunction!(canonicalize_fragments, m)?)?;
+    m.add_function(wrap_pyfunction!(compare_barcode_sets, m)?)?;
+    m.add_function(wrap_pyfunction!(fragments_per_mb, m)?)?;
+    m.add_function(wrap_pyfunction!(subset_fragments, m)?)?;
+    m.add_function(wrap_pyfunction!(fragment_file_stats, m)?)?;
+    m.add_function(wrap_pyfunction!(celltype_coverage_jaccard, m)?)?;
+    m.add_function(wrap_pyfunction!(binned_coverage, m)?)?;
+    m.add_function(wrap_pyfunction!(frip_per_celltype, m)?)?;
+    m.add_function(wrap_pyfunction!(validate_fragment_file, m)?)?;
+    m.add_function(wrap_pyfunction!(quick_check, m)?)?;
+    m.add_function(wrap_pyfunction!(verify_split_completeness, m)?)?;
+    Ok(())
+}
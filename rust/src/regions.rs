@@ -0,0 +1,92 @@
+use crate::custom_errors::{FragmentToolsError, FragmentToolsResult};
+use rust_htslib::bgzf::Reader;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+
+/// Sorted, non-overlapping (start, end) regions per contig.
+pub(crate) type ContigToRegions = HashMap<String, Vec<(usize, usize)>>;
+
+/// Reads a BED file of regions (plain or gzip compressed) into sorted, non-overlapping
+/// (start, end) intervals per contig. Overlapping and adjacent regions are merged.
+///
+/// # Arguments
+///
+/// * `path_to_bed` - Path to the BED file.
+/// * `description` - What the regions are (e.g. `peaks`), used in error messages.
+pub(crate) fn read_bed_regions(
+    path_to_bed: &str,
+    description: &str,
+) -> FragmentToolsResult<ContigToRegions> {
+    let reader = Reader::from_path(path_to_bed).map_err(|_| {
+        FragmentToolsError::InvalidArgument(format!(
+            "Could not open {} file {}",
+            description, path_to_bed
+        ))
+    })?;
+    let mut contig_to_regions: ContigToRegions = HashMap::new();
+    for line in BufReader::new(reader).lines() {
+        let line = line.map_err(|e| {
+            FragmentToolsError::InvalidArgument(format!(
+                "Could not read {} file {}: {}",
+                description, path_to_bed, e
+            ))
+        })?;
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let region = (
+            fields.next(),
+            fields.next().and_then(|start| start.parse::<usize>().ok()),
+            fields.next().and_then(|end| end.parse::<usize>().ok()),
+        );
+        let (Some(contig), Some(start), Some(end)) = region else {
+            return Err(FragmentToolsError::InvalidArgument(format!(
+                "Invalid region in {} file {}: {:?}",
+                description, path_to_bed, line
+            )));
+        };
+        contig_to_regions
+            .entry(contig.to_string())
+            .or_default()
+            .push((start, end));
+    }
+    for regions in contig_to_regions.values_mut() {
+        regions.sort_unstable();
+        let mut merged_regions: Vec<(usize, usize)> = Vec::with_capacity(regions.len());
+        for &(start, end) in regions.iter() {
+            match merged_regions.last_mut() {
+                Some(last_region) if start <= last_region.1 => {
+                    last_region.1 = last_region.1.max(end)
+                }
+                _ => merged_regions.push((start, end)),
+            }
+        }
+        *regions = merged_regions;
+    }
+    Ok(contig_to_regions)
+}
+
+/// Returns the regions of a contig, empty when the contig has no regions.
+pub(crate) fn regions_of_contig<'a>(
+    contig_to_regions: &'a ContigToRegions,
+    contig: &str,
+) -> &'a [(usize, usize)] {
+    contig_to_regions
+        .get(contig)
+        .map(|regions| regions.as_slice())
+        .unwrap_or_default()
+}
+
+/// Whether a (half-open) interval overlaps any of the sorted, non-overlapping regions.
+pub(crate) fn overlaps_region(regions: &[(usize, usize)], start: usize, end: usize) -> bool {
+    // the first region ending after the start of the interval is the only one that can overlap it
+    let index = regions.partition_point(|&(_, region_end)| region_end <= start);
+    regions
+        .get(index)
+        .is_some_and(|&(region_start, _)| region_start < end)
+}
@@ -0,0 +1,86 @@
+/// Derives the seed of the random numbers of one contig of one input from a single seed.
+///
+/// The sub-seed is the seed XOR the stream (multiplied by the golden ratio constant of SplitMix64)
+/// XOR the 64-bit FNV-1a hash of the contig name, mixed with one SplitMix64 step so that nearby
+/// seeds, streams and contig names give unrelated sub-seeds. As every contig of every input gets
+/// its own random numbers, sampling does not depend on the order in which inputs and contigs are
+/// read, e.g. by different threads or in batches.
+///
+/// # Arguments
+///
+/// * `seed` - Seed given by the user.
+/// * `stream` - Number of the input which is sampled (e.g. the index of a file).
+/// * `contig` - Name of the contig.
+pub(crate) fn derive_seed(seed: u64, stream: u64, contig: &str) -> u64 {
+    let contig_hash = contig
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    let mut state = seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ contig_hash;
+    splitmix64(&mut state)
+}
+
+/// Returns the next random number of a SplitMix64 generator with this state.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Keeps fragments with a fixed probability, using a small seedable random number generator
+/// (SplitMix64), so the same seed always keeps the same fragments.
+///
+/// The generator is reseeded at the start of each contig, see `derive_seed`.
+///
+/// # Fields
+///
+/// * `keep_probability` - Probability of keeping a fragment, between 0 and 1.
+/// * `seed` - Seed given by the user.
+/// * `stream` - Number of the input which is sampled.
+/// * `contig` - Contig of the previous fragment, of which the random numbers are used.
+/// * `state` - State of the random number generator.
+pub(crate) struct FragmentSampler {
+    keep_probability: f64,
+    seed: u64,
+    stream: u64,
+    contig: Option<String>,
+    state: u64,
+}
+
+impl FragmentSampler {
+    /// Creates a new FragmentSampler.
+    ///
+    /// # Arguments
+    ///
+    /// * `keep_probability` - Probability of keeping a fragment, between 0 and 1.
+    /// * `seed` - Seed of the random number generator.
+    /// * `stream` - Number of the input which is sampled (e.g. the index of a file), so inputs
+    ///     sampled with the same seed get independent random numbers.
+    pub(crate) fn new(keep_probability: f64, seed: u64, stream: u64) -> FragmentSampler {
+        FragmentSampler {
+            keep_probability,
+            seed,
+            stream,
+            contig: None,
+            state: 0,
+        }
+    }
+
+    /// Returns whether the next fragment is kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `contig` - Contig of the fragment.
+    pub(crate) fn keep(&mut self, contig: &str) -> bool {
+        if self.contig.as_deref() != Some(contig) {
+            self.state = derive_seed(self.seed, self.stream, contig);
+            self.contig = Some(contig.to_string());
+        }
+        // the 53 high bits give a uniform number in [0, 1)
+        let random_number = (splitmix64(&mut self.state) >> 11) as f64 / (1_u64 << 53) as f64;
+        random_number < self.keep_probability
+    }
+}
@@ -1,30 +1,64 @@
+use crate::aggregate_fragments::{
+    create_thread_pool, finish_temporary_file, temporary_path, write_fragments,
+};
+use crate::custom_errors::{FragmentFileErrorKind, FragmentToolsError, FragmentToolsResult};
+use crate::fragment::{
+    BarcodeRename, BarcodeTransform, ContigNameMode, DuplicateCollapser, DuplicateHandling,
+    Fragment, FragmentColumns, FragmentFormat, ScorePairValue, ScorePredicate, TabixColumns,
+    ZeroLengthHandling,
+};
+use crate::parquet_writer::{OutputCodec, ParquetFragmentWriter};
+use crate::regions::{overlaps_region, read_bed_regions, regions_of_contig, ContigToRegions};
+use crate::summary::{SplitSizeEstimate, SplitSummary};
+use crate::tabix::{
+    build_tabix_index, cell_barcode_of_read, contigs_to_process, for_each_fragment_in_contig,
+    open_fragments_file, FragmentsFileReader, TabixIndex, WHOLE_CONTIG,
+};
 use itertools::Itertools;
-use rust_htslib::bgzf::Writer;
-use rust_htslib::tbx::{self, Read as TbxRead};
+use regex::Regex;
+use rust_htslib::bgzf::{CompressionLevel, Reader, Writer};
 use rust_htslib::tpool::ThreadPool;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 /// Splits a tabix-index fragment file into multiple files based on cell type.
-use std::io::Write;
+use std::fs::{remove_file, rename, File};
+use std::io::{BufRead, BufReader, Error, Write};
+use std::path::Path;
+use std::time::Instant;
 
 /// A lazy BGZF writer that only opens the file when the first write is called.
 ///
+/// The file is written to a temporary path and only moved to its final path by `finish`,
+/// so no partially written file is left at the final path when splitting fails.
+///
 /// # Fields
 ///
 /// * `writer` - The BGZF writer.
-/// * `path` - The path to the file.
-/// * `tpool` - The thread pool to use for writing.
+/// * `path` - The (final) path to the file.
+/// * `tpool` - The thread pool to use for writing, shared or owned by this writer.
 /// * `written` - Whether the file has been written to yet.
+/// * `hasher` - If set, SHA-256 hash of the uncompressed bytes written so far.
+/// * `browser_optimized` - Whether to write blocks of `BROWSER_BGZF_BLOCK_SIZE` bytes
+///     at `BROWSER_COMPRESSION_LEVEL`, see `SplitOptions::browser_optimized`.
+/// * `bytes_in_block` - Number of uncompressed bytes written to the current block.
 ///
 /// # Methods
 ///
 /// * `new` - Creates a new LazyBgzfWriter.
 /// * `write` - Opens the file, if it has not been opened yet, and writes the given bytes to it.
+/// * `flush` - Ends the current block, if the file was written to.
+/// * `finish` - Closes the file and moves it to its final path.
 
 struct LazyBgzfWriter<'a> {
+    // declared before `tpool`, so the writer is dropped before an owned thread pool
     writer: Option<Writer>,
     path: String,
-    tpool: &'a ThreadPool,
+    tpool: WriterThreadPool<'a>,
     written: bool,
+    hasher: Option<Sha256>,
+    browser_optimized: bool,
+    bytes_in_block: usize,
 }
 
 impl LazyBgzfWriter<'_> {
@@ -34,13 +68,24 @@ impl LazyBgzfWriter<'_> {
     ///
     /// * `path` - The path to the file.
     /// * `tpool` - The thread pool to use for writing.
+    /// * `compute_checksum` - Whether to hash the uncompressed bytes that are written.
+    /// * `browser_optimized` - Whether to write blocks of `BROWSER_BGZF_BLOCK_SIZE` bytes
+    ///     at `BROWSER_COMPRESSION_LEVEL`.
 
-    fn new(path: String, tpool: &ThreadPool) -> LazyBgzfWriter {
+    fn new(
+        path: String,
+        tpool: WriterThreadPool,
+        compute_checksum: bool,
+        browser_optimized: bool,
+    ) -> LazyBgzfWriter {
         LazyBgzfWriter {
             writer: None,
             path,
             tpool,
             written: false,
+            hasher: compute_checksum.then(Sha256::new),
+            browser_optimized,
+            bytes_in_block: 0,
         }
     }
 
@@ -50,21 +95,718 @@ impl LazyBgzfWriter<'_> {
     ///
     /// * `bytes` - The bytes to write.
     fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
-        self.written = true;
         if self.writer.is_none() {
-            let mut writer = Writer::from_path(&self.path)
-                .unwrap_or_else(|_| panic!("Could not open file {} for writing", self.path));
+            let compression_level = if self.browser_optimized {
+                CompressionLevel::Level(BROWSER_COMPRESSION_LEVEL)
+            } else {
+                CompressionLevel::Default
+            };
+            let mut writer =
+                Writer::from_path_with_level(temporary_path(&self.path), compression_level)
+                    .map_err(|_| {
+                        Error::other(format!("Could not open file {} for writing", self.path))
+                    })?;
+            let tpool = match &mut self.tpool {
+                WriterThreadPool::Shared(tpool) => *tpool,
+                WriterThreadPool::Owned {
+                    number_of_threads,
+                    tpool,
+                } => {
+                    if tpool.is_none() {
+                        *tpool = Some(ThreadPool::new(*number_of_threads).map_err(|_| {
+                            Error::other(format!(
+                                "Could not create thread pool with {} threads for {}",
+                                number_of_threads, self.path
+                            ))
+                        })?);
+                    }
+                    tpool.as_ref().unwrap()
+                }
+            };
             writer
-                .set_thread_pool(self.tpool)
-                .unwrap_or_else(|_| panic!("Could not set thread pool {}", self.path));
+                .set_thread_pool(tpool)
+                .map_err(|_| Error::other(format!("Could not set thread pool {}", self.path)))?;
             self.writer = Some(writer);
         }
-        self.writer.as_mut().unwrap().write(bytes)
+        self.written = true;
+        // hash the bytes before compression, so the checksum does not depend on the bgzf block layout
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(bytes);
+        }
+        let write_error = |e: Error| {
+            Error::new(
+                e.kind(),
+                format!("Could not write to file {}: {}", self.path, e),
+            )
+        };
+        let writer = self.writer.as_mut().unwrap();
+        if self.browser_optimized {
+            // end a block each time it holds BROWSER_BGZF_BLOCK_SIZE bytes, flushing ends the current block
+            let mut remaining_bytes = bytes;
+            while !remaining_bytes.is_empty() {
+                let number_of_bytes = remaining_bytes
+                    .len()
+                    .min(BROWSER_BGZF_BLOCK_SIZE - self.bytes_in_block);
+                writer
+                    .write_all(&remaining_bytes[..number_of_bytes])
+                    .map_err(write_error)?;
+                self.bytes_in_block += number_of_bytes;
+                remaining_bytes = &remaining_bytes[number_of_bytes..];
+                if self.bytes_in_block == BROWSER_BGZF_BLOCK_SIZE {
+                    writer.flush().map_err(write_error)?;
+                    self.bytes_in_block = 0;
+                }
+            }
+        } else {
+            writer.write_all(bytes).map_err(write_error)?;
+        }
+        Ok(bytes.len())
+    }
+
+    /// Ends the current block, if the file was written to.
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush().map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("Could not write to file {}: {}", self.path, e),
+                )
+            })?;
+            self.bytes_in_block = 0;
+        }
+        Ok(())
+    }
+
+    /// Closes the file, if it was written to, and moves it to its final path.
+    fn finish(mut self) -> std::io::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            finish_temporary_file(writer, &self.path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the hex encoded SHA-256 checksum of the uncompressed content, if it was computed.
+    fn checksum(&self) -> Option<String> {
+        self.hasher.as_ref().map(|hasher| {
+            hasher
+                .clone()
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect()
+        })
+    }
+}
+
+/// Number of uncompressed bytes per BGZF block of the files written with `SplitOptions::browser_optimized`.
+///
+/// Genome browsers (IGV, UCSC) decompress whole blocks for each region they display and fetch them
+/// with HTTP range requests, so blocks of 16 KiB instead of the usual ~64 KiB make those requests
+/// smaller and faster, at the cost of a few percent larger files.
+pub const BROWSER_BGZF_BLOCK_SIZE: usize = 0x4000;
+
+/// Compression level of the files written with `SplitOptions::browser_optimized`.
+///
+/// The default level of zlib, higher levels hardly shrink fragment files but are much slower to write,
+/// and the level does not influence the speed of decompression.
+pub const BROWSER_COMPRESSION_LEVEL: i8 = 6;
+
+/// Default of `SplitOptions::max_open_files` when grouping by cell barcode, below the default limit
+/// of open files per process on Linux (1024).
+pub const DEFAULT_MAX_OPEN_FILES: usize = 1000;
+
+/// Number of files from which splitting warns that it uses many inodes,
+/// which can run out on filesystems with a fixed number of inodes (e.g. ext4).
+const MANY_FILES_WARNING_THRESHOLD: usize = 100_000;
+
+/// Fraction of `SplitOptions::expected_cell_types` by which the number of cell types may differ from it
+/// before splitting warns about it.
+const EXPECTED_CELL_TYPES_TOLERANCE: f64 = 0.5;
+
+/// Number of fragments after which splitting calls `SplitOptions::interrupt_check` again.
+pub const INTERRUPT_CHECK_INTERVAL: u64 = 1_000_000;
+
+/// Suffix of the files written by a split which stopped early, see `SplitOptions::return_partial_on_error`.
+pub const PARTIAL_OUTPUT_SUFFIX: &str = ".partial";
+
+/// Thread pool of a `LazyBgzfWriter`.
+///
+/// # Variants
+///
+/// * `Shared` - A thread pool shared by all writers.
+/// * `Owned` - A thread pool with this number of threads, created when the writer is opened.
+enum WriterThreadPool<'a> {
+    Shared(&'a ThreadPool),
+    Owned {
+        number_of_threads: u32,
+        tpool: Option<ThreadPool>,
+    },
+}
+
+/// How the files per cell type are given threads to compress their output.
+///
+/// # Variants
+///
+/// * `Shared` - All files share one thread pool with `number_of_threads` threads.
+/// * `PerWriter` - Each file gets its own thread pool with `number_of_threads` threads,
+///     created when the first fragment is written to it. With many cell types this starts
+///     many more threads, but the files do not wait on each other's compression jobs.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WriterPoolStrategy {
+    Shared,
+    PerWriter,
+}
+
+impl WriterPoolStrategy {
+    /// Parse a writer pool strategy ("shared" or "per_writer").
+    pub fn parse(s: &str) -> Result<WriterPoolStrategy, String> {
+        match s {
+            "shared" => Ok(WriterPoolStrategy::Shared),
+            "per_writer" => Ok(WriterPoolStrategy::PerWriter),
+            _ => Err(format!(
+                "Invalid writer pool strategy {:?}, should be one of \"shared\" or \"per_writer\"",
+                s
+            )),
+        }
     }
 }
 
-fn sanitize_string_for_filename(s: String) -> String {
-    s.replace([' ', '/'], "_")
+/// How fragments of a cell barcode which maps to several cell types are assigned.
+///
+/// # Variants
+///
+/// * `All` - Write the fragments to every cell type.
+/// * `First` - Write the fragments only to the first cell type, sorted by name.
+/// * `Error` - Do not split, but return an error.
+#[derive(Clone, Copy)]
+pub enum CellTypeAssignment {
+    All,
+    First,
+    Error,
+}
+
+impl CellTypeAssignment {
+    /// Parse an assignment ("all", "first" or "error").
+    pub fn parse(s: &str) -> Result<CellTypeAssignment, String> {
+        match s {
+            "all" => Ok(CellTypeAssignment::All),
+            "first" => Ok(CellTypeAssignment::First),
+            "error" => Ok(CellTypeAssignment::Error),
+            _ => Err(format!(
+                "Invalid assignment {:?}, should be one of \"all\", \"first\" or \"error\"",
+                s
+            )),
+        }
+    }
+
+    /// Applies the assignment to a HashMap mapping cell barcodes to cell types.
+    ///
+    /// # Arguments
+    ///
+    /// * `cell_barcode_to_cell_type` - A HashMap mapping cell barcodes to cell types.
+    fn apply(
+        &self,
+        mut cell_barcode_to_cell_type: HashMap<String, Vec<String>>,
+    ) -> Result<HashMap<String, Vec<String>>, String> {
+        match self {
+            CellTypeAssignment::All => {}
+            CellTypeAssignment::First => {
+                for cell_types in cell_barcode_to_cell_type.values_mut() {
+                    cell_types.sort();
+                    cell_types.truncate(1);
+                }
+            }
+            CellTypeAssignment::Error => {
+                let ambiguous_cell_barcodes: Vec<&String> = cell_barcode_to_cell_type
+                    .iter()
+                    .filter(|(_, cell_types)| cell_types.iter().unique().count() > 1)
+                    .map(|(cell_barcode, _)| cell_barcode)
+                    .sorted()
+                    .collect();
+                if !ambiguous_cell_barcodes.is_empty() {
+                    return Err(format!(
+                        "{} cell barcode(s) map to more than one cell type: {}",
+                        ambiguous_cell_barcodes.len(),
+                        ambiguous_cell_barcodes.iter().join(", ")
+                    ));
+                }
+            }
+        }
+        Ok(cell_barcode_to_cell_type)
+    }
+}
+
+/// Whether fragments are written to one file per cell type or one file per cell barcode.
+///
+/// # Variants
+///
+/// * `CellType` - Write one file per cell type.
+/// * `Barcode` - Write one file per cell barcode, named after the cell barcode. The cell types of
+///     the cell barcodes are not used, only fragments of the given cell barcodes are written.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SplitGroupBy {
+    CellType,
+    Barcode,
+}
+
+impl SplitGroupBy {
+    /// Parse a grouping ("cell_type" or "barcode").
+    pub fn parse(s: &str) -> Result<SplitGroupBy, String> {
+        match s {
+            "cell_type" => Ok(SplitGroupBy::CellType),
+            "barcode" => Ok(SplitGroupBy::Barcode),
+            _ => Err(format!(
+                "Invalid group_by {:?}, should be one of \"cell_type\" or \"barcode\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Builds a HashMap mapping cell barcodes to cell types from parallel lists of cell barcodes and cell types,
+/// e.g. the index and a cell type column of an AnnData `obs` table.
+///
+/// A repeated pair of cell barcode and cell type is only added once, a cell barcode which is repeated
+/// with different cell types maps to all of them, in the order in which they are given.
+///
+/// # Arguments
+///
+/// * `cell_barcodes` - The cell barcodes.
+/// * `cell_types` - The cell type of each cell barcode, in the same order.
+pub fn cell_barcode_to_cell_type_from_lists(
+    cell_barcodes: Vec<String>,
+    cell_types: Vec<String>,
+) -> FragmentToolsResult<HashMap<String, Vec<String>>> {
+    if cell_barcodes.len() != cell_types.len() {
+        return Err(FragmentToolsError::InvalidArgument(format!(
+            "cell_barcodes and cell_types should have the same length, got {} and {}",
+            cell_barcodes.len(),
+            cell_types.len()
+        )));
+    }
+    let mut cell_barcode_to_cell_type: HashMap<String, Vec<String>> =
+        HashMap::with_capacity(cell_barcodes.len());
+    for (cell_barcode, cell_type) in cell_barcodes.into_iter().zip(cell_types) {
+        let cell_types_of_barcode = cell_barcode_to_cell_type.entry(cell_barcode).or_default();
+        // cell barcodes rarely have more than a few cell types, so a linear search is fine
+        if !cell_types_of_barcode.contains(&cell_type) {
+            cell_types_of_barcode.push(cell_type);
+        }
+    }
+    Ok(cell_barcode_to_cell_type)
+}
+
+/// Reads a HashMap mapping cell barcodes to cell types from a TSV file with two columns,
+/// the cell barcode and the cell type, without header.
+///
+/// The file is read line by line, so only the HashMap is kept in memory, also for millions of cell barcodes.
+/// It can be gzip or BGZF compressed, or uncompressed. Empty lines and lines starting with `#` are skipped.
+/// A repeated pair of cell barcode and cell type is only added once, a cell barcode which is repeated
+/// with different cell types maps to all of them, in the order of the file.
+///
+/// # Arguments
+///
+/// * `path_to_barcode_tsv` - Path to the TSV file.
+pub fn read_cell_barcode_to_cell_type(
+    path_to_barcode_tsv: &str,
+) -> FragmentToolsResult<HashMap<String, Vec<String>>> {
+    let reader = Reader::from_path(path_to_barcode_tsv).map_err(|e| {
+        FragmentToolsError::InvalidArgument(format!(
+            "Could not open barcode file {}: {}",
+            path_to_barcode_tsv, e
+        ))
+    })?;
+    let mut cell_barcode_to_cell_type: HashMap<String, Vec<String>> = HashMap::new();
+    for (line_index, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.map_err(|e| {
+            FragmentToolsError::InvalidArgument(format!(
+                "Could not read barcode file {}: {}",
+                path_to_barcode_tsv, e
+            ))
+        })?;
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (cell_barcode, cell_type) = match line.split('\t').collect_tuple() {
+            Some((cell_barcode, cell_type))
+                if !cell_barcode.is_empty() && !cell_type.is_empty() =>
+            {
+                (cell_barcode, cell_type)
+            }
+            _ => {
+                return Err(FragmentToolsError::InvalidArgument(format!(
+                    "Invalid line {} in barcode file {}: {:?}, \
+                    it should have two tab separated columns: the cell barcode and the cell type",
+                    line_index + 1,
+                    path_to_barcode_tsv,
+                    line
+                )))
+            }
+        };
+        let cell_types_of_barcode = cell_barcode_to_cell_type
+            .entry(cell_barcode.to_string())
+            .or_default();
+        if !cell_types_of_barcode
+            .iter()
+            .any(|known_cell_type| known_cell_type == cell_type)
+        {
+            cell_types_of_barcode.push(cell_type.to_string());
+        }
+    }
+    Ok(cell_barcode_to_cell_type)
+}
+
+/// Predicate deciding whether a fragment is written, see `SplitOptions::fragment_filter`.
+pub type FragmentFilter<'a> = dyn Fn(&Fragment) -> FragmentToolsResult<bool> + Sync + 'a;
+
+/// Check whether splitting was interrupted (e.g. with Ctrl-C), returning an error when it was,
+/// see `SplitOptions::interrupt_check`.
+pub type InterruptCheck<'a> = dyn Fn() -> FragmentToolsResult<()> + Sync + 'a;
+
+/// Called after each contig with a cell type, the contig and the number of fragments of the contig
+/// written for the cell type, see `SplitOptions::progress_callback`.
+pub type ProgressCallback<'a> = dyn Fn(&str, &str, u64) -> FragmentToolsResult<()> + Sync + 'a;
+
+/// Bins of the fragment length histograms per cell type, see `SplitOptions::length_histogram`.
+///
+/// # Fields
+///
+/// * `bin_size` - Width of each bin in bp, e.g. 10 to count lengths 0-9, 10-19, ... in separate bins.
+/// * `max_length` - Fragments of at least this length (in bp) are counted in the last bin.
+///     Should be a multiple of `bin_size`.
+#[derive(Clone, Copy)]
+pub struct LengthHistogramBins {
+    pub bin_size: u64,
+    pub max_length: u64,
+}
+
+impl Default for LengthHistogramBins {
+    fn default() -> Self {
+        LengthHistogramBins {
+            bin_size: 10,
+            max_length: 1000,
+        }
+    }
+}
+
+impl LengthHistogramBins {
+    /// Returns the number of bins: `max_length / bin_size` bins of `bin_size` bp,
+    /// followed by one bin for the fragments of at least `max_length` bp.
+    pub fn number_of_bins(&self) -> usize {
+        (self.max_length / self.bin_size) as usize + 1
+    }
+
+    /// Returns the index of the bin of a fragment length.
+    pub fn bin(&self, length: u64) -> usize {
+        (length.min(self.max_length) / self.bin_size) as usize
+    }
+
+    fn validate(&self) -> FragmentToolsResult<()> {
+        if self.bin_size == 0
+            || self.max_length == 0
+            || !self.max_length.is_multiple_of(self.bin_size)
+        {
+            return Err(FragmentToolsError::InvalidArgument(format!(
+                "Invalid length histogram with bin size {} and maximum length {}, \
+                the maximum length should be a positive multiple of the bin size",
+                self.bin_size, self.max_length
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Fragment length histograms of the written fragments per cell type, see `SplitOptions::length_histogram`.
+struct LengthHistograms<'a> {
+    bins: LengthHistogramBins,
+    cell_type_to_histogram: HashMap<&'a String, Vec<u64>>,
+}
+
+impl<'a> LengthHistograms<'a> {
+    fn new(bins: LengthHistogramBins) -> Self {
+        LengthHistograms {
+            bins,
+            cell_type_to_histogram: HashMap::new(),
+        }
+    }
+
+    /// Counts a fragment of `length` bp written for a cell type.
+    fn add(&mut self, cell_type: &'a String, length: usize) {
+        let bins = self.bins;
+        self.cell_type_to_histogram
+            .entry(cell_type)
+            .or_insert_with(|| vec![0; bins.number_of_bins()])[bins.bin(length as u64)] += 1;
+    }
+
+    /// Returns the histograms of the cell types, all zero for cell types without fragments.
+    fn into_histograms(self, cell_types: &[&String]) -> HashMap<String, Vec<u64>> {
+        cell_types
+            .iter()
+            .map(|&cell_type| {
+                (
+                    cell_type.to_string(),
+                    self.cell_type_to_histogram
+                        .get(cell_type)
+                        .cloned()
+                        .unwrap_or_else(|| vec![0; self.bins.number_of_bins()]),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Options for splitting a fragment file by cell type.
+///
+/// # Fields
+///
+/// * `number_of_threads` - Number of threads to use for writing, in total or per file depending on
+///     `writer_pool_strategy`.
+/// * `writer_pool_strategy` - Whether the files per cell type share one thread pool or each get their own.
+/// * `compute_checksums` - Whether to compute a SHA-256 checksum of the uncompressed content of each output file.
+/// * `fragment_filter` - If set, only fragments for which the filter returns true are written.
+/// * `score_predicate` - If set, only fragments of which the score satisfies the predicate are written.
+/// * `missing_score_passes` - Whether fragments without a score are written when `score_predicate` is set.
+/// * `barcode_tag` - If set, the cell barcode column contains a SAM-style tag with this name (e.g. `CB:Z:AACG`),
+///     of which the value is used to look up the cell type.
+/// * `strand_column` - If set, column (0-based) of the strand (`+`, `-` or `.`) of the fragments, which is
+///     validated when fragments are parsed. Fragments are written with their strand column unchanged,
+///     parsed fragments with the strand after the score. Parquet output does not include the strand.
+/// * `assignment` - How fragments of cell barcodes which map to several cell types are assigned.
+/// * `path_to_tar_archive` - If set, the files per cell type are moved into this tar archive
+///     after splitting, instead of being kept in the output folder.
+/// * `output_codec` - Codec of the files per cell type.
+/// * `output_extension` - If set, the extension (without leading dot) of the files per cell type,
+///     e.g. `fragments.tsv.bgz`, instead of the extension of `output_codec`.
+/// * `file_contigs` - If set, the contigs of the fragments file, used instead of reading them from the index.
+///     Contigs which are not in the index result in an error when they are fetched.
+/// * `comment_char` - If set, lines starting with this character are header lines. When the index was created
+///     with another comment character, tabix lists them as records on contigs starting with this character,
+///     these contigs are skipped. A header line which is still read as a fragment results in an error.
+/// * `duplicate_handling` - How duplicate fragments (same chromosome, start, end and cell barcode)
+///     are written per cell type. Collapsed fragments are written in the standard layout,
+///     with the value of `barcode_tag` as cell barcode.
+/// * `zero_length_handling` - How fragments with start equal to end are handled,
+///     dropped fragments are counted in the `SplitSummary`.
+/// * `barcode_rename` - If set, the cell barcodes are renamed in the written fragments
+///     (the cell barcode column is replaced by the new name), or fragments of cell barcodes
+///     without a new name are dropped. Cell types are looked up with the original cell barcodes
+///     (transformed by `barcode_transform`, if set), which are also the cell barcodes that are renamed.
+/// * `split_regex` - If set, a regular expression with at least one capture group, of which the first group,
+///     matched against the cell barcode, is used as cell type (e.g. `-(sample[A-Z])$`), instead of
+///     `cell_barcode_to_cell_type`, which should then be empty. Fragments of cell barcodes which do not
+///     match are not written. The cell barcodes are collected in an extra pass over the fragments file.
+/// * `path_to_counts_file` - If set, a TSV file with the number of fragments written per cell type so far,
+///     which is replaced after each contig, so it shows the progress of long runs. It is written to a
+///     temporary file first and then moved, so it is never read partially written.
+/// * `browser_optimized` - Whether to write the files per cell type for genome browsers: in BGZF blocks of
+///     `BROWSER_BGZF_BLOCK_SIZE` uncompressed bytes, at compression level `BROWSER_COMPRESSION_LEVEL`,
+///     each with a tabix index (`.tbi`). The indexes are added to `path_to_tar_archive`, if set.
+///     Requires the bgzf `output_codec`.
+/// * `path_to_blacklist` - If set, path to a BED file with blacklist regions (plain or gzip compressed),
+///     fragments overlapping any of these regions are not written. They are counted in the `SplitSummary`.
+/// * `group_by` - Whether to write one file per cell type or one file per cell barcode.
+///     When grouping by cell barcode, the cell barcodes of `cell_barcode_to_cell_type` are
+///     only used as whitelist, and their cell barcodes take the place of cell types everywhere
+///     (file names, checksums, counts). `assignment` is not used then, `split_regex` can not be used.
+/// * `max_open_files` - Maximum number of files to write at the same time, as the files are kept open until
+///     all contigs are written. When grouping by cell barcode, the fragments file is read once for each batch
+///     of (by default `DEFAULT_MAX_OPEN_FILES`) cell barcodes, see `split_fragments_by_cell_barcode_in_passes`.
+///     When grouping by cell type, there is no limit by default, and more cell types than this number
+///     result in an error.
+/// * `score_precision` - If set, scores are decimal numbers, which are written with this many decimals
+///     (at most `MAX_SCORE_PRECISION`), also when duplicates are collapsed by summing their scores.
+///     Requires the bgzf `output_codec` and can not be combined with `score_predicate`.
+/// * `contig_order` - If set, the order in which contigs are processed (and written to the files per
+///     cell type), instead of the sorted contigs of `chromsizes`. Only listed contigs which are in the
+///     fragments file are processed. Listed contigs which are not in `chromsizes` result in an error.
+/// * `score_pair` - If set, the score column can also contain a comma separated pair of integers,
+///     of which this value is used as score, see `FragmentFormat::score_pair`.
+///     Can not be combined with `score_precision`.
+/// * `expected_cell_types` - If set, the number of cell types the annotation should have. A warning is printed
+///     when the number of cell types differs more than `EXPECTED_CELL_TYPES_TOLERANCE` from it, which usually
+///     means that the wrong annotation was given (e.g. sample labels instead of cell types).
+/// * `interrupt_check` - If set, called before each contig and every `INTERRUPT_CHECK_INTERVAL` fragments,
+///     splitting stops with its error when it returns one.
+/// * `return_partial_on_error` - Whether to return a partial `SplitSummary` instead of an error when splitting
+///     stops while processing the contigs (e.g. when it is interrupted or a fragment is malformed).
+///     The files written so far are closed, so they are valid, and moved to their final path followed by
+///     `PARTIAL_OUTPUT_SUFFIX`. They contain the fragments of the completed contigs of the summary,
+///     and possibly some fragments of the contig on which splitting stopped. They are not indexed
+///     (`browser_optimized`, `create_index`) or archived (`path_to_tar_archive`).
+/// * `create_index` - Whether to create a tabix index (`.tbi`) for each written file, as `browser_optimized` does,
+///     but without changing the BGZF blocks. The indexes are added to `path_to_tar_archive`, if set.
+///     Requires the bgzf `output_codec`.
+/// * `barcode_transform` - If set, the cell barcodes of the fragments file are transformed with it before
+///     they are looked up in `cell_barcode_to_cell_type` (or matched against `split_regex`), e.g. to strip
+///     a `-1` suffix. The fragments are written with their original cell barcode, unless `barcode_rename` is set.
+/// * `contig_name_mode` - How the contig names of `chromsizes`, `contig_order` and the blacklist are normalized
+///     before they are compared with the contigs of the fragments file, e.g. `StripChr` when `chromsizes`
+///     uses `chr1` and the fragments file `1`. Contigs are processed (and reported) with their name in the
+///     fragments file, the fragments are written unchanged.
+/// * `shift` - If set, the number of bp by which the start and end of each written fragment are moved,
+///     e.g. `(4, -5)` for the Tn5 insertion, see `Fragment::shifted`. Positions are clamped between 0 and
+///     the size of the contig in `chromsizes`. Shifted fragments are written in the standard layout.
+/// * `progress_callback` - If set, called after each contig is written, once for each cell type (in sorted order),
+///     with the cell type, the contig and the number of fragments of the contig written for the cell type
+///     (which can be 0). Splitting stops with its error when it returns one.
+/// * `length_histogram` - If set, a histogram of the lengths (end - start) of the written fragments
+///     is counted per cell type with these bins and returned in the `SplitSummary`, e.g. for QC of the
+///     nucleosome banding. Lengths are those of the written fragments, so after `shift`.
+/// * `path_to_unassigned_output` - If set, the fragments of cell barcodes without cell type are written
+///     as read (BGZF compressed, without any filtering) to this file, e.g. to notice that most fragments are
+///     dropped because the cell barcodes of the annotation do not match those of the fragments file.
+///     The file is only created when there are such fragments. Their number is returned in the `SplitSummary`.
+/// * `verbose` - Whether to print progress messages.
+pub struct SplitOptions<'a> {
+    pub number_of_threads: u32,
+    pub writer_pool_strategy: WriterPoolStrategy,
+    pub compute_checksums: bool,
+    pub fragment_filter: Option<&'a FragmentFilter<'a>>,
+    pub score_predicate: Option<&'a ScorePredicate>,
+    pub missing_score_passes: bool,
+    pub barcode_tag: Option<&'a str>,
+    pub strand_column: Option<usize>,
+    pub assignment: CellTypeAssignment,
+    pub path_to_tar_archive: Option<&'a str>,
+    pub output_codec: OutputCodec,
+    pub output_extension: Option<&'a str>,
+    pub file_contigs: Option<&'a [String]>,
+    pub comment_char: Option<char>,
+    pub duplicate_handling: DuplicateHandling,
+    pub zero_length_handling: ZeroLengthHandling,
+    pub barcode_rename: Option<&'a BarcodeRename>,
+    pub split_regex: Option<&'a str>,
+    pub path_to_counts_file: Option<&'a str>,
+    pub browser_optimized: bool,
+    pub path_to_blacklist: Option<&'a str>,
+    pub group_by: SplitGroupBy,
+    pub max_open_files: Option<usize>,
+    pub score_precision: Option<u32>,
+    pub contig_order: Option<&'a [String]>,
+    pub score_pair: Option<ScorePairValue>,
+    pub expected_cell_types: Option<usize>,
+    pub interrupt_check: Option<&'a InterruptCheck<'a>>,
+    pub return_partial_on_error: bool,
+    pub create_index: bool,
+    pub barcode_transform: Option<&'a BarcodeTransform>,
+    pub contig_name_mode: ContigNameMode,
+    pub shift: Option<(i64, i64)>,
+    pub progress_callback: Option<&'a ProgressCallback<'a>>,
+    pub length_histogram: Option<LengthHistogramBins>,
+    pub path_to_unassigned_output: Option<&'a str>,
+    pub verbose: bool,
+}
+
+impl Default for SplitOptions<'_> {
+    fn default() -> Self {
+        SplitOptions {
+            number_of_threads: 5,
+            writer_pool_strategy: WriterPoolStrategy::Shared,
+            compute_checksums: false,
+            fragment_filter: None,
+            score_predicate: None,
+            missing_score_passes: false,
+            barcode_tag: None,
+            strand_column: None,
+            assignment: CellTypeAssignment::All,
+            path_to_tar_archive: None,
+            output_codec: OutputCodec::Bgzf,
+            output_extension: None,
+            file_contigs: None,
+            comment_char: Some('#'),
+            duplicate_handling: DuplicateHandling::Keep,
+            zero_length_handling: ZeroLengthHandling::Drop,
+            barcode_rename: None,
+            split_regex: None,
+            path_to_counts_file: None,
+            browser_optimized: false,
+            path_to_blacklist: None,
+            group_by: SplitGroupBy::CellType,
+            max_open_files: None,
+            score_precision: None,
+            contig_order: None,
+            score_pair: None,
+            expected_cell_types: None,
+            interrupt_check: None,
+            return_partial_on_error: false,
+            create_index: false,
+            barcode_transform: None,
+            contig_name_mode: ContigNameMode::Keep,
+            shift: None,
+            progress_callback: None,
+            length_histogram: None,
+            path_to_unassigned_output: None,
+            verbose: false,
+        }
+    }
+}
+
+/// Maximum number of bytes of a cell type name in a file name, longer names are truncated.
+///
+/// Most filesystems limit file names to 255 bytes, this leaves room for a hash suffix and the extension.
+const MAX_FILENAME_LENGTH: usize = 200;
+
+/// Returns a cell type name which can be used in a file name.
+///
+/// Spaces and slashes are replaced by underscores. Names longer than `MAX_FILENAME_LENGTH` bytes are truncated
+/// and suffixed with `_` and the first 8 hex characters of the SHA-256 hash of the whole name,
+/// so names which only differ after the truncation still get different file names.
+pub(crate) fn sanitize_string_for_filename(s: String) -> String {
+    let s = s.replace([' ', '/'], "_");
+    if s.len() <= MAX_FILENAME_LENGTH {
+        return s;
+    }
+    let hash: String = Sha256::digest(s.as_bytes())
+        .iter()
+        .take(4)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    // truncate at a character boundary
+    let mut truncated_length = MAX_FILENAME_LENGTH;
+    while !s.is_char_boundary(truncated_length) {
+        truncated_length -= 1;
+    }
+    format!("{}_{}", &s[..truncated_length], hash)
+}
+
+/// Checks that the cell types get different file names, see `sanitize_string_for_filename`.
+///
+/// Otherwise cell types like `"CD4 T"` and `"CD4/T"` would be written to the same file.
+/// The error lists the cell types of each file name which is shared.
+fn check_file_name_collisions<'a>(
+    cell_types: impl IntoIterator<Item = &'a String>,
+) -> FragmentToolsResult<()> {
+    let mut file_name_to_cell_types: BTreeMap<String, Vec<&String>> = BTreeMap::new();
+    for cell_type in cell_types {
+        file_name_to_cell_types
+            .entry(sanitize_string_for_filename(cell_type.to_string()))
+            .or_default()
+            .push(cell_type);
+    }
+    let collisions: Vec<String> = file_name_to_cell_types
+        .iter()
+        .filter(|(_, cell_types)| cell_types.len() > 1)
+        .map(|(file_name, cell_types)| {
+            format!(
+                "{} have file name {:?}",
+                cell_types
+                    .iter()
+                    .sorted()
+                    .map(|cell_type| format!("{:?}", cell_type))
+                    .join(", "),
+                file_name
+            )
+        })
+        .collect();
+    if !collisions.is_empty() {
+        return Err(FragmentToolsError::InvalidArgument(format!(
+            "Cell types would be written to the same file, as spaces and slashes are replaced \
+            by underscores in file names: {}. Rename them so they get different file names",
+            collisions.join("; ")
+        )));
+    }
+    Ok(())
 }
 
 /// Splits a tabix-index fragment file into multiple files based on cell type.
@@ -73,101 +815,1493 @@ fn sanitize_string_for_filename(s: String) -> String {
 ///
 /// * `path_to_fragments` - Path to the fragments file.
 /// * `path_to_output_folder` - Path to the output folder,
-///     one file per cell type will be written here and the cell type name will be used as the filename.
+///     one file per cell type will be written here and the cell type name will be used as the filename,
+///     with spaces and slashes replaced by underscores. Cell types which get the same filename are an error.
 ///     If there are no fragments for a cell type, no file will be written for that cell type.
 /// * `cell_barcode_to_cell_type` - A HashMap mapping cell barcodes to cell types.
 /// * `chromsizes` - A HashMap mapping contig names to contig sizes.
-/// * `number_of_threads` - Number of threads to use for writing.
-/// * `verbose` - Whether to print progress messages.
+///     If empty, all contigs of the fragments file (as listed in its index) are processed.
+/// * `options` - Options, see `SplitOptions`.
+///
+/// # Returns
+///
+/// A `SplitSummary` with the cell types for which a file was written and the paths of those files.
+/// If `options.compute_checksums` is set, it contains the checksums of the output files,
+/// cell types for which no file was written are not included.
 
 pub fn split_fragments_by_cell_barcode(
     path_to_fragments: &String,
     path_to_output_folder: &String,
     cell_barcode_to_cell_type: HashMap<String, Vec<String>>,
     chromsizes: HashMap<String, u64>,
-    number_of_threads: u32,
-    verbose: bool,
-) {
+    options: &SplitOptions,
+) -> FragmentToolsResult<SplitSummary> {
+    let SplitOptions {
+        number_of_threads,
+        writer_pool_strategy,
+        compute_checksums,
+        fragment_filter,
+        score_predicate,
+        missing_score_passes,
+        barcode_tag,
+        strand_column,
+        assignment,
+        path_to_tar_archive,
+        output_codec,
+        output_extension,
+        file_contigs,
+        comment_char,
+        duplicate_handling,
+        zero_length_handling,
+        barcode_rename,
+        split_regex,
+        path_to_counts_file,
+        browser_optimized,
+        path_to_blacklist,
+        group_by,
+        max_open_files,
+        score_precision,
+        contig_order: requested_contig_order,
+        score_pair,
+        expected_cell_types,
+        interrupt_check,
+        return_partial_on_error,
+        create_index,
+        barcode_transform,
+        contig_name_mode,
+        shift,
+        progress_callback,
+        length_histogram,
+        path_to_unassigned_output,
+        verbose,
+    } = *options;
+    if output_codec == OutputCodec::Parquet && compute_checksums {
+        return Err(FragmentToolsError::InvalidArgument(
+            "Checksums can only be computed for bgzf output".to_string(),
+        ));
+    }
+    if output_codec == OutputCodec::Parquet && browser_optimized {
+        return Err(FragmentToolsError::InvalidArgument(
+            "browser_optimized can only be used with bgzf output".to_string(),
+        ));
+    }
+    if output_codec == OutputCodec::Parquet && create_index {
+        return Err(FragmentToolsError::InvalidArgument(
+            "Tabix indexes can only be created for bgzf output".to_string(),
+        ));
+    }
+    if score_precision.is_some() && output_codec == OutputCodec::Parquet {
+        return Err(FragmentToolsError::InvalidArgument(
+            "Decimal scores (score_precision) can only be written to bgzf output".to_string(),
+        ));
+    }
+    if score_precision.is_some() && score_predicate.is_some() {
+        return Err(FragmentToolsError::InvalidArgument(
+            "score_predicate can not be used with decimal scores (score_precision)".to_string(),
+        ));
+    }
+    if let Some(length_histogram) = &length_histogram {
+        length_histogram.validate()?;
+    }
+    let output_extension = output_extension.unwrap_or(output_codec.extension());
+    if output_extension.is_empty() || output_extension.contains('/') {
+        return Err(FragmentToolsError::InvalidArgument(format!(
+            "Invalid output extension {:?}, it should not be empty or contain a /",
+            output_extension
+        )));
+    }
+    let split_regex = split_regex.map(compile_split_regex).transpose()?;
+    if split_regex.is_some() && !cell_barcode_to_cell_type.is_empty() {
+        return Err(FragmentToolsError::InvalidArgument(
+            "Cell types can not be given when splitting by split_regex".to_string(),
+        ));
+    }
+    let cell_barcode_to_cell_type = match group_by {
+        SplitGroupBy::CellType => assignment
+            .apply(cell_barcode_to_cell_type)
+            .map_err(FragmentToolsError::InvalidArgument)?,
+        SplitGroupBy::Barcode => {
+            if split_regex.is_some() {
+                return Err(FragmentToolsError::InvalidArgument(
+                    "split_regex can not be used when grouping by barcode".to_string(),
+                ));
+            }
+            let max_open_files = max_open_files.unwrap_or(DEFAULT_MAX_OPEN_FILES);
+            if cell_barcode_to_cell_type.len() > max_open_files {
+                return split_fragments_by_cell_barcode_in_passes(
+                    path_to_fragments,
+                    path_to_output_folder,
+                    cell_barcode_to_cell_type.into_keys().collect(),
+                    chromsizes,
+                    options,
+                    max_open_files,
+                );
+            }
+            // every cell barcode is its own group
+            cell_barcode_to_cell_type
+                .into_keys()
+                .map(|cell_barcode| (cell_barcode.clone(), vec![cell_barcode]))
+                .collect()
+        }
+    };
+    let format = FragmentFormat::new("\t", false, barcode_tag)
+        .map_err(FragmentToolsError::InvalidArgument)?
+        .with_columns(
+            FragmentColumns::new(0, 1, 2, 3, 4, strand_column)
+                .map_err(FragmentToolsError::InvalidArgument)?,
+        )
+        .with_score_precision(score_precision)
+        .and_then(|format| format.with_score_pair(score_pair))
+        .map_err(FragmentToolsError::InvalidArgument)?;
+
     // Initialize reader
-    let mut tbx_reader = tbx::Reader::from_path(path_to_fragments)
-        .unwrap_or_else(|_| panic!("Could not open file {}", path_to_fragments));
+    let mut tbx_reader = open_fragments_file(path_to_fragments)?;
+
+    // reading the contigs from the index can be skipped when the caller already knows them
+    let contigs_in_fragments_file = match file_contigs {
+        Some(file_contigs) => file_contigs.to_vec(),
+        None => tbx_reader.seqnames(),
+    };
+
+    // Header lines are only skipped by tabix when the index was created with their comment character,
+    // otherwise they are indexed as records on a contig of which the name starts with the comment character.
+    let contigs_in_fragments_file: Vec<String> = match comment_char {
+        Some(comment_char) => {
+            let (header_contigs, contigs): (Vec<String>, Vec<String>) = contigs_in_fragments_file
+                .into_iter()
+                .partition(|contig| contig.starts_with(comment_char));
+            if !header_contigs.is_empty() {
+                println!(
+                    "Warning: the index of {} lists header lines as fragments (on contig(s) {}), \
+                    it was probably created without comment character {:?}. \
+                    These lines are skipped, re-index the file to avoid this.",
+                    path_to_fragments,
+                    header_contigs.join(", "),
+                    comment_char
+                );
+            }
+            contigs
+        }
+        None => contigs_in_fragments_file,
+    };
+
+    // Without chromsizes, all contigs of the fragments file are processed, fetching each one as a whole.
+    let chromsizes = if chromsizes.is_empty() {
+        if contigs_in_fragments_file.is_empty() {
+            return Err(FragmentToolsError::InvalidArgument(format!(
+                "chromsizes is empty and no contigs could be read from the index of {}",
+                path_to_fragments
+            )));
+        }
+        log(
+            "No chromsizes given, using the contigs of the fragments file",
+            verbose,
+        );
+        contigs_in_fragments_file
+            .iter()
+            .map(|contig| (contig.clone(), WHOLE_CONTIG))
+            .collect()
+    } else if contig_name_mode == ContigNameMode::Keep {
+        chromsizes
+    } else {
+        chromsizes
+            .into_iter()
+            .map(|(contig, size)| (contig_name_mode.apply(&contig).into_owned(), size))
+            .collect()
+    };
+    let requested_contig_order: Option<Vec<String>> = requested_contig_order.map(|contig_order| {
+        contig_order
+            .iter()
+            .map(|contig| contig_name_mode.apply(contig).into_owned())
+            .collect()
+    });
+
+    // Report contigs in the fragments file which are not in chromsizes, these are never processed.
+    // The number of fragments on those contigs is taken from the index, if available.
+    let tabix_index = TabixIndex::load(path_to_fragments);
+    let skipped_contigs: Vec<String> = contigs_in_fragments_file
+        .iter()
+        .filter(|contig| !chromsizes.contains_key(*contig))
+        .map(|contig| {
+            match tabix_index.as_ref().and_then(|tabix_index| {
+                tbx_reader
+                    .tid(contig)
+                    .ok()
+                    .and_then(|contig_id| tabix_index.number_of_records(contig_id))
+            }) {
+                Some(number_of_fragments) => {
+                    format!("{} ({} fragments)", contig, number_of_fragments)
+                }
+                None => contig.to_string(),
+            }
+        })
+        .collect();
+    if !skipped_contigs.is_empty() {
+        println!(
+            "Warning: skipping {} contig(s) of {} because they are not in chromsizes: {}",
+            skipped_contigs.len(),
+            path_to_fragments,
+            skipped_contigs.join(", ")
+        );
+    }
+
+    let comment_prefix: Option<String> = comment_char.map(String::from);
+    let contig_order = match requested_contig_order.as_deref() {
+        Some(requested_contig_order) => {
+            let mut seen_contigs: HashSet<&String> = HashSet::new();
+            let mut contig_order: Vec<&String> = Vec::new();
+            for contig in requested_contig_order {
+                let Some((contig, _)) = chromsizes.get_key_value(contig) else {
+                    return Err(FragmentToolsError::InvalidArgument(format!(
+                        "Contig {:?} of contig_order is not in chromsizes.",
+                        contig
+                    )));
+                };
+                if !seen_contigs.insert(contig) {
+                    return Err(FragmentToolsError::InvalidArgument(format!(
+                        "Contig {:?} occurs more than once in contig_order.",
+                        contig
+                    )));
+                }
+                if contigs_in_fragments_file.contains(contig) {
+                    contig_order.push(contig);
+                } else {
+                    log(
+                        &format!(
+                            "Skipping contig {} because it is not in the fragments file",
+                            contig
+                        ),
+                        verbose,
+                    );
+                }
+            }
+            contig_order
+        }
+        None => contigs_to_process(&contigs_in_fragments_file, &chromsizes, verbose),
+    };
+    if contig_order.is_empty() && !contigs_in_fragments_file.is_empty() {
+        println!(
+            "Warning: none of the contigs of chromsizes are in {} (e.g. {} in the fragments file), \
+            so no fragments are written. Check that both use the same contig names, \
+            contig_name_mode can add or strip the chr prefix of the contigs of chromsizes.",
+            path_to_fragments,
+            contigs_in_fragments_file.iter().take(3).join(", ")
+        );
+    }
+    let contig_to_blacklist = match path_to_blacklist {
+        Some(path_to_blacklist) => read_bed_regions(path_to_blacklist, "blacklist")?
+            .into_iter()
+            .map(|(contig, regions)| (contig_name_mode.apply(&contig).into_owned(), regions))
+            .collect(),
+        None => ContigToRegions::new(),
+    };
+
+    // With a split regex, the cell types are the captured values of the cell barcodes in the fragments file.
+    let cell_barcode_to_cell_type = match &split_regex {
+        Some(split_regex) => group_cell_barcodes_by_regex(
+            &mut tbx_reader,
+            path_to_fragments,
+            &contig_order,
+            &chromsizes,
+            &format,
+            comment_prefix.as_deref(),
+            split_regex,
+            barcode_transform,
+            verbose,
+        )?,
+        None => cell_barcode_to_cell_type,
+    };
+    // Each cell barcode gets an index, with which it is marked when a fragment of it is written,
+    // so the distinct cell barcodes per cell type can be counted after all contigs are written.
+    let cell_barcode_to_cell_type: HashMap<String, (usize, Vec<String>)> =
+        cell_barcode_to_cell_type
+            .into_iter()
+            .enumerate()
+            .map(|(barcode_index, (cell_barcode, cell_types))| {
+                (cell_barcode, (barcode_index, cell_types))
+            })
+            .collect();
 
     // Initialize writers
     // Use lazy writer to avoid generating empty files
-    let writer_tpool = ThreadPool::new(number_of_threads).unwrap_or_else(|_| {
-        panic!(
-            "Could not create thread pool with {} threads",
-            number_of_threads
-        )
-    });
+    let writer_tpool = match writer_pool_strategy {
+        WriterPoolStrategy::Shared => Some(create_thread_pool(number_of_threads)?),
+        WriterPoolStrategy::PerWriter => None,
+    };
     let mut cell_type_to_writer: HashMap<&String, LazyBgzfWriter> = HashMap::new();
+    let mut cell_type_to_parquet_writer: HashMap<&String, ParquetFragmentWriter> = HashMap::new();
     let unique_cell_types: Vec<&String> = cell_barcode_to_cell_type
         .values()
-        .flatten()
+        .flat_map(|(_, cell_types)| cell_types)
         .unique()
+        .sorted()
         .collect();
-    for cell_type in unique_cell_types {
+    if let Some(max_open_files) = max_open_files {
+        if unique_cell_types.len() > max_open_files {
+            return Err(FragmentToolsError::InvalidArgument(format!(
+                "Splitting into {} files, which is more than max_open_files ({})",
+                unique_cell_types.len(),
+                max_open_files
+            )));
+        }
+    }
+    if let Some(expected_cell_types) = expected_cell_types {
+        if unique_cell_types.len().abs_diff(expected_cell_types) as f64
+            > expected_cell_types as f64 * EXPECTED_CELL_TYPES_TOLERANCE
+        {
+            println!(
+                "Warning: splitting into {} cell types, while {} were expected, \
+                check that the annotation maps cell barcodes to cell types (and not e.g. to samples)",
+                unique_cell_types.len(),
+                expected_cell_types
+            );
+        }
+    }
+    if unique_cell_types.len() > MANY_FILES_WARNING_THRESHOLD {
+        println!(
+            "Warning: writing up to {} files to {}, each file uses an inode, \
+            check that the filesystem has enough free inodes (df -i)",
+            unique_cell_types.len(),
+            path_to_output_folder
+        );
+    }
+    check_file_name_collisions(unique_cell_types.iter().copied())?;
+    let writer_thread_pool = || match &writer_tpool {
+        Some(writer_tpool) => WriterThreadPool::Shared(writer_tpool),
+        None => WriterThreadPool::Owned {
+            number_of_threads,
+            tpool: None,
+        },
+    };
+    let mut truncated_file_names: HashMap<String, String> = HashMap::new();
+    for &cell_type in unique_cell_types.iter() {
         let cell_type_name = sanitize_string_for_filename(cell_type.clone().to_string());
+        if cell_type.len() > MAX_FILENAME_LENGTH {
+            println!(
+                "Warning: cell type {:?} is too long to use as file name, its fragments are written to {}.{}",
+                cell_type, cell_type_name, output_extension
+            );
+            truncated_file_names.insert(cell_type.to_string(), cell_type_name.clone());
+        }
         let path_to_output = format!(
-            "{}/{}.fragments.tsv.gz",
-            path_to_output_folder, cell_type_name
+            "{}/{}.{}",
+            path_to_output_folder, cell_type_name, output_extension
         );
-        let lazy_writer = LazyBgzfWriter::new(path_to_output, &writer_tpool);
-        cell_type_to_writer.insert(cell_type, lazy_writer);
+        match output_codec {
+            OutputCodec::Bgzf => {
+                let lazy_writer = LazyBgzfWriter::new(
+                    path_to_output,
+                    writer_thread_pool(),
+                    compute_checksums,
+                    browser_optimized,
+                );
+                cell_type_to_writer.insert(cell_type, lazy_writer);
+            }
+            OutputCodec::Parquet => {
+                let parquet_writer =
+                    ParquetFragmentWriter::new(path_to_output, Some(cell_type.to_string()));
+                cell_type_to_parquet_writer.insert(cell_type, parquet_writer);
+            }
+        }
     }
 
-    // initialize variables to store read data
-    let mut read: Vec<u8> = Vec::new();
+    let mut unassigned_writer = path_to_unassigned_output.map(|path_to_unassigned_output| {
+        LazyBgzfWriter::new(
+            path_to_unassigned_output.to_string(),
+            writer_thread_pool(),
+            false,
+            false,
+        )
+    });
+    let mut number_of_unassigned_fragments: u64 = 0;
+
+    let mut barcode_has_fragments: Vec<bool> = vec![false; cell_barcode_to_cell_type.len()];
+    // duplicates are consecutive per cell type, as the fragments of a contig are read in order
+    let mut cell_type_to_duplicate_collapser: HashMap<&String, DuplicateCollapser> = HashMap::new();
+    let mut cell_type_to_fragment_count: HashMap<&String, u64> = HashMap::new();
+    let mut length_histograms: Option<LengthHistograms> =
+        length_histogram.map(LengthHistograms::new);
+    let mut number_of_zero_length_fragments: u64 = 0;
+    let mut number_of_blacklisted_fragments: u64 = 0;
 
-    let contigs_in_fragments_file = tbx_reader.seqnames();
+    let mut contig_to_seconds: HashMap<String, f64> = HashMap::new();
+    let mut contig_to_fragments_per_second: HashMap<String, f64> = HashMap::new();
+    let mut completed_contigs: Vec<String> = Vec::new();
+    let mut contig_to_written_fragments: HashMap<String, u64> = HashMap::new();
 
-    for contig in chromsizes.keys().sorted() {
-        if !contigs_in_fragments_file.contains(contig) {
-            log(
-                &format!(
-                    "Skipping contig {} because it is not in the fragments file",
-                    contig
-                ),
-                verbose,
-            );
-            continue;
+    // Writes the fragments of a contig, returning the number of fragments read and written.
+    let mut process_contig = |contig: &String| -> FragmentToolsResult<(u64, u64)> {
+        if let Some(interrupt_check) = interrupt_check {
+            interrupt_check()?;
         }
-        log(&format!("Processing contig {}", contig), verbose);
-        // get contig id and size and fetch whole contig
-        let contig_id = tbx_reader
-            .tid(contig)
-            .unwrap_or_else(|_| panic!("Could not get contig id for contig {}", contig));
+        let number_of_written_fragments: u64 = cell_type_to_fragment_count.values().sum();
+        let previous_cell_type_to_fragment_count =
+            progress_callback.map(|_| cell_type_to_fragment_count.clone());
+        let mut number_of_contig_fragments: u64 = 0;
         let contig_size = chromsizes.get(contig).unwrap();
-        tbx_reader
-            .fetch(contig_id, 0, *contig_size)
-            .unwrap_or_else(|_| panic!("Could not fetch contig {} from fragments file", contig));
-
-        // read first read of contig
-        let mut not_at_end = tbx_reader.read(&mut read).unwrap();
-        let mut read_as_str = String::from_utf8(read.clone()).unwrap();
-
-        // loop over reads
-        while not_at_end {
-            let read_cb = read_as_str.split('\t').nth(3).unwrap().to_string();
-            if let Some(cell_types) = cell_barcode_to_cell_type.get(&read_cb) {
-                for cell_type in cell_types {
-                    let writer = cell_type_to_writer.get_mut(cell_type).unwrap();
-                    writer.write(&read).unwrap();
-                    writer.write(b"\n").unwrap();
+        let blacklist = regions_of_contig(&contig_to_blacklist, contig);
+        for_each_fragment_in_contig(
+            &mut tbx_reader,
+            path_to_fragments,
+            contig,
+            *contig_size,
+            |read| {
+                number_of_contig_fragments += 1;
+                if let Some(interrupt_check) = interrupt_check {
+                    if number_of_contig_fragments.is_multiple_of(INTERRUPT_CHECK_INTERVAL) {
+                        interrupt_check()?;
+                    }
+                }
+                if let Some(comment_prefix) = &comment_prefix {
+                    if read.starts_with(comment_prefix.as_bytes()) {
+                        return Err(FragmentToolsError::InvalidFragmentFile(
+                            FragmentFileErrorKind::Malformed,
+                            format!(
+                                "Header line {:?} of {} was read as a fragment, re-index the file \
+                                with comment character {:?}",
+                                String::from_utf8_lossy(read),
+                                path_to_fragments,
+                                comment_prefix
+                            ),
+                        ));
+                    }
                 }
+                let read_cb = format
+                    .cell_barcode(cell_barcode_of_read(read, path_to_fragments)?)
+                    .map_err(|e| {
+                        FragmentToolsError::InvalidFragmentFile(
+                            FragmentFileErrorKind::Malformed,
+                            format!("{} ({})", e, path_to_fragments),
+                        )
+                    })?;
+                let read_cb = match barcode_transform {
+                    Some(barcode_transform) => barcode_transform.apply(read_cb),
+                    None => Cow::Borrowed(read_cb),
+                };
+                if let Some((cell_barcode, (barcode_index, cell_types))) =
+                    cell_barcode_to_cell_type.get_key_value(read_cb.as_ref())
+                {
+                    let new_barcode = match barcode_rename {
+                        Some(barcode_rename) => match barcode_rename.rename(cell_barcode) {
+                            Some(new_barcode) => Some(new_barcode),
+                            None => return Ok(()),
+                        },
+                        None => None,
+                    };
+                    if zero_length_handling != ZeroLengthHandling::Keep && is_zero_length_read(read)
+                    {
+                        if zero_length_handling == ZeroLengthHandling::Error {
+                            return Err(FragmentToolsError::InvalidFragmentFile(
+                                FragmentFileErrorKind::InvalidCoordinates,
+                                format!(
+                                    "Zero-length fragment {:?} in {}",
+                                    String::from_utf8_lossy(read),
+                                    path_to_fragments
+                                ),
+                            ));
+                        }
+                        number_of_zero_length_fragments += 1;
+                        return Ok(());
+                    }
+                    if !blacklist.is_empty() {
+                        if let Some((start, end)) = positions_of_read(read) {
+                            if overlaps_region(blacklist, start, end) {
+                                number_of_blacklisted_fragments += 1;
+                                return Ok(());
+                            }
+                        }
+                    }
+                    // fragments are only parsed when needed
+                    let fragment = if score_predicate.is_some()
+                        || fragment_filter.is_some()
+                        || output_codec == OutputCodec::Parquet
+                        || duplicate_handling != DuplicateHandling::Keep
+                        || format.score_precision.is_some()
+                        || shift.is_some()
+                    {
+                        Some(parse_read(read, path_to_fragments, &format)?)
+                    } else {
+                        None
+                    };
+                    if let Some(fragment) = &fragment {
+                        if let Some(score_predicate) = score_predicate {
+                            let passes = match fragment.score {
+                                Some(score) => score_predicate.matches(score),
+                                None => missing_score_passes,
+                            };
+                            if !passes {
+                                return Ok(());
+                            }
+                        }
+                        if let Some(fragment_filter) = fragment_filter {
+                            if !fragment_filter(fragment)? {
+                                return Ok(());
+                            }
+                        }
+                    }
+                    let fragment = match (fragment, shift) {
+                        (Some(fragment), Some(shift)) => {
+                            Some(fragment.shifted(shift, Some(*contig_size)))
+                        }
+                        (fragment, _) => fragment,
+                    };
+                    let renamed_read = new_barcode
+                        .map(|new_barcode| replace_cell_barcode_of_read(read, new_barcode));
+                    barcode_has_fragments[*barcode_index] = true;
+                    for cell_type in cell_types {
+                        match (output_codec, &fragment) {
+                            (_, Some(fragment))
+                                if duplicate_handling != DuplicateHandling::Keep =>
+                            {
+                                if let Some(fragment) = cell_type_to_duplicate_collapser
+                                    .entry(cell_type)
+                                    .or_insert_with(|| {
+                                        DuplicateCollapser::new(
+                                            duplicate_handling,
+                                            format.score_precision,
+                                        )
+                                    })
+                                    .push(fragment.clone())
+                                {
+                                    write_parsed_fragment(
+                                        cell_type,
+                                        &fragment,
+                                        new_barcode_of_fragment(
+                                            &fragment,
+                                            barcode_transform,
+                                            barcode_rename,
+                                        )
+                                        .as_deref(),
+                                        format.score_precision,
+                                        &mut cell_type_to_writer,
+                                        &mut cell_type_to_parquet_writer,
+                                        &mut cell_type_to_fragment_count,
+                                        length_histograms.as_mut(),
+                                    )?;
+                                }
+                            }
+                            (_, Some(fragment))
+                                if output_codec == OutputCodec::Parquet
+                                    || format.score_precision.is_some()
+                                    || shift.is_some() =>
+                            {
+                                write_parsed_fragment(
+                                    cell_type,
+                                    fragment,
+                                    new_barcode,
+                                    format.score_precision,
+                                    &mut cell_type_to_writer,
+                                    &mut cell_type_to_parquet_writer,
+                                    &mut cell_type_to_fragment_count,
+                                    length_histograms.as_mut(),
+                                )?;
+                            }
+                            _ => {
+                                let writer = cell_type_to_writer.get_mut(cell_type).unwrap();
+                                writer
+                                    .write(renamed_read.as_deref().unwrap_or(read))
+                                    .and_then(|_| writer.write(b"\n"))
+                                    .map_err(|e| FragmentToolsError::Io(e.to_string()))?;
+                                *cell_type_to_fragment_count.entry(cell_type).or_default() += 1;
+                                if let (Some(length_histograms), Some((start, end))) =
+                                    (length_histograms.as_mut(), positions_of_read(read))
+                                {
+                                    length_histograms.add(cell_type, end.saturating_sub(start));
+                                }
+                            }
+                        }
+                    }
+                } else if let Some(unassigned_writer) = unassigned_writer.as_mut() {
+                    unassigned_writer
+                        .write(read)
+                        .and_then(|_| unassigned_writer.write(b"\n"))
+                        .map_err(|e| FragmentToolsError::Io(e.to_string()))?;
+                    number_of_unassigned_fragments += 1;
+                }
+                Ok(())
+            },
+        )?;
+        for (cell_type, duplicate_collapser) in cell_type_to_duplicate_collapser.iter_mut() {
+            if let Some(fragment) = duplicate_collapser.finish() {
+                write_parsed_fragment(
+                    cell_type,
+                    &fragment,
+                    new_barcode_of_fragment(&fragment, barcode_transform, barcode_rename)
+                        .as_deref(),
+                    format.score_precision,
+                    &mut cell_type_to_writer,
+                    &mut cell_type_to_parquet_writer,
+                    &mut cell_type_to_fragment_count,
+                    length_histograms.as_mut(),
+                )?;
             }
-            read.clear();
-            not_at_end = tbx_reader.read(&mut read).unwrap();
-            read_as_str = String::from_utf8(read.clone()).unwrap();
         }
 
         // flush buffers
-        for writer in cell_type_to_writer.values_mut() {
-            if writer.written {
-                writer.writer.as_mut().unwrap().flush().unwrap();
+        for writer in cell_type_to_writer
+            .values_mut()
+            .chain(unassigned_writer.as_mut())
+        {
+            writer
+                .flush()
+                .map_err(|e| FragmentToolsError::Io(e.to_string()))?;
+        }
+
+        if let Some(path_to_counts_file) = path_to_counts_file {
+            write_counts_file(
+                path_to_counts_file,
+                &unique_cell_types,
+                &cell_type_to_fragment_count,
+            )?;
+        }
+
+        if let (Some(progress_callback), Some(previous_cell_type_to_fragment_count)) =
+            (progress_callback, &previous_cell_type_to_fragment_count)
+        {
+            for &cell_type in unique_cell_types.iter() {
+                let fragment_count = |cell_type_to_fragment_count: &HashMap<&String, u64>| {
+                    cell_type_to_fragment_count
+                        .get(cell_type)
+                        .copied()
+                        .unwrap_or(0)
+                };
+                progress_callback(
+                    cell_type,
+                    contig,
+                    fragment_count(&cell_type_to_fragment_count)
+                        - fragment_count(previous_cell_type_to_fragment_count),
+                )?;
+            }
+        }
+
+        Ok((
+            number_of_contig_fragments,
+            cell_type_to_fragment_count.values().sum::<u64>() - number_of_written_fragments,
+        ))
+    };
+
+    // error on which splitting stopped, when a partial summary is returned
+    let mut stop_error: Option<FragmentToolsError> = None;
+    for &contig in contig_order.iter() {
+        log(&format!("Processing contig {}", contig), verbose);
+        let contig_start_time = Instant::now();
+        let (number_of_contig_fragments, number_of_written_fragments) = match process_contig(contig)
+        {
+            Ok(numbers_of_fragments) => numbers_of_fragments,
+            Err(error) if return_partial_on_error => {
+                println!(
+                    "Warning: splitting {} stopped at contig {}: {}",
+                    path_to_fragments, contig, error
+                );
+                stop_error = Some(error);
+                break;
+            }
+            Err(error) => return Err(error),
+        };
+
+        // a contig which takes long for its number of fragments often has a stale index
+        let seconds = contig_start_time.elapsed().as_secs_f64();
+        let fragments_per_second = if seconds > 0.0 {
+            number_of_contig_fragments as f64 / seconds
+        } else {
+            0.0
+        };
+        log(
+            &format!(
+                "Processed {} fragment(s) of contig {} in {:.3}s ({:.0} fragments/s)",
+                number_of_contig_fragments, contig, seconds, fragments_per_second
+            ),
+            verbose,
+        );
+        contig_to_seconds.insert(contig.to_string(), seconds);
+        contig_to_fragments_per_second.insert(contig.to_string(), fragments_per_second);
+        completed_contigs.push(contig.to_string());
+        contig_to_written_fragments.insert(contig.to_string(), number_of_written_fragments);
+    }
+
+    let cell_type_to_checksum: Option<HashMap<String, String>> = compute_checksums.then(|| {
+        cell_type_to_writer
+            .iter()
+            .filter(|(_, writer)| writer.written)
+            .map(|(cell_type, writer)| (cell_type.to_string(), writer.checksum().unwrap()))
+            .collect()
+    });
+
+    // close all files and move them to their final path
+    let mut output_files: Vec<(String, String)> = Vec::new();
+    for (cell_type, writer) in cell_type_to_writer.into_iter() {
+        if writer.written {
+            output_files.push((cell_type.to_string(), writer.path.clone()));
+        }
+        writer
+            .finish()
+            .map_err(|e| FragmentToolsError::Io(e.to_string()))?;
+    }
+    for (cell_type, writer) in cell_type_to_parquet_writer.into_iter() {
+        if writer.written() {
+            output_files.push((cell_type.to_string(), writer.path.clone()));
+        }
+        writer.finish()?;
+    }
+    output_files.sort();
+    let mut unassigned_output_file: Option<String> = None;
+    if let Some(unassigned_writer) = unassigned_writer {
+        if unassigned_writer.written {
+            unassigned_output_file = Some(unassigned_writer.path.clone());
+        }
+        unassigned_writer
+            .finish()
+            .map_err(|e| FragmentToolsError::Io(e.to_string()))?;
+    }
+    if stop_error.is_some() {
+        for path_to_output in output_files
+            .iter_mut()
+            .map(|(_, path_to_output)| path_to_output)
+            .chain(unassigned_output_file.as_mut())
+        {
+            let path_to_partial_output = format!("{}{}", path_to_output, PARTIAL_OUTPUT_SUFFIX);
+            rename(&path_to_output, &path_to_partial_output).map_err(|e| {
+                FragmentToolsError::Io(format!(
+                    "Could not move {} to {}: {}",
+                    path_to_output, path_to_partial_output, e
+                ))
+            })?;
+            *path_to_output = path_to_partial_output;
+        }
+    }
+    let mut written_files: Vec<String> = output_files
+        .iter()
+        .map(|(_, path_to_output)| path_to_output.clone())
+        .collect();
+    if (browser_optimized || create_index) && stop_error.is_none() {
+        for path_to_output in written_files.clone() {
+            log(&format!("Indexing {}", path_to_output), verbose);
+            build_tabix_index(&path_to_output, &TabixColumns::default())?;
+            written_files.push(format!("{}.tbi", path_to_output));
+        }
+    }
+    written_files.sort();
+    if number_of_zero_length_fragments > 0 {
+        println!(
+            "Warning: dropped {} zero-length fragment(s) (with start equal to end) of {}",
+            number_of_zero_length_fragments, path_to_fragments
+        );
+    }
+    if let Some(path_to_blacklist) = path_to_blacklist {
+        log(
+            &format!(
+                "Dropped {} fragment(s) overlapping blacklist regions of {}",
+                number_of_blacklisted_fragments, path_to_blacklist
+            ),
+            verbose,
+        );
+    }
+    if let Some(path_to_unassigned_output) = path_to_unassigned_output {
+        log(
+            &format!(
+                "Wrote {} fragment(s) of cell barcodes without cell type to {}",
+                number_of_unassigned_fragments, path_to_unassigned_output
+            ),
+            verbose,
+        );
+    }
+    if let (Some(path_to_tar_archive), None) = (path_to_tar_archive, &stop_error) {
+        write_tar_archive(path_to_tar_archive, &written_files, verbose)?;
+    }
+
+    let mut cell_type_to_distinct_barcodes: HashMap<String, u64> = HashMap::new();
+    for (barcode_index, cell_types) in cell_barcode_to_cell_type.values() {
+        if barcode_has_fragments[*barcode_index] {
+            for cell_type in cell_types.iter().unique() {
+                *cell_type_to_distinct_barcodes
+                    .entry(cell_type.to_string())
+                    .or_default() += 1;
             }
         }
     }
+
+    Ok(SplitSummary {
+        contig_order: contig_order.into_iter().cloned().collect(),
+        checksums: cell_type_to_checksum,
+        distinct_barcodes: cell_type_to_distinct_barcodes,
+        truncated_file_names,
+        zero_length_fragments: number_of_zero_length_fragments,
+        blacklisted_fragments: number_of_blacklisted_fragments,
+        seconds_per_contig: contig_to_seconds,
+        fragments_per_second_per_contig: contig_to_fragments_per_second,
+        completed_contigs,
+        written_fragments_per_contig: contig_to_written_fragments,
+        fragments_per_cell_type: unique_cell_types
+            .iter()
+            .map(|&cell_type| {
+                (
+                    cell_type.to_string(),
+                    cell_type_to_fragment_count
+                        .get(cell_type)
+                        .copied()
+                        .unwrap_or(0),
+                )
+            })
+            .collect(),
+        error: stop_error.map(|error| error.to_string()),
+        output_files,
+        length_histograms: length_histograms
+            .map(|length_histograms| length_histograms.into_histograms(&unique_cell_types)),
+        unassigned_fragments: number_of_unassigned_fragments,
+    })
+}
+
+/// Splits a tabix-index fragment file into one file per cell barcode, in passes which each write the files
+/// of at most `max_open_files` cell barcodes, so no more files are open at the same time.
+///
+/// The fragments file is read once per pass. Options which write one file for all cell barcodes
+/// (`path_to_unassigned_output` and `path_to_counts_file`) or stop splitting early (`return_partial_on_error`)
+/// can not be used. The tar archive (`path_to_tar_archive`) is written after the last pass.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `path_to_output_folder` - Path to the output folder.
+/// * `cell_barcodes` - The cell barcodes to split into, see `SplitGroupBy::Barcode`.
+/// * `chromsizes` - A HashMap mapping contig names to contig sizes.
+/// * `options` - Options, see `SplitOptions`.
+/// * `max_open_files` - Maximum number of cell barcodes per pass.
+///
+/// # Returns
+///
+/// A `SplitSummary` of all passes, see `split_fragments_by_cell_barcode`.
+fn split_fragments_by_cell_barcode_in_passes(
+    path_to_fragments: &String,
+    path_to_output_folder: &String,
+    cell_barcodes: Vec<String>,
+    chromsizes: HashMap<String, u64>,
+    options: &SplitOptions,
+    max_open_files: usize,
+) -> FragmentToolsResult<SplitSummary> {
+    for (option_name, is_set) in [
+        (
+            "path_to_unassigned_output",
+            options.path_to_unassigned_output.is_some(),
+        ),
+        ("path_to_counts_file", options.path_to_counts_file.is_some()),
+        ("return_partial_on_error", options.return_partial_on_error),
+    ] {
+        if is_set {
+            return Err(FragmentToolsError::InvalidArgument(format!(
+                "{} can not be used when splitting into {} files, which is more than max_open_files ({})",
+                option_name,
+                cell_barcodes.len(),
+                max_open_files
+            )));
+        }
+    }
+    let cell_barcodes: Vec<String> = cell_barcodes.into_iter().sorted().collect();
+    check_file_name_collisions(cell_barcodes.iter())?;
+    if cell_barcodes.len() > MANY_FILES_WARNING_THRESHOLD {
+        println!(
+            "Warning: writing up to {} files to {}, each file uses an inode, \
+            check that the filesystem has enough free inodes (df -i)",
+            cell_barcodes.len(),
+            path_to_output_folder
+        );
+    }
+    let pass_options = SplitOptions {
+        max_open_files: Some(max_open_files),
+        path_to_tar_archive: None,
+        ..*options
+    };
+    let number_of_passes = cell_barcodes.len().div_ceil(max_open_files);
+    let mut summary: Option<SplitSummary> = None;
+    for (pass_index, cell_barcodes_of_pass) in cell_barcodes.chunks(max_open_files).enumerate() {
+        log(
+            &format!(
+                "Splitting by cell barcode, pass {} of {} ({} cell barcodes)",
+                pass_index + 1,
+                number_of_passes,
+                cell_barcodes_of_pass.len()
+            ),
+            options.verbose,
+        );
+        let pass_summary = split_fragments_by_cell_barcode(
+            path_to_fragments,
+            path_to_output_folder,
+            cell_barcodes_of_pass
+                .iter()
+                .map(|cell_barcode| (cell_barcode.clone(), Vec::new()))
+                .collect(),
+            chromsizes.clone(),
+            &pass_options,
+        )?;
+        summary = Some(match summary {
+            Some(summary) => combine_pass_summaries(summary, pass_summary),
+            None => pass_summary,
+        });
+    }
+    let summary = summary.unwrap();
+    if let Some(path_to_tar_archive) = options.path_to_tar_archive {
+        let mut written_files: Vec<String> = Vec::new();
+        for (_, path_to_output) in summary.output_files.iter() {
+            written_files.push(path_to_output.clone());
+            if options.browser_optimized || options.create_index {
+                written_files.push(format!("{}.tbi", path_to_output));
+            }
+        }
+        written_files.sort();
+        write_tar_archive(path_to_tar_archive, &written_files, options.verbose)?;
+    }
+    Ok(summary)
+}
+
+/// Combines the summaries of two passes of `split_fragments_by_cell_barcode_in_passes`,
+/// which read the same contigs and wrote the files of different cell barcodes.
+///
+/// # Arguments
+///
+/// * `summary` - Summary of the previous passes.
+/// * `pass_summary` - Summary of the next pass.
+fn combine_pass_summaries(mut summary: SplitSummary, pass_summary: SplitSummary) -> SplitSummary {
+    if let (Some(checksums), Some(pass_checksums)) =
+        (summary.checksums.as_mut(), pass_summary.checksums)
+    {
+        checksums.extend(pass_checksums);
+    }
+    summary
+        .distinct_barcodes
+        .extend(pass_summary.distinct_barcodes);
+    summary
+        .truncated_file_names
+        .extend(pass_summary.truncated_file_names);
+    summary.zero_length_fragments += pass_summary.zero_length_fragments;
+    summary.blacklisted_fragments += pass_summary.blacklisted_fragments;
+    for (contig, pass_seconds) in pass_summary.seconds_per_contig {
+        let seconds = summary
+            .seconds_per_contig
+            .entry(contig.clone())
+            .or_default();
+        let fragments_per_second = summary
+            .fragments_per_second_per_contig
+            .entry(contig.clone())
+            .or_default();
+        // each pass reads all fragments of the contig
+        let number_of_fragments = *fragments_per_second * *seconds
+            + pass_summary.fragments_per_second_per_contig[&contig] * pass_seconds;
+        *seconds += pass_seconds;
+        *fragments_per_second = if *seconds > 0.0 {
+            number_of_fragments / *seconds
+        } else {
+            0.0
+        };
+    }
+    for (contig, number_of_fragments) in pass_summary.written_fragments_per_contig {
+        *summary
+            .written_fragments_per_contig
+            .entry(contig)
+            .or_default() += number_of_fragments;
+    }
+    summary
+        .fragments_per_cell_type
+        .extend(pass_summary.fragments_per_cell_type);
+    summary.output_files.extend(pass_summary.output_files);
+    summary.output_files.sort();
+    if let (Some(length_histograms), Some(pass_length_histograms)) = (
+        summary.length_histograms.as_mut(),
+        pass_summary.length_histograms,
+    ) {
+        length_histograms.extend(pass_length_histograms);
+    }
+    summary
+}
+
+/// Splits a tabix-index fragment file into multiple files based on cell type,
+/// with the cell types of the cell barcodes read from a TSV file, see `read_cell_barcode_to_cell_type`.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `path_to_output_folder` - Path to the output folder, see `split_fragments_by_cell_barcode`.
+/// * `path_to_barcode_tsv` - Path to a (gzip compressed) TSV file with two columns, the cell barcode
+///     and the cell type, without header.
+/// * `chromsizes` - A HashMap mapping contig names to contig sizes.
+///     If empty, all contigs of the fragments file (as listed in its index) are processed.
+/// * `options` - Options, see `SplitOptions`.
+///
+/// # Returns
+///
+/// A `SplitSummary`, see `split_fragments_by_cell_barcode`.
+pub fn split_fragments_by_cell_barcode_from_file(
+    path_to_fragments: &String,
+    path_to_output_folder: &String,
+    path_to_barcode_tsv: &str,
+    chromsizes: HashMap<String, u64>,
+    options: &SplitOptions,
+) -> FragmentToolsResult<SplitSummary> {
+    log(
+        &format!("Reading cell types of {}", path_to_barcode_tsv),
+        options.verbose,
+    );
+    let cell_barcode_to_cell_type = read_cell_barcode_to_cell_type(path_to_barcode_tsv)?;
+    split_fragments_by_cell_barcode(
+        path_to_fragments,
+        path_to_output_folder,
+        cell_barcode_to_cell_type,
+        chromsizes,
+        options,
+    )
+}
+
+/// Compiles the regular expression of `SplitOptions::split_regex`, which should have at least one capture group.
+fn compile_split_regex(split_regex: &str) -> FragmentToolsResult<Regex> {
+    let regex = Regex::new(split_regex).map_err(|e| {
+        FragmentToolsError::InvalidArgument(format!("Invalid split_regex {:?}: {}", split_regex, e))
+    })?;
+    // the implicit group of the whole match is included in the number of capture groups
+    if regex.captures_len() < 2 {
+        return Err(FragmentToolsError::InvalidArgument(format!(
+            "Invalid split_regex {:?}, it should have at least one capture group",
+            split_regex
+        )));
+    }
+    Ok(regex)
+}
+
+/// Maps the cell barcodes of a fragments file to the first capture group of a regular expression.
+///
+/// # Arguments
+///
+/// * `tbx_reader` - Reader of the fragments file.
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `contigs` - The contigs to read.
+/// * `chromsizes` - A HashMap mapping contig names to contig sizes.
+/// * `format` - Format of the cell barcode column.
+/// * `comment_prefix` - If set, lines starting with this prefix are skipped.
+/// * `split_regex` - The regular expression.
+/// * `barcode_transform` - If set, the transformation of the cell barcodes, which is applied before matching them.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// A HashMap mapping each matching (transformed) cell barcode to its captured value.
+/// Cell barcodes which do not match, or of which the first group is empty, are left out (with a warning).
+#[allow(clippy::too_many_arguments)]
+fn group_cell_barcodes_by_regex(
+    tbx_reader: &mut FragmentsFileReader,
+    path_to_fragments: &str,
+    contigs: &[&String],
+    chromsizes: &HashMap<String, u64>,
+    format: &FragmentFormat,
+    comment_prefix: Option<&str>,
+    split_regex: &Regex,
+    barcode_transform: Option<&BarcodeTransform>,
+    verbose: bool,
+) -> FragmentToolsResult<HashMap<String, Vec<String>>> {
+    let mut cell_barcodes: HashSet<String> = HashSet::new();
+    for &contig in contigs {
+        log(
+            &format!("Collecting cell barcodes of contig {}", contig),
+            verbose,
+        );
+        for_each_fragment_in_contig(
+            tbx_reader,
+            path_to_fragments,
+            contig,
+            *chromsizes.get(contig).unwrap(),
+            |read| {
+                // header lines result in an error when splitting
+                if comment_prefix.is_some_and(|prefix| read.starts_with(prefix.as_bytes())) {
+                    return Ok(());
+                }
+                let cell_barcode = format
+                    .cell_barcode(cell_barcode_of_read(read, path_to_fragments)?)
+                    .map_err(|e| {
+                        FragmentToolsError::InvalidFragmentFile(
+                            FragmentFileErrorKind::Malformed,
+                            format!("{} ({})", e, path_to_fragments),
+                        )
+                    })?;
+                let cell_barcode = match barcode_transform {
+                    Some(barcode_transform) => barcode_transform.apply(cell_barcode),
+                    None => Cow::Borrowed(cell_barcode),
+                };
+                if !cell_barcodes.contains(cell_barcode.as_ref()) {
+                    cell_barcodes.insert(cell_barcode.into_owned());
+                }
+                Ok(())
+            },
+        )?;
+    }
+
+    let mut cell_barcode_to_group: HashMap<String, Vec<String>> = HashMap::new();
+    let mut number_of_unmatched_cell_barcodes = 0;
+    for cell_barcode in cell_barcodes {
+        match split_regex
+            .captures(&cell_barcode)
+            .and_then(|captures| captures.get(1))
+            .filter(|group| !group.is_empty())
+            .map(|group| group.as_str().to_string())
+        {
+            Some(group) => {
+                cell_barcode_to_group.insert(cell_barcode, vec![group]);
+            }
+            None => number_of_unmatched_cell_barcodes += 1,
+        }
+    }
+    if number_of_unmatched_cell_barcodes > 0 {
+        println!(
+            "Warning: {} cell barcode(s) of {} do not match split_regex {:?}, their fragments are not written",
+            number_of_unmatched_cell_barcodes,
+            path_to_fragments,
+            split_regex.as_str()
+        );
+    }
+    Ok(cell_barcode_to_group)
+}
+
+/// Estimates the output of splitting a fragment file by cell barcode, without writing anything.
+///
+/// The fragments per cell type are counted exactly in a single pass over all contigs of the index.
+/// The size of each output file is estimated as its number of fragments times an average number of
+/// compressed bytes per fragment.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `cell_barcode_to_cell_type` - A HashMap mapping cell barcodes to cell types.
+///     Fragments of a cell barcode with several cell types are counted for each of them.
+/// * `bytes_per_fragment` - Average number of compressed bytes per fragment. If None, the average
+///     of the fragments file itself (its size divided by its number of fragments) is used.
+/// * `verbose` - Whether to print progress messages.
+pub fn estimate_split_sizes(
+    path_to_fragments: &str,
+    cell_barcode_to_cell_type: &HashMap<String, Vec<String>>,
+    bytes_per_fragment: Option<f64>,
+    verbose: bool,
+) -> FragmentToolsResult<HashMap<String, SplitSizeEstimate>> {
+    if let Some(bytes_per_fragment) = bytes_per_fragment {
+        if !bytes_per_fragment.is_finite() || bytes_per_fragment < 0.0 {
+            return Err(FragmentToolsError::InvalidArgument(format!(
+                "bytes_per_fragment should be a non-negative number, got {}",
+                bytes_per_fragment
+            )));
+        }
+    }
+
+    // every cell type gets an estimate, also when none of its cell barcodes has fragments
+    let mut cell_type_to_number_of_fragments: HashMap<&String, u64> = cell_barcode_to_cell_type
+        .values()
+        .flatten()
+        .map(|cell_type| (cell_type, 0))
+        .collect();
+    let mut number_of_fragments: u64 = 0;
+
+    let mut tbx_reader = open_fragments_file(path_to_fragments)?;
+    for contig in tbx_reader.seqnames() {
+        log(&format!("Counting fragments of contig {}", contig), verbose);
+        for_each_fragment_in_contig(
+            &mut tbx_reader,
+            path_to_fragments,
+            &contig,
+            WHOLE_CONTIG,
+            |read| {
+                number_of_fragments += 1;
+                let read_cb = cell_barcode_of_read(read, path_to_fragments)?;
+                if let Some(cell_types) = cell_barcode_to_cell_type.get(read_cb) {
+                    for cell_type in cell_types {
+                        *cell_type_to_number_of_fragments.get_mut(cell_type).unwrap() += 1;
+                    }
+                }
+                Ok(())
+            },
+        )?;
+    }
+
+    let bytes_per_fragment = match bytes_per_fragment {
+        Some(bytes_per_fragment) => bytes_per_fragment,
+        None if number_of_fragments == 0 => 0.0,
+        None => {
+            let file_size = std::fs::metadata(path_to_fragments)
+                .map_err(|e| {
+                    FragmentToolsError::Io(format!(
+                        "Could not get the size of {}: {}",
+                        path_to_fragments, e
+                    ))
+                })?
+                .len();
+            file_size as f64 / number_of_fragments as f64
+        }
+    };
+    log(
+        &format!(
+            "Estimating sizes with {:.2} bytes per fragment",
+            bytes_per_fragment
+        ),
+        verbose,
+    );
+
+    Ok(cell_type_to_number_of_fragments
+        .into_iter()
+        .map(|(cell_type, number_of_fragments)| {
+            (
+                cell_type.to_string(),
+                SplitSizeEstimate {
+                    number_of_fragments,
+                    estimated_bytes: (number_of_fragments as f64 * bytes_per_fragment).round()
+                        as u64,
+                },
+            )
+        })
+        .collect())
+}
+
+/// Writes a parsed fragment to the file of a cell type, in the standard layout.
+///
+/// # Arguments
+///
+/// * `cell_type` - The cell type.
+/// * `fragment` - The fragment.
+/// * `new_barcode` - If set, the cell barcode of the fragment is written with this new name,
+///     see `SplitOptions::barcode_rename`.
+/// * `score_precision` - Number of decimals of the score, see `FragmentFormat::score_precision`.
+/// * `cell_type_to_writer` - BGZF writers per cell type.
+/// * `cell_type_to_parquet_writer` - Parquet writers per cell type, used instead if the cell type has one.
+/// * `cell_type_to_fragment_count` - Number of fragments written per cell type, incremented for the fragment.
+/// * `length_histograms` - If set, fragment length histograms per cell type, in which the fragment is counted.
+#[allow(clippy::too_many_arguments)]
+fn write_parsed_fragment<'a>(
+    cell_type: &'a String,
+    fragment: &Fragment,
+    new_barcode: Option<&str>,
+    score_precision: Option<u32>,
+    cell_type_to_writer: &mut HashMap<&String, LazyBgzfWriter>,
+    cell_type_to_parquet_writer: &mut HashMap<&String, ParquetFragmentWriter>,
+    cell_type_to_fragment_count: &mut HashMap<&'a String, u64>,
+    length_histograms: Option<&mut LengthHistograms<'a>>,
+) -> FragmentToolsResult<()> {
+    *cell_type_to_fragment_count.entry(cell_type).or_default() += 1;
+    if let Some(length_histograms) = length_histograms {
+        length_histograms.add(cell_type, fragment.end.saturating_sub(fragment.start));
+    }
+    let renamed_fragment = new_barcode.map(|new_barcode| Fragment {
+        cell_barcode: new_barcode.to_string(),
+        ..fragment.clone()
+    });
+    let fragment = renamed_fragment.as_ref().unwrap_or(fragment);
+    if let Some(parquet_writer) = cell_type_to_parquet_writer.get_mut(cell_type) {
+        return parquet_writer.write(fragment);
+    }
+    cell_type_to_writer
+        .get_mut(cell_type)
+        .unwrap()
+        .write(
+            format!(
+                "{}\n",
+                fragment.to_string_with_score_precision(score_precision)
+            )
+            .as_bytes(),
+        )
+        .map_err(|e| FragmentToolsError::Io(e.to_string()))?;
+    Ok(())
+}
+
+/// Returns the new name of the cell barcode of a parsed fragment, see `SplitOptions::barcode_rename`.
+///
+/// The cell barcode is transformed first, as it was to look up its cell types. Returns `None` if the
+/// cell barcodes are not renamed.
+///
+/// # Arguments
+///
+/// * `fragment` - The fragment, with its cell barcode as read from the fragments file.
+/// * `barcode_transform` - If set, the transformation of the cell barcodes, see `SplitOptions::barcode_transform`.
+/// * `barcode_rename` - If set, the new names of the (transformed) cell barcodes.
+fn new_barcode_of_fragment(
+    fragment: &Fragment,
+    barcode_transform: Option<&BarcodeTransform>,
+    barcode_rename: Option<&BarcodeRename>,
+) -> Option<String> {
+    let barcode_rename = barcode_rename?;
+    let cell_barcode = match barcode_transform {
+        Some(barcode_transform) => barcode_transform.apply(&fragment.cell_barcode),
+        None => Cow::Borrowed(fragment.cell_barcode.as_str()),
+    };
+    barcode_rename.rename(&cell_barcode).map(str::to_string)
+}
+
+/// Returns the start and end of a fragment (line), without parsing the other columns.
+///
+/// Returns None when the start or end column is missing or not a number,
+/// these lines are reported when the fragment is parsed.
+fn positions_of_read(read: &[u8]) -> Option<(usize, usize)> {
+    let mut fields = read.split(|&byte| byte == b'\t').skip(1);
+    let mut parse_position =
+        || -> Option<usize> { std::str::from_utf8(fields.next()?).ok()?.parse().ok() };
+    Some((parse_position()?, parse_position()?))
+}
+
+/// Whether a fragment (line) starts where it ends, without parsing the other columns.
+///
+/// Lines of which the start or end column is missing or not a number are not zero-length.
+fn is_zero_length_read(read: &[u8]) -> bool {
+    positions_of_read(read).is_some_and(|(start, end)| start == end)
+}
+
+/// Returns a copy of a fragment (line) of which the cell barcode column (fourth column) is replaced.
+///
+/// # Arguments
+///
+/// * `read` - The fragment, as read from the fragments file.
+/// * `cell_barcode` - The cell barcode to write instead.
+fn replace_cell_barcode_of_read(read: &[u8], cell_barcode: &str) -> Vec<u8> {
+    let mut renamed_read: Vec<u8> = Vec::with_capacity(read.len() + cell_barcode.len());
+    for (index, field) in read.split(|byte| *byte == b'\t').enumerate() {
+        if index > 0 {
+            renamed_read.push(b'\t');
+        }
+        renamed_read.extend_from_slice(if index == 3 {
+            cell_barcode.as_bytes()
+        } else {
+            field
+        });
+    }
+    renamed_read
+}
+
+/// Writes the number of fragments written per cell type to a TSV file (with header `cell_type\tfragments`).
+///
+/// The file is written to a temporary path and then moved to its final path, replacing the previous version,
+/// so readers never see a partially written file.
+///
+/// # Arguments
+///
+/// * `path_to_counts_file` - Path to the counts file.
+/// * `cell_types` - The cell types, in the order in which they are written.
+///     Cell types without fragments so far are written with a count of 0.
+/// * `cell_type_to_fragment_count` - Number of fragments written per cell type.
+fn write_counts_file(
+    path_to_counts_file: &str,
+    cell_types: &[&String],
+    cell_type_to_fragment_count: &HashMap<&String, u64>,
+) -> FragmentToolsResult<()> {
+    let write_error = |e: std::io::Error| {
+        FragmentToolsError::Io(format!(
+            "Could not write counts file {}: {}",
+            path_to_counts_file, e
+        ))
+    };
+    let mut counts = String::from("cell_type\tfragments\n");
+    for cell_type in cell_types {
+        counts.push_str(&format!(
+            "{}\t{}\n",
+            cell_type,
+            cell_type_to_fragment_count.get(cell_type).unwrap_or(&0)
+        ));
+    }
+    std::fs::write(temporary_path(path_to_counts_file), counts).map_err(write_error)?;
+    rename(temporary_path(path_to_counts_file), path_to_counts_file).map_err(write_error)
+}
+
+/// Moves files into a (new) tar archive, each file is added under its file name.
+///
+/// # Arguments
+///
+/// * `path_to_tar_archive` - Path to the tar archive.
+/// * `paths_to_files` - Paths to the files to move into the archive.
+/// * `verbose` - Whether to print progress messages.
+fn write_tar_archive(
+    path_to_tar_archive: &str,
+    paths_to_files: &[String],
+    verbose: bool,
+) -> FragmentToolsResult<()> {
+    log(&format!("Writing {}", path_to_tar_archive), verbose);
+    let tar_error = |e: std::io::Error| {
+        FragmentToolsError::Io(format!(
+            "Could not write tar archive {}: {}",
+            path_to_tar_archive, e
+        ))
+    };
+    let mut tar_builder = tar::Builder::new(File::create(path_to_tar_archive).map_err(tar_error)?);
+    for path_to_file in paths_to_files {
+        let file_name = Path::new(path_to_file).file_name().unwrap();
+        tar_builder
+            .append_path_with_name(path_to_file, file_name)
+            .map_err(tar_error)?;
+    }
+    tar_builder.finish().map_err(tar_error)?;
+    for path_to_file in paths_to_files {
+        remove_file(path_to_file).map_err(|e| {
+            FragmentToolsError::Io(format!("Could not remove file {}: {}", path_to_file, e))
+        })?;
+    }
+    Ok(())
+}
+
+/// Parses a fragment read from a fragments file.
+fn parse_read(
+    read: &[u8],
+    path_to_fragments: &str,
+    format: &FragmentFormat,
+) -> FragmentToolsResult<Fragment> {
+    std::str::from_utf8(read)
+        .map_err(|e| e.to_string())
+        .and_then(|line| Fragment::new_from_string_with_format(line, format))
+        .map_err(|e| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Malformed,
+                format!("{} ({})", e, path_to_fragments),
+            )
+        })
+}
+
+/// Splits in-memory fragments into multiple groups based on cell type.
+///
+/// # Arguments
+///
+/// * `fragments` - Fragments to split, in any order.
+/// * `path_to_output_folder` - Path to the output folder. If set,
+///     one file per cell type will be written here and the cell type name will be used as the filename,
+///     with spaces and slashes replaced by underscores. Cell types which get the same filename are an error.
+///     If there are no fragments for a cell type, no file will be written for that cell type.
+/// * `cell_barcode_to_cell_type` - A HashMap mapping cell barcodes to cell types.
+/// * `number_of_threads` - Number of threads to use for writing.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// A HashMap mapping cell types to their sorted fragments.
+pub fn split_fragments_in_memory(
+    mut fragments: Vec<Fragment>,
+    path_to_output_folder: Option<&String>,
+    cell_barcode_to_cell_type: HashMap<String, Vec<String>>,
+    number_of_threads: u32,
+    verbose: bool,
+) -> FragmentToolsResult<HashMap<String, Vec<Fragment>>> {
+    log("Sorting fragments", verbose);
+    fragments.sort_unstable();
+
+    let mut cell_type_to_fragments: HashMap<String, Vec<Fragment>> = HashMap::new();
+    for fragment in fragments {
+        if let Some(cell_types) = cell_barcode_to_cell_type.get(&fragment.cell_barcode) {
+            for cell_type in cell_types {
+                cell_type_to_fragments
+                    .entry(cell_type.to_string())
+                    .or_default()
+                    .push(fragment.clone());
+            }
+        }
+    }
+
+    if let Some(path_to_output_folder) = path_to_output_folder {
+        check_file_name_collisions(cell_type_to_fragments.keys())?;
+        for (cell_type, fragments) in cell_type_to_fragments.iter() {
+            let path_to_output = format!(
+                "{}/{}.{}",
+                path_to_output_folder,
+                sanitize_string_for_filename(cell_type.to_string()),
+                OutputCodec::Bgzf.extension()
+            );
+            log(&format!("Writing {}", path_to_output), verbose);
+            write_fragments(fragments, &path_to_output, number_of_threads)?;
+        }
+    }
+    Ok(cell_type_to_fragments)
 }
 
 fn log(message: &str, verbose: bool) {
@@ -0,0 +1,136 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+/// Summary of splitting a fragment file by cell type.
+///
+/// # Fields
+///
+/// * `contig_order` - Contigs in the order in which they were written.
+/// * `checksums` - If computed, a HashMap mapping cell types to the (hex encoded) SHA-256 checksums
+///     of the uncompressed content of their output files.
+/// * `distinct_barcodes` - A HashMap mapping cell types to the number of distinct cell barcodes
+///     of the fragments written for them. Cell types without fragments are not included.
+/// * `truncated_file_names` - A HashMap mapping cell types which are too long to use as file name
+///     to the truncated name (without extension) of their output files.
+/// * `zero_length_fragments` - Number of dropped zero-length fragments (with start equal to end)
+///     of the annotated cell barcodes.
+/// * `blacklisted_fragments` - Number of dropped fragments of the annotated cell barcodes
+///     which overlap a blacklist region.
+/// * `seconds_per_contig` - A HashMap mapping the processed contigs to the elapsed time (in seconds)
+///     of reading and writing their fragments.
+/// * `fragments_per_second_per_contig` - A HashMap mapping the processed contigs to the number of
+///     fragments read per second (of all cell barcodes), 0 when no time elapsed.
+/// * `completed_contigs` - Contigs of which all fragments were written, in the order in which they were written.
+///     The same as `contig_order`, unless splitting stopped early (see `error`).
+/// * `written_fragments_per_contig` - A HashMap mapping the completed contigs to the number of fragments
+///     written for them, a fragment written for several cell types is counted for each of them.
+/// * `fragments_per_cell_type` - A HashMap mapping all cell types to the number of fragments written
+///     to their output file, 0 for cell types without file. A fragment of a cell barcode with several
+///     cell types is counted for each of them.
+/// * `error` - If splitting stopped early and a partial summary was returned, the error on which it stopped,
+///     see `SplitOptions::return_partial_on_error`.
+/// * `output_files` - The cell types for which a file was written and the paths of those files,
+///     sorted by cell type. Index files are not included.
+/// * `length_histograms` - If counted, a HashMap mapping all cell types to the histogram of the lengths
+///     of the fragments written for them, see `SplitOptions::length_histogram`.
+/// * `unassigned_fragments` - Number of fragments of cell barcodes without cell type written to
+///     `SplitOptions::path_to_unassigned_output`, 0 when it is not set.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+pub struct SplitSummary {
+    pub contig_order: Vec<String>,
+    pub checksums: Option<HashMap<String, String>>,
+    pub distinct_barcodes: HashMap<String, u64>,
+    pub truncated_file_names: HashMap<String, String>,
+    pub zero_length_fragments: u64,
+    pub blacklisted_fragments: u64,
+    pub seconds_per_contig: HashMap<String, f64>,
+    pub fragments_per_second_per_contig: HashMap<String, f64>,
+    pub completed_contigs: Vec<String>,
+    pub written_fragments_per_contig: HashMap<String, u64>,
+    pub fragments_per_cell_type: HashMap<String, u64>,
+    pub error: Option<String>,
+    pub output_files: Vec<(String, String)>,
+    pub length_histograms: Option<HashMap<String, Vec<u64>>>,
+    pub unassigned_fragments: u64,
+}
+
+/// Estimated output of splitting a fragment file for a single cell type.
+///
+/// # Fields
+///
+/// * `number_of_fragments` - Number of fragments that would be written, this number is exact.
+/// * `estimated_bytes` - Estimated size of the BGZF compressed output file in bytes.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+pub struct SplitSizeEstimate {
+    pub number_of_fragments: u64,
+    pub estimated_bytes: u64,
+}
+
+/// Summary of merging fragment files.
+///
+/// # Fields
+///
+/// * `contig_order` - Contigs in the order in which they were written.
+/// * `zero_length_fragments` - Number of dropped zero-length fragments (with start equal to end).
+/// * `shard_paths` - If the output was written in shards, the paths of the shards in sorted order.
+/// * `shard_ranges` - For each shard, the contig and start of its first fragment
+///     and the contig and end of its last fragment.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+pub struct MergeSummary {
+    pub contig_order: Vec<String>,
+    pub zero_length_fragments: u64,
+    pub shard_paths: Vec<String>,
+    pub shard_ranges: Vec<(String, u64, String, u64)>,
+}
+
+/// Report of validating a fragment file.
+///
+/// # Fields
+///
+/// * `number_of_fragments` - Number of fragments in the file.
+/// * `contig_order` - Contigs in the order in which they appear in the file.
+/// * `fragments_per_contig` - A HashMap mapping contigs to their number of fragments.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+pub struct ValidationReport {
+    pub number_of_fragments: u64,
+    pub contig_order: Vec<String>,
+    pub fragments_per_contig: HashMap<String, u64>,
+}
+
+/// Report of comparing the output files of a split with the fragments file that was split.
+///
+/// # Fields
+///
+/// * `expected_fragments` - Number of fragments the output files should contain together.
+/// * `missing_fragments` - Number of expected fragments which are not in the output files.
+/// * `extra_fragments` - Number of fragments in the output files which were not expected.
+/// * `missing_examples` - Some of the missing fragments (lines), sorted.
+/// * `extra_examples` - Some of the extra fragments (lines), sorted.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+pub struct SplitCompletenessReport {
+    pub expected_fragments: u64,
+    pub missing_fragments: u64,
+    pub extra_fragments: u64,
+    pub missing_examples: Vec<String>,
+    pub extra_examples: Vec<String>,
+}
+
+/// Statistics of a fragment file, read from its tabix index when it has one.
+///
+/// # Fields
+///
+/// * `contigs` - Contigs of the file: in the order of the index header when indexed,
+///     otherwise in the order in which they appear in the file.
+/// * `records_per_contig` - A HashMap mapping contigs to their number of records. Taken from the metadata
+///     of the index when indexed, contigs without recorded count are not included.
+/// * `indexed` - Whether the statistics were read from a tabix index.
+/// * `sorted` - Whether the fragments of each contig are in one block and, when scanned, sorted by start position.
+///     An index can only be created for a file of which the fragments are sorted by start position per contig.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+pub struct FragmentFileStats {
+    pub contigs: Vec<String>,
+    pub records_per_contig: HashMap<String, u64>,
+    pub indexed: bool,
+    pub sorted: bool,
+}
@@ -0,0 +1,690 @@
+use crate::custom_errors::{FragmentFileErrorKind, FragmentToolsError, FragmentToolsResult};
+use crate::fragment::TabixColumns;
+use itertools::Itertools;
+use rust_htslib::bgzf;
+use rust_htslib::htslib;
+use rust_htslib::tbx::{self, Read as TbxRead};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+
+/// Contig size to use with `for_each_fragment_in_contig` to fetch a whole contig of unknown size.
+pub(crate) const WHOLE_CONTIG: u64 = i64::MAX as u64;
+
+/// Number of bytes at the start of a file which are needed to detect its compression, see `Compression`.
+pub(crate) const COMPRESSION_HEADER_LENGTH: usize = 14;
+
+/// Compression of a file.
+///
+/// # Variants
+///
+/// * `Bgzf` - BGZF (bgzip) compressed, which can be tabix indexed.
+/// * `Gzip` - Plain gzip compressed, which can only be read from start to end.
+/// * `Other` - Not gzip compressed (e.g. plain text).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Compression {
+    Bgzf,
+    Gzip,
+    Other,
+}
+
+impl Compression {
+    /// Detects the compression from the first `COMPRESSION_HEADER_LENGTH` bytes of a file (or fewer for short files).
+    /// A BGZF file is a gzip file of which the first member has a "BC" extra subfield.
+    pub(crate) fn of_header(header: &[u8]) -> Compression {
+        if header.len() < 2 || header[..2] != [0x1f, 0x8b] {
+            Compression::Other
+        } else if header.len() == COMPRESSION_HEADER_LENGTH
+            && header[2..4] == [0x08, 0x04]
+            && header[12..14] == [b'B', b'C']
+        {
+            Compression::Bgzf
+        } else {
+            Compression::Gzip
+        }
+    }
+
+    /// Detects the compression of a file, `None` if it can not be read.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the file.
+    pub(crate) fn of_file(path: &str) -> Option<Compression> {
+        let mut header = Vec::with_capacity(COMPRESSION_HEADER_LENGTH);
+        File::open(path)
+            .ok()?
+            .take(COMPRESSION_HEADER_LENGTH as u64)
+            .read_to_end(&mut header)
+            .ok()?;
+        Some(Compression::of_header(&header))
+    }
+}
+
+/// Reader of a tabix-indexed fragment file, see `open_fragments_file`.
+///
+/// Dereferences to the tabix reader. It also remembers whether the index turned out to be stale
+/// (see `for_each_fragment_in_contig`), so the file only needs to be scanned once to locate the contigs.
+///
+/// # Fields
+///
+/// * `tbx_reader` - Tabix reader of the fragments file.
+/// * `contig_offsets_without_index` - Virtual file offsets of the first and past the last fragment of each contig,
+///   found with a linear scan after the index turned out to be stale. `None` as long as the index is used.
+pub(crate) struct FragmentsFileReader {
+    tbx_reader: tbx::Reader,
+    contig_offsets_without_index: Option<HashMap<Vec<u8>, (i64, i64)>>,
+}
+
+impl Deref for FragmentsFileReader {
+    type Target = tbx::Reader;
+
+    fn deref(&self) -> &tbx::Reader {
+        &self.tbx_reader
+    }
+}
+
+impl DerefMut for FragmentsFileReader {
+    fn deref_mut(&mut self) -> &mut tbx::Reader {
+        &mut self.tbx_reader
+    }
+}
+
+/// Opens a tabix-indexed fragment file.
+///
+/// Plain gzip compressed files can not be indexed, opening one results in an error asking to recompress it
+/// with bgzip.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+pub(crate) fn open_fragments_file(
+    path_to_fragments: &str,
+) -> FragmentToolsResult<FragmentsFileReader> {
+    let tbx_reader = tbx::Reader::from_path(path_to_fragments).map_err(|_| {
+        // an existing file without (readable) index can not be opened either
+        if !Path::new(path_to_fragments).exists() {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not open file {}", path_to_fragments),
+            )
+        } else if Compression::of_file(path_to_fragments) == Some(Compression::Gzip) {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Index,
+                format!(
+                    "Could not open file {}: it is compressed with gzip instead of bgzip, so it can not be \
+                    tabix indexed. Recompress it with bgzip (e.g. zcat {} | bgzip > fragments.tsv.gz) \
+                    and index it with tabix -p bed",
+                    path_to_fragments, path_to_fragments
+                ),
+            )
+        } else if !has_tabix_index(path_to_fragments) {
+            let compressed_index = format!("{}.tbi.gz", path_to_fragments);
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Index,
+                if Path::new(&compressed_index).exists() {
+                    format!(
+                        "Could not open file {}, it has no tabix index: {} is a gzip compressed index, \
+                        which can not be read. Decompress it or regenerate the index (e.g. with tabix -p bed)",
+                        path_to_fragments, compressed_index
+                    )
+                } else {
+                    format!(
+                        "Could not open file {}, it has no tabix index",
+                        path_to_fragments
+                    )
+                },
+            )
+        } else if TabixIndex::load(path_to_fragments).is_none() {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Index,
+                format!(
+                    "Could not open file {}: {}",
+                    path_to_fragments,
+                    unreadable_index_message(path_to_fragments)
+                ),
+            )
+        } else {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not open file {}", path_to_fragments),
+            )
+        }
+    })?;
+    Ok(FragmentsFileReader {
+        tbx_reader,
+        contig_offsets_without_index: None,
+    })
+}
+
+/// Returns whether a tabix (`.tbi`) or CSI (`.csi`) index exists next to a fragments file.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+pub(crate) fn has_tabix_index(path_to_fragments: &str) -> bool {
+    [".tbi", ".csi"]
+        .iter()
+        .any(|extension| Path::new(&format!("{}{}", path_to_fragments, extension)).exists())
+}
+
+/// Describes a tabix index which exists but can not be loaded, see `has_tabix_index`.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+pub(crate) fn unreadable_index_message(path_to_fragments: &str) -> String {
+    format!(
+        "the tabix index of {} could not be read, it may be corrupt or in another format. \
+        Regenerate the index (e.g. with tabix -p bed)",
+        path_to_fragments
+    )
+}
+
+/// Creates a tabix index (`.tbi`) for a BGZF compressed fragment file, sorted by contig and position.
+///
+/// The first line which is indexed is checked against the columns of the index first, so a wrong
+/// column layout results in a clear error instead of an index with wrong positions.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `tabix_columns` - Column layout of the index, `TabixColumns::default()` for fragment files.
+pub(crate) fn build_tabix_index(
+    path_to_fragments: &str,
+    tabix_columns: &TabixColumns,
+) -> FragmentToolsResult<()> {
+    check_first_line_for_index(path_to_fragments, tabix_columns)?;
+    let c_path = CString::new(path_to_fragments).map_err(|_| {
+        FragmentToolsError::InvalidArgument(format!("Invalid path {:?}", path_to_fragments))
+    })?;
+    let tabix_conf = htslib::tbx_conf_t {
+        preset: htslib::TBX_UCSC as i32,
+        sc: tabix_columns.seq_col as i32 + 1,
+        bc: tabix_columns.begin_col as i32 + 1,
+        ec: tabix_columns.end_col as i32 + 1,
+        meta_char: tabix_columns.comment_char as i32,
+        line_skip: tabix_columns.skip_lines as i32,
+    };
+    let status = unsafe { htslib::tbx_index_build(c_path.as_ptr(), 0, &tabix_conf) };
+    if status < 0 {
+        return Err(FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Index,
+            format!(
+                "Could not create a tabix index for {}, it should be BGZF compressed and sorted by contig and position",
+                path_to_fragments
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the first line which would be indexed (skipping `skip_lines` lines and comment lines)
+/// against the columns of the index, see `TabixColumns::check_line`.
+fn check_first_line_for_index(
+    path_to_fragments: &str,
+    tabix_columns: &TabixColumns,
+) -> FragmentToolsResult<()> {
+    let reader = bgzf::Reader::from_path(path_to_fragments).map_err(|_| {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Unreadable,
+            format!("Could not open file {}", path_to_fragments),
+        )
+    })?;
+    let first_line = BufReader::new(reader)
+        .lines()
+        .skip(tabix_columns.skip_lines)
+        .find(|line| {
+            line.as_ref()
+                .map_or(true, |line| !line.starts_with(tabix_columns.comment_char))
+        })
+        .transpose()
+        .map_err(|e| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not read file {}: {}", path_to_fragments, e),
+            )
+        })?;
+    match first_line {
+        Some(first_line) => tabix_columns.check_line(&first_line).map_err(|e| {
+            FragmentToolsError::InvalidArgument(format!(
+                "Could not create a tabix index for {}: {}",
+                path_to_fragments, e
+            ))
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Returns the contigs of chromsizes that are present in the fragments file, sorted by name.
+///
+/// # Arguments
+///
+/// * `contigs_in_fragments_file` - Contigs of the fragments file, e.g. from `tbx::Reader::seqnames`.
+/// * `chromsizes` - A HashMap mapping contig names to contig sizes.
+/// * `verbose` - Whether to print progress messages.
+pub(crate) fn contigs_to_process<'a>(
+    contigs_in_fragments_file: &[String],
+    chromsizes: &'a HashMap<String, u64>,
+    verbose: bool,
+) -> Vec<&'a String> {
+    chromsizes
+        .keys()
+        .sorted()
+        .filter(|contig| {
+            let in_fragments_file = contigs_in_fragments_file.contains(contig);
+            if !in_fragments_file {
+                log(
+                    &format!(
+                        "Skipping contig {} because it is not in the fragments file",
+                        contig
+                    ),
+                    verbose,
+                );
+            }
+            in_fragments_file
+        })
+        .collect()
+}
+
+/// Fetches a whole contig from a fragments file and calls `f` on each fragment (line).
+///
+/// The index is used to seek to the start of the contig. When the fragments file was changed
+/// after the index was created, this seek can end up at the wrong position. If the first fragment
+/// after the seek is not on the requested contig (or can not be read), the index is considered stale:
+/// the file is scanned once to locate all contigs, and this and all following contigs are read from
+/// the offsets found by that scan instead of from the index.
+///
+/// # Arguments
+///
+/// * `tbx_reader` - Reader of the fragments file.
+/// * `path_to_fragments` - Path to the fragments file, used in error messages.
+/// * `contig` - Name of the contig to fetch.
+/// * `contig_size` - Size of the contig, fragments starting at or after it are skipped.
+/// * `f` - Function to call on each fragment.
+pub(crate) fn for_each_fragment_in_contig<F>(
+    tbx_reader: &mut FragmentsFileReader,
+    path_to_fragments: &str,
+    contig: &str,
+    contig_size: u64,
+    mut f: F,
+) -> FragmentToolsResult<()>
+where
+    F: FnMut(&[u8]) -> FragmentToolsResult<()>,
+{
+    if let Some(contig_offsets) = &tbx_reader.contig_offsets_without_index {
+        return for_each_fragment_in_contig_without_index(
+            path_to_fragments,
+            contig,
+            contig_size,
+            contig_offsets.get(contig.as_bytes()).copied(),
+            f,
+        );
+    }
+
+    // get contig id and fetch whole contig
+    let contig_id = tbx_reader.tid(contig).map_err(|_| {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Index,
+            format!(
+                "Could not get contig id for contig {} in {}",
+                contig, path_to_fragments
+            ),
+        )
+    })?;
+    tbx_reader.fetch(contig_id, 0, contig_size).map_err(|_| {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Index,
+            format!(
+                "Could not fetch contig {} from fragments file {}",
+                contig, path_to_fragments
+            ),
+        )
+    })?;
+
+    // check that the seek ended up at a fragment of the requested contig
+    let mut read: Vec<u8> = Vec::new();
+    let (index_is_valid, mut has_read) = match tbx_reader.read(&mut read) {
+        Ok(true) => (contig_of_read(&read) == contig.as_bytes(), true),
+        // no fragments, which is only expected when the index does not list any for this contig
+        Ok(false) => (
+            TabixIndex::load(path_to_fragments)
+                .and_then(|tabix_index| tabix_index.number_of_records(contig_id))
+                .unwrap_or(0)
+                == 0,
+            false,
+        ),
+        Err(_) => (false, false),
+    };
+    if !index_is_valid {
+        println!(
+            "Warning: the index of {} seems to be out of date for contig {}, \
+            falling back to scanning the whole file for this and the following contigs. \
+            Re-index the file to avoid this.",
+            path_to_fragments, contig
+        );
+        tbx_reader.contig_offsets_without_index = Some(scan_contig_offsets(path_to_fragments)?);
+        return for_each_fragment_in_contig(tbx_reader, path_to_fragments, contig, contig_size, f);
+    }
+
+    // loop over reads
+    while has_read {
+        f(&read)?;
+        read.clear();
+        has_read = tbx_reader.read(&mut read).map_err(|_| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!(
+                    "Could not read fragment on contig {} from {}",
+                    contig, path_to_fragments
+                ),
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Scans a fragments file from start to end and returns the virtual file offsets of the first fragment
+/// and past the last fragment of each contig.
+///
+/// When the fragments of a contig are not in one block, the offsets span all blocks of that contig.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+fn scan_contig_offsets(
+    path_to_fragments: &str,
+) -> FragmentToolsResult<HashMap<Vec<u8>, (i64, i64)>> {
+    let mut bgzf_file = BgzfFile::open(path_to_fragments)?;
+    let mut contig_offsets: HashMap<Vec<u8>, (i64, i64)> = HashMap::new();
+    let mut read: Vec<u8> = Vec::new();
+    let mut offset = bgzf_file.tell();
+    while bgzf_file.read_line(&mut read, path_to_fragments)? {
+        let next_offset = bgzf_file.tell();
+        if !read.starts_with(b"#") {
+            let contig = contig_of_read(&read);
+            match contig_offsets.get_mut(contig) {
+                Some((_, end)) => *end = next_offset,
+                None => {
+                    contig_offsets.insert(contig.to_vec(), (offset, next_offset));
+                }
+            }
+        }
+        offset = next_offset;
+        read.clear();
+    }
+    Ok(contig_offsets)
+}
+
+/// Reads the fragments of a contig between the offsets found by `scan_contig_offsets` and calls `f` on each
+/// fragment (line) of the contig which starts before `contig_size`, like fetching it with the index would.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file.
+/// * `contig` - Name of the contig.
+/// * `contig_size` - Size of the contig.
+/// * `contig_offsets` - Virtual file offsets of the first and past the last fragment of the contig,
+///   `None` if the contig has no fragments.
+/// * `f` - Function to call on each fragment.
+fn for_each_fragment_in_contig_without_index<F>(
+    path_to_fragments: &str,
+    contig: &str,
+    contig_size: u64,
+    contig_offsets: Option<(i64, i64)>,
+    mut f: F,
+) -> FragmentToolsResult<()>
+where
+    F: FnMut(&[u8]) -> FragmentToolsResult<()>,
+{
+    let Some((start, end)) = contig_offsets else {
+        return Ok(());
+    };
+    let mut bgzf_file = BgzfFile::open(path_to_fragments)?;
+    bgzf_file.seek(start, path_to_fragments)?;
+    let mut read: Vec<u8> = Vec::new();
+    while bgzf_file.tell() < end && bgzf_file.read_line(&mut read, path_to_fragments)? {
+        if !read.starts_with(b"#")
+            && contig_of_read(&read) == contig.as_bytes()
+            && start_of_read(&read).is_none_or(|start| start < contig_size)
+        {
+            f(&read)?;
+        }
+        read.clear();
+    }
+    Ok(())
+}
+
+/// Returns the contig (first column) of a fragment.
+fn contig_of_read(read: &[u8]) -> &[u8] {
+    read.split(|byte| *byte == b'\t').next().unwrap_or_default()
+}
+
+/// Returns the start position (second column) of a fragment, `None` if it is not a number.
+fn start_of_read(read: &[u8]) -> Option<u64> {
+    std::str::from_utf8(read.split(|byte| *byte == b'\t').nth(1)?)
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Returns the cell barcode (fourth column) of a fragment.
+///
+/// # Arguments
+///
+/// * `read` - The fragment, as read from the fragments file.
+/// * `path_to_fragments` - Path to the fragments file, used in error messages.
+pub(crate) fn cell_barcode_of_read<'a>(
+    read: &'a [u8],
+    path_to_fragments: &str,
+) -> FragmentToolsResult<&'a str> {
+    std::str::from_utf8(read)
+        .ok()
+        .and_then(|read_as_str| read_as_str.split('\t').nth(3))
+        .ok_or_else(|| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Malformed,
+                format!(
+                    "Fragment in {} has no cell barcode column: {}",
+                    path_to_fragments,
+                    String::from_utf8_lossy(read)
+                ),
+            )
+        })
+}
+
+/// Tabix index of a fragment file, loaded directly through htslib.
+///
+/// `rust_htslib::tbx::Reader` does not expose the metadata stored in the index,
+/// so the index is loaded separately to access it.
+///
+/// # Fields
+///
+/// * `tbx` - Pointer to the htslib tabix index.
+pub(crate) struct TabixIndex {
+    tbx: *mut htslib::tbx_t,
+}
+
+impl TabixIndex {
+    /// Loads the tabix index of a fragment file, returns `None` if it could not be loaded.
+    ///
+    /// # Arguments
+    ///
+    /// * `path_to_fragments` - Path to the (indexed) fragments file.
+    pub fn load(path_to_fragments: &str) -> Option<TabixIndex> {
+        let c_path = CString::new(path_to_fragments).ok()?;
+        let tbx = unsafe { htslib::tbx_index_load(c_path.as_ptr()) };
+        if tbx.is_null() {
+            None
+        } else {
+            Some(TabixIndex { tbx })
+        }
+    }
+
+    /// Returns the number of records on a contig, as recorded in the index.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Contig id, as returned by `tbx::Reader::tid`.
+    pub fn number_of_records(&self, tid: u64) -> Option<u64> {
+        let mut mapped: u64 = 0;
+        let mut unmapped: u64 = 0;
+        let status = unsafe {
+            htslib::hts_idx_get_stat((*self.tbx).idx, tid as i32, &mut mapped, &mut unmapped)
+        };
+        if status < 0 {
+            None
+        } else {
+            Some(mapped)
+        }
+    }
+
+    /// Returns the virtual file offsets of the first and the last chunk of a contig, as recorded in the index.
+    ///
+    /// All fragments of the contig lie between these offsets, so when the ranges of two contigs overlap,
+    /// the fragments of those contigs are not in separate blocks.
+    /// Returns `None` if there are no fragments on the contig.
+    ///
+    /// # Arguments
+    ///
+    /// * `tid` - Contig id, as returned by `tbx::Reader::tid`.
+    pub fn contig_offsets(&self, tid: u64) -> Option<(u64, u64)> {
+        let itr = unsafe {
+            htslib::hts_itr_query(
+                (*self.tbx).idx,
+                tid as i32,
+                0,
+                WHOLE_CONTIG as i64,
+                Some(htslib::tbx_readrec),
+            )
+        };
+        if itr.is_null() {
+            return None;
+        }
+        let chunks = unsafe {
+            if (*itr).n_off > 0 {
+                std::slice::from_raw_parts((*itr).off, (*itr).n_off as usize)
+            } else {
+                &[]
+            }
+        };
+        let offsets = chunks
+            .iter()
+            .map(|chunk| chunk.u)
+            .min()
+            .zip(chunks.iter().map(|chunk| chunk.v).max());
+        unsafe { htslib::hts_itr_destroy(itr) };
+        offsets
+    }
+}
+
+impl Drop for TabixIndex {
+    fn drop(&mut self) {
+        unsafe { htslib::tbx_destroy(self.tbx) };
+    }
+}
+
+/// BGZF compressed file, opened directly through htslib.
+///
+/// `rust_htslib::bgzf::Reader` can not seek, so the file is opened separately to read parts of it.
+///
+/// # Fields
+///
+/// * `bgzf` - Pointer to the htslib BGZF file.
+/// * `line` - Buffer of the last line which was read.
+struct BgzfFile {
+    bgzf: *mut htslib::BGZF,
+    line: htslib::kstring_t,
+}
+
+impl BgzfFile {
+    /// Opens a BGZF compressed file for reading.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the file.
+    fn open(path: &str) -> FragmentToolsResult<BgzfFile> {
+        let unreadable = || {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not open file {}", path),
+            )
+        };
+        let c_path = CString::new(path).map_err(|_| unreadable())?;
+        let bgzf = unsafe { htslib::bgzf_open(c_path.as_ptr(), c"r".as_ptr()) };
+        if bgzf.is_null() {
+            return Err(unreadable());
+        }
+        Ok(BgzfFile {
+            bgzf,
+            line: htslib::kstring_t {
+                l: 0,
+                m: 0,
+                s: std::ptr::null_mut(),
+            },
+        })
+    }
+
+    /// Returns the virtual file offset of the next line.
+    fn tell(&self) -> i64 {
+        unsafe { ((*self.bgzf).block_address << 16) | ((*self.bgzf).block_offset as i64 & 0xFFFF) }
+    }
+
+    /// Seeks to a virtual file offset, as returned by `tell`.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - Virtual file offset.
+    /// * `path` - Path to the file, used in error messages.
+    fn seek(&mut self, offset: i64, path: &str) -> FragmentToolsResult<()> {
+        if unsafe { htslib::bgzf_seek(self.bgzf, offset, htslib::SEEK_SET as i32) } < 0 {
+            return Err(FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not seek in file {}", path),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads the next line (without line ending) into `read`, returns `false` at the end of the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `read` - Buffer to which the line is appended.
+    /// * `path` - Path to the file, used in error messages.
+    fn read_line(&mut self, read: &mut Vec<u8>, path: &str) -> FragmentToolsResult<bool> {
+        let status = unsafe { htslib::bgzf_getline(self.bgzf, b'\n' as i32, &mut self.line) };
+        match status {
+            -1 => Ok(false),
+            status if status < -1 => Err(FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not read fragment from {}", path),
+            )),
+            _ => {
+                if self.line.l > 0 {
+                    read.extend_from_slice(unsafe {
+                        std::slice::from_raw_parts(self.line.s as *const u8, self.line.l)
+                    });
+                }
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl Drop for BgzfFile {
+    fn drop(&mut self) {
+        unsafe {
+            htslib::bgzf_close(self.bgzf);
+            htslib::hts_free(self.line.s as *mut _);
+        }
+    }
+}
+
+fn log(message: &str, verbose: bool) {
+    if verbose {
+        println!("{}", message);
+    }
+}
@@ -0,0 +1,589 @@
+use crate::custom_errors::{FragmentFileErrorKind, FragmentToolsError, FragmentToolsResult};
+use crate::fragment::{Fragment, FragmentFormat};
+use crate::summary::{SplitCompletenessReport, ValidationReport};
+use crate::tabix::{
+    cell_barcode_of_read, for_each_fragment_in_contig, has_tabix_index, open_fragments_file,
+    unreadable_index_message, Compression, TabixIndex, COMPRESSION_HEADER_LENGTH, WHOLE_CONTIG,
+};
+use itertools::Itertools;
+use rust_htslib::bgzf::Reader;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Validates a fragment file by reading it from start to end.
+///
+/// Checks that every line can be parsed, that the start of each fragment is not after its end,
+/// that the fragments of each contig are in one block and sorted by start position and,
+/// if chromsizes are given, that each fragment lies within its contig.
+/// Lines starting with `#` are skipped.
+///
+/// Fragments with the same start are sorted, whatever the order of their ends. A fragment which starts
+/// at most `unsorted_tolerance` bp before the largest start seen on its contig so far only results in a
+/// warning. Such files are not properly sorted: fetching a region through a tabix index or merging them
+/// can miss or misplace these fragments, so the tolerance should only cover known rounding artefacts.
+///
+/// With more than one thread and a tabix index, the contigs are validated in parallel,
+/// see `validate_fragment_file_by_contig`.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file (BGZF compressed or uncompressed).
+/// * `format` - Layout of the lines of the file.
+/// * `chromsizes` - Optional HashMap mapping contig names to contig sizes.
+/// * `unsorted_tolerance` - Number of bp a fragment may start before the previous fragments of its contig.
+/// * `number_of_threads` - Number of contigs to validate at the same time.
+/// * `verbose` - Whether to print progress messages.
+///
+/// # Returns
+///
+/// A `ValidationReport`, or an `InvalidFragmentFile` error describing the first problem
+/// (with its line number, or its position on its contig when validating in parallel).
+pub fn validate_fragment_file(
+    path_to_fragments: &str,
+    format: &FragmentFormat,
+    chromsizes: Option<&HashMap<String, u64>>,
+    unsorted_tolerance: u64,
+    number_of_threads: usize,
+    verbose: bool,
+) -> FragmentToolsResult<ValidationReport> {
+    if number_of_threads > 1 {
+        if let Some(tabix_index) = TabixIndex::load(path_to_fragments) {
+            return validate_fragment_file_by_contig(
+                path_to_fragments,
+                &tabix_index,
+                format,
+                chromsizes,
+                unsorted_tolerance,
+                number_of_threads,
+                verbose,
+            );
+        }
+        if has_tabix_index(path_to_fragments) {
+            println!(
+                "Warning: {}, validating with one thread",
+                unreadable_index_message(path_to_fragments)
+            );
+        } else {
+            log(
+                &format!(
+                    "No tabix index found for {}, validating with one thread",
+                    path_to_fragments
+                ),
+                verbose,
+            );
+        }
+    }
+    let reader = Reader::from_path(path_to_fragments).map_err(|_| {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Unreadable,
+            format!("Could not open file {}", path_to_fragments),
+        )
+    })?;
+    let invalid = |kind: FragmentFileErrorKind, line_number: usize, message: String| {
+        FragmentToolsError::InvalidFragmentFile(
+            kind,
+            format!(
+                "{} (line {} of {})",
+                message, line_number, path_to_fragments
+            ),
+        )
+    };
+
+    let mut report = ValidationReport {
+        number_of_fragments: 0,
+        contig_order: Vec::new(),
+        fragments_per_contig: HashMap::new(),
+    };
+    let mut finished_contigs: HashSet<String> = HashSet::new();
+    let mut previous: Option<Fragment> = None;
+    let mut max_start: usize = 0;
+    let mut number_of_tolerated_fragments: u64 = 0;
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.map_err(|e| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not read file {}: {}", path_to_fragments, e),
+            )
+        })?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fragment = Fragment::new_from_string_with_format(&line, format)
+            .map_err(|e| invalid(FragmentFileErrorKind::Malformed, line_number, e))?;
+        check_fragment(&fragment, chromsizes)
+            .map_err(|e| invalid(FragmentFileErrorKind::InvalidCoordinates, line_number, e))?;
+        match &previous {
+            Some(previous) if previous.chrom == fragment.chrom => {
+                if fragment.start < max_start {
+                    if (max_start - fragment.start) as u64 > unsorted_tolerance {
+                        return Err(invalid(
+                            FragmentFileErrorKind::Unsorted,
+                            line_number,
+                            format!(
+                                "Fragments are not sorted by start position: {} comes after {}",
+                                fragment.start, max_start
+                            ),
+                        ));
+                    }
+                    number_of_tolerated_fragments += 1;
+                }
+            }
+            _ => {
+                if let Some(previous) = &previous {
+                    finished_contigs.insert(previous.chrom.clone());
+                }
+                if finished_contigs.contains(&fragment.chrom) {
+                    return Err(invalid(
+                        FragmentFileErrorKind::Unsorted,
+                        line_number,
+                        format!(
+                            "Fragments of contig {} are not in one block",
+                            fragment.chrom
+                        ),
+                    ));
+                }
+                log(&format!("Validating contig {}", fragment.chrom), verbose);
+                report.contig_order.push(fragment.chrom.clone());
+                max_start = 0;
+            }
+        }
+        max_start = max_start.max(fragment.start);
+        report.number_of_fragments += 1;
+        *report
+            .fragments_per_contig
+            .entry(fragment.chrom.clone())
+            .or_default() += 1;
+        previous = Some(fragment);
+    }
+    warn_about_tolerated_fragments(
+        path_to_fragments,
+        number_of_tolerated_fragments,
+        unsorted_tolerance,
+    );
+    Ok(report)
+}
+
+/// Validates the contigs of a tabix-indexed fragment file in parallel, each with its own reader.
+///
+/// The fragments of each contig are fetched through the index and checked like in `validate_fragment_file`.
+/// That the fragments of each contig are in one block is checked with the file offsets of the contigs
+/// recorded in the index, which also give the order of the contigs in the file.
+/// Problems are reported by their position on the contig instead of their line number.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the (tabix-indexed) fragments file.
+/// * `tabix_index` - The tabix index of the fragments file.
+/// * `format` - Layout of the lines of the file.
+/// * `chromsizes` - Optional HashMap mapping contig names to contig sizes.
+/// * `unsorted_tolerance` - Number of bp a fragment may start before the previous fragments of its contig.
+/// * `number_of_threads` - Number of contigs to validate at the same time.
+/// * `verbose` - Whether to print progress messages.
+fn validate_fragment_file_by_contig(
+    path_to_fragments: &str,
+    tabix_index: &TabixIndex,
+    format: &FragmentFormat,
+    chromsizes: Option<&HashMap<String, u64>>,
+    unsorted_tolerance: u64,
+    number_of_threads: usize,
+    verbose: bool,
+) -> FragmentToolsResult<ValidationReport> {
+    let tbx_reader = open_fragments_file(path_to_fragments)?;
+
+    // order the contigs by their position in the file, skipping contigs without fragments
+    let mut contigs_with_offsets: Vec<(String, (u64, u64))> = Vec::new();
+    for contig in tbx_reader.seqnames() {
+        let offsets = tbx_reader
+            .tid(&contig)
+            .ok()
+            .and_then(|contig_id| tabix_index.contig_offsets(contig_id));
+        if let Some(offsets) = offsets {
+            contigs_with_offsets.push((contig, offsets));
+        }
+    }
+    contigs_with_offsets.sort_by_key(|(_, (start, _))| *start);
+    for ((previous_contig, (_, previous_end)), (contig, (start, _))) in
+        contigs_with_offsets.iter().tuple_windows()
+    {
+        if start < previous_end {
+            return Err(FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unsorted,
+                format!(
+                    "Fragments of contigs {} and {} are not in separate blocks ({})",
+                    previous_contig, contig, path_to_fragments
+                ),
+            ));
+        }
+    }
+    let contig_order: Vec<String> = contigs_with_offsets
+        .into_iter()
+        .map(|(contig, _)| contig)
+        .collect();
+
+    // each thread takes the next contig that is not validated yet
+    let next_contig = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<FragmentToolsResult<ContigCounts>>>> =
+        Mutex::new((0..contig_order.len()).map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..number_of_threads.min(contig_order.len()) {
+            scope.spawn(|| loop {
+                let contig_index = next_contig.fetch_add(1, Ordering::SeqCst);
+                let Some(contig) = contig_order.get(contig_index) else {
+                    break;
+                };
+                log(&format!("Validating contig {}", contig), verbose);
+                let result = validate_contig(
+                    path_to_fragments,
+                    contig,
+                    format,
+                    chromsizes,
+                    unsorted_tolerance,
+                );
+                results.lock().unwrap()[contig_index] = Some(result);
+            });
+        }
+    });
+
+    let mut report = ValidationReport {
+        number_of_fragments: 0,
+        contig_order: Vec::new(),
+        fragments_per_contig: HashMap::new(),
+    };
+    let mut number_of_tolerated_fragments: u64 = 0;
+    for (contig, result) in contig_order.into_iter().zip(results.into_inner().unwrap()) {
+        let (number_of_fragments, number_of_tolerated_fragments_on_contig) = result.unwrap()?;
+        number_of_tolerated_fragments += number_of_tolerated_fragments_on_contig;
+        report.number_of_fragments += number_of_fragments;
+        report
+            .fragments_per_contig
+            .insert(contig.clone(), number_of_fragments);
+        report.contig_order.push(contig);
+    }
+    warn_about_tolerated_fragments(
+        path_to_fragments,
+        number_of_tolerated_fragments,
+        unsorted_tolerance,
+    );
+    Ok(report)
+}
+
+/// Number of fragments of a contig and number of them which were only sorted within the unsorted tolerance.
+type ContigCounts = (u64, u64);
+
+/// Validates the fragments of one contig of a tabix-indexed fragment file.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the (tabix-indexed) fragments file.
+/// * `contig` - Name of the contig.
+/// * `format` - Layout of the lines of the file.
+/// * `chromsizes` - Optional HashMap mapping contig names to contig sizes.
+/// * `unsorted_tolerance` - Number of bp a fragment may start before the previous fragments of the contig.
+///
+/// # Returns
+///
+/// The number of fragments on the contig and the number of them which were only sorted within the tolerance.
+fn validate_contig(
+    path_to_fragments: &str,
+    contig: &str,
+    format: &FragmentFormat,
+    chromsizes: Option<&HashMap<String, u64>>,
+    unsorted_tolerance: u64,
+) -> FragmentToolsResult<ContigCounts> {
+    let mut tbx_reader = open_fragments_file(path_to_fragments)?;
+    let mut number_of_fragments: u64 = 0;
+    let mut number_of_tolerated_fragments: u64 = 0;
+    let mut max_start: Option<usize> = None;
+    for_each_fragment_in_contig(
+        &mut tbx_reader,
+        path_to_fragments,
+        contig,
+        WHOLE_CONTIG,
+        |read| {
+            number_of_fragments += 1;
+            let invalid = |kind: FragmentFileErrorKind, message: String| {
+                FragmentToolsError::InvalidFragmentFile(
+                    kind,
+                    format!(
+                        "{} (fragment {} of contig {} in {})",
+                        message, number_of_fragments, contig, path_to_fragments
+                    ),
+                )
+            };
+            let fragment = std::str::from_utf8(read)
+                .map_err(|e| e.to_string())
+                .and_then(|line| Fragment::new_from_string_with_format(line, format))
+                .map_err(|e| invalid(FragmentFileErrorKind::Malformed, e))?;
+            check_fragment(&fragment, chromsizes)
+                .map_err(|e| invalid(FragmentFileErrorKind::InvalidCoordinates, e))?;
+            if let Some(max_start) = max_start {
+                if fragment.start < max_start {
+                    if (max_start - fragment.start) as u64 > unsorted_tolerance {
+                        return Err(invalid(
+                            FragmentFileErrorKind::Unsorted,
+                            format!(
+                                "Fragments are not sorted by start position: {} comes after {}",
+                                fragment.start, max_start
+                            ),
+                        ));
+                    }
+                    number_of_tolerated_fragments += 1;
+                }
+            }
+            max_start = max_start.max(Some(fragment.start));
+            Ok(())
+        },
+    )?;
+    Ok((number_of_fragments, number_of_tolerated_fragments))
+}
+
+/// Warns about fragments which were only sorted within the unsorted tolerance, if there are any.
+fn warn_about_tolerated_fragments(
+    path_to_fragments: &str,
+    number_of_tolerated_fragments: u64,
+    unsorted_tolerance: u64,
+) {
+    if number_of_tolerated_fragments > 0 {
+        println!(
+            "Warning: {} fragment(s) of {} start before a previous fragment of their contig \
+            (by at most {} bp), the file is not properly sorted",
+            number_of_tolerated_fragments, path_to_fragments, unsorted_tolerance
+        );
+    }
+}
+
+/// Checks that the start of a fragment is not after its end and, if chromsizes are given,
+/// that the fragment lies within its contig.
+fn check_fragment(
+    fragment: &Fragment,
+    chromsizes: Option<&HashMap<String, u64>>,
+) -> Result<(), String> {
+    if fragment.start > fragment.end {
+        return Err(format!(
+            "Start {} is after end {} of fragment",
+            fragment.start, fragment.end
+        ));
+    }
+    if let Some(chromsizes) = chromsizes {
+        match chromsizes.get(&fragment.chrom) {
+            None => return Err(format!("Contig {} is not in chromsizes", fragment.chrom)),
+            Some(&size) if fragment.end as u64 > size => {
+                return Err(format!(
+                    "End {} of fragment is after the end of contig {} ({})",
+                    fragment.end, fragment.chrom, size
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Maximum number of missing and extra fragments listed in a `SplitCompletenessReport`.
+const MAX_REPORTED_DISCREPANCIES: usize = 10;
+
+/// Checks that the output files of splitting a fragments file contain exactly the fragments
+/// which should have been written, e.g. to audit a split.
+///
+/// Every fragment (line) of the fragments file of which the cell barcode is in `cell_barcode_to_cell_type`
+/// is expected once per cell type of its cell barcode. The lines of all output files together are compared
+/// with the expected lines, as multisets, so only splits which write the fragments unchanged can be verified
+/// (no collapsed duplicates, renamed cell barcodes or filtered fragments). Lines starting with `#` are skipped.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file that was split.
+/// * `paths_to_output_files` - Paths to the output files of the split.
+/// * `cell_barcode_to_cell_type` - A HashMap mapping cell barcodes to cell types, as used for the split.
+/// * `verbose` - Whether to print progress messages.
+pub fn verify_split_completeness(
+    path_to_fragments: &str,
+    paths_to_output_files: &[String],
+    cell_barcode_to_cell_type: &HashMap<String, Vec<String>>,
+    verbose: bool,
+) -> FragmentToolsResult<SplitCompletenessReport> {
+    // number of times each line is expected, minus the number of times it was written
+    let mut line_to_balance: HashMap<String, i64> = HashMap::new();
+    let mut expected_fragments: u64 = 0;
+    log(&format!("Reading {}", path_to_fragments), verbose);
+    for_each_line(path_to_fragments, |line| {
+        let cell_barcode = cell_barcode_of_read(line.as_bytes(), path_to_fragments)?;
+        if let Some(cell_types) = cell_barcode_to_cell_type.get(cell_barcode) {
+            expected_fragments += cell_types.len() as u64;
+            *line_to_balance.entry(line).or_default() += cell_types.len() as i64;
+        }
+        Ok(())
+    })?;
+    for path_to_output_file in paths_to_output_files {
+        log(&format!("Reading {}", path_to_output_file), verbose);
+        for_each_line(path_to_output_file, |line| {
+            *line_to_balance.entry(line).or_default() -= 1;
+            Ok(())
+        })?;
+    }
+
+    let mut report = SplitCompletenessReport {
+        expected_fragments,
+        missing_fragments: 0,
+        extra_fragments: 0,
+        missing_examples: Vec::new(),
+        extra_examples: Vec::new(),
+    };
+    for (line, balance) in line_to_balance.into_iter().sorted() {
+        if balance > 0 {
+            report.missing_fragments += balance as u64;
+            if report.missing_examples.len() < MAX_REPORTED_DISCREPANCIES {
+                report.missing_examples.push(line);
+            }
+        } else if balance < 0 {
+            report.extra_fragments += balance.unsigned_abs();
+            if report.extra_examples.len() < MAX_REPORTED_DISCREPANCIES {
+                report.extra_examples.push(line);
+            }
+        }
+    }
+    log(
+        &format!(
+            "{} missing and {} extra fragment(s) of {} expected",
+            report.missing_fragments, report.extra_fragments, report.expected_fragments
+        ),
+        verbose,
+    );
+    Ok(report)
+}
+
+/// Calls `f` on each line of a (BGZF compressed or uncompressed) file, skipping empty lines
+/// and lines starting with `#`.
+fn for_each_line<F>(path: &str, mut f: F) -> FragmentToolsResult<()>
+where
+    F: FnMut(String) -> FragmentToolsResult<()>,
+{
+    let reader = Reader::from_path(path).map_err(|_| {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Unreadable,
+            format!("Could not open file {}", path),
+        )
+    })?;
+    for line in BufReader::new(reader).lines() {
+        let line = line.map_err(|e| {
+            FragmentToolsError::InvalidFragmentFile(
+                FragmentFileErrorKind::Unreadable,
+                format!("Could not read file {}: {}", path, e),
+            )
+        })?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        f(line)?;
+    }
+    Ok(())
+}
+
+/// Checks a fragment file quickly, without parsing its lines: a lighter alternative to
+/// `validate_fragment_file` for huge files.
+///
+/// The lines are only counted, except for every `sample_stride`-th line of which the contig and
+/// start are read to spot-check that the contigs are in blocks and sorted by start position.
+/// Unsorted fragments between the sampled lines are not noticed, use `validate_fragment_file` for that.
+///
+/// # Arguments
+///
+/// * `path_to_fragments` - Path to the fragments file (BGZF compressed or uncompressed).
+/// * `sample_stride` - Check the sort order of every `sample_stride`-th line, at least 1.
+///
+/// # Returns
+///
+/// A tuple with the number of lines (including comment lines), whether the file is BGZF compressed,
+/// whether it has a tabix index and whether the sampled lines are sorted.
+pub fn quick_check(
+    path_to_fragments: &str,
+    sample_stride: usize,
+) -> FragmentToolsResult<(u64, bool, bool, bool)> {
+    if sample_stride == 0 {
+        return Err(FragmentToolsError::InvalidArgument(
+            "sample_stride should be at least 1".to_string(),
+        ));
+    }
+    let read_error = |e: std::io::Error| {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Unreadable,
+            format!("Could not read file {}: {}", path_to_fragments, e),
+        )
+    };
+    let open_error = || {
+        FragmentToolsError::InvalidFragmentFile(
+            FragmentFileErrorKind::Unreadable,
+            format!("Could not open file {}", path_to_fragments),
+        )
+    };
+
+    let mut header = Vec::with_capacity(COMPRESSION_HEADER_LENGTH);
+    File::open(path_to_fragments)
+        .map_err(|_| open_error())?
+        .take(COMPRESSION_HEADER_LENGTH as u64)
+        .read_to_end(&mut header)
+        .map_err(read_error)?;
+    let is_bgzf = Compression::of_header(&header) == Compression::Bgzf;
+
+    let reader = Reader::from_path(path_to_fragments).map_err(|_| open_error())?;
+    let mut reader = BufReader::with_capacity(1 << 17, reader);
+    let mut line: Vec<u8> = Vec::new();
+    let mut number_of_lines: u64 = 0;
+    let mut appears_sorted = true;
+    let mut previous_contig: Option<Vec<u8>> = None;
+    let mut previous_start: u64 = 0;
+    let mut finished_contigs: HashSet<Vec<u8>> = HashSet::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).map_err(read_error)? == 0 {
+            break;
+        }
+        number_of_lines += 1;
+        if !appears_sorted
+            || !(number_of_lines - 1).is_multiple_of(sample_stride as u64)
+            || line.starts_with(b"#")
+        {
+            continue;
+        }
+        let mut fields = line.split(|&byte| byte == b'\t');
+        let (Some(contig), Some(start)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let Some(start) = std::str::from_utf8(start)
+            .ok()
+            .and_then(|start| start.trim_end().parse::<u64>().ok())
+        else {
+            continue;
+        };
+        match &previous_contig {
+            Some(previous_contig) if previous_contig.as_slice() == contig => {
+                appears_sorted = start >= previous_start;
+            }
+            _ => {
+                if let Some(previous_contig) = previous_contig.take() {
+                    finished_contigs.insert(previous_contig);
+                }
+                appears_sorted = !finished_contigs.contains(contig);
+                previous_contig = Some(contig.to_vec());
+            }
+        }
+        previous_start = start;
+    }
+    Ok((
+        number_of_lines,
+        is_bgzf,
+        has_tabix_index(path_to_fragments),
+        appears_sorted,
+    ))
+}
+
+fn log(message: &str, verbose: bool) {
+    if verbose {
+        println!("{}", message);
+    }
+}
@@ -71,6 +71,10 @@ def command_split_fragments_by_cell_type(args):
         Whether to print progress.
     args.clear_temp_folder: bool
         Whether to clear the temporary folder.
+    args.temp_cleanup: Optional[str]
+        When to remove the temporary files: "always", "on_success" or "never".
+    args.temp_prefix: str
+        Prefix of the folders per sample in the temporary folder.
     args.separator: str
         Separator for text files.
     args.sample_column_name: str
@@ -81,6 +85,8 @@ def command_split_fragments_by_cell_type(args):
         Column name for the cell type
     args.cell_barcode_column_name: str
         Column name for the cell barcode
+    args.error_policy: str
+        Whether to stop at the first error or to report all errors at the end.
     """
     # Check arguments before doing anything else.
     import os
@@ -177,5 +183,46 @@ def command_split_fragments_by_cell_type(args):
         chromsizes = chromsizes,
         n_cpu = args.n_cpu,
         verbose = args.verbose,
-        clear_temp_folder = args.clear_temp_folder
+        clear_temp_folder = args.clear_temp_folder,
+        error_policy = args.error_policy,
+        temp_cleanup = args.temp_cleanup,
+        temp_prefix = args.temp_prefix
+    )
+
+def command_aggregate_fragment_files(args):
+    """
+    Merge sorted fragment files into a single sorted fragment file.
+
+    Parameters
+    ----------
+    args: Namespace
+        Command line arguments.
+
+    Arguments
+    ---------
+    args.path_to_fragment_files: List[str]
+        Paths to the sorted fragment files.
+    args.path_to_output_file: str
+        Path to the merged (BGZF compressed) fragment file.
+    args.n_cpu: int
+        Number of threads to use for reading and writing.
+    args.merge_contigs_in_parallel: bool
+        Whether to merge `args.n_cpu` contigs at the same time.
+    args.verbose: bool
+        Whether to print progress.
+    """
+    # Check arguments before doing anything else.
+    import os
+    for path_to_fragment_file in args.path_to_fragment_files:
+        if not os.path.exists(path_to_fragment_file):
+            raise FileNotFoundError(f"Fragments file not found: {path_to_fragment_file}")
+
+    from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = args.path_to_fragment_files,
+        path_to_output_file = args.path_to_output_file,
+        number_of_threads = args.n_cpu,
+        verbose = args.verbose,
+        merge_contigs_in_parallel = args.merge_contigs_in_parallel,
     )
@@ -8,6 +8,7 @@ from rich_argparse import RichHelpFormatter
 
 import scatac_fragment_tools
 from scatac_fragment_tools.cli.commands import (
+    command_aggregate_fragment_files,
     command_fragment_to_bigwigs,
     command_split_fragments_by_cell_type,
 )
@@ -221,6 +222,25 @@ def add_split_fragments_by_cell_type_subparser(
         default = False,
         help = "Whether to clear the temporary folder.",
     )
+    parser.add_optional_argument(
+        "--temp_cleanup",
+        dest = "temp_cleanup",
+        action = "store",
+        type = str,
+        choices = ["always", "on_success", "never"],
+        default = None,
+        help = "When to remove the temporary files: always (also when the split fails), "
+        "on_success or never. Defaults to on_success with --clear_temp and never otherwise.",
+    )
+    parser.add_optional_argument(
+        "--temp_prefix",
+        dest = "temp_prefix",
+        action = "store",
+        type = str,
+        default = "",
+        help = "Prefix of the folders per sample in the temporary folder, "
+        "to keep the temporary files of runs sharing a temporary folder apart.",
+    )
     parser.add_optional_argument(
         "-s",
         "--sep",
@@ -262,11 +282,75 @@ def add_split_fragments_by_cell_type_subparser(
         default = "cell_barcode",
         help = "Column name for the cell barcode",
     )
+    parser.add_optional_argument(
+        "--error_policy",
+        dest = "error_policy",
+        action = "store",
+        type = str,
+        choices = ["fail_fast", "collect"],
+        default = "fail_fast",
+        help = "Whether to stop at the first error (fail_fast) or to process all samples "
+        "and cell types and report all errors at the end (collect).",
+    )
+    return parser.get_parser()
+
+def add_aggregate_fragment_files_subparser(
+    subparsers: argparse._SubParsersAction,
+) -> Dict[str, argparse.ArgumentParser]:
+    parser = SubparserBuilder(
+        name = "aggregate",
+        subparsers = subparsers,
+        func = command_aggregate_fragment_files,
+        description = "Merge sorted fragment files into a single sorted fragment file."
+    )
+    parser.add_required_argument(
+        "-i",
+        "--fragments",
+        dest = "path_to_fragment_files",
+        action = "store",
+        type = str,
+        nargs = "+",
+        help = "Paths to the sorted fragment files.",
+    )
+    parser.add_required_argument(
+        "-o",
+        "--output",
+        dest = "path_to_output_file",
+        action = "store",
+        type = str,
+        help = "Path to the merged (BGZF compressed) fragment file.",
+    )
+    parser.add_optional_argument(
+        "-n",
+        "--n_cpu",
+        dest = "n_cpu",
+        action = "store",
+        type = int,
+        default = 1,
+        help = "Number of threads to use for reading and writing.",
+    )
+    parser.add_optional_argument(
+        "--merge_contigs_in_parallel",
+        dest = "merge_contigs_in_parallel",
+        action = "store_true",
+        default = False,
+        help = "Whether to merge --n_cpu contigs at the same time. Requires tabix indexed "
+        "fragment files.",
+    )
+    parser.add_optional_argument(
+        "-v",
+        "--verbose",
+        dest = "verbose",
+        action = "store_true",
+        default = False,
+        help = "Whether to print progress.",
+    )
     return parser.get_parser()
 
 _PARSERS_CREATOR_FUNCS = [
     add_fragments_to_bigwig_subparser,
-    add_split_fragments_by_cell_type_subparser
+    add_split_fragments_by_cell_type_subparser,
+    add_aggregate_fragment_files_subparser
 ]
 
 
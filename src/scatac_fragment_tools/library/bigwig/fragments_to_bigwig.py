@@ -30,6 +30,12 @@ def get_chromosome_sizes(chrom_sizes_filename: str):
     return chrom_sizes
 
 
+def is_gzip_compressed(filename: str) -> bool:
+    """Check the magic bytes of a file, so (b)gzip compressed files are detected with any extension (e.g. .bgz)."""
+    with open(filename, "rb") as fh:
+        return fh.read(2) == b"\x1f\x8b"
+
+
 def normalise_filepath(path: str | Path, check_not_directory: bool = True) -> str:
     """Create a string path, expanding the home directory if present."""
     path = os.path.expanduser(path)
@@ -96,7 +102,8 @@ def read_bed_to_polars_df(
 
     # Set the correct open function, depending upon if the fragments BED file is gzip
     # compressed or not.
-    open_fn = gzip.open if bed_filename.endswith(".gz") else open
+    compression = "gzip" if is_gzip_compressed(bed_filename) else None
+    open_fn = gzip.open if compression == "gzip" else open
 
     skip_rows = 0
     column_count = 0
@@ -151,7 +158,8 @@ def read_bed_to_polars_df(
         # Read BED file with pyarrow.
         bed_df_pl = pl.from_arrow(
             pa.csv.read_csv(
-                bed_filename,
+                # pyarrow guesses the compression from the extension, so set it explicitly.
+                pa.input_stream(bed_filename, compression=compression),
                 read_options=pa.csv.ReadOptions(
                     use_threads=True,
                     skip_rows=skip_rows,
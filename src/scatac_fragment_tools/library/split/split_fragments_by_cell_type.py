@@ -1,7 +1,7 @@
 from __future__ import annotations
 
 import os
-from typing import Dict, List
+from typing import Any, Callable, Dict, List, Optional, Tuple, Union
 
 import joblib
 
@@ -9,8 +9,133 @@ from scatac_fragment_tools import _rust_scatac_fragment_tools
 
 NUMBER_OF_WRITER_THREADS = 5
 
-def _santize_string_for_filename(s: str) -> str:
-    return s.replace(" ", "_").replace("/", "_")
+ERROR_POLICIES = ("fail_fast", "collect")
+
+TEMP_CLEANUP_POLICIES = ("always", "on_success", "never")
+
+# Files next to a fragment file per cell type: while it is written, when the split stopped early and its index.
+SIBLING_FILE_SUFFIXES = (".tmp", ".partial", ".tbi")
+
+def _call_and_return_error(func: Callable, **kwargs) -> Tuple[Any, Optional[str]]:
+    try:
+        return func(**kwargs), None
+    except Exception as e:
+        return None, f"{type(e).__name__}: {e}"
+
+def _split_and_return_output_files(**kwargs) -> List[Tuple[str, str]]:
+    """
+    Split a fragment file by cell barcode and return the (cell type, path) tuples
+    of the files which were written.
+    """
+    return _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(**kwargs).output_files
+
+def _number_of_jobs_and_threads(n_cpu: int, n_tasks: int, max_parallel_tasks: Optional[int]):
+    """
+    Divide a budget of n_cpu threads over tasks which each write with their own threads.
+
+    Returns the number of tasks to run at the same time and the number of writer threads
+    per task, so that together they do not use more than n_cpu threads.
+    """
+    n_jobs = max(1, min(n_cpu, n_tasks))
+    if max_parallel_tasks is not None:
+        n_jobs = min(n_jobs, max_parallel_tasks)
+    number_of_threads = max(1, min(NUMBER_OF_WRITER_THREADS, n_cpu // n_jobs))
+    return n_jobs, number_of_threads
+
+def _remove_temp_files(
+    sample_to_temp_folder: Dict[str, str],
+    sample_to_output_files: Dict[str, List[str]],
+    output_extension: str,
+    verbose: bool):
+    """
+    Remove the fragment files per cell type (with their temporary, partial and index files)
+    from the temporary folder of each sample, and the folders themselves when they are empty.
+
+    The fragment files of a sample are the output files of its split. When its split failed,
+    they are not known, and the fragment files with output_extension in the temporary folder
+    of the sample are removed instead.
+
+    This is best-effort: files which were not written are skipped and errors are ignored,
+    so it can be used to clean up after a failed split.
+    """
+    for sample, path_to_sample_temp_folder in sample_to_temp_folder.items():
+        if sample in sample_to_output_files:
+            paths_to_files = [
+                f"{path_to_fragment_file}{suffix}"
+                for path_to_fragment_file in sample_to_output_files[sample]
+                for suffix in ("", *SIBLING_FILE_SUFFIXES)
+            ]
+        else:
+            try:
+                file_names = sorted(os.listdir(path_to_sample_temp_folder))
+            except OSError:
+                file_names = []
+            paths_to_files = [
+                os.path.join(path_to_sample_temp_folder, file_name)
+                for file_name in file_names
+                if any(
+                    file_name.endswith(f".{output_extension}{suffix}")
+                    for suffix in ("", *SIBLING_FILE_SUFFIXES)
+                )
+            ]
+        for path_to_file in paths_to_files:
+            if os.path.exists(path_to_file):
+                if verbose:
+                    print(f"Removing {path_to_file}")
+                try:
+                    os.remove(path_to_file)
+                except OSError:
+                    pass
+        try:
+            os.rmdir(path_to_sample_temp_folder)
+        except OSError:
+            pass
+
+def _run_in_parallel(
+    func: Callable,
+    task_name_to_kwargs: Dict[str, Dict[str, Any]],
+    n_cpu: int,
+    error_policy: str,
+    step_name: str,
+    task_name_to_result: Optional[Dict[str, Any]] = None) -> Dict[str, Any]:
+    """
+    Run func for each set of keyword arguments, in parallel.
+
+    Returns a dictionary mapping the task names to the return values of func.
+    With error_policy "fail_fast", the first error is raised as is.
+    With error_policy "collect", all tasks are run and a single ValueError
+    listing every failed task is raised afterwards.
+    If task_name_to_result is given, the return values are also added to it, with error_policy
+    "collect" also those of the tasks which succeeded when other tasks failed.
+    """
+    if task_name_to_result is None:
+        task_name_to_result = {}
+    if error_policy == "fail_fast":
+        results = joblib.Parallel(n_jobs=n_cpu)(
+            joblib.delayed(func)(**kwargs)
+            for kwargs in task_name_to_kwargs.values()
+        )
+        task_name_to_result.update(zip(task_name_to_kwargs, results))
+        return task_name_to_result
+    results_and_errors = joblib.Parallel(n_jobs=n_cpu)(
+        joblib.delayed(_call_and_return_error)(func, **kwargs)
+        for kwargs in task_name_to_kwargs.values()
+    )
+    task_name_to_result.update(
+        (task_name, result)
+        for task_name, (result, error) in zip(task_name_to_kwargs, results_and_errors)
+        if error is None
+    )
+    failed_tasks = [
+        f"{task_name}: {error}"
+        for task_name, (_, error) in zip(task_name_to_kwargs, results_and_errors)
+        if error is not None
+    ]
+    if len(failed_tasks) > 0:
+        raise ValueError(
+            f"{step_name} failed for {len(failed_tasks)} task(s):\n" + "\n".join(failed_tasks)
+        )
+    return task_name_to_result
 
 def split_fragment_files_by_cell_type(
     sample_to_fragment_file: Dict[str, str],
@@ -20,7 +145,13 @@ def split_fragment_files_by_cell_type(
     chromsizes: Dict[str, int],
     n_cpu: int = 1,
     verbose: bool = False,
-    clear_temp_folder: bool = False):
+    clear_temp_folder: bool = False,
+    error_policy: str = "fail_fast",
+    add_source_column: bool = False,
+    output_extension: str = "fragments.tsv.gz",
+    max_parallel_cell_types: Optional[int] = None,
+    temp_cleanup: Optional[str] = None,
+    temp_prefix: str = ""):
     """
     Split fragment files by cell type.
 
@@ -43,8 +174,46 @@ def split_fragment_files_by_cell_type(
     verbose : bool, optional
         Whether to print progress. The default is False.
     clear_temp_folder : bool, optional
-        Whether to clear the temporary folder. The default is False.
+        Whether to clear the temporary folder, when the split succeeds.
+        Only used when temp_cleanup is None. The default is False.
+    error_policy : str, optional
+        What to do when splitting or merging fails.
+        "fail_fast" raises the first error,
+        "collect" runs all samples and cell types and raises one error listing all failures.
+        The default is "fail_fast".
+    add_source_column : bool, optional
+        Whether to add a column with the file name of the fragment file each fragment
+        comes from, after the last column of the fragment. The default is False.
+    output_extension : str, optional
+        Extension (without leading dot) of the fragment files per cell type,
+        e.g. "fragments.tsv.bgz". The default is "fragments.tsv.gz".
+    max_parallel_cell_types : int, optional
+        Maximum number of cell types to merge at the same time. The n_cpu threads are divided
+        over the cell types merged at the same time, so many small cell types are merged
+        in parallel with few writer threads each, while fewer (large) cell types get more
+        writer threads each. The default is None, which merges up to n_cpu cell types at the same time.
+    temp_cleanup : str, optional
+        When the fragment files per cell type and sample are removed from the temporary folder.
+        "always" removes them after the split, also when it fails (best-effort),
+        "on_success" only when the split succeeds (they are kept to investigate a failure)
+        and "never" keeps them. The default is None, which is "on_success" if
+        clear_temp_folder is set and "never" otherwise.
+    temp_prefix : str, optional
+        Prefix of the folders per sample in the temporary folder (named "{temp_prefix}{sample}"),
+        so runs sharing a temporary folder do not overwrite or remove each other's files.
+        The default is "".
     """
+    if error_policy not in ERROR_POLICIES:
+        raise ValueError(f"error_policy must be one of {ERROR_POLICIES}, got {error_policy}.")
+    if max_parallel_cell_types is not None and max_parallel_cell_types < 1:
+        raise ValueError(f"max_parallel_cell_types must be at least 1, got {max_parallel_cell_types}.")
+    if temp_cleanup is None:
+        temp_cleanup = "on_success" if clear_temp_folder else "never"
+    if temp_cleanup not in TEMP_CLEANUP_POLICIES:
+        raise ValueError(f"temp_cleanup must be one of {TEMP_CLEANUP_POLICIES}, got {temp_cleanup}.")
+    if os.sep in temp_prefix:
+        raise ValueError(f"temp_prefix should be a file name prefix without {os.sep}, got {temp_prefix}.")
+
     # Check wether same samples in sample_to_fragment_file
     # and sample_to_cell_type_to_cell_barcodes
     if set(sample_to_fragment_file.keys()) != set(sample_to_cell_type_to_cell_barcodes.keys()):
@@ -63,67 +232,206 @@ def split_fragment_files_by_cell_type(
         os.makedirs(path_to_output_folder)
 
     # Create a folder for each sample
-    for sample in sample_to_fragment_file:
-        os.makedirs(os.path.join(path_to_temp_folder, sample), exist_ok=True)
-
-    # Split fragment files by cell barcode, in parallel
-    if verbose:
-        print("Splitting fragments ...")
-    joblib.Parallel(n_jobs=n_cpu)(
-        joblib.delayed(_rust_scatac_fragment_tools.split_fragments_by_cell_barcode)
-            (
-                path_to_fragments = sample_to_fragment_file[sample],
-                path_to_output_folder = os.path.join(path_to_temp_folder, sample),
-                cell_type_to_cell_barcodes = sample_to_cell_type_to_cell_barcodes[sample],
-                chromsizes = chromsizes,
-                verbose = verbose
-            )
-            for sample in sample_to_cell_type_to_cell_barcodes
-    )
+    sample_to_temp_folder = {
+        sample: os.path.join(path_to_temp_folder, f"{temp_prefix}{sample}")
+        for sample in sample_to_fragment_file
+    }
+    for path_to_sample_temp_folder in sample_to_temp_folder.values():
+        os.makedirs(path_to_sample_temp_folder, exist_ok=True)
+
+    # output files of the split of each sample, as (cell type, path) tuples
+    task_name_to_output_files: Dict[str, List[Tuple[str, str]]] = {}
+    split_succeeded = False
+    try:
+        # Split fragment files by cell barcode, in parallel
+        if verbose:
+            print("Splitting fragments ...")
+        n_jobs, number_of_threads = _number_of_jobs_and_threads(
+            n_cpu, len(sample_to_fragment_file), None
+        )
+        _run_in_parallel(
+            func = _split_and_return_output_files,
+            task_name_to_kwargs = {
+                f"sample {sample}": dict(
+                    path_to_fragments = sample_to_fragment_file[sample],
+                    path_to_output_folder = sample_to_temp_folder[sample],
+                    cell_type_to_cell_barcodes = sample_to_cell_type_to_cell_barcodes[sample],
+                    chromsizes = chromsizes,
+                    verbose = verbose,
+                    output_extension = output_extension,
+                    number_of_threads = number_of_threads
+                )
+                for sample in sample_to_cell_type_to_cell_barcodes
+            },
+            n_cpu = n_jobs,
+            error_policy = error_policy,
+            step_name = "Splitting fragments",
+            task_name_to_result = task_name_to_output_files
+        )
 
-    # Check wether all files were create successfully
-    # and create a dictionary mapping cell types to fragment files
-    cell_type_to_fragment_files: Dict[str, List[str]] = {}
-    for sample in sample_to_cell_type_to_cell_barcodes:
-        for cell_type in sample_to_cell_type_to_cell_barcodes[sample]:
-            cell_type_sanitized = _santize_string_for_filename(cell_type)
-            path_to_fragment_file = os.path.join(path_to_temp_folder, sample, f"{cell_type_sanitized}.fragments.tsv.gz")
+        # Create a dictionary mapping the file names of the cell types to fragment files.
+        # No file is written when a sample has no fragments for a cell type.
+        cell_type_to_fragment_files: Dict[str, List[str]] = {}
+        cell_type_to_source_labels: Dict[str, List[str]] = {}
+        for sample in sample_to_cell_type_to_cell_barcodes:
+            cell_type_to_path = dict(task_name_to_output_files[f"sample {sample}"])
+            for cell_type in sample_to_cell_type_to_cell_barcodes[sample]:
+                if cell_type not in cell_type_to_path:
+                    if verbose:
+                        print(f"No fragments for cell type {cell_type} in sample {sample}")
+                    continue
+                file_name = os.path.basename(cell_type_to_path[cell_type])
+                if file_name not in cell_type_to_fragment_files:
+                    cell_type_to_fragment_files[file_name] = []
+                    cell_type_to_source_labels[file_name] = []
+                cell_type_to_fragment_files[file_name].append(cell_type_to_path[cell_type])
+                cell_type_to_source_labels[file_name].append(
+                    os.path.basename(sample_to_fragment_file[sample])
+                )
+
+        # Merge fragment files by cell type, in parallel
+        if verbose:
+            print("Merging fragments ...")
+        n_jobs, number_of_threads = _number_of_jobs_and_threads(
+            n_cpu, len(cell_type_to_fragment_files), max_parallel_cell_types
+        )
+        _run_in_parallel(
+            func = _rust_scatac_fragment_tools.merge_fragment_files,
+            task_name_to_kwargs = {
+                f"cell type file {file_name}": dict(
+                    path_to_fragment_files = cell_type_to_fragment_files[file_name],
+                    path_to_output_file = os.path.join(path_to_output_folder, file_name),
+                    number_of_threads = number_of_threads,
+                    verbose = verbose,
+                    source_labels = cell_type_to_source_labels[file_name] if add_source_column else None
+                )
+                for file_name in cell_type_to_fragment_files
+            },
+            n_cpu = n_jobs,
+            error_policy = error_policy,
+            step_name = "Merging fragments"
+        )
+
+        # Check wether all files were create successfully
+        for file_name in cell_type_to_fragment_files:
+            path_to_fragment_file = os.path.join(path_to_output_folder, file_name)
             if not os.path.exists(path_to_fragment_file):
-                raise ValueError(f"Fragment file {path_to_fragment_file} does not exist.")
-            if cell_type_sanitized not in cell_type_to_fragment_files:
-                cell_type_to_fragment_files[cell_type_sanitized] = []
-            cell_type_to_fragment_files[cell_type_sanitized].append(path_to_fragment_file)
-
-    # Merge fragment files by cell type, in parallel
-    if verbose:
-        print("Merging fragments ...")
-    joblib.Parallel(n_jobs=n_cpu)(
-        joblib.delayed(_rust_scatac_fragment_tools.merge_fragment_files)
-            (
-                path_to_fragment_files = cell_type_to_fragment_files[cell_type],
-                path_to_output_file = os.path.join(path_to_output_folder, f"{cell_type}.fragments.tsv.gz"),
-                number_of_threads = NUMBER_OF_WRITER_THREADS,
-                verbose = verbose
+                Warning(f"Fragment file {path_to_fragment_file} does not exist.")
+        split_succeeded = True
+    finally:
+        # Clear temporary folder
+        if temp_cleanup == "always" or (temp_cleanup == "on_success" and split_succeeded):
+            if verbose:
+                print("Clearing temporary folder ...")
+            _remove_temp_files(
+                sample_to_temp_folder,
+                {
+                    sample: [path for _, path in task_name_to_output_files[f"sample {sample}"]]
+                    for sample in sample_to_temp_folder
+                    if f"sample {sample}" in task_name_to_output_files
+                },
+                output_extension,
+                verbose
             )
-            for cell_type in cell_type_to_fragment_files
-    )
 
-    # Check wether all files were create successfully
-    for cell_type in cell_type_to_fragment_files:
-        cell_type_sanitized = _santize_string_for_filename(cell_type)
-        path_to_fragment_file = os.path.join(path_to_output_folder, f"{cell_type_sanitized}.fragments.tsv.gz")
-        if not os.path.exists(path_to_fragment_file):
-            Warning(f"Fragment file {path_to_fragment_file} does not exist.")
 
-    # Clear temporary folder
-    if clear_temp_folder:
-        if verbose:
-            print("Clearing temporary folder ...")
-        for cell_type in cell_type_to_fragment_files:
-            for fragment_file in cell_type_to_fragment_files[cell_type]:
-                if fragment_file.startswith(path_to_temp_folder) and fragment_file.endswith(".fragments.tsv.gz"):
-                    if verbose:
-                        print(f"Removing {fragment_file}")
-                    os.remove(fragment_file)
 
+def split_fragment_files_by_cell_type_with_shared_annotation(
+    fragment_files: List[str],
+    path_to_temp_folder: str,
+    path_to_output_folder: str,
+    cell_barcode_to_cell_type: Dict[str, Union[str, List[str]]],
+    chromsizes: Dict[str, int],
+    n_cpu: int = 1,
+    verbose: bool = False,
+    clear_temp_folder: bool = False,
+    error_policy: str = "fail_fast",
+    add_source_column: bool = False,
+    output_extension: str = "fragments.tsv.gz",
+    max_parallel_cell_types: Optional[int] = None,
+    temp_cleanup: Optional[str] = None,
+    temp_prefix: str = ""):
+    """
+    Split fragment files by cell type, using one annotation for all files.
 
+    This is a convenience wrapper around `split_fragment_files_by_cell_type`
+    for the case where all fragment files share the same cell barcode namespace.
+
+    Parameters
+    ----------
+    fragment_files : List[str]
+        List of paths to fragment files.
+    path_to_temp_folder : str
+        Path to temporary folder, used for writing fragment files
+        per cell type split by fragment file.
+    path_to_output_folder : str
+        Path to output folder, used for writing fragment files
+        per cell type (merged across fragment files).
+    cell_barcode_to_cell_type : Dict[str, Union[str, List[str]]]
+        Dictionary mapping cell barcodes to a cell type or a list of cell types.
+        The same mapping is used for every fragment file.
+    chromsizes : Dict[str, int]
+        Dictionary mapping chromosome names to chromosome sizes.
+    n_cpu : int, optional
+        Number of cores to use. The default is 1.
+    verbose : bool, optional
+        Whether to print progress. The default is False.
+    clear_temp_folder : bool, optional
+        Whether to clear the temporary folder. The default is False.
+    error_policy : str, optional
+        What to do when splitting or merging fails, see `split_fragment_files_by_cell_type`.
+        The default is "fail_fast".
+    add_source_column : bool, optional
+        Whether to add a column with the file name of the fragment file each fragment
+        comes from, see `split_fragment_files_by_cell_type`. The default is False.
+    output_extension : str, optional
+        Extension (without leading dot) of the fragment files per cell type,
+        see `split_fragment_files_by_cell_type`. The default is "fragments.tsv.gz".
+    max_parallel_cell_types : int, optional
+        Maximum number of cell types to merge at the same time,
+        see `split_fragment_files_by_cell_type`. The default is None.
+    temp_cleanup : str, optional
+        When the fragment files per cell type and fragment file are removed from the temporary folder:
+        "always", "on_success" or "never", see `split_fragment_files_by_cell_type`. The default is None.
+    temp_prefix : str, optional
+        Prefix of the folders per fragment file in the temporary folder,
+        see `split_fragment_files_by_cell_type`. The default is "".
+    """
+    if len(set(fragment_files)) != len(fragment_files):
+        raise ValueError("fragment_files contains duplicate paths.")
+
+    # Invert cell_barcode_to_cell_type
+    cell_type_to_cell_barcodes: Dict[str, list] = {}
+    for cell_barcode, cell_types in cell_barcode_to_cell_type.items():
+        if isinstance(cell_types, str):
+            cell_types = [cell_types]
+        for cell_type in cell_types:
+            if cell_type not in cell_type_to_cell_barcodes:
+                cell_type_to_cell_barcodes[cell_type] = []
+            cell_type_to_cell_barcodes[cell_type].append(cell_barcode)
+
+    # Every fragment file gets its own (numbered) sample name,
+    # which is only used for naming the folders in the temporary folder.
+    sample_to_fragment_file = {
+        str(i): fragment_file for i, fragment_file in enumerate(fragment_files)
+    }
+    sample_to_cell_type_to_cell_barcodes = {
+        sample: cell_type_to_cell_barcodes for sample in sample_to_fragment_file
+    }
+
+    split_fragment_files_by_cell_type(
+        sample_to_fragment_file = sample_to_fragment_file,
+        path_to_temp_folder = path_to_temp_folder,
+        path_to_output_folder = path_to_output_folder,
+        sample_to_cell_type_to_cell_barcodes = sample_to_cell_type_to_cell_barcodes,
+        chromsizes = chromsizes,
+        n_cpu = n_cpu,
+        verbose = verbose,
+        clear_temp_folder = clear_temp_folder,
+        error_policy = error_policy,
+        add_source_column = add_source_column,
+        output_extension = output_extension,
+        max_parallel_cell_types = max_parallel_cell_types,
+        temp_cleanup = temp_cleanup,
+        temp_prefix = temp_prefix
+    )
@@ -0,0 +1,38 @@
+import gzip
+import os
+
+from conftest import read_fragments_as_text
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+
+def test_concatenate_repairs_missing_trailing_newline(tmp_path):
+    path_to_chr1 = os.path.join(tmp_path, "chr1.fragments.tsv.gz")
+    path_to_chr2 = os.path.join(tmp_path, "chr2.fragments.tsv.gz")
+    with gzip.open(path_to_chr1, "wt") as f:
+        # no newline after the last fragment
+        f.write("chr1\t10\t20\tAAAA-1\t1\nchr1\t30\t40\tBBBB-1\t2")
+    with gzip.open(path_to_chr2, "wt") as f:
+        f.write("chr2\t10\t20\tAAAA-1\t3\n")
+
+    path_to_output_file = os.path.join(tmp_path, "concatenated.fragments.tsv.gz")
+    _rust_scatac_fragment_tools.concatenate_fragment_files(
+        path_to_fragment_files = [path_to_chr1, path_to_chr2],
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+    )
+    assert read_fragments_as_text(path_to_output_file) == (
+        "chr1\t10\t20\tAAAA-1\t1\n"
+        "chr1\t30\t40\tBBBB-1\t2\n"
+        "chr2\t10\t20\tAAAA-1\t3\n"
+    )
+
+    # the output can be merged without running records into each other
+    path_to_merged_file = os.path.join(tmp_path, "merged.fragments.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [path_to_output_file],
+        path_to_output_file = path_to_merged_file,
+        number_of_threads = 1,
+        verbose = False,
+    )
+    assert read_fragments_as_text(path_to_merged_file) == read_fragments_as_text(path_to_output_file)
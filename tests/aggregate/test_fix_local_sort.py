@@ -0,0 +1,60 @@
+import os
+import random
+
+import pytest
+from conftest import read_fragments, write_fragments
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+
+def sorted_fragments():
+    rng = random.Random(0)
+    fragments = {
+        (chrom, start, start + rng.randint(1, 500), f"BARCODE_{rng.randint(0, 20)}-1", rng.randint(1, 5))
+        for chrom in ["chr1", "chr10", "chr2"]
+        for start in rng.sample(range(100_000), 200)
+    }
+    return sorted(fragments)
+
+
+def test_fix_local_sort_reorders_nearly_sorted_file(tmp_path):
+    fragments = sorted_fragments()
+    window = 5
+    # move fragments `window` positions backwards or forwards, within disjoint blocks
+    unsorted_fragments = []
+    for block_index, index in enumerate(range(0, len(fragments), window + 1)):
+        block = fragments[index:index + window + 1]
+        if block_index % 2 == 0:
+            block = block[1:] + block[:1]
+        else:
+            block = block[-1:] + block[:-1]
+        unsorted_fragments.extend(block)
+    assert unsorted_fragments != fragments
+
+    path_to_input_file = os.path.join(tmp_path, "unsorted.fragments.tsv.gz")
+    path_to_output_file = os.path.join(tmp_path, "sorted.fragments.tsv.gz")
+    write_fragments(path_to_input_file, unsorted_fragments)
+    number_of_unsorted_fragments = _rust_scatac_fragment_tools.fix_local_sort(
+        path_to_input_file = path_to_input_file,
+        path_to_output_file = path_to_output_file,
+        window = window,
+    )
+    assert number_of_unsorted_fragments > 0
+    assert read_fragments(path_to_output_file) == [[str(field) for field in fragment] for fragment in fragments]
+
+
+def test_fix_local_sort_with_disorder_larger_than_window(tmp_path):
+    fragments = sorted_fragments()
+    fragments.insert(20, fragments.pop(0))
+    path_to_input_file = os.path.join(tmp_path, "unsorted.fragments.tsv.gz")
+    write_fragments(path_to_input_file, fragments)
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "more than 10 positions out of order",
+    ) as e:
+        _rust_scatac_fragment_tools.fix_local_sort(
+            path_to_input_file = path_to_input_file,
+            path_to_output_file = os.path.join(tmp_path, "sorted.fragments.tsv.gz"),
+            window = 10,
+        )
+    assert e.value.kind == "unsorted"
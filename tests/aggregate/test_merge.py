@@ -0,0 +1,1278 @@
+import gzip
+import os
+import pathlib
+import subprocess
+import sys
+
+import pytest
+from conftest import read_fragments
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+
+
+def test_merge_orders_identical_coordinates_deterministically(tmp_path):
+    path_to_tie_a = str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))
+    path_to_tie_b = str(TEST_DIRECTORY.joinpath("tie_b.fragments.tsv.gz"))
+    for run, path_to_fragment_files in enumerate(
+        [[path_to_tie_a, path_to_tie_b]] * 3 + [[path_to_tie_b, path_to_tie_a]]
+    ):
+        path_to_output_file = os.path.join(tmp_path, f"merged_{run}.tsv.gz")
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = path_to_fragment_files,
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+        )
+        merged = read_fragments(path_to_output_file)
+        # Ties on coordinates are broken by barcode and then by input file order.
+        first_file_score, second_file_score = (
+            ("1", "3") if path_to_fragment_files[0] == path_to_tie_a else ("3", "1")
+        )
+        assert merged == [
+            ["chr1", "10", "20", "AAAA-1", "2"],
+            ["chr1", "10", "20", "BBBB-1", first_file_score],
+            ["chr1", "10", "20", "BBBB-1", second_file_score],
+            ["chr1", "30", "40", "AAAA-1", "1"],
+        ]
+
+
+
+def test_merge_sorts_interleaved_files_with_shared_starts_across_contigs(tmp_path):
+    # both files have fragments on several contigs, which share their start positions
+    fragments_per_file = [
+        [
+            ["chr1", "10", "20", "CCCC-1", "1"],
+            ["chr1", "10", "30", "AAAA-1", "1"],
+            ["chr10", "10", "20", "BBBB-1", "1"],
+            ["chr2", "5", "20", "AAAA-1", "1"],
+            ["chr2", "10", "20", "AAAA-1", "1"],
+        ],
+        [
+            ["chr1", "5", "20", "BBBB-1", "2"],
+            ["chr1", "10", "20", "AAAA-1", "2"],
+            ["chr10", "5", "20", "AAAA-1", "2"],
+            ["chr10", "10", "20", "AAAA-1", "2"],
+            ["chr2", "10", "20", "BBBB-1", "2"],
+        ],
+    ]
+    path_to_fragment_files = []
+    for i, fragments in enumerate(fragments_per_file):
+        path_to_fragment_file = os.path.join(tmp_path, f"interleaved_{i}.fragments.tsv.gz")
+        with gzip.open(path_to_fragment_file, "wt") as f:
+            f.writelines("\t".join(fragment) + "\n" for fragment in fragments)
+        path_to_fragment_files.append(path_to_fragment_file)
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        verbose = False,
+    )
+    merged = read_fragments(path_to_output_file)
+    # sorted by contig, then start, end and cell barcode
+    assert merged == sorted(
+        fragments_per_file[0] + fragments_per_file[1],
+        key = lambda fragment: (fragment[0], int(fragment[1]), int(fragment[2]), fragment[3]),
+    )
+
+def test_merge_reads_all_members_of_concatenated_files(tmp_path):
+    # Equivalent of `cat tie_b.fragments.tsv.gz tie_a.fragments.tsv.gz > concatenated.fragments.tsv.gz`.
+    path_to_concatenated = os.path.join(tmp_path, "concatenated.fragments.tsv.gz")
+    with open(path_to_concatenated, "wb") as f:
+        for file_name in ["tie_b.fragments.tsv.gz", "tie_a.fragments.tsv.gz"]:
+            f.write(TEST_DIRECTORY.joinpath(file_name).read_bytes())
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [path_to_concatenated],
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        verbose = False,
+    )
+    assert read_fragments(path_to_output_file) == [
+        ["chr1", "10", "20", "AAAA-1", "2"],
+        ["chr1", "10", "20", "BBBB-1", "3"],
+        ["chr1", "10", "20", "BBBB-1", "1"],
+        ["chr1", "30", "40", "AAAA-1", "1"],
+    ]
+
+
+def test_merge_reads_plain_gzip_files(tmp_path):
+    path_to_bgzf = str(TEST_DIRECTORY.parent.joinpath("split", "a.fragments.tsv.gz"))
+    path_to_plain_gzip = os.path.join(tmp_path, "plain.fragments.tsv.gz")
+    with gzip.open(path_to_bgzf, "rb") as f_in, gzip.open(path_to_plain_gzip, "wb") as f_out:
+        f_out.write(f_in.read())
+    for name, path_to_fragment_file in [("bgzf", path_to_bgzf), ("plain_gzip", path_to_plain_gzip)]:
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_fragment_file],
+            path_to_output_file = os.path.join(tmp_path, f"{name}.merged.tsv.gz"),
+            number_of_threads = 1,
+            verbose = False,
+        )
+    assert read_fragments(os.path.join(tmp_path, "plain_gzip.merged.tsv.gz")) == read_fragments(
+        os.path.join(tmp_path, "bgzf.merged.tsv.gz")
+    )
+
+
+def test_merge_more_files_than_max_open_files(tmp_path):
+    # Split sample A per barcode, so there are more files than max_open_files.
+    path_to_split_folder = os.path.join(tmp_path, "split")
+    os.makedirs(path_to_split_folder)
+    path_to_a_fragments = str(TEST_DIRECTORY.parent.joinpath("split", "a.fragments.tsv.gz"))
+    with gzip.open(path_to_a_fragments, "rt") as f:
+        cell_barcodes = sorted({line.split("\t")[3] for line in f})
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = path_to_a_fragments,
+        path_to_output_folder = path_to_split_folder,
+        cell_type_to_cell_barcodes = {cell_barcode: [cell_barcode] for cell_barcode in cell_barcodes},
+        chromsizes = {"chr1": 248956422, "chr2": 242193529},
+        verbose = False,
+    )
+    path_to_fragment_files = sorted(
+        os.path.join(path_to_split_folder, file_name) for file_name in os.listdir(path_to_split_folder)
+    )
+    assert len(path_to_fragment_files) > 4
+
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        verbose = False,
+        max_open_files = 2,
+    )
+    merged = read_fragments(path_to_output_file)
+    assert merged == sorted(
+        read_fragments(path_to_a_fragments),
+        key = lambda fragment: (fragment[0], int(fragment[1]), int(fragment[2]), fragment[3]),
+    )
+    # Intermediate files are removed.
+    assert sorted(os.listdir(tmp_path)) == ["merged.tsv.gz", "split"]
+
+
+# Merges the files given as arguments in a new process, printing by how much its peak memory usage grew.
+MERGE_AND_REPORT_MEMORY_GROWTH = """
+import resource, sys
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+before = resource.getrusage(resource.RUSAGE_SELF).ru_maxrss
+_rust_scatac_fragment_tools.merge_fragment_files(
+    path_to_fragment_files = sys.argv[2:],
+    path_to_output_file = sys.argv[1],
+    number_of_threads = 1,
+    verbose = False,
+    memory_mode = "low",
+)
+print(resource.getrusage(resource.RUSAGE_SELF).ru_maxrss - before)
+"""
+
+
+def test_merge_memory_does_not_grow_with_input_size(tmp_path):
+    pytest.importorskip("resource")
+    # Many small files stand in for a large input, ru_maxrss is in KiB on Linux.
+    memory_growth_in_kib = {}
+    uncompressed_size_in_kib = {}
+    for fragments_per_file in [1000, 16000]:
+        path_to_folder = tmp_path.joinpath(str(fragments_per_file))
+        os.makedirs(path_to_folder)
+        path_to_fragment_files = []
+        uncompressed_size = 0
+        for file_index in range(50):
+            content = "".join(
+                f"chr1\t{start}\t{start + 100}\tBC{file_index:04d}-1\t1\n"
+                for start in range(file_index, file_index + fragments_per_file * 10, 10)
+            )
+            uncompressed_size += len(content)
+            path_to_fragment_file = str(path_to_folder.joinpath(f"part_{file_index}.fragments.tsv.gz"))
+            with gzip.open(path_to_fragment_file, "wt", compresslevel = 1) as f:
+                f.write(content)
+            path_to_fragment_files.append(path_to_fragment_file)
+        path_to_output_file = str(path_to_folder.joinpath("merged.tsv.gz"))
+        result = subprocess.run(
+            [sys.executable, "-c", MERGE_AND_REPORT_MEMORY_GROWTH, path_to_output_file, *path_to_fragment_files],
+            capture_output = True,
+            text = True,
+        )
+        assert result.returncode == 0, result.stderr
+        memory_growth_in_kib[fragments_per_file] = int(result.stdout.split()[-1])
+        uncompressed_size_in_kib[fragments_per_file] = uncompressed_size // 1024
+        with gzip.open(path_to_output_file, "rt") as f:
+            assert sum(1 for _ in f) == 50 * fragments_per_file
+    # Reading whole files into memory would grow with the input, streaming only needs buffers per open file.
+    assert memory_growth_in_kib[16000] - memory_growth_in_kib[1000] < (
+        uncompressed_size_in_kib[16000] - uncompressed_size_in_kib[1000]
+    ) / 2
+
+
+def test_merge_to_parquet(tmp_path):
+    pq = pytest.importorskip("pyarrow.parquet")
+    path_to_fragment_files = [
+        str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz")),
+        str(TEST_DIRECTORY.joinpath("tie_b.fragments.tsv.gz")),
+    ]
+    path_to_tsv_output = os.path.join(tmp_path, "merged.tsv.gz")
+    path_to_parquet_output = os.path.join(tmp_path, "merged.parquet")
+    for path_to_output_file, output_codec in [
+        (path_to_tsv_output, "bgzf"),
+        (path_to_parquet_output, "parquet"),
+    ]:
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = path_to_fragment_files,
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            output_codec = output_codec,
+        )
+    table = pq.read_table(path_to_parquet_output)
+    assert table.column_names == ["chrom", "start", "end", "barcode", "score"]
+    assert [
+        [row["chrom"], str(row["start"]), str(row["end"]), row["barcode"], str(row["score"])]
+        for row in table.to_pylist()
+    ] == read_fragments(path_to_tsv_output)
+
+
+def test_merge_adds_unique_fragment_ids(tmp_path):
+    path_to_fragment_files = [
+        str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz")),
+        str(TEST_DIRECTORY.joinpath("tie_b.fragments.tsv.gz")),
+    ]
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    path_to_output_file_with_ids = os.path.join(tmp_path, "merged_with_ids.tsv.gz")
+    for path, add_fragment_ids in [(path_to_output_file, False), (path_to_output_file_with_ids, True)]:
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = path_to_fragment_files,
+            path_to_output_file = path,
+            number_of_threads = 1,
+            verbose = False,
+            add_fragment_ids = add_fragment_ids,
+        )
+    merged = read_fragments(path_to_output_file)
+    merged_with_ids = read_fragments(path_to_output_file_with_ids)
+    # The ID is an extra last column, the other columns (and their order) are unchanged.
+    assert [fragment[:-1] for fragment in merged_with_ids] == merged
+    fragment_ids = [fragment[-1] for fragment in merged_with_ids]
+    assert len(set(fragment_ids)) == len(fragment_ids)
+    assert fragment_ids == [f"frag_{n}" for n in range(1, len(merged) + 1)]
+
+    with pytest.raises(ValueError, match = "only be added to bgzf output"):
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = path_to_fragment_files,
+            path_to_output_file = os.path.join(tmp_path, "merged.parquet"),
+            number_of_threads = 1,
+            verbose = False,
+            output_codec = "parquet",
+            add_fragment_ids = True,
+        )
+
+
+def test_merge_memory_modes_write_identical_output(tmp_path):
+    path_to_split_folder = os.path.join(tmp_path, "split")
+    os.makedirs(path_to_split_folder)
+    path_to_a_fragments = str(TEST_DIRECTORY.parent.joinpath("split", "a.fragments.tsv.gz"))
+    with gzip.open(path_to_a_fragments, "rt") as f:
+        cell_barcodes = sorted({line.split("\t")[3] for line in f})
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = path_to_a_fragments,
+        path_to_output_folder = path_to_split_folder,
+        cell_type_to_cell_barcodes = {cell_barcode: [cell_barcode] for cell_barcode in cell_barcodes},
+        chromsizes = {"chr1": 248956422, "chr2": 242193529},
+        verbose = False,
+    )
+    path_to_fragment_files = sorted(
+        os.path.join(path_to_split_folder, file_name) for file_name in os.listdir(path_to_split_folder)
+    )
+    merged = {}
+    for memory_mode in ["low", "fast"]:
+        path_to_output_file = os.path.join(tmp_path, f"merged_{memory_mode}.tsv.gz")
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = path_to_fragment_files,
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            memory_mode = memory_mode,
+        )
+        merged[memory_mode] = read_fragments(path_to_output_file)
+    assert len(merged["low"]) > 0
+    assert merged["low"] == merged["fast"]
+
+    with pytest.raises(ValueError, match = "Invalid memory mode"):
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = path_to_fragment_files,
+            path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+            number_of_threads = 1,
+            verbose = False,
+            memory_mode = "medium",
+        )
+
+
+@pytest.mark.parametrize(
+    "normalize_columns, missing_score, expected",
+    [
+        (
+            "none",
+            0,
+            [
+                ["chr1", "10", "20", "AAAA-1", "2"],
+                ["chr1", "15", "25", "BBBB-1"],
+                ["chr1", "30", "40", "AAAA-1", "1"],
+                ["chr2", "5", "50", "CCCC-1"],
+            ],
+        ),
+        (
+            "max",
+            0,
+            [
+                ["chr1", "10", "20", "AAAA-1", "2"],
+                ["chr1", "15", "25", "BBBB-1", "0"],
+                ["chr1", "30", "40", "AAAA-1", "1"],
+                ["chr2", "5", "50", "CCCC-1", "0"],
+            ],
+        ),
+        (
+            "max",
+            7,
+            [
+                ["chr1", "10", "20", "AAAA-1", "2"],
+                ["chr1", "15", "25", "BBBB-1", "7"],
+                ["chr1", "30", "40", "AAAA-1", "1"],
+                ["chr2", "5", "50", "CCCC-1", "7"],
+            ],
+        ),
+        (
+            "min",
+            0,
+            [
+                ["chr1", "10", "20", "AAAA-1"],
+                ["chr1", "15", "25", "BBBB-1"],
+                ["chr1", "30", "40", "AAAA-1"],
+                ["chr2", "5", "50", "CCCC-1"],
+            ],
+        ),
+    ],
+)
+def test_merge_normalizes_columns(tmp_path, normalize_columns, missing_score, expected):
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [str(TEST_DIRECTORY.joinpath("mixed_columns.fragments.tsv.gz"))],
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        verbose = False,
+        normalize_columns = normalize_columns,
+        missing_score = missing_score,
+    )
+    assert read_fragments(path_to_output_file) == expected
+
+
+def test_merge_with_invalid_column_normalization(tmp_path):
+    with pytest.raises(ValueError, match = "Invalid column normalization"):
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [str(TEST_DIRECTORY.joinpath("mixed_columns.fragments.tsv.gz"))],
+            path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+            number_of_threads = 1,
+            verbose = False,
+            normalize_columns = "all",
+        )
+
+
+def test_merge_with_differing_column_counts(tmp_path):
+    path_to_fragment_file = os.path.join(tmp_path, "column_counts.fragments.tsv.gz")
+    with gzip.open(path_to_fragment_file, "wt") as f:
+        f.write(
+            "chr1\t10\t20\tAAAA-1\n"
+            "chr1\t30\t40\tBBBB-1\t2\n"
+            "chr1\t50\t60\tCCCC-1\t3\tsample_1\n"
+        )
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [path_to_fragment_file],
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        verbose = False,
+    )
+    # Columns after the score are dropped, lines without a score keep 4 columns.
+    assert read_fragments(path_to_output_file) == [
+        ["chr1", "10", "20", "AAAA-1"],
+        ["chr1", "30", "40", "BBBB-1", "2"],
+        ["chr1", "50", "60", "CCCC-1", "3"],
+    ]
+
+    # A line without a cell barcode is an error, not a crash.
+    with gzip.open(path_to_fragment_file, "at") as f:
+        f.write("chr1\t70\t80\n")
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "Invalid number of fields in fragment file: expected at least 4, got 3",
+    ) as e:
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_fragment_file],
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+        )
+    assert e.value.kind == "malformed"
+
+
+
+def test_merge_skips_comment_lines_and_reports_line_of_malformed_fragment(tmp_path):
+    path_to_fragment_file = os.path.join(tmp_path, "header.fragments.tsv.gz")
+    with gzip.open(path_to_fragment_file, "wt") as f:
+        f.write(
+            "# id=sample_1\n"
+            "chr1\t10\t20\tAAAA-1\t1\n"
+            "chr1\t30\t40\tBBBB-1\t2\n"
+        )
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+
+    def merge():
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_fragment_file],
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+        )
+
+    merge()
+    assert read_fragments(path_to_output_file) == [
+        ["chr1", "10", "20", "AAAA-1", "1"],
+        ["chr1", "30", "40", "BBBB-1", "2"],
+    ]
+
+    with gzip.open(path_to_fragment_file, "at") as f:
+        f.write("chr1\tfifty\t60\tCCCC-1\t3\n")
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "Invalid number \"fifty\" .* \\(line 4 of .*header.fragments.tsv.gz\\)",
+    ) as e:
+        merge()
+    assert e.value.kind == "malformed"
+
+def test_merge_warns_about_more_threads_than_cores(tmp_path, capfd):
+    path_to_fragment_files = [str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))]
+    for number_of_threads in [1, 2 * os.cpu_count() + 1]:
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = path_to_fragment_files,
+            path_to_output_file = os.path.join(tmp_path, f"merged_{number_of_threads}.tsv.gz"),
+            number_of_threads = number_of_threads,
+            verbose = False,
+        )
+        output = capfd.readouterr().out
+        if number_of_threads == 1:
+            assert "Warning" not in output
+        else:
+            assert f"number_of_threads ({number_of_threads}) is more than twice" in output
+
+
+def test_merge_with_shuffled_columns(tmp_path):
+    # sample, barcode, chrom, start, end, score
+    path_to_shuffled = os.path.join(tmp_path, "shuffled.fragments.tsv.gz")
+    with gzip.open(path_to_shuffled, "wt") as f:
+        f.write("sample_1\tAAAA-1\tchr1\t10\t20\t2\n")
+        f.write("sample_1\tBBBB-1\tchr1\t15\t25\n")
+        f.write("sample_1\tAAAA-1\tchr2\t5\t50\t1\n")
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [path_to_shuffled],
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        verbose = False,
+        chrom_column = 2,
+        start_column = 3,
+        end_column = 4,
+        barcode_column = 1,
+        score_column = 5,
+    )
+    # The output has the standard column order, the sample column is dropped.
+    assert read_fragments(path_to_output_file) == [
+        ["chr1", "10", "20", "AAAA-1", "2"],
+        ["chr1", "15", "25", "BBBB-1"],
+        ["chr2", "5", "50", "AAAA-1", "1"],
+    ]
+
+    with pytest.raises(ValueError, match = "Columns should be distinct"):
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_shuffled],
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            chrom_column = 2,
+            start_column = 2,
+        )
+
+
+def test_merge_with_invalid_source_labels(tmp_path):
+    path_to_tie_a = str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))
+    path_to_tie_b = str(TEST_DIRECTORY.joinpath("tie_b.fragments.tsv.gz"))
+    for path_to_fragment_files, kwargs, match in [
+        ([path_to_tie_a, path_to_tie_b], dict(source_labels = ["a"]), "Got 1 source labels for 2 fragment files"),
+        (
+            [path_to_tie_a, path_to_tie_b],
+            dict(source_labels = ["a", "b"], output_codec = "parquet"),
+            "only be added to bgzf output",
+        ),
+        (
+            [path_to_tie_a, path_to_tie_b, path_to_tie_a],
+            dict(source_labels = ["a", "b", "c"], max_open_files = 2),
+            "at most max_open_files",
+        ),
+    ]:
+        with pytest.raises(ValueError, match = match):
+            _rust_scatac_fragment_tools.merge_fragment_files(
+                path_to_fragment_files = path_to_fragment_files,
+                path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+                number_of_threads = 1,
+                verbose = False,
+                **kwargs,
+            )
+
+
+@pytest.mark.parametrize(
+    "duplicate_handling, expected",
+    [
+        (
+            "keep",
+            [
+                ["chr1", "10", "20", "AAAA-1", "2"],
+                ["chr1", "10", "20", "BBBB-1", "1"],
+                ["chr1", "10", "20", "BBBB-1", "3"],
+                ["chr1", "10", "20", "BBBB-1", "1"],
+                ["chr1", "30", "40", "AAAA-1", "1"],
+                ["chr1", "30", "40", "AAAA-1", "1"],
+            ],
+        ),
+        (
+            "collapse_sum_score",
+            [
+                ["chr1", "10", "20", "AAAA-1", "2"],
+                ["chr1", "10", "20", "BBBB-1", "5"],
+                ["chr1", "30", "40", "AAAA-1", "2"],
+            ],
+        ),
+        (
+            "collapse_count",
+            [
+                ["chr1", "10", "20", "AAAA-1", "1"],
+                ["chr1", "10", "20", "BBBB-1", "3"],
+                ["chr1", "30", "40", "AAAA-1", "2"],
+            ],
+        ),
+    ],
+)
+def test_merge_with_duplicate_handling(tmp_path, duplicate_handling, expected):
+    path_to_tie_a = str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))
+    path_to_tie_b = str(TEST_DIRECTORY.joinpath("tie_b.fragments.tsv.gz"))
+    # with max_open_files = 2, duplicates are spread over an intermediate file and the last input file
+    for max_open_files in [2, 3]:
+        path_to_output_file = os.path.join(tmp_path, f"merged_{max_open_files}.tsv.gz")
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_tie_a, path_to_tie_b, path_to_tie_a],
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            max_open_files = max_open_files,
+            duplicate_handling = duplicate_handling,
+            allow_duplicate_inputs = True,
+        )
+        assert read_fragments(path_to_output_file) == expected
+
+
+def test_merge_with_invalid_duplicate_handling(tmp_path):
+    path_to_tie_a = str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))
+    for kwargs, match in [
+        (dict(duplicate_handling = "collapse"), "Invalid duplicate handling"),
+        (
+            dict(duplicate_handling = "collapse_count", collapse_duplicates = True),
+            "can not be given together",
+        ),
+        (
+            dict(duplicate_handling = "collapse_count", source_labels = ["a"]),
+            "can not be added when duplicates are collapsed",
+        ),
+    ]:
+        with pytest.raises(ValueError, match = match):
+            _rust_scatac_fragment_tools.merge_fragment_files(
+                path_to_fragment_files = [path_to_tie_a],
+                path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+                number_of_threads = 1,
+                verbose = False,
+                **kwargs,
+            )
+
+
+def test_merge_with_min_score(tmp_path):
+    path_to_tie_a = str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))
+    path_to_tie_b = str(TEST_DIRECTORY.joinpath("tie_b.fragments.tsv.gz"))
+    path_to_missing_scores = os.path.join(tmp_path, "missing_scores.fragments.tsv.gz")
+    with gzip.open(path_to_missing_scores, "wt") as f:
+        f.write("chr1\t50\t60\tAAAA-1\n")
+    # fragments are filtered before their duplicates are collapsed, also in intermediate files
+    for max_open_files in [2, 3]:
+        path_to_output_file = os.path.join(tmp_path, f"merged_{max_open_files}.tsv.gz")
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_tie_a, path_to_tie_b, path_to_missing_scores],
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            max_open_files = max_open_files,
+            duplicate_handling = "collapse_sum_score",
+            min_score = 2,
+        )
+        assert read_fragments(path_to_output_file) == [
+            ["chr1", "10", "20", "AAAA-1", "2"],
+            ["chr1", "10", "20", "BBBB-1", "3"],
+        ]
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [path_to_tie_a, path_to_missing_scores],
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        verbose = False,
+        min_score = 2,
+        missing_score_passes = True,
+    )
+    assert read_fragments(path_to_output_file) == [["chr1", "50", "60", "AAAA-1"]]
+
+
+def test_merge_with_min_score_and_decimal_scores(tmp_path):
+    with pytest.raises(ValueError, match = "score_predicate can not be used with decimal scores"):
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))],
+            path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+            number_of_threads = 1,
+            verbose = False,
+            min_score = 2,
+            score_precision = 2,
+        )
+
+
+def test_merge_collapses_duplicates_of_resequenced_libraries(tmp_path):
+    path_to_run_1 = os.path.join(tmp_path, "run_1.fragments.tsv.gz")
+    path_to_run_2 = os.path.join(tmp_path, "run_2.fragments.tsv.gz")
+    with gzip.open(path_to_run_1, "wt") as f:
+        f.write("chr1\t10\t20\tAAAA-1\n")
+        f.write("chr1\t10\t20\tBBBB-1\t3\n")
+        f.write("chr1\t30\t40\tAAAA-1\n")
+        f.write("chr2\t10\t20\tAAAA-1\t2\n")
+    with gzip.open(path_to_run_2, "wt") as f:
+        f.write("chr1\t10\t20\tAAAA-1\n")
+        f.write("chr1\t10\t20\tBBBB-1\n")
+        f.write("chr2\t10\t20\tAAAA-1\t2\n")
+    # with max_open_files = 2, duplicates are spread over an intermediate file and the last input file
+    for max_open_files in [2, 3]:
+        path_to_output_file = os.path.join(tmp_path, f"merged_{max_open_files}.tsv.gz")
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_run_1, path_to_run_2, path_to_run_2],
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            max_open_files = max_open_files,
+            collapse_duplicates = True,
+            allow_duplicate_inputs = True,
+        )
+        # missing scores count as one read, fragments without duplicates are unchanged
+        assert read_fragments(path_to_output_file) == [
+            ["chr1", "10", "20", "AAAA-1", "3"],
+            ["chr1", "10", "20", "BBBB-1", "5"],
+            ["chr1", "30", "40", "AAAA-1"],
+            ["chr2", "10", "20", "AAAA-1", "6"],
+        ]
+
+
+def test_merge_with_shift(tmp_path):
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [
+            str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz")),
+            str(TEST_DIRECTORY.joinpath("tie_b.fragments.tsv.gz")),
+        ],
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        verbose = False,
+        duplicate_handling = "collapse_sum_score",
+        shift = (-15, 5),
+    )
+    # starts are clamped at 0
+    assert read_fragments(path_to_output_file) == [
+        ["chr1", "0", "25", "AAAA-1", "2"],
+        ["chr1", "0", "25", "BBBB-1", "4"],
+        ["chr1", "15", "45", "AAAA-1", "1"],
+    ]
+
+
+def test_merge_with_different_contig_orders(tmp_path):
+    # Both files are sorted, but by a different contig order, so chr10 would appear twice in the output.
+    path_to_lexicographic = os.path.join(tmp_path, "lexicographic.fragments.tsv.gz")
+    path_to_natural = os.path.join(tmp_path, "natural.fragments.tsv.gz")
+    with gzip.open(path_to_lexicographic, "wt") as f:
+        f.write("chr10\t10\t20\tAAAA-1\t1\nchr2\t10\t20\tAAAA-1\t1\n")
+    with gzip.open(path_to_natural, "wt") as f:
+        f.write("chr2\t30\t40\tBBBB-1\t1\nchr10\t30\t40\tBBBB-1\t1\n")
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = (
+            "Contig chr10 appears again in the merged output after contig chr2: "
+            f"fragments of chr10 from {path_to_natural} come after fragments of chr10 from {path_to_lexicographic}"
+        ),
+    ):
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_lexicographic, path_to_natural],
+            path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+            number_of_threads = 1,
+            verbose = False,
+        )
+
+
+@pytest.mark.parametrize("merge_contigs_in_parallel", [False, True])
+def test_merge_indexed_files_sorted_in_natural_contig_order(tmp_path, merge_contigs_in_parallel):
+    # The contig order is taken from the tabix indexes. Without them, chr10 of the first file would be
+    # written before chr2 of the second file (lexicographic order), and chr10 would appear twice in the output.
+    path_to_fragment_files = []
+    for name, cell_barcode, contigs in [("a", "AAAA-1", ["chr1", "chr10"]), ("b", "BBBB-1", ["chr1", "chr2", "chr10"])]:
+        path_to_plain_file = os.path.join(tmp_path, f"{name}.fragments.tsv")
+        with open(path_to_plain_file, "w") as f:
+            for contig in contigs:
+                f.write(f"{contig}\t10\t20\t{cell_barcode}\t1\n")
+        path_to_fragment_file = path_to_plain_file + ".gz"
+        _rust_scatac_fragment_tools.rebgzip(
+            path_to_input_file = path_to_plain_file,
+            path_to_output_file = path_to_fragment_file,
+            number_of_threads = 1,
+            create_index = True,
+        )
+        path_to_fragment_files.append(path_to_fragment_file)
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    summary = _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 2,
+        verbose = False,
+        merge_contigs_in_parallel = merge_contigs_in_parallel,
+    )
+    assert summary.contig_order == ["chr1", "chr2", "chr10"]
+    assert read_fragments(path_to_output_file) == [
+        ["chr1", "10", "20", "AAAA-1", "1"],
+        ["chr1", "10", "20", "BBBB-1", "1"],
+        ["chr2", "10", "20", "BBBB-1", "1"],
+        ["chr10", "10", "20", "AAAA-1", "1"],
+        ["chr10", "10", "20", "BBBB-1", "1"],
+    ]
+
+
+@pytest.mark.parametrize(
+    "unsorted_lines, match",
+    [
+        (
+            ["chr1\t30\t40\tAAAA-1\t1", "chr1\t10\t20\tAAAA-1\t1"],
+            "Fragment chr1:10-20 \\(line 3 of .*unsorted.fragments.tsv.gz\\) comes after fragment chr1:30-40",
+        ),
+        (
+            ["chr1\t10\t40\tAAAA-1\t1", "chr1\t10\t20\tAAAA-1\t1"],
+            "Fragment chr1:10-20 \\(line 3 of .*\\) comes after fragment chr1:10-40",
+        ),
+        (
+            ["chr1\t10\t20\tAAAA-1\t1", "chr2\t10\t20\tAAAA-1\t1", "chr1\t30\t40\tAAAA-1\t1"],
+            "Fragment chr1:30-40 \\(line 4 of .*\\) comes after fragment chr2:10-20",
+        ),
+    ],
+)
+def test_merge_with_unsorted_file(tmp_path, unsorted_lines, match):
+    path_to_sorted = str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))
+    path_to_unsorted = os.path.join(tmp_path, "unsorted.fragments.tsv.gz")
+    with gzip.open(path_to_unsorted, "wt") as f:
+        f.write("#header\n")
+        f.write("".join(f"{line}\n" for line in unsorted_lines))
+    with pytest.raises(_rust_scatac_fragment_tools.InvalidFragmentFileError, match = match) as e:
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_sorted, path_to_unsorted],
+            path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+            number_of_threads = 1,
+            verbose = False,
+        )
+    assert e.value.kind == "unsorted"
+
+
+def test_merge_with_barcode_rename(tmp_path):
+    path_to_tie_a = str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))
+    path_to_tie_b = str(TEST_DIRECTORY.joinpath("tie_b.fragments.tsv.gz"))
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    for drop_unrenamed_barcodes, expected in [
+        (
+            False,
+            # fragments are sorted by their original barcode, AAAA-1 before BBBB-1
+            [
+                ["chr1", "10", "20", "ZZZZ-1", "2"],
+                ["chr1", "10", "20", "BBBB-1", "1"],
+                ["chr1", "10", "20", "BBBB-1", "3"],
+                ["chr1", "30", "40", "ZZZZ-1", "1"],
+            ],
+        ),
+        (
+            True,
+            [
+                ["chr1", "10", "20", "ZZZZ-1", "2"],
+                ["chr1", "30", "40", "ZZZZ-1", "1"],
+            ],
+        ),
+    ]:
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_tie_a, path_to_tie_b],
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            barcode_rename = {"AAAA-1": "ZZZZ-1"},
+            drop_unrenamed_barcodes = drop_unrenamed_barcodes,
+        )
+        assert read_fragments(path_to_output_file) == expected
+
+
+@pytest.mark.parametrize(
+    "strand_column, score_column, lines",
+    [
+        (5, 4, ["chr1\t10\t20\tAAAA-1\t3\t+", "chr1\t15\t30\tBBBB-1\t1\t-", "chr2\t5\t9\tAAAA-1\t2\t."]),
+        # the score is optional when it comes after the strand
+        (4, 5, ["chr1\t10\t20\tAAAA-1\t+", "chr1\t15\t30\tBBBB-1\t-\t1", "chr2\t5\t9\tAAAA-1\t."]),
+    ],
+)
+def test_merge_strand_column_in_batches(tmp_path, strand_column, score_column, lines):
+    path_to_fragment_files = []
+    for file_index in range(3):
+        path_to_fragment_file = os.path.join(tmp_path, f"strand_{file_index}.tsv.gz")
+        with gzip.open(path_to_fragment_file, "wt") as f:
+            for line in lines:
+                f.write(f"{line}\n")
+        path_to_fragment_files.append(path_to_fragment_file)
+
+    def merge(max_open_files):
+        path_to_output_file = os.path.join(tmp_path, f"merged_{max_open_files}.tsv.gz")
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = path_to_fragment_files,
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            strand_column = strand_column,
+            score_column = score_column,
+            max_open_files = max_open_files,
+        )
+        return read_fragments(path_to_output_file)
+
+    merged = merge(max_open_files = 2)
+    assert merged == merge(max_open_files = 3)
+    assert [fragment[-1] for fragment in merged] == ["+"] * 3 + ["-"] * 3 + ["."] * 3
+
+
+def test_merge_with_memory_mapped_inputs(tmp_path):
+    # a multi-member file, an uncompressed file and a BGZF file
+    path_to_concatenated = os.path.join(tmp_path, "concatenated.fragments.tsv.gz")
+    with open(path_to_concatenated, "wb") as f:
+        for file_name in ["tie_b.fragments.tsv.gz", "tie_a.fragments.tsv.gz"]:
+            f.write(TEST_DIRECTORY.joinpath(file_name).read_bytes())
+    path_to_uncompressed = os.path.join(tmp_path, "uncompressed.fragments.tsv")
+    with open(path_to_uncompressed, "w") as f:
+        f.write("chr1\t15\t25\tCCCC-1\t1\nchr2\t10\t20\tCCCC-1\t2\n")
+    path_to_fragment_files = [
+        path_to_concatenated,
+        path_to_uncompressed,
+        str(TEST_DIRECTORY.parent.joinpath("split", "a.fragments.tsv.gz")),
+    ]
+
+    merged = {}
+    for memory_map_inputs in [False, True]:
+        path_to_output_file = os.path.join(tmp_path, f"merged_{memory_map_inputs}.tsv.gz")
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = path_to_fragment_files,
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            memory_map_inputs = memory_map_inputs,
+        )
+        merged[memory_map_inputs] = read_fragments(path_to_output_file)
+    assert ["chr1", "15", "25", "CCCC-1", "1"] in merged[False]
+    assert merged[True] == merged[False]
+
+
+@pytest.mark.parametrize(
+    "zero_length, expected_zero_length_fragments",
+    [("keep", 0), ("drop", 2)],
+)
+def test_merge_with_zero_length_fragments(tmp_path, zero_length, expected_zero_length_fragments):
+    path_to_zero_length = str(TEST_DIRECTORY.parent.joinpath("split", "zero_length.fragments.tsv.gz"))
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    summary = _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [path_to_zero_length, str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))],
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        verbose = False,
+        zero_length = zero_length,
+    )
+    assert summary.zero_length_fragments == expected_zero_length_fragments
+    zero_length_fragments = [fragment for fragment in read_fragments(path_to_output_file) if fragment[1] == fragment[2]]
+    assert zero_length_fragments == (
+        [["chr1", "150", "150", "AAAA-1", "1"], ["chr2", "50", "50", "CCCC-1", "2"]] if zero_length == "keep" else []
+    )
+
+
+def test_merge_with_zero_length_fragments_error(tmp_path):
+    with pytest.raises(_rust_scatac_fragment_tools.InvalidFragmentFileError, match = "Zero-length fragment") as e:
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [str(TEST_DIRECTORY.parent.joinpath("split", "zero_length.fragments.tsv.gz"))],
+            path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+            number_of_threads = 1,
+            verbose = False,
+            zero_length = "error",
+        )
+    assert e.value.kind == "invalid_coordinates"
+
+
+def test_merge_sums_decimal_scores(tmp_path):
+    path_to_weighted_a = os.path.join(tmp_path, "weighted_a.fragments.tsv.gz")
+    path_to_weighted_b = os.path.join(tmp_path, "weighted_b.fragments.tsv.gz")
+    with gzip.open(path_to_weighted_a, "wt") as f:
+        f.write("chr1\t10\t20\tAAAA-1\t0.25\n")
+        f.write("chr1\t30\t40\tAAAA-1\t0.3333\n")
+    with gzip.open(path_to_weighted_b, "wt") as f:
+        f.write("chr1\t10\t20\tAAAA-1\t1.75\n")
+        f.write("chr1\t30\t40\tBBBB-1\t2\n")
+    # with max_open_files = 2, the intermediate file keeps the scores exactly
+    for max_open_files in [2, 3]:
+        path_to_output_file = os.path.join(tmp_path, f"merged_{max_open_files}.tsv.gz")
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_weighted_a, path_to_weighted_b, path_to_weighted_b],
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            max_open_files = max_open_files,
+            duplicate_handling = "collapse_sum_score",
+            score_precision = 2,
+            allow_duplicate_inputs = True,
+        )
+        # 0.25 + 1.75 + 1.75 = 3.75, 0.3333 is rounded to 2 decimals
+        assert read_fragments(path_to_output_file) == [
+            ["chr1", "10", "20", "AAAA-1", "3.75"],
+            ["chr1", "30", "40", "AAAA-1", "0.33"],
+            ["chr1", "30", "40", "BBBB-1", "4.00"],
+        ]
+
+
+@pytest.mark.parametrize("score_precision", [7, -1])
+def test_merge_with_invalid_score_precision(tmp_path, score_precision):
+    with pytest.raises((ValueError, OverflowError)):
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))],
+            path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+            number_of_threads = 1,
+            verbose = False,
+            score_precision = score_precision,
+        )
+
+
+def test_merge_with_input_weights(tmp_path):
+    # each sample has its own barcode, so the share of each sample can be counted
+    number_of_fragments = 4000
+    input_weights = [0.2, 0.5, 1.0]
+    path_to_fragment_files = []
+    for sample in range(len(input_weights)):
+        path_to_fragment_file = os.path.join(tmp_path, f"sample_{sample}.tsv.gz")
+        with gzip.open(path_to_fragment_file, "wt") as f:
+            for start in range(number_of_fragments):
+                f.write(f"chr1\t{10 * start}\t{10 * start + 50}\tSAMPLE_{sample}-1\t1\n")
+        path_to_fragment_files.append(path_to_fragment_file)
+
+    merged_per_max_open_files = {}
+    for max_open_files in [2, 3]:
+        path_to_output_file = os.path.join(tmp_path, f"merged_{max_open_files}.tsv.gz")
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = path_to_fragment_files,
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            max_open_files = max_open_files,
+            input_weights = input_weights,
+            sampling_seed = 42,
+        )
+        merged_per_max_open_files[max_open_files] = read_fragments(path_to_output_file)
+    # the inputs are sampled when they are read, so merging in batches keeps the same fragments
+    merged = merged_per_max_open_files[3]
+    assert merged_per_max_open_files[2] == merged
+
+    for sample, input_weight in enumerate(input_weights):
+        number_of_kept_fragments = sum(fragment[3] == f"SAMPLE_{sample}-1" for fragment in merged)
+        assert abs(number_of_kept_fragments / number_of_fragments - input_weight) < 0.05
+
+    # another seed keeps other fragments
+    path_to_output_file = os.path.join(tmp_path, "merged_other_seed.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        verbose = False,
+        input_weights = input_weights,
+        sampling_seed = 43,
+    )
+    assert read_fragments(path_to_output_file) != merged
+
+
+@pytest.mark.parametrize(
+    "input_weights, match",
+    [
+        ([0.5], "Got 1 input weights for 2 fragment files"),
+        ([0.5, 1.5], "Input weights should be between 0 and 1, got 1.5"),
+        ([-0.1, 1.0], "Input weights should be between 0 and 1, got -0.1"),
+    ],
+)
+def test_merge_with_invalid_input_weights(tmp_path, input_weights, match):
+    with pytest.raises(ValueError, match = match):
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [
+                str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz")),
+                str(TEST_DIRECTORY.joinpath("tie_b.fragments.tsv.gz")),
+            ],
+            path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+            number_of_threads = 1,
+            verbose = False,
+            input_weights = input_weights,
+        )
+
+
+def test_merge_with_duplicate_inputs(tmp_path, capfd):
+    path_to_tie_a = str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))
+    path_to_tie_b = str(TEST_DIRECTORY.joinpath("tie_b.fragments.tsv.gz"))
+    # the same file, listed through another relative path
+    path_to_tie_a_again = os.path.relpath(path_to_tie_a, os.getcwd())
+    path_to_fragment_files = [path_to_tie_a, path_to_tie_b, path_to_tie_a_again]
+    path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+    with pytest.raises(ValueError, match = "tie_a.fragments.tsv.gz \\(2 times\\)"):
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = path_to_fragment_files,
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+        )
+    assert not os.path.exists(path_to_output_file)
+
+    # when allowed, the fragments of the duplicated file are written twice
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        verbose = False,
+        allow_duplicate_inputs = True,
+    )
+    assert "fragment files are listed more than once" in capfd.readouterr().out
+    assert len(read_fragments(path_to_output_file)) == 2 * len(read_fragments(path_to_tie_a)) + len(
+        read_fragments(path_to_tie_b)
+    )
+
+
+@pytest.mark.parametrize("n_output_shards", [1, 2, 5])
+def test_merge_into_shards(tmp_path, n_output_shards):
+    path_to_fragment_files = [
+        str(TEST_DIRECTORY.parent.joinpath("split", "a.fragments.tsv.gz")),
+        str(TEST_DIRECTORY.parent.joinpath("split", "b.fragments.tsv.gz")),
+    ]
+    path_to_merged_file = os.path.join(tmp_path, "merged.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = path_to_merged_file,
+        number_of_threads = 1,
+        verbose = False,
+    )
+    merged = read_fragments(path_to_merged_file)
+
+    path_to_shard_folder = os.path.join(tmp_path, "shards")
+    os.makedirs(path_to_shard_folder)
+    summary = _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = os.path.join(path_to_shard_folder, "merged.tsv.gz"),
+        number_of_threads = 1,
+        verbose = False,
+        n_output_shards = n_output_shards,
+    )
+    assert summary.shard_paths == [
+        os.path.join(path_to_shard_folder, f"merged.shard_{shard_index}.tsv.gz") for shard_index in range(n_output_shards)
+    ]
+    # only the shards and their indexes are written
+    assert sorted(os.listdir(path_to_shard_folder)) == sorted(
+        file_name
+        for shard_path in summary.shard_paths
+        for file_name in [os.path.basename(shard_path), os.path.basename(shard_path) + ".tbi"]
+    )
+
+    shards = [read_fragments(shard_path) for shard_path in summary.shard_paths]
+    assert [fragment for shard in shards for fragment in shard] == merged
+    # the shards have about the same number of fragments
+    assert max(len(shard) for shard in shards) <= 2 * len(merged) / n_output_shards
+    for shard_path, shard, shard_range in zip(summary.shard_paths, shards, summary.shard_ranges):
+        assert shard_range == (shard[0][0], int(shard[0][1]), shard[-1][0], int(shard[-1][2]))
+        report = _rust_scatac_fragment_tools.validate_fragment_file(path_to_fragments = shard_path)
+        assert report.number_of_fragments == len(shard)
+        # each shard can be read through its index
+        stats = _rust_scatac_fragment_tools.fragment_file_stats(shard_path)
+        assert stats.indexed
+        assert stats.contigs == report.contig_order
+
+
+def test_merge_into_shards_with_invalid_options(tmp_path):
+    path_to_tie_a = str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))
+    path_to_tie_b = str(TEST_DIRECTORY.joinpath("tie_b.fragments.tsv.gz"))
+    for kwargs, match in [
+        (dict(n_output_shards = 0), "n_output_shards should be at least 1"),
+        (dict(n_output_shards = 2, output_codec = "parquet"), "Shards can only be written to bgzf output"),
+    ]:
+        with pytest.raises(ValueError, match = match):
+            _rust_scatac_fragment_tools.merge_fragment_files(
+                path_to_fragment_files = [path_to_tie_a, path_to_tie_b],
+                path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+                number_of_threads = 1,
+                verbose = False,
+                **kwargs,
+            )
+
+
+def test_merge_with_input_weights_is_deterministic(tmp_path):
+    path_to_fragment_file = os.path.join(tmp_path, "sample.tsv.gz")
+    path_to_chr2_fragment_file = os.path.join(tmp_path, "sample_chr2.tsv.gz")
+    with gzip.open(path_to_fragment_file, "wt") as f, gzip.open(path_to_chr2_fragment_file, "wt") as f_chr2:
+        for chrom in ["chr1", "chr2"]:
+            for start in range(1000):
+                line = f"{chrom}\t{10 * start}\t{10 * start + 50}\tAAAA-1\t1\n"
+                f.write(line)
+                if chrom == "chr2":
+                    f_chr2.write(line)
+
+    def downsample(path_to_input_file, number_of_threads, sampling_seed):
+        path_to_output_file = os.path.join(tmp_path, "downsampled.tsv.gz")
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_input_file],
+            path_to_output_file = path_to_output_file,
+            number_of_threads = number_of_threads,
+            verbose = False,
+            input_weights = [0.5],
+            sampling_seed = sampling_seed,
+        )
+        return read_fragments(path_to_output_file)
+
+    downsampled = downsample(path_to_fragment_file, 1, 7)
+    assert downsample(path_to_fragment_file, 4, 7) == downsampled
+    assert downsample(path_to_fragment_file, 1, 8) != downsampled
+    # each contig is sampled with its own random numbers, which do not depend on the other contigs
+    assert downsample(path_to_chr2_fragment_file, 1, 7) == [
+        fragment for fragment in downsampled if fragment[0] == "chr2"
+    ]
+
+
+def test_merge_with_score_pairs(tmp_path):
+    path_to_fragment_file = os.path.join(tmp_path, "score_pairs.fragments.tsv.gz")
+    with gzip.open(path_to_fragment_file, "wt") as f:
+        f.write("chr1\t10\t20\tAAAA-1\t3,5\n")
+        f.write("chr1\t10\t20\tAAAA-1\t2\n")
+        f.write("chr1\t30\t40\tBBBB-1\t1,4\n")
+
+    def merge(number_of_files = 1, **kwargs):
+        path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz")
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_fragment_file] * number_of_files,
+            path_to_output_file = path_to_output_file,
+            number_of_threads = 1,
+            verbose = False,
+            allow_duplicate_inputs = number_of_files > 1,
+            **kwargs,
+        )
+        return read_fragments(path_to_output_file)
+
+    # pairs round-trip unchanged
+    assert merge(score_pair = "first") == read_fragments(path_to_fragment_file)
+    # also through the intermediate files of batched merges
+    assert merge(3, score_pair = "first", max_open_files = 2) == sorted(
+        read_fragments(path_to_fragment_file) * 3,
+        key = lambda fragment: (fragment[0], int(fragment[1]), int(fragment[2]), fragment[3]),
+    )
+    assert merge(3, score_pair = "first", max_open_files = 2, duplicate_handling = "collapse_sum_score") == [
+        ["chr1", "10", "20", "AAAA-1", "15"],
+        ["chr1", "30", "40", "BBBB-1", "3"],
+    ]
+    # collapsed duplicates get the sum of the first values (3 + 2) as score, unchanged pairs are kept
+    assert merge(score_pair = "first", duplicate_handling = "collapse_sum_score") == [
+        ["chr1", "10", "20", "AAAA-1", "5"],
+        ["chr1", "30", "40", "BBBB-1", "1,4"],
+    ]
+
+    with pytest.raises(_rust_scatac_fragment_tools.InvalidFragmentFileError, match = "Invalid number \"3,5\""):
+        merge()
+    with pytest.raises(ValueError, match = "Score pairs can not be used with decimal scores"):
+        merge(score_pair = "first", score_precision = 2)
+
+    with gzip.open(path_to_fragment_file, "wt") as f:
+        f.write("chr1\t10\t20\tAAAA-1\t3,five\n")
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "Invalid score pair \"3,five\"",
+    ) as e:
+        merge(score_pair = "first")
+    assert e.value.kind == "malformed"
+
+
+@pytest.mark.parametrize(
+    "kwargs",
+    [
+        dict(number_of_threads = 1),
+        dict(number_of_threads = 4),
+        dict(number_of_threads = 4, max_open_files = 2),
+        dict(number_of_threads = 4, input_weights = [0.5, 0.5, 0.5], sampling_seed = 3),
+    ],
+)
+def test_merge_contigs_in_parallel(tmp_path, kwargs):
+    path_to_fragment_files = [
+        str(TEST_DIRECTORY.parent.joinpath("split", file_name))
+        for file_name in ["a.fragments.tsv.gz", "b.fragments.tsv.gz", "scores.fragments.tsv.gz"]
+    ]
+    path_to_merged_file = os.path.join(tmp_path, "merged.tsv.gz")
+    summary = _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = path_to_merged_file,
+        verbose = False,
+        **kwargs,
+    )
+
+    path_to_parallel_folder = os.path.join(tmp_path, "parallel")
+    os.makedirs(path_to_parallel_folder)
+    path_to_parallel_merged_file = os.path.join(path_to_parallel_folder, "merged.tsv.gz")
+    parallel_summary = _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = path_to_parallel_merged_file,
+        verbose = False,
+        merge_contigs_in_parallel = True,
+        **kwargs,
+    )
+    assert read_fragments(path_to_parallel_merged_file) == read_fragments(path_to_merged_file)
+    assert parallel_summary.contig_order == summary.contig_order
+    # the temporary files of the contigs are removed, the output is indexed
+    assert sorted(os.listdir(path_to_parallel_folder)) == ["merged.tsv.gz", "merged.tsv.gz.tbi"]
+    stats = _rust_scatac_fragment_tools.fragment_file_stats(path_to_parallel_merged_file)
+    assert stats.indexed
+    assert stats.contigs == summary.contig_order
+
+
+def test_merge_contigs_in_parallel_with_invalid_options(tmp_path):
+    path_to_tie_a = str(TEST_DIRECTORY.joinpath("tie_a.fragments.tsv.gz"))
+    path_to_a = str(TEST_DIRECTORY.parent.joinpath("split", "a.fragments.tsv.gz"))
+    for kwargs, match in [
+        (dict(output_codec = "parquet"), "Contigs can only be merged in parallel for bgzf output"),
+        (dict(add_fragment_ids = True), "Fragment IDs can not be added when merging contigs in parallel"),
+    ]:
+        with pytest.raises(ValueError, match = match):
+            _rust_scatac_fragment_tools.merge_fragment_files(
+                path_to_fragment_files = [path_to_a],
+                path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+                number_of_threads = 2,
+                verbose = False,
+                merge_contigs_in_parallel = True,
+                **kwargs,
+            )
+    # the contigs of each file are read through its index
+    with pytest.raises(_rust_scatac_fragment_tools.InvalidFragmentFileError, match = "it has no tabix index"):
+        _rust_scatac_fragment_tools.merge_fragment_files(
+            path_to_fragment_files = [path_to_a, path_to_tie_a],
+            path_to_output_file = os.path.join(tmp_path, "merged.tsv.gz"),
+            number_of_threads = 2,
+            verbose = False,
+            merge_contigs_in_parallel = True,
+        )
@@ -0,0 +1,141 @@
+import gzip
+import os
+import pathlib
+import struct
+
+import pytest
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+
+PATH_TO_A_FRAGMENTS = str(TEST_DIRECTORY.parent.joinpath("split", "a.fragments.tsv.gz"))
+
+
+def uncompressed_block_sizes(path_to_bgzf_file):
+    """Returns the uncompressed size of each BGZF block, from the BSIZE and ISIZE fields."""
+    with open(path_to_bgzf_file, "rb") as f:
+        data = f.read()
+    block_sizes = []
+    offset = 0
+    while offset < len(data):
+        # BSIZE (total block size - 1) is stored in the BC extra subfield, at offset 16 of the header
+        (total_block_size_minus_1,) = struct.unpack_from("<H", data, offset + 16)
+        offset += total_block_size_minus_1 + 1
+        (uncompressed_size,) = struct.unpack_from("<I", data, offset - 4)
+        block_sizes.append(uncompressed_size)
+    return block_sizes
+
+
+def test_rebgzip_writes_uniform_blocks(tmp_path):
+    path_to_output_file = os.path.join(tmp_path, "rebgzipped.fragments.tsv.gz")
+    _rust_scatac_fragment_tools.rebgzip(
+        path_to_input_file = PATH_TO_A_FRAGMENTS,
+        path_to_output_file = path_to_output_file,
+        block_size = 100,
+        number_of_threads = 1,
+    )
+    with gzip.open(PATH_TO_A_FRAGMENTS, "rb") as f:
+        content = f.read()
+    with gzip.open(path_to_output_file, "rb") as f:
+        assert f.read() == content
+
+    # the last block is the empty EOF block
+    block_sizes = uncompressed_block_sizes(path_to_output_file)
+    assert block_sizes[-1] == 0
+    assert block_sizes[:-2] == [100] * (len(content) // 100)
+    assert sum(block_sizes) == len(content)
+
+
+def test_rebgzip_with_index(tmp_path):
+    path_to_output_file = os.path.join(tmp_path, "rebgzipped.fragments.tsv.gz")
+    _rust_scatac_fragment_tools.rebgzip(
+        path_to_input_file = PATH_TO_A_FRAGMENTS,
+        path_to_output_file = path_to_output_file,
+        block_size = 256,
+        number_of_threads = 1,
+        create_index = True,
+    )
+    assert os.path.exists(path_to_output_file + ".tbi")
+
+    # splitting fetches each contig through the index
+    cell_type_to_cell_barcodes = {"all": ["TTAGCTTAGGAGAACA-1", "TGTGACAGTACAACGG-1"]}
+    for output_folder, path_to_fragments in [("original", PATH_TO_A_FRAGMENTS), ("rebgzipped", path_to_output_file)]:
+        os.makedirs(tmp_path.joinpath(output_folder))
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_fragments,
+            path_to_output_folder = str(tmp_path.joinpath(output_folder)),
+            cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+            chromsizes = {"chr1": 248956422, "chr2": 242193529},
+            verbose = False,
+        )
+    with gzip.open(tmp_path.joinpath("original", "all.fragments.tsv.gz"), "rb") as f:
+        expected = f.read()
+    assert len(expected) > 0
+    with gzip.open(tmp_path.joinpath("rebgzipped", "all.fragments.tsv.gz"), "rb") as f:
+        assert f.read() == expected
+
+
+def test_rebgzip_with_invalid_block_size(tmp_path):
+    with pytest.raises(ValueError, match = "block_size should be between 1 and 65280"):
+        _rust_scatac_fragment_tools.rebgzip(
+            path_to_input_file = PATH_TO_A_FRAGMENTS,
+            path_to_output_file = os.path.join(tmp_path, "rebgzipped.fragments.tsv.gz"),
+            block_size = 65281,
+        )
+
+
+def test_rebgzip_with_index_for_custom_columns(tmp_path):
+    # move the cell barcode to the first column and add a header line
+    path_to_input_file = os.path.join(tmp_path, "barcode_first.tsv")
+    with gzip.open(PATH_TO_A_FRAGMENTS, "rt") as f_in, open(path_to_input_file, "w") as f_out:
+        f_out.write("barcode\tchrom\tstart\tend\tcount\n")
+        for line in f_in:
+            chrom, start, end, barcode, count = line.rstrip("\n").split("\t")
+            f_out.write("\t".join([barcode, chrom, start, end, count]) + "\n")
+    path_to_output_file = os.path.join(tmp_path, "barcode_first.tsv.gz")
+    _rust_scatac_fragment_tools.rebgzip(
+        path_to_input_file = path_to_input_file,
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        create_index = True,
+        tabix_columns = (1, 2, 3, "#", 1),
+    )
+    assert os.path.exists(path_to_output_file + ".tbi")
+
+    # the contigs and number of records per contig are read from the index
+    stats = _rust_scatac_fragment_tools.fragment_file_stats(path_to_output_file)
+    expected_stats = _rust_scatac_fragment_tools.fragment_file_stats(PATH_TO_A_FRAGMENTS)
+    assert stats.indexed
+    assert stats.contigs == expected_stats.contigs
+    assert stats.records_per_contig == expected_stats.records_per_contig
+
+
+def test_rebgzip_with_tabix_columns_not_matching_the_file(tmp_path):
+    with pytest.raises(ValueError, match = "has no integer start position in tabix column 3"):
+        _rust_scatac_fragment_tools.rebgzip(
+            path_to_input_file = PATH_TO_A_FRAGMENTS,
+            path_to_output_file = os.path.join(tmp_path, "rebgzipped.fragments.tsv.gz"),
+            number_of_threads = 1,
+            create_index = True,
+            tabix_columns = (0, 3, 2, "#", 0),
+        )
+
+
+@pytest.mark.parametrize(
+    "tabix_preset, tabix_columns, message",
+    [
+        ("vcf", None, "Invalid tabix preset"),
+        ("bed", (0, 1, 2, "#", 0), "can not be given together"),
+        (None, (0, 1, 1, "#", 0), "Tabix columns should be distinct"),
+    ],
+)
+def test_rebgzip_with_invalid_tabix_layout(tmp_path, tabix_preset, tabix_columns, message):
+    with pytest.raises(ValueError, match = message):
+        _rust_scatac_fragment_tools.rebgzip(
+            path_to_input_file = PATH_TO_A_FRAGMENTS,
+            path_to_output_file = os.path.join(tmp_path, "rebgzipped.fragments.tsv.gz"),
+            create_index = True,
+            tabix_preset = tabix_preset,
+            tabix_columns = tabix_columns,
+        )
@@ -0,0 +1,39 @@
+import os
+import pathlib
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+SPLIT_TEST_DIRECTORY = pathlib.Path(__file__).parent.parent.absolute().joinpath("split")
+
+# a and b have disjoint sets of cell barcodes (19 and 20 barcodes)
+PATH_TO_A_FRAGMENTS = str(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz"))
+PATH_TO_B_FRAGMENTS = str(SPLIT_TEST_DIRECTORY.joinpath("b.fragments.tsv.gz"))
+
+
+def test_compare_barcode_sets(tmp_path):
+    assert _rust_scatac_fragment_tools.compare_barcode_sets(
+        PATH_TO_A_FRAGMENTS, PATH_TO_B_FRAGMENTS
+    ) == (19, 0, 20)
+    assert _rust_scatac_fragment_tools.compare_barcode_sets(
+        PATH_TO_A_FRAGMENTS, PATH_TO_A_FRAGMENTS
+    ) == (0, 19, 0)
+
+    # the merge of a and b contains the barcodes of both
+    path_to_merged_file = os.path.join(tmp_path, "merged.fragments.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [PATH_TO_A_FRAGMENTS, PATH_TO_B_FRAGMENTS],
+        path_to_output_file = path_to_merged_file,
+        number_of_threads = 1,
+        verbose = False,
+    )
+    path_to_indexed_file = os.path.join(tmp_path, "indexed.fragments.tsv.gz")
+    _rust_scatac_fragment_tools.rebgzip(
+        path_to_input_file = path_to_merged_file,
+        path_to_output_file = path_to_indexed_file,
+        number_of_threads = 1,
+        create_index = True,
+    )
+    assert _rust_scatac_fragment_tools.compare_barcode_sets(
+        path_to_fragments_a = PATH_TO_A_FRAGMENTS,
+        path_to_fragments_b = path_to_indexed_file,
+    ) == (0, 19, 20)
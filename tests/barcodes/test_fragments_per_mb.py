@@ -0,0 +1,50 @@
+import pathlib
+
+import pytest
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+SPLIT_TEST_DIRECTORY = pathlib.Path(__file__).parent.parent.absolute().joinpath("split")
+
+# a has 20 fragments of 19 cell barcodes, 10 on chr1 and 10 on chr2
+PATH_TO_A_FRAGMENTS = str(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz"))
+
+
+def test_fragments_per_mb():
+    # TTAGCTTAGGAGAACA-1 has 2 fragments on chr1, on a genome of 4 Mb
+    cell_barcode_to_fragments_per_mb = _rust_scatac_fragment_tools.fragments_per_mb(
+        PATH_TO_A_FRAGMENTS, {"chr1": 1_000_000, "chr2": 3_000_000}
+    )
+    assert len(cell_barcode_to_fragments_per_mb) == 19
+    assert cell_barcode_to_fragments_per_mb["TTAGCTTAGGAGAACA-1"] == pytest.approx(0.5)
+    assert sum(cell_barcode_to_fragments_per_mb.values()) == pytest.approx(20 / 4)
+
+
+def test_fragments_per_mb_with_genome_size_override():
+    cell_barcode_to_fragments_per_mb = _rust_scatac_fragment_tools.fragments_per_mb(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        chromsizes = {"chr1": 248956422},
+        genome_size_override = 500_000,
+    )
+    # only the fragments on chr1 are counted
+    assert sum(cell_barcode_to_fragments_per_mb.values()) == pytest.approx(10 / 0.5)
+    assert cell_barcode_to_fragments_per_mb["TTAGCTTAGGAGAACA-1"] == pytest.approx(4.0)
+
+
+def test_fragments_per_mb_with_empty_genome():
+    with pytest.raises(ValueError, match = "The genome size should be larger than 0"):
+        _rust_scatac_fragment_tools.fragments_per_mb(PATH_TO_A_FRAGMENTS, {})
+
+
+def test_fragments_per_mb_as_arrow():
+    pl = pytest.importorskip("polars")
+    chromsizes = {"chr1": 1_000_000, "chr2": 3_000_000}
+    cell_barcode_to_fragments_per_mb = _rust_scatac_fragment_tools.fragments_per_mb(
+        PATH_TO_A_FRAGMENTS, chromsizes
+    )
+    df = pl.read_ipc_stream(
+        _rust_scatac_fragment_tools.fragments_per_mb(PATH_TO_A_FRAGMENTS, chromsizes, output_format = "arrow")
+    )
+    assert df.columns == ["cell_barcode", "fragments_per_mb"]
+    assert df["cell_barcode"].to_list() == sorted(cell_barcode_to_fragments_per_mb)
+    assert dict(df.iter_rows()) == cell_barcode_to_fragments_per_mb
@@ -0,0 +1,40 @@
+import os
+import pathlib
+import shutil
+
+import pytest
+from conftest import read_fragments
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+SPLIT_TEST_DIRECTORY = pathlib.Path(__file__).parent.parent.absolute().joinpath("split")
+
+PATH_TO_A_FRAGMENTS = str(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz"))
+
+CELL_BARCODES = ["TTAGCTTAGGAGAACA-1", "CATGCCTTCTCTGACC-1", "AACGAGGCATCATGTG-1", "NOT_IN_FILE-1"]
+
+
+@pytest.mark.parametrize("indexed", [True, False])
+def test_subset_fragments(tmp_path, indexed):
+    path_to_fragments = PATH_TO_A_FRAGMENTS
+    if not indexed:
+        path_to_fragments = str(tmp_path.joinpath("a.fragments.tsv.gz"))
+        shutil.copy(PATH_TO_A_FRAGMENTS, path_to_fragments)
+    path_to_output_file = str(tmp_path.joinpath("subset.fragments.tsv.gz"))
+    number_of_fragments = _rust_scatac_fragment_tools.subset_fragments(
+        path_to_fragments = path_to_fragments,
+        path_to_output_file = path_to_output_file,
+        cell_barcodes = CELL_BARCODES,
+        create_index = True,
+    )
+
+    expected = [
+        fragment
+        for fragment in read_fragments(PATH_TO_A_FRAGMENTS, skip_comments = True)
+        if fragment[3] in CELL_BARCODES
+    ]
+    assert len(expected) > 0
+    assert number_of_fragments == len(expected)
+    assert read_fragments(path_to_output_file, skip_comments = True) == expected
+    assert {fragment[3] for fragment in expected} == set(CELL_BARCODES[:3])
+    assert os.path.exists(path_to_output_file + ".tbi")
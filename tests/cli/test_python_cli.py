@@ -0,0 +1,90 @@
+import os
+import pathlib
+import sys
+
+import pytest
+from conftest import read_fragments_as_text
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+pytest.importorskip("rich_argparse")
+
+from scatac_fragment_tools.cli.main import main  # noqa: E402
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+AGGREGATE_DIRECTORY = TEST_DIRECTORY.parent.joinpath("aggregate")
+
+PATH_TO_FRAGMENT_FILES = [
+    str(AGGREGATE_DIRECTORY.joinpath("tie_a.fragments.tsv.gz")),
+    str(AGGREGATE_DIRECTORY.joinpath("tie_b.fragments.tsv.gz")),
+]
+
+
+def test_aggregate_command(tmp_path, monkeypatch):
+    path_to_output_file = os.path.join(tmp_path, "cli.fragments.tsv.gz")
+    monkeypatch.setattr(
+        sys,
+        "argv",
+        ["scatac_fragment_tools", "aggregate", "-i", *PATH_TO_FRAGMENT_FILES, "-o", path_to_output_file],
+    )
+    assert main() == 0
+
+    path_to_expected_file = os.path.join(tmp_path, "expected.fragments.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = PATH_TO_FRAGMENT_FILES,
+        path_to_output_file = path_to_expected_file,
+        number_of_threads = 1,
+        verbose = False,
+    )
+    assert read_fragments_as_text(path_to_output_file) == read_fragments_as_text(path_to_expected_file)
+
+
+def test_aggregate_command_with_missing_fragment_file(tmp_path, monkeypatch):
+    monkeypatch.setattr(
+        sys,
+        "argv",
+        [
+            "scatac_fragment_tools",
+            "aggregate",
+            "-i",
+            os.path.join(tmp_path, "missing.fragments.tsv.gz"),
+            "-o",
+            os.path.join(tmp_path, "merged.fragments.tsv.gz"),
+        ],
+    )
+    with pytest.raises(FileNotFoundError, match = "missing.fragments.tsv.gz"):
+        main()
+
+
+def test_aggregate_command_with_merge_contigs_in_parallel(tmp_path, monkeypatch):
+    path_to_fragment_files = [
+        str(TEST_DIRECTORY.parent.joinpath("split", file_name))
+        for file_name in ["a.fragments.tsv.gz", "b.fragments.tsv.gz"]
+    ]
+    path_to_output_file = os.path.join(tmp_path, "cli.fragments.tsv.gz")
+    monkeypatch.setattr(
+        sys,
+        "argv",
+        [
+            "scatac_fragment_tools",
+            "aggregate",
+            "-i",
+            *path_to_fragment_files,
+            "-o",
+            path_to_output_file,
+            "-n",
+            "2",
+            "--merge_contigs_in_parallel",
+        ],
+    )
+    assert main() == 0
+    assert os.path.exists(path_to_output_file + ".tbi")
+
+    path_to_expected_file = os.path.join(tmp_path, "expected.fragments.tsv.gz")
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = path_to_expected_file,
+        number_of_threads = 1,
+        verbose = False,
+    )
+    assert read_fragments_as_text(path_to_output_file) == read_fragments_as_text(path_to_expected_file)
@@ -0,0 +1,191 @@
+import os
+import pathlib
+import subprocess
+
+import pytest
+from conftest import read_fragments_as_text
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+SPLIT_DIRECTORY = TEST_DIRECTORY.parent.joinpath("split")
+RUST_DIRECTORY = TEST_DIRECTORY.parent.parent.joinpath("rust")
+
+PATH_TO_A_FRAGMENTS = str(SPLIT_DIRECTORY.joinpath("a.fragments.tsv.gz"))
+PATH_TO_ANNOTATION = str(SPLIT_DIRECTORY.joinpath("cell_type_annotation.tsv"))
+PATH_TO_CHROMSIZES = str(SPLIT_DIRECTORY.joinpath("hg38.chrom.sizes"))
+
+CHROMSIZES = {"chr1": 248956422, "chr2": 242193529}
+
+
+def find_binary():
+    """
+    Path to the Rust binary, set with SCATAC_FRAGMENT_TOOLS_RS
+    or built with `cargo build --no-default-features` in the rust folder.
+    """
+    if os.environ.get("SCATAC_FRAGMENT_TOOLS_RS"):
+        return os.environ["SCATAC_FRAGMENT_TOOLS_RS"]
+    for profile in ["release", "debug"]:
+        path = RUST_DIRECTORY.joinpath("target", profile, "scatac_fragment_tools_rs")
+        if path.exists():
+            return str(path)
+    return None
+
+
+PATH_TO_BINARY = find_binary()
+
+if PATH_TO_BINARY is None:
+    # CI builds the binary, so a missing binary there is an error instead of a reason to skip
+    if os.environ.get("CI"):
+        pytest.fail("Rust binary scatac_fragment_tools_rs is not built", pytrace = False)
+    pytest.skip("Rust binary scatac_fragment_tools_rs is not built", allow_module_level = True)
+
+
+def run_binary(*args):
+    return subprocess.run(
+        [PATH_TO_BINARY, *args], capture_output=True, text=True
+    )
+
+
+def cell_type_to_cell_barcodes_of_sample(sample):
+    cell_type_to_cell_barcodes = {}
+    with open(PATH_TO_ANNOTATION) as f:
+        header = f.readline().rstrip("\n").split("\t")
+        for line in f:
+            row = dict(zip(header, line.rstrip("\n").split("\t")))
+            if row["sample"] == sample:
+                cell_type_to_cell_barcodes.setdefault(row["cell_type"], []).append(
+                    row["cell_barcode"]
+                )
+    return cell_type_to_cell_barcodes
+
+
+def test_binary_split_matches_python(tmp_path):
+    binary_output_folder = tmp_path.joinpath("binary")
+    python_output_folder = tmp_path.joinpath("python")
+    binary_output_folder.mkdir()
+    python_output_folder.mkdir()
+
+    result = run_binary(
+        "split",
+        "--fragments", PATH_TO_A_FRAGMENTS,
+        "--annotation", PATH_TO_ANNOTATION,
+        "--sample", "A",
+        "--chromsizes", PATH_TO_CHROMSIZES,
+        "--output-folder", str(binary_output_folder),
+    )
+    assert result.returncode == 0, result.stderr
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(python_output_folder),
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes_of_sample("A"),
+        chromsizes = CHROMSIZES,
+        verbose = False,
+    )
+
+    binary_files = sorted(os.listdir(binary_output_folder))
+    assert binary_files == sorted(os.listdir(python_output_folder))
+    assert len(binary_files) > 0
+    for file_name in binary_files:
+        assert read_fragments_as_text(binary_output_folder.joinpath(file_name)) == read_fragments_as_text(
+            python_output_folder.joinpath(file_name)
+        )
+
+
+
+def test_binary_split_prints_counts(tmp_path):
+    result = run_binary(
+        "split",
+        "--fragments", PATH_TO_A_FRAGMENTS,
+        "--annotation", PATH_TO_ANNOTATION,
+        "--sample", "A",
+        "--chromsizes", PATH_TO_CHROMSIZES,
+        "--output-folder", str(tmp_path),
+        "--print-counts",
+    )
+    assert result.returncode == 0, result.stderr
+    lines = result.stdout.splitlines()
+    # the table follows any warnings
+    header_index = lines.index("cell_type\tfragments")
+    counts = dict(line.split("\t") for line in lines[header_index + 1:])
+    assert sorted(counts) == sorted(cell_type_to_cell_barcodes_of_sample("A"))
+    for cell_type, number_of_fragments in counts.items():
+        path_to_output = tmp_path.joinpath(f"{cell_type}.fragments.tsv.gz")
+        expected = len(read_fragments_as_text(path_to_output).splitlines()) if path_to_output.exists() else 0
+        assert int(number_of_fragments) == expected
+
+def test_binary_aggregate_matches_python(tmp_path):
+    path_to_fragment_files = [
+        str(SPLIT_DIRECTORY.joinpath("a.fragments.tsv.gz")),
+        str(SPLIT_DIRECTORY.joinpath("b.fragments.tsv.gz")),
+    ]
+    path_to_binary_output = str(tmp_path.joinpath("binary.fragments.tsv.gz"))
+    path_to_python_output = str(tmp_path.joinpath("python.fragments.tsv.gz"))
+
+    result = run_binary(
+        "aggregate", *path_to_fragment_files, "--output", path_to_binary_output
+    )
+    assert result.returncode == 0, result.stderr
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = path_to_python_output,
+        number_of_threads = 1,
+        verbose = False,
+    )
+
+    assert read_fragments_as_text(path_to_binary_output) == read_fragments_as_text(path_to_python_output)
+
+
+def test_binary_aggregate_with_shift_matches_python(tmp_path):
+    path_to_fragment_files = [str(SPLIT_DIRECTORY.joinpath("a.fragments.tsv.gz"))]
+    path_to_binary_output = str(tmp_path.joinpath("binary.fragments.tsv.gz"))
+    path_to_python_output = str(tmp_path.joinpath("python.fragments.tsv.gz"))
+
+    result = run_binary(
+        "aggregate", *path_to_fragment_files, "--output", path_to_binary_output, "--shift", "-5,4"
+    )
+    assert result.returncode == 0, result.stderr
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = path_to_fragment_files,
+        path_to_output_file = path_to_python_output,
+        number_of_threads = 1,
+        verbose = False,
+        shift = (-5, 4),
+    )
+
+    assert read_fragments_as_text(path_to_binary_output) == read_fragments_as_text(path_to_python_output)
+    assert run_binary(
+        "aggregate", *path_to_fragment_files, "--output", path_to_binary_output, "--shift", "4"
+    ).returncode != 0
+
+
+def test_binary_validate_reports_fragments_per_contig():
+    result = run_binary("validate", PATH_TO_A_FRAGMENTS, "--chromsizes", PATH_TO_CHROMSIZES)
+    assert result.returncode == 0, result.stderr
+
+    report = _rust_scatac_fragment_tools.validate_fragment_file(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        chromsizes = CHROMSIZES,
+    )
+    assert result.stdout.splitlines() == [
+        f"{contig}\t{report.fragments_per_contig[contig]}"
+        for contig in report.contig_order
+    ]
+    assert sum(report.fragments_per_contig.values()) == report.number_of_fragments
+
+
+def test_binary_validate_fails_on_unsorted_file(tmp_path):
+    path_to_fragments = tmp_path.joinpath("unsorted.fragments.tsv")
+    path_to_fragments.write_text(
+        "chr1\t100\t200\tAAAA-1\t1\n"
+        "chr1\t50\t150\tAAAA-1\t1\n"
+    )
+
+    result = run_binary("validate", str(path_to_fragments))
+    assert result.returncode != 0
+    assert "not sorted by start position" in result.stderr
+    assert "line 2" in result.stderr
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError, match="line 2"
+    ):
+        _rust_scatac_fragment_tools.validate_fragment_file(str(path_to_fragments))
@@ -0,0 +1,42 @@
+import gzip
+
+
+def _open(path, mode):
+    if mode.startswith("r"):
+        with open(path, "rb") as f:
+            is_gzip_compressed = f.read(2) == b"\x1f\x8b"
+    else:
+        is_gzip_compressed = str(path).endswith(".gz")
+    if is_gzip_compressed:
+        return gzip.open(path, mode)
+    return open(path, mode)
+
+
+def read_fragments(path_to_fragment_file, skip_comments = False):
+    """Read a (gzip compressed) fragment file as a list of fragments, each a list of its fields."""
+    with _open(path_to_fragment_file, "rt") as f:
+        return [
+            line.rstrip("\n").split("\t")
+            for line in f
+            if not (skip_comments and line.startswith("#"))
+        ]
+
+
+def read_fragments_as_text(path_to_fragment_file):
+    """Read the full content of a (gzip compressed) fragment file."""
+    with _open(path_to_fragment_file, "rt") as f:
+        return f.read()
+
+
+def write_fragments(path, fragments):
+    """
+    Write fragments, given as lines or as sequences of fields, to a fragment file.
+
+    The file is gzip compressed when the path ends with ".gz".
+    """
+    with _open(path, "wt") as f:
+        for fragment in fragments:
+            if not isinstance(fragment, str):
+                fragment = "\t".join(str(field) for field in fragment)
+            f.write(fragment + "\n")
+    return str(path)
@@ -0,0 +1,57 @@
+import gzip
+import os
+import pathlib
+
+import pytest
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+
+
+def test_bedpe_to_fragments(tmp_path):
+    path_to_output_file = os.path.join(tmp_path, "fragments.tsv.gz")
+    _rust_scatac_fragment_tools.bedpe_to_fragments(
+        path_to_bedpe = str(TEST_DIRECTORY.joinpath("pairs.bedpe")),
+        path_to_output_file = path_to_output_file,
+    )
+    with gzip.open(path_to_output_file, "rt") as f:
+        fragments = [line.rstrip("\n").split("\t") for line in f]
+    # The interchromosomal pair is dropped and the output is sorted.
+    assert fragments == [
+        ["chr1", "50", "170", "AAACGAAAGTCATCGT-1"],
+        ["chr1", "100", "300", "AAACGAAAGCGAGCAC-1"],
+        ["chr1", "420", "550", "AAACGAAAGTCATCGT-1"],
+    ]
+
+
+def test_bedpe_to_fragments_with_score_column(tmp_path):
+    path_to_output_file = os.path.join(tmp_path, "fragments.tsv.gz")
+    _rust_scatac_fragment_tools.bedpe_to_fragments(
+        path_to_bedpe = str(TEST_DIRECTORY.joinpath("pairs.bedpe")),
+        path_to_output_file = path_to_output_file,
+        score_column = 7,
+    )
+    with gzip.open(path_to_output_file, "rt") as f:
+        fragments = [line.rstrip("\n").split("\t") for line in f]
+    assert [fragment[4] for fragment in fragments] == ["60", "60", "60"]
+
+
+@pytest.mark.parametrize(
+    "line, message",
+    [
+        ("chr1\t100\t150\tchr1\t250\n", "Line 2 of .* has no column 6"),
+        ("chr1\t100\t150\tchr1\tx\t300\tAAACGAAAGCGAGCAC-1\n", "Invalid position in column 5 on line 2"),
+    ],
+)
+def test_bedpe_to_fragments_with_malformed_line(tmp_path, line, message):
+    path_to_bedpe = os.path.join(tmp_path, "malformed.bedpe")
+    with open(path_to_bedpe, "w") as f:
+        f.write("chr1\t50\t100\tchr1\t120\t170\tAAACGAAAGTCATCGT-1\n")
+        f.write(line)
+    with pytest.raises(_rust_scatac_fragment_tools.InvalidFragmentFileError, match = message) as e:
+        _rust_scatac_fragment_tools.bedpe_to_fragments(
+            path_to_bedpe = path_to_bedpe,
+            path_to_output_file = os.path.join(tmp_path, "fragments.tsv.gz"),
+        )
+    assert e.value.kind == "malformed"
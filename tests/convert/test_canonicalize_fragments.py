@@ -0,0 +1,73 @@
+import gzip
+import os
+import pathlib
+
+import pytest
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+
+
+def canonicalize(tmp_path, **kwargs):
+    path_to_output_file = os.path.join(tmp_path, "canonical.tsv.gz")
+    number_of_fragments = _rust_scatac_fragment_tools.canonicalize_fragments(
+        path_to_fragments = str(TEST_DIRECTORY.joinpath("messy.fragments.tsv")),
+        path_to_output_file = path_to_output_file,
+        number_of_threads = 1,
+        **kwargs,
+    )
+    with gzip.open(path_to_output_file, "rt") as f:
+        fragments = [line.rstrip("\n").split("\t") for line in f]
+    assert number_of_fragments == len(fragments)
+    # the output is tabix indexed
+    assert _rust_scatac_fragment_tools.fragment_file_stats(path_to_output_file).indexed
+    return fragments
+
+
+@pytest.mark.parametrize(
+    "contig_name_mode, expected",
+    [
+        (
+            "add_chr",
+            [
+                ["chr1", "100", "200", "AAAA-1", "4"],
+                ["chr1", "100", "200", "BBBB-1", "1"],
+                ["chr1", "300", "400", "AAAA-1", "2"],
+                ["chr10", "50", "80", "AAAA-1", "1"],
+                ["chr2", "500", "600", "BBBB-1", "5"],
+            ],
+        ),
+        (
+            "strip_chr",
+            [
+                ["1", "100", "200", "AAAA-1", "4"],
+                ["1", "100", "200", "BBBB-1", "1"],
+                ["1", "300", "400", "AAAA-1", "2"],
+                ["10", "50", "80", "AAAA-1", "1"],
+                ["2", "500", "600", "BBBB-1", "5"],
+            ],
+        ),
+    ],
+)
+def test_canonicalize_fragments(tmp_path, contig_name_mode, expected):
+    # the header is dropped, the extra column is stripped, missing scores are 1 and duplicates are summed
+    assert canonicalize(tmp_path, contig_name_mode = contig_name_mode) == expected
+
+
+def test_canonicalize_fragments_keeps_contig_names(tmp_path):
+    # without contig name normalization, contigs with different names are not duplicates
+    assert canonicalize(tmp_path) == [
+        ["1", "100", "200", "AAAA-1", "1"],
+        ["2", "500", "600", "BBBB-1", "1"],
+        ["chr1", "100", "200", "AAAA-1", "3"],
+        ["chr1", "100", "200", "BBBB-1", "1"],
+        ["chr1", "300", "400", "AAAA-1", "2"],
+        ["chr10", "50", "80", "AAAA-1", "1"],
+        ["chr2", "500", "600", "BBBB-1", "4"],
+    ]
+
+
+def test_canonicalize_fragments_with_invalid_contig_name_mode(tmp_path):
+    with pytest.raises(ValueError, match = "Invalid contig name mode"):
+        canonicalize(tmp_path, contig_name_mode = "upper")
@@ -0,0 +1,204 @@
+import math
+import os
+
+import pytest
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+PATH_TO_FRAGMENTS = os.path.join(os.path.dirname(__file__), "jaccard.fragments.tsv.gz")
+
+CHROMSIZES = {"chr1": 1000, "chr2": 1000}
+
+
+def test_celltype_coverage_jaccard():
+    # With bins of 100 bp, type_1 covers bins 0, 1 and 2 on chr1 and bin 0 on chr2,
+    # type_2 covers bins 2 and 4 on chr1: 1 shared bin out of 5 covered bins.
+    cell_types, jaccard = _rust_scatac_fragment_tools.celltype_coverage_jaccard(
+        path_to_fragments = PATH_TO_FRAGMENTS,
+        cell_type_to_cell_barcodes = {"type_1": ["A"], "type_2": ["B"]},
+        chromsizes = CHROMSIZES,
+        bin_size = 100,
+    )
+    assert cell_types == ["type_1", "type_2"]
+    assert jaccard[0][0] == 1.0
+    assert jaccard[1][1] == 1.0
+    assert math.isclose(jaccard[0][1], 0.2)
+    assert math.isclose(jaccard[1][0], 0.2)
+
+
+def test_celltype_coverage_jaccard_without_fragments_is_nan():
+    cell_types, jaccard = _rust_scatac_fragment_tools.celltype_coverage_jaccard(
+        path_to_fragments = PATH_TO_FRAGMENTS,
+        cell_type_to_cell_barcodes = {"type_1": ["A"], "empty": ["NOT_IN_FILE"]},
+        chromsizes = CHROMSIZES,
+        bin_size = 100,
+    )
+    assert cell_types == ["empty", "type_1"]
+    assert math.isnan(jaccard[0][0])
+    assert jaccard[0][1] == 0.0
+
+
+@pytest.mark.parametrize(
+    "bin_assignment, expected_type_1, expected_type_2",
+    [
+        # chr1:150-250 of type_1 is split over bins 1 and 2 of chr1
+        ("split", {0: 1.0, 1: 0.5, 2: 0.5, 10: 1.0}, {2: 1.0, 4: 1.0}),
+        ("start", {0: 1.0, 1: 1.0, 10: 1.0}, {2: 1.0, 4: 1.0}),
+    ],
+)
+def test_binned_coverage(bin_assignment, expected_type_1, expected_type_2):
+    chrom_to_first_bin, cell_type_to_bins = _rust_scatac_fragment_tools.binned_coverage(
+        path_to_fragments = PATH_TO_FRAGMENTS,
+        chromsizes = CHROMSIZES,
+        bin_size = 100,
+        cell_type_to_cell_barcodes = {"type_1": ["A"], "type_2": ["B"]},
+        bin_assignment = bin_assignment,
+    )
+    assert chrom_to_first_bin == {"chr1": 0, "chr2": 10}
+    assert set(cell_type_to_bins) == {"type_1", "type_2"}
+    for cell_type, expected in [("type_1", expected_type_1), ("type_2", expected_type_2)]:
+        bins = cell_type_to_bins[cell_type]
+        assert len(bins) == 20
+        assert {bin: count for bin, count in enumerate(bins) if count != 0} == pytest.approx(expected)
+
+
+def test_binned_coverage_clips_fragments_to_contig_end():
+    # chr1 has 3 bins, chr1:150-250 of type_1 is clipped to chr1:150-220 and chr1:450-480 of type_2 is dropped
+    chrom_to_first_bin, cell_type_to_bins = _rust_scatac_fragment_tools.binned_coverage(
+        path_to_fragments = PATH_TO_FRAGMENTS,
+        chromsizes = {"chr1": 220, "chr2": 1000},
+        bin_size = 100,
+        cell_type_to_cell_barcodes = {"type_1": ["A"], "type_2": ["B"]},
+    )
+    assert chrom_to_first_bin == {"chr1": 0, "chr2": 3}
+    assert cell_type_to_bins["type_1"][:4] == pytest.approx([1.0, 50 / 70, 20 / 70, 1.0])
+    assert cell_type_to_bins["type_2"][:4] == pytest.approx([0.0, 0.0, 1.0, 0.0])
+    assert sum(cell_type_to_bins["type_2"]) == 1.0
+
+
+def test_binned_coverage_with_invalid_options():
+    with pytest.raises(ValueError, match = "Invalid bin assignment"):
+        _rust_scatac_fragment_tools.binned_coverage(
+            path_to_fragments = PATH_TO_FRAGMENTS,
+            chromsizes = CHROMSIZES,
+            bin_size = 100,
+            cell_type_to_cell_barcodes = {"type_1": ["A"]},
+            bin_assignment = "end",
+        )
+    with pytest.raises(ValueError, match = "bin_size must be larger than 0"):
+        _rust_scatac_fragment_tools.binned_coverage(
+            path_to_fragments = PATH_TO_FRAGMENTS,
+            chromsizes = CHROMSIZES,
+            bin_size = 0,
+            cell_type_to_cell_barcodes = {"type_1": ["A"]},
+        )
+    with pytest.raises(ValueError, match = "Invalid result format"):
+        _rust_scatac_fragment_tools.binned_coverage(
+            path_to_fragments = PATH_TO_FRAGMENTS,
+            chromsizes = CHROMSIZES,
+            bin_size = 100,
+            cell_type_to_cell_barcodes = {"type_1": ["A"]},
+            output_format = "pandas",
+        )
+
+
+def test_binned_coverage_as_arrow():
+    pl = pytest.importorskip("polars")
+    kwargs = dict(
+        path_to_fragments = PATH_TO_FRAGMENTS,
+        chromsizes = {"chr1": 220, "chr2": 1000},
+        bin_size = 100,
+        cell_type_to_cell_barcodes = {"type_2": ["B"], "type_1": ["A"]},
+    )
+    chrom_to_first_bin, cell_type_to_bins = _rust_scatac_fragment_tools.binned_coverage(**kwargs)
+    df = pl.read_ipc_stream(_rust_scatac_fragment_tools.binned_coverage(output_format = "arrow", **kwargs))
+    # one row per bin, in the order of the bins, with the counts of each cell type in a column
+    assert df.columns == ["chrom", "start", "end", "type_1", "type_2"]
+    assert df.height == 13
+    assert df.row(2) == ("chr1", 200, 220, cell_type_to_bins["type_1"][2], cell_type_to_bins["type_2"][2])
+    assert df.row(chrom_to_first_bin["chr2"])[:3] == ("chr2", 0, 100)
+    assert df["type_1"].to_list() == cell_type_to_bins["type_1"]
+    assert df["type_2"].to_list() == cell_type_to_bins["type_2"]
+
+
+def test_frip_per_celltype(tmp_path):
+    path_to_peaks = os.path.join(tmp_path, "peaks.bed")
+    with open(path_to_peaks, "w") as f:
+        f.write("track name=peaks\n")
+        f.write("chr1\t90\t160\n")
+        f.write("chr1\t95\t120\n")
+        f.write("chr1\t470\t600\n")
+        f.write("chr3\t0\t10\n")
+    # type_1 has 2 of its 3 fragments in a peak (chr1:0-100 and chr1:150-250),
+    # type_2 has 1 of its 2 fragments in a peak (chr1:450-480, not chr1:200-300).
+    frip = _rust_scatac_fragment_tools.frip_per_celltype(
+        path_to_fragments = PATH_TO_FRAGMENTS,
+        path_to_peaks = path_to_peaks,
+        cell_type_to_cell_barcodes = {"type_1": ["A"], "type_2": ["B"], "empty": ["NOT_IN_FILE"]},
+    )
+    assert set(frip) == {"type_1", "type_2", "empty"}
+    assert math.isclose(frip["type_1"], 2 / 3)
+    assert math.isclose(frip["type_2"], 0.5)
+    assert math.isnan(frip["empty"])
+
+
+def test_coverage_with_blacklist(tmp_path):
+    path_to_blacklist = os.path.join(tmp_path, "blacklist.bed")
+    with open(path_to_blacklist, "w") as f:
+        # overlaps chr1:200-300 of type_2, not chr1:150-250 of type_1 (the end is exclusive)
+        f.write("chr1\t250\t260\n")
+    cell_types, jaccard = _rust_scatac_fragment_tools.celltype_coverage_jaccard(
+        path_to_fragments = PATH_TO_FRAGMENTS,
+        cell_type_to_cell_barcodes = {"type_1": ["A"], "type_2": ["B"]},
+        chromsizes = CHROMSIZES,
+        bin_size = 100,
+        path_to_blacklist = path_to_blacklist,
+    )
+    # type_2 only covers bin 4 on chr1 now, which type_1 does not cover
+    assert jaccard[0][1] == 0.0
+
+    path_to_peaks = os.path.join(tmp_path, "peaks.bed")
+    with open(path_to_peaks, "w") as f:
+        f.write("chr1\t470\t600\n")
+    frip = _rust_scatac_fragment_tools.frip_per_celltype(
+        path_to_fragments = PATH_TO_FRAGMENTS,
+        path_to_peaks = path_to_peaks,
+        cell_type_to_cell_barcodes = {"type_1": ["A"], "type_2": ["B"]},
+        path_to_blacklist = path_to_blacklist,
+    )
+    # chr1:450-480 is the only remaining fragment of type_2
+    assert frip["type_2"] == 1.0
+
+
+def test_frip_per_celltype_with_strand_aware_tn5_shift(tmp_path):
+    # chr1:100-200 on the - strand (M), chr2:100-200 on the + strand (P) and chr3:100-200 without strand (U)
+    path_to_fragments = os.path.join(os.path.dirname(__file__), "stranded.fragments.tsv.gz")
+    path_to_peaks = os.path.join(tmp_path, "peaks.bed")
+
+    def frip_with_peak(chrom, start, end, tn5_shift):
+        with open(path_to_peaks, "w") as f:
+            f.write(f"{chrom}\t{start}\t{end}\n")
+        frip = _rust_scatac_fragment_tools.frip_per_celltype(
+            path_to_fragments = path_to_fragments,
+            path_to_peaks = path_to_peaks,
+            cell_type_to_cell_barcodes = {"minus": ["M"], "plus": ["P"], "unknown": ["U"]},
+            strand_column = 5,
+            tn5_shift = tn5_shift,
+        )
+        return {cell_type for cell_type, value in frip.items() if value == 1.0}
+
+    # the minus strand fragment is moved 5 bp to the left, to chr1:95-195
+    assert frip_with_peak("chr1", 95, 96, tn5_shift = False) == set()
+    assert frip_with_peak("chr1", 94, 95, tn5_shift = True) == set()
+    assert frip_with_peak("chr1", 95, 96, tn5_shift = True) == {"minus"}
+    assert frip_with_peak("chr1", 194, 195, tn5_shift = True) == {"minus"}
+    assert frip_with_peak("chr1", 195, 196, tn5_shift = True) == set()
+    assert frip_with_peak("chr1", 195, 196, tn5_shift = False) == {"minus"}
+    # the plus strand fragment is moved 4 bp to the right, to chr2:104-204
+    assert frip_with_peak("chr2", 103, 104, tn5_shift = True) == set()
+    assert frip_with_peak("chr2", 203, 204, tn5_shift = True) == {"plus"}
+    assert frip_with_peak("chr2", 204, 205, tn5_shift = True) == set()
+    # the fragment without strand spans both cut sites, to chr3:104-195
+    assert frip_with_peak("chr3", 103, 104, tn5_shift = True) == set()
+    assert frip_with_peak("chr3", 104, 105, tn5_shift = True) == {"unknown"}
+    assert frip_with_peak("chr3", 195, 196, tn5_shift = True) == set()
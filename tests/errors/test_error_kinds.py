@@ -0,0 +1,126 @@
+import gzip
+import pathlib
+import shutil
+
+import pytest
+from conftest import write_fragments
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+
+SPLIT_TEST_DIRECTORY = TEST_DIRECTORY.parent.joinpath("split")
+
+
+@pytest.mark.parametrize(
+    "lines, chromsizes, kind",
+    [
+        (["chr1\t10\t20\tAAAA-1\t1", "chr1\t15"], None, "malformed"),
+        (["chr1\t10\tx\tAAAA-1\t1"], None, "malformed"),
+        (["chr1\t30\t20\tAAAA-1\t1"], None, "invalid_coordinates"),
+        (["chr1\t10\t20\tAAAA-1\t1"], {"chr1": 15}, "invalid_coordinates"),
+        (["chr1\t10\t20\tAAAA-1\t1", "chr1\t5\t20\tAAAA-1\t1"], None, "unsorted"),
+        (
+            ["chr1\t10\t20\tAAAA-1\t1", "chr2\t10\t20\tAAAA-1\t1", "chr1\t30\t40\tAAAA-1\t1"],
+            None,
+            "unsorted",
+        ),
+    ],
+)
+def test_validation_error_kinds(tmp_path, lines, chromsizes, kind):
+    path_to_fragments = write_fragments(tmp_path.joinpath("fragments.tsv"), lines)
+    with pytest.raises(_rust_scatac_fragment_tools.InvalidFragmentFileError) as exception_info:
+        _rust_scatac_fragment_tools.validate_fragment_file(
+            path_to_fragments = path_to_fragments,
+            chromsizes = chromsizes,
+        )
+    assert exception_info.value.kind == kind
+
+
+def test_missing_file_is_unreadable(tmp_path):
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError, match = "Could not open file"
+    ) as exception_info:
+        _rust_scatac_fragment_tools.validate_fragment_file(
+            path_to_fragments = str(tmp_path.joinpath("missing.fragments.tsv.gz")),
+        )
+    assert exception_info.value.kind == "unreadable"
+
+
+def test_file_without_index_has_index_kind(tmp_path):
+    path_to_fragments = write_fragments(
+        tmp_path.joinpath("fragments.tsv"), ["chr1\t10\t20\tAAAA-1\t1"]
+    )
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError, match = "has no tabix index"
+    ) as exception_info:
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_fragments,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {"type_1": ["AAAA-1"]},
+            chromsizes = {"chr1": 1000},
+            verbose = False,
+        )
+    assert exception_info.value.kind == "index"
+
+
+def test_invalid_argument_kind(tmp_path):
+    with pytest.raises(ValueError, match = "Invalid assignment") as exception_info:
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = str(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz")),
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {"type_1": ["AAAA-1"]},
+            chromsizes = {"chr1": 1000},
+            verbose = False,
+            assignment = "random",
+        )
+    assert exception_info.value.kind == "invalid_argument"
+
+
+def copy_with_index(tmp_path, index_content, index_extension = ".tbi"):
+    path_to_fragments = str(tmp_path.joinpath("a.fragments.tsv.gz"))
+    shutil.copy(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz"), path_to_fragments)
+    with open(path_to_fragments + index_extension, "wb") as f:
+        f.write(index_content)
+    return path_to_fragments
+
+
+def test_corrupt_index_has_index_kind(tmp_path, capfd):
+    path_to_fragments = copy_with_index(tmp_path, b"not a tabix index")
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "could not be read, it may be corrupt or in another format",
+    ) as exception_info:
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_fragments,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {"type_1": ["TTAGCTTAGGAGAACA-1"]},
+            chromsizes = {"chr1": 248956422},
+            verbose = False,
+        )
+    assert exception_info.value.kind == "index"
+
+    # functions which can read the whole file instead fall back to it
+    stats = _rust_scatac_fragment_tools.fragment_file_stats(
+        path_to_fragments = path_to_fragments,
+        allow_scan = True,
+    )
+    assert not stats.indexed
+    assert stats.records_per_contig == {"chr1": 10, "chr2": 10}
+    report = _rust_scatac_fragment_tools.validate_fragment_file(
+        path_to_fragments = path_to_fragments,
+        number_of_threads = 2,
+    )
+    assert report.number_of_fragments == 20
+    assert capfd.readouterr().out.count("may be corrupt or in another format") == 2
+
+
+def test_gzip_compressed_index_is_reported(tmp_path):
+    with open(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz.tbi"), "rb") as f:
+        path_to_fragments = copy_with_index(tmp_path, gzip.compress(f.read()), ".tbi.gz")
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = r"a.fragments.tsv.gz.tbi.gz is a gzip compressed index",
+    ) as exception_info:
+        _rust_scatac_fragment_tools.fragments_per_mb(path_to_fragments, {"chr1": 248956422})
+    assert exception_info.value.kind == "index"
@@ -0,0 +1,86 @@
+import gzip
+import os
+import pathlib
+import shutil
+import subprocess
+
+import pytest
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.parent.absolute()
+
+PATH_TO_A_FRAGMENTS = str(TEST_DIRECTORY.joinpath("split", "a.fragments.tsv.gz"))
+PATH_TO_B_FRAGMENTS = str(TEST_DIRECTORY.joinpath("split", "b.fragments.tsv.gz"))
+
+# the empty block which ends every BGZF file
+BGZF_EOF_BLOCK = bytes.fromhex("1f8b08040000000000ff0600424302001b0003000000000000000000")
+
+
+def write_outputs(output_folder):
+    """Writes an output file with each of the tools and returns their paths."""
+    paths = {
+        tool: os.path.join(output_folder, f"{tool}.fragments.tsv.gz")
+        for tool in ["merge", "concatenate", "rebgzip", "fix_local_sort", "subset", "bedpe"]
+    }
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [PATH_TO_A_FRAGMENTS, PATH_TO_B_FRAGMENTS],
+        path_to_output_file = paths["merge"],
+        number_of_threads = 1,
+        verbose = False,
+    )
+    _rust_scatac_fragment_tools.concatenate_fragment_files(
+        path_to_fragment_files = [PATH_TO_A_FRAGMENTS, PATH_TO_B_FRAGMENTS],
+        path_to_output_file = paths["concatenate"],
+    )
+    _rust_scatac_fragment_tools.rebgzip(
+        path_to_input_file = PATH_TO_A_FRAGMENTS,
+        path_to_output_file = paths["rebgzip"],
+        block_size = 1000,
+        number_of_threads = 1,
+    )
+    _rust_scatac_fragment_tools.fix_local_sort(
+        path_to_input_file = PATH_TO_A_FRAGMENTS,
+        path_to_output_file = paths["fix_local_sort"],
+        window = 10,
+    )
+    _rust_scatac_fragment_tools.subset_fragments(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_file = paths["subset"],
+        cell_barcodes = ["TTAGCTTAGGAGAACA-1", "CATGCCTTCTCTGACC-1"],
+    )
+    _rust_scatac_fragment_tools.bedpe_to_fragments(
+        path_to_bedpe = str(TEST_DIRECTORY.joinpath("convert", "pairs.bedpe")),
+        path_to_output_file = paths["bedpe"],
+    )
+    split_folder = os.path.join(output_folder, "split")
+    os.makedirs(split_folder)
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = split_folder,
+        cell_type_to_cell_barcodes = {
+            "type_1": ["TTAGCTTAGGAGAACA-1", "ATATTCCTCTTGTACT-1"],
+            "type_2": ["TGTGACAGTACAACGG-1", "CATGCCTTCTCTGACC-1"],
+        },
+        chromsizes = {"chr1": 248956422, "chr2": 242193529},
+        verbose = False,
+    )
+    split_paths = sorted(os.path.join(split_folder, file_name) for file_name in os.listdir(split_folder))
+    assert len(split_paths) == 2
+    return list(paths.values()) + split_paths
+
+
+def test_outputs_end_with_bgzf_eof_block(tmp_path):
+    for path in write_outputs(str(tmp_path)):
+        with open(path, "rb") as f:
+            assert f.read()[-len(BGZF_EOF_BLOCK):] == BGZF_EOF_BLOCK, path
+        # reading with the gzip module checks the CRC and size of each member
+        with gzip.open(path, "rb") as f:
+            assert len(f.read()) > 0, path
+        assert not os.path.exists(path + ".tmp")
+
+
+@pytest.mark.skipif(shutil.which("gzip") is None, reason = "gzip is not installed")
+def test_outputs_pass_gzip_test(tmp_path):
+    for path in write_outputs(str(tmp_path)):
+        subprocess.run(["gzip", "-t", path], check = True)
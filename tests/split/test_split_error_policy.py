@@ -0,0 +1,45 @@
+import os
+
+import pytest
+
+from scatac_fragment_tools.library.split.split_fragments_by_cell_type import (
+    split_fragment_files_by_cell_type,
+)
+
+CHROMSIZES = {"chr1": 248956422, "chr2": 242193529}
+
+
+def split_missing_fragment_files(tmp_path, error_policy):
+    split_fragment_files_by_cell_type(
+        sample_to_fragment_file = {
+            "A": os.path.join(tmp_path, "missing_a.fragments.tsv.gz"),
+            "B": os.path.join(tmp_path, "missing_b.fragments.tsv.gz"),
+        },
+        path_to_temp_folder = os.path.join(tmp_path, "temp"),
+        path_to_output_folder = os.path.join(tmp_path, "output"),
+        sample_to_cell_type_to_cell_barcodes = {
+            "A": {"type_1": ["TTAGCTTAGGAGAACA-1"]},
+            "B": {"type_1": ["ATTACCTGTGTGCTTA-1"]},
+        },
+        chromsizes = CHROMSIZES,
+        error_policy = error_policy,
+    )
+
+
+def test_error_policy_collect_reports_all_failures(tmp_path):
+    with pytest.raises(ValueError, match="failed for 2 task") as excinfo:
+        split_missing_fragment_files(tmp_path, "collect")
+    assert "sample A" in str(excinfo.value)
+    assert "missing_a.fragments.tsv.gz" in str(excinfo.value)
+    assert "sample B" in str(excinfo.value)
+    assert "missing_b.fragments.tsv.gz" in str(excinfo.value)
+
+
+def test_error_policy_fail_fast_raises_first_failure(tmp_path):
+    with pytest.raises(ValueError, match="missing_a.fragments.tsv.gz"):
+        split_missing_fragment_files(tmp_path, "fail_fast")
+
+
+def test_invalid_error_policy(tmp_path):
+    with pytest.raises(ValueError, match="error_policy"):
+        split_missing_fragment_files(tmp_path, "ignore")
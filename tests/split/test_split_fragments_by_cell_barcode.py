@@ -0,0 +1,1928 @@
+import gzip
+import hashlib
+import os
+import pathlib
+import tarfile
+
+import pytest
+from conftest import read_fragments
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+
+PATH_TO_A_FRAGMENTS = str(TEST_DIRECTORY.joinpath("a.fragments.tsv.gz"))
+
+CHROMSIZES = {"chr1": 248956422, "chr2": 242193529}
+
+CELL_TYPE_TO_CELL_BARCODES = {
+    "type_1": ["TTAGCTTAGGAGAACA-1", "ATATTCCTCTTGTACT-1"],
+    "type_2": ["TGTGACAGTACAACGG-1", "CATGCCTTCTCTGACC-1", "ATCGAGTAGGTTCGAG-1"],
+    "type_3": ["CTCTCAGGTCCCTTTG-1", "TTCGGTCTCACGTGTA-1", "GTGACATCATTGTTCT-1"],
+    "type_4": ["AAGGAGCCATCGACCG-1", "ACCAAACTCTTAAGCG-1", "CATTGGATCTCTTCCT-1"],
+    "type_5": ["AGGCGAAAGGTCTTTG-1", "AACGAGGCATCATGTG-1", "CTACTTAGTCATGAGG-1"],
+}
+
+
+def test_split_reports_contigs_missing_from_chromsizes(tmp_path, capfd):
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = {"chr1": CHROMSIZES["chr1"]},
+        verbose = False,
+    )
+    output = capfd.readouterr().out
+    assert "skipping 1 contig(s)" in output
+    assert "chr2 (10 fragments)" in output
+    # Only fragments on chr1 are written.
+    for file_name in os.listdir(tmp_path):
+        assert {
+            fragment[0] for fragment in read_fragments(os.path.join(tmp_path, file_name))
+        } == {"chr1"}
+
+
+def test_split_with_contig_name_mode(tmp_path, capfd):
+    chromsizes_without_chr = {"1": CHROMSIZES["chr1"], "2": CHROMSIZES["chr2"]}
+    for output_folder, chromsizes, contig_name_mode in [
+        ("chr", CHROMSIZES, "keep"),
+        ("add_chr", chromsizes_without_chr, "add_chr"),
+        ("keep", chromsizes_without_chr, "keep"),
+    ]:
+        os.makedirs(tmp_path.joinpath(output_folder))
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path.joinpath(output_folder)),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = chromsizes,
+            verbose = False,
+            contig_name_mode = contig_name_mode,
+        )
+    output = capfd.readouterr().out
+    # with the chr prefix added to the contigs of chromsizes, all contigs are processed
+    file_names = sorted(os.listdir(tmp_path.joinpath("chr")))
+    assert file_names == sorted(os.listdir(tmp_path.joinpath("add_chr")))
+    for file_name in file_names:
+        assert read_fragments(tmp_path.joinpath("add_chr", file_name)) == read_fragments(
+            tmp_path.joinpath("chr", file_name)
+        )
+    # without it, no contigs match and no files are written
+    assert os.listdir(tmp_path.joinpath("keep")) == []
+    assert "none of the contigs of chromsizes are in" in output
+
+    with pytest.raises(ValueError, match = "Invalid contig name mode"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = chromsizes_without_chr,
+            verbose = False,
+            contig_name_mode = "chr",
+        )
+
+
+def test_split_plain_gzip_file(tmp_path):
+    # gzip.open writes plain gzip, which can not be tabix indexed.
+    path_to_plain_gzip = os.path.join(tmp_path, "plain.fragments.tsv.gz")
+    with gzip.open(PATH_TO_A_FRAGMENTS, "rb") as f_in, gzip.open(path_to_plain_gzip, "wb") as f_out:
+        f_out.write(f_in.read())
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "it is compressed with gzip instead of bgzip, so it can not be tabix indexed",
+    ) as e:
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_plain_gzip,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+        )
+    assert e.value.kind == "index"
+
+
+def test_split_checksums_are_reproducible(tmp_path):
+    cell_type_to_checksum_per_run = []
+    for run in range(2):
+        path_to_output_folder = os.path.join(tmp_path, f"run_{run}")
+        os.makedirs(path_to_output_folder)
+        cell_type_to_checksum_per_run.append(
+            _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+                path_to_fragments = PATH_TO_A_FRAGMENTS,
+                path_to_output_folder = path_to_output_folder,
+                cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+                chromsizes = CHROMSIZES,
+                verbose = False,
+                compute_checksums = True,
+            ).checksums
+        )
+    assert cell_type_to_checksum_per_run[0] == cell_type_to_checksum_per_run[1]
+    assert set(cell_type_to_checksum_per_run[0]) == set(CELL_TYPE_TO_CELL_BARCODES)
+    # The checksum is computed over the uncompressed content.
+    for cell_type, checksum in cell_type_to_checksum_per_run[0].items():
+        with gzip.open(os.path.join(tmp_path, "run_0", f"{cell_type}.fragments.tsv.gz"), "rb") as f:
+            assert hashlib.sha256(f.read()).hexdigest() == checksum
+
+
+def test_split_without_checksums_returns_none(tmp_path):
+    assert _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+    ).checksums is None
+
+
+def test_split_with_fragment_filter(tmp_path):
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        fragment_filter = lambda chrom, start, end, cell_barcode, score: start % 2 == 1,
+    )
+    number_of_fragments = 0
+    for file_name in os.listdir(tmp_path):
+        for fragment in read_fragments(os.path.join(tmp_path, file_name)):
+            assert int(fragment[1]) % 2 == 1
+            number_of_fragments += 1
+    selected_cell_barcodes = {
+        cell_barcode
+        for cell_barcodes in CELL_TYPE_TO_CELL_BARCODES.values()
+        for cell_barcode in cell_barcodes
+    }
+    assert number_of_fragments == sum(
+        fragment[3] in selected_cell_barcodes and int(fragment[1]) % 2 == 1
+        for fragment in read_fragments(PATH_TO_A_FRAGMENTS)
+    )
+    assert number_of_fragments > 0
+
+
+def test_split_with_failing_fragment_filter(tmp_path):
+    def fragment_filter(chrom, start, end, cell_barcode, score):
+        raise RuntimeError("filter failed")
+
+    with pytest.raises(RuntimeError, match = "filter failed"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            fragment_filter = fragment_filter,
+        )
+
+
+def test_split_falls_back_to_linear_scan_for_stale_index(tmp_path, capfd):
+    # The index was created before a fragment on chr1 was removed from the file,
+    # so seeking to chr2 with the index ends up in the middle of a fragment.
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = str(TEST_DIRECTORY.joinpath("stale_index.fragments.tsv.gz")),
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = {"type_1": ["AAAA-1", "CCCC-1"]},
+        chromsizes = {"chr1": 10000, "chr2": 1000},
+        verbose = False,
+    )
+    output = capfd.readouterr().out
+    assert "seems to be out of date for contig chr2" in output
+    assert read_fragments(os.path.join(tmp_path, "type_1.fragments.tsv.gz")) == [
+        ["chr1", "100", "200", "AAAA-1", "1"],
+        ["chr1", "5000", "6000", "CCCC-1", "1"],
+        ["chr2", "100", "200", "AAAA-1", "1"],
+        ["chr2", "300", "400", "CCCC-1", "1"],
+    ]
+
+
+def test_split_scans_file_once_for_stale_index(tmp_path, capfd):
+    # Index a file, then replace it with a version without the first half of chr1,
+    # so the index is stale for chr2 and chr3.
+    path_to_fragments = os.path.join(tmp_path, "fragments.tsv.gz")
+    for version, number_of_removed_fragments in [("old", 0), ("new", 10)]:
+        path_to_plain_file = os.path.join(tmp_path, f"{version}.fragments.tsv")
+        with open(path_to_plain_file, "w") as f:
+            for start in range(100 * number_of_removed_fragments, 2000, 100):
+                f.write(f"chr1\t{start}\t{start + 50}\tAAAA-1\t1\n")
+            for contig in ["chr2", "chr3"]:
+                for start in [100, 200, 300]:
+                    f.write(f"{contig}\t{start}\t{start + 50}\tAAAA-1\t1\n")
+        _rust_scatac_fragment_tools.rebgzip(
+            path_to_input_file = path_to_plain_file,
+            path_to_output_file = path_to_fragments,
+            number_of_threads = 1,
+            create_index = version == "old",
+        )
+
+    path_to_output_folder = os.path.join(tmp_path, "split")
+    os.makedirs(path_to_output_folder)
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = path_to_fragments,
+        path_to_output_folder = path_to_output_folder,
+        cell_type_to_cell_barcodes = {"type_1": ["AAAA-1"]},
+        chromsizes = {"chr1": 10000, "chr2": 10000, "chr3": 250},
+        verbose = False,
+    )
+    output = capfd.readouterr().out
+    assert output.count("seems to be out of date") == 1
+    # fragments starting after the end of chr3 are skipped, like when fetching with the index
+    assert read_fragments(os.path.join(path_to_output_folder, "type_1.fragments.tsv.gz")) == [
+        ["chr1", str(start), str(start + 50), "AAAA-1", "1"] for start in range(1000, 2000, 100)
+    ] + [
+        ["chr2", "100", "150", "AAAA-1", "1"],
+        ["chr2", "200", "250", "AAAA-1", "1"],
+        ["chr2", "300", "350", "AAAA-1", "1"],
+        ["chr3", "100", "150", "AAAA-1", "1"],
+        ["chr3", "200", "250", "AAAA-1", "1"],
+    ]
+
+
+def test_split_into_tar_archive(tmp_path):
+    path_to_output_folder = os.path.join(tmp_path, "files")
+    path_to_tar_folder = os.path.join(tmp_path, "tar")
+    for folder in [path_to_output_folder, path_to_tar_folder]:
+        os.makedirs(folder)
+    path_to_tar_archive = os.path.join(path_to_tar_folder, "fragments.tar")
+    for path_to_tar_archive_or_none in [None, path_to_tar_archive]:
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = (
+                path_to_tar_folder if path_to_tar_archive_or_none else path_to_output_folder
+            ),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            path_to_tar_archive = path_to_tar_archive_or_none,
+        )
+    # Only the archive is kept.
+    assert os.listdir(path_to_tar_folder) == ["fragments.tar"]
+    with tarfile.open(path_to_tar_archive) as tar:
+        assert sorted(tar.getnames()) == sorted(os.listdir(path_to_output_folder))
+        for member in tar.getmembers():
+            with gzip.open(tar.extractfile(member), "rt") as f:
+                content = [line.rstrip("\n").split("\t") for line in f]
+            assert content == read_fragments(os.path.join(path_to_output_folder, member.name))
+
+
+def split_scores_fragments(path_to_output_folder, **kwargs):
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = str(TEST_DIRECTORY.joinpath("scores.fragments.tsv.gz")),
+        path_to_output_folder = path_to_output_folder,
+        cell_type_to_cell_barcodes = {"type_1": ["AAAA-1"]},
+        chromsizes = {"chr1": 1000, "chr2": 1000},
+        verbose = False,
+        **kwargs,
+    )
+    return [
+        fragment[1:]
+        for fragment in read_fragments(os.path.join(path_to_output_folder, "type_1.fragments.tsv.gz"))
+    ]
+
+
+def test_split_with_score_range_predicate(tmp_path):
+    assert split_scores_fragments(str(tmp_path), score_predicate = "2..10") == [
+        ["300", "400", "AAAA-1", "2"],
+        ["500", "600", "AAAA-1", "9"],
+    ]
+    assert split_scores_fragments(str(tmp_path), score_predicate = "2..=10") == [
+        ["300", "400", "AAAA-1", "2"],
+        ["500", "600", "AAAA-1", "9"],
+        ["700", "800", "AAAA-1", "10"],
+    ]
+    assert split_scores_fragments(str(tmp_path), score_predicate = "1,10") == [
+        ["100", "200", "AAAA-1", "1"],
+        ["700", "800", "AAAA-1", "10"],
+    ]
+
+
+def test_split_with_score_predicate_and_missing_scores(tmp_path):
+    assert split_scores_fragments(
+        str(tmp_path), score_predicate = "9..", missing_score_passes = True
+    ) == [
+        ["150", "250", "AAAA-1"],
+        ["500", "600", "AAAA-1", "9"],
+        ["700", "800", "AAAA-1", "10"],
+        ["100", "200", "AAAA-1"],
+    ]
+    assert split_scores_fragments(str(tmp_path), score_predicate = "9..") == [
+        ["500", "600", "AAAA-1", "9"],
+        ["700", "800", "AAAA-1", "10"],
+    ]
+
+
+def test_split_with_min_score(tmp_path):
+    assert split_scores_fragments(str(tmp_path), min_score = 2) == [
+        ["300", "400", "AAAA-1", "2"],
+        ["500", "600", "AAAA-1", "9"],
+        ["700", "800", "AAAA-1", "10"],
+    ]
+    assert split_scores_fragments(str(tmp_path), min_score = 10, missing_score_passes = True) == [
+        ["150", "250", "AAAA-1"],
+        ["700", "800", "AAAA-1", "10"],
+        ["100", "200", "AAAA-1"],
+    ]
+    with pytest.raises(ValueError, match = "can not be given together"):
+        split_scores_fragments(str(tmp_path), min_score = 2, score_predicate = "2..")
+
+
+def test_split_with_shift(tmp_path):
+    assert split_scores_fragments(str(tmp_path), shift = (4, -5)) == [
+        ["104", "195", "AAAA-1", "1"],
+        ["154", "245", "AAAA-1"],
+        ["304", "395", "AAAA-1", "2"],
+        ["504", "595", "AAAA-1", "9"],
+        ["704", "795", "AAAA-1", "10"],
+        ["104", "195", "AAAA-1"],
+    ]
+    # positions are clamped between 0 and the contig size (1000)
+    assert split_scores_fragments(str(tmp_path), shift = (-150, 250), min_score = 9) == [
+        ["350", "850", "AAAA-1", "9"],
+        ["550", "1000", "AAAA-1", "10"],
+    ]
+    assert split_scores_fragments(str(tmp_path), shift = (-150, 0), score_predicate = "1") == [
+        ["0", "200", "AAAA-1", "1"],
+    ]
+
+
+def test_split_with_invalid_score_predicate(tmp_path):
+    for score_predicate in ["a..5", "5..5", "1,-2"]:
+        with pytest.raises(ValueError):
+            split_scores_fragments(str(tmp_path), score_predicate = score_predicate)
+
+
+def test_split_and_merge_return_contig_order(tmp_path):
+    # The file and chromsizes are in natural order, fragments are written in lexicographic order.
+    chromsizes = {"chr1": 1000, "chr2": 1000, "chr10": 1000, "chrX": 1000}
+    split_summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = str(TEST_DIRECTORY.joinpath("contig_order.fragments.tsv.gz")),
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = {"type_1": ["AAAA-1"], "type_2": ["CCCC-1"]},
+        chromsizes = chromsizes,
+        verbose = False,
+    )
+    assert split_summary.contig_order == ["chr1", "chr10", "chr2"]
+    assert split_summary.contig_order != ["chr1", "chr2", "chr10"]
+
+    path_to_merged = os.path.join(tmp_path, "merged.fragments.tsv.gz")
+    merge_summary = _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [
+            os.path.join(tmp_path, "type_1.fragments.tsv.gz"),
+            os.path.join(tmp_path, "type_2.fragments.tsv.gz"),
+        ],
+        path_to_output_file = path_to_merged,
+        number_of_threads = 1,
+        verbose = False,
+    )
+    assert merge_summary.contig_order == ["chr1", "chr10", "chr2"]
+    assert [fragment[0] for fragment in read_fragments(path_to_merged)] == [
+        "chr1", "chr1", "chr10", "chr2"
+    ]
+
+
+def test_split_with_custom_contig_order(tmp_path):
+    chromsizes = {"chr1": 1000, "chr2": 1000, "chr10": 1000, "chrX": 1000}
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = str(TEST_DIRECTORY.joinpath("contig_order.fragments.tsv.gz")),
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = {"type_1": ["AAAA-1", "CCCC-1"]},
+        chromsizes = chromsizes,
+        verbose = False,
+        # chrX is not in the fragments file, chr1 is left out.
+        contig_order = ["chr2", "chrX", "chr10"],
+    )
+    assert summary.contig_order == ["chr2", "chr10"]
+    assert [
+        fragment[0] for fragment in read_fragments(os.path.join(tmp_path, "type_1.fragments.tsv.gz"))
+    ] == ["chr2", "chr10"]
+
+
+def test_split_with_invalid_contig_order(tmp_path):
+    for contig_order, message in [
+        (["chr1", "chr3"], "not in chromsizes"),
+        (["chr1", "chr2", "chr1"], "more than once"),
+    ]:
+        with pytest.raises(ValueError, match = message):
+            _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+                path_to_fragments = PATH_TO_A_FRAGMENTS,
+                path_to_output_folder = str(tmp_path),
+                cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+                chromsizes = CHROMSIZES,
+                verbose = False,
+                contig_order = contig_order,
+            )
+
+
+def test_split_leaves_no_partial_output_on_failure(tmp_path):
+    number_of_calls = []
+
+    def fragment_filter(chrom, start, end, cell_barcode, score):
+        # fail after some fragments are written, on the second contig
+        number_of_calls.append(chrom)
+        if chrom == "chr2":
+            raise RuntimeError("failed mid-write")
+        return True
+
+    with pytest.raises(RuntimeError, match = "failed mid-write"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            fragment_filter = fragment_filter,
+        )
+    assert "chr1" in number_of_calls
+    file_names = os.listdir(tmp_path)
+    assert len(file_names) > 0
+    assert all(file_name.endswith(".fragments.tsv.gz.tmp") for file_name in file_names)
+
+
+
+def test_split_returns_partial_summary_on_interruption(tmp_path, capfd):
+    def fragment_filter(chrom, start, end, cell_barcode, score):
+        # interrupt after the first contig
+        if chrom == "chr2":
+            raise KeyboardInterrupt
+        return True
+
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        fragment_filter = fragment_filter,
+        return_partial_on_error = True,
+    )
+    assert "splitting" in capfd.readouterr().out
+    selected_cell_barcodes = {
+        cell_barcode
+        for cell_barcodes in CELL_TYPE_TO_CELL_BARCODES.values()
+        for cell_barcode in cell_barcodes
+    }
+    expected_chr1_fragments = [
+        fragment
+        for fragment in read_fragments(PATH_TO_A_FRAGMENTS)
+        if fragment[0] == "chr1" and fragment[3] in selected_cell_barcodes
+    ]
+    assert summary.contig_order == ["chr1", "chr2"]
+    assert summary.completed_contigs == ["chr1"]
+    assert summary.written_fragments_per_contig == {"chr1": len(expected_chr1_fragments)}
+    assert "KeyboardInterrupt" in summary.error
+    # the written files are complete bgzf files, marked as partial
+    file_names = os.listdir(tmp_path)
+    assert len(file_names) > 0
+    assert all(file_name.endswith(".fragments.tsv.gz.partial") for file_name in file_names)
+    written_fragments = [
+        fragment
+        for file_name in file_names
+        for fragment in read_fragments(os.path.join(tmp_path, file_name))
+    ]
+    assert sorted(written_fragments) == sorted(expected_chr1_fragments)
+
+
+def test_split_without_error_returns_complete_summary(tmp_path):
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        return_partial_on_error = True,
+    )
+    assert summary.completed_contigs == summary.contig_order
+    assert summary.error is None
+    assert all(file_name.endswith(".fragments.tsv.gz") for file_name in os.listdir(tmp_path))
+
+def test_split_with_barcode_tag(tmp_path):
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = str(TEST_DIRECTORY.joinpath("barcode_tag.fragments.tsv.gz")),
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = {"type_1": ["AAAA-1"], "type_2": ["CCCC-1"]},
+        chromsizes = {"chr1": 1000, "chr2": 1000},
+        verbose = False,
+        barcode_tag = "CB",
+    )
+    assert read_fragments(os.path.join(tmp_path, "type_1.fragments.tsv.gz")) == [
+        ["chr1", "100", "200", "CB:Z:AAAA-1", "1"],
+        ["chr2", "100", "200", "UB:Z:TTTT CB:Z:AAAA-1", "1"],
+    ]
+    assert read_fragments(os.path.join(tmp_path, "type_2.fragments.tsv.gz")) == [
+        ["chr1", "300", "400", "CB:Z:CCCC-1 UB:Z:GGTT", "1"],
+    ]
+
+
+def test_split_with_missing_barcode_tag(tmp_path):
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "does not contain a XB:Z:<barcode> tag",
+    ):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = str(TEST_DIRECTORY.joinpath("barcode_tag.fragments.tsv.gz")),
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {"type_1": ["AAAA-1"]},
+            chromsizes = {"chr1": 1000, "chr2": 1000},
+            verbose = False,
+            barcode_tag = "XB",
+        )
+    with pytest.raises(ValueError, match = "Invalid barcode tag"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = str(TEST_DIRECTORY.joinpath("barcode_tag.fragments.tsv.gz")),
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {"type_1": ["AAAA-1"]},
+            chromsizes = {"chr1": 1000, "chr2": 1000},
+            verbose = False,
+            barcode_tag = "CB:Z",
+        )
+
+
+def test_split_with_first_assignment(tmp_path):
+    # TTAGCTTAGGAGAACA-1 maps to both type_1 and type_0.
+    cell_type_to_cell_barcodes = {
+        "type_1": ["TTAGCTTAGGAGAACA-1", "ATATTCCTCTTGTACT-1"],
+        "type_0": ["TTAGCTTAGGAGAACA-1"],
+    }
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        assignment = "first",
+    )
+    assert {
+        fragment[3] for fragment in read_fragments(os.path.join(tmp_path, "type_0.fragments.tsv.gz"))
+    } == {"TTAGCTTAGGAGAACA-1"}
+    assert {
+        fragment[3] for fragment in read_fragments(os.path.join(tmp_path, "type_1.fragments.tsv.gz"))
+    } == {"ATATTCCTCTTGTACT-1"}
+
+
+def test_split_with_error_assignment(tmp_path):
+    with pytest.raises(ValueError, match = "1 cell barcode\\(s\\) map to more than one cell type"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {
+                "type_1": ["TTAGCTTAGGAGAACA-1"],
+                "type_2": ["TTAGCTTAGGAGAACA-1"],
+            },
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            assignment = "error",
+        )
+    assert os.listdir(tmp_path) == []
+
+
+def test_split_to_parquet(tmp_path):
+    pq = pytest.importorskip("pyarrow.parquet")
+    os.makedirs(tmp_path.joinpath("tsv"))
+    os.makedirs(tmp_path.joinpath("parquet"))
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path.joinpath("tsv")),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+    )
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path.joinpath("parquet")),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        output_codec = "parquet",
+    )
+    for file_name in os.listdir(tmp_path.joinpath("tsv")):
+        cell_type = file_name[: -len(".fragments.tsv.gz")]
+        parquet_file = pq.ParquetFile(tmp_path.joinpath("parquet", f"{cell_type}.fragments.parquet"))
+        table = parquet_file.read()
+        assert table.column_names == ["chrom", "start", "end", "barcode", "score", "cell_type"]
+        records = [
+            [row["chrom"], str(row["start"]), str(row["end"]), row["barcode"], str(row["score"])]
+            for row in table.to_pylist()
+        ]
+        assert records == read_fragments(tmp_path.joinpath("tsv", file_name))
+        assert set(table.column("cell_type").to_pylist()) == {cell_type}
+        # each row group only contains fragments of one contig
+        for i in range(parquet_file.num_row_groups):
+            assert len(set(parquet_file.read_row_group(i).column("chrom").to_pylist())) == 1
+
+
+def test_split_to_parquet_rejects_checksums(tmp_path):
+    with pytest.raises(ValueError, match = "Checksums can only be computed for bgzf output"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            compute_checksums = True,
+            output_codec = "parquet",
+        )
+
+
+def test_split_with_file_contigs(tmp_path):
+    for output_folder, file_contigs in [("index", None), ("file_contigs", ["chr1", "chr2"])]:
+        os.makedirs(tmp_path.joinpath(output_folder))
+        summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path.joinpath(output_folder)),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            file_contigs = file_contigs,
+        )
+        assert summary.contig_order == ["chr1", "chr2"]
+    file_names = sorted(os.listdir(tmp_path.joinpath("index")))
+    assert file_names == sorted(os.listdir(tmp_path.joinpath("file_contigs")))
+    for file_name in file_names:
+        assert read_fragments(tmp_path.joinpath("index", file_name)) == read_fragments(
+            tmp_path.joinpath("file_contigs", file_name)
+        )
+
+
+def test_split_with_file_contigs_not_in_index(tmp_path):
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "Could not get contig id for contig chr3",
+    ):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = {**CHROMSIZES, "chr3": 198295559},
+            verbose = False,
+            file_contigs = ["chr1", "chr2", "chr3"],
+        )
+
+
+def test_split_with_empty_chromsizes(tmp_path):
+    # Without chromsizes, the contigs are taken from the index of the fragments file.
+    for output_folder, chromsizes in [("chromsizes", CHROMSIZES), ("index", {})]:
+        os.makedirs(tmp_path.joinpath(output_folder))
+        summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path.joinpath(output_folder)),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = chromsizes,
+            verbose = False,
+        )
+        assert summary.contig_order == ["chr1", "chr2"]
+    file_names = sorted(os.listdir(tmp_path.joinpath("chromsizes")))
+    assert len(file_names) > 0
+    assert file_names == sorted(os.listdir(tmp_path.joinpath("index")))
+    for file_name in file_names:
+        assert read_fragments(tmp_path.joinpath("chromsizes", file_name)) == read_fragments(
+            tmp_path.joinpath("index", file_name)
+        )
+
+
+def test_split_reports_distinct_barcodes(tmp_path):
+    cell_type_to_cell_barcodes = {
+        **CELL_TYPE_TO_CELL_BARCODES,
+        "type_6": ["TTAGCTTAGGAGAACA-1", "NOT_IN_FRAGMENTS-1"],
+        "empty": ["NOT_IN_FRAGMENTS-1"],
+    }
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+    )
+    cell_barcodes_in_fragments = {fragment[3] for fragment in read_fragments(PATH_TO_A_FRAGMENTS)}
+    expected = {
+        cell_type: len(set(cell_barcodes) & cell_barcodes_in_fragments)
+        for cell_type, cell_barcodes in cell_type_to_cell_barcodes.items()
+    }
+    assert expected["type_6"] == 1
+    assert summary.distinct_barcodes == {
+        cell_type: count for cell_type, count in expected.items() if count > 0
+    }
+
+
+def test_split_warns_about_more_threads_than_cores(tmp_path, capfd):
+    number_of_threads = 2 * os.cpu_count() + 1
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        number_of_threads = number_of_threads,
+        verbose = False,
+    )
+    output = capfd.readouterr().out
+    assert f"number_of_threads ({number_of_threads}) is more than twice" in output
+
+
+def test_split_skips_header_lines_indexed_as_fragments(tmp_path, capfd):
+    # The index of this file was created without "#" as comment character, so it lists
+    # the header line as a fragment on contig "#chr1".
+    path_to_fragments = str(TEST_DIRECTORY.joinpath("comment_header.fragments.tsv.gz"))
+    cell_type_to_cell_barcodes = {"type_1": ["AAAA-1", "header"], "type_2": ["BBBB-1"]}
+    os.makedirs(tmp_path.joinpath("skipped"))
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = path_to_fragments,
+        path_to_output_folder = str(tmp_path.joinpath("skipped")),
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+        chromsizes = {},
+        verbose = False,
+    )
+    assert summary.contig_order == ["chr1", "chr2"]
+    assert "lists header lines as fragments (on contig(s) #chr1)" in capfd.readouterr().out
+    assert read_fragments(tmp_path.joinpath("skipped", "type_1.fragments.tsv.gz")) == [
+        ["chr1", "10", "20", "AAAA-1", "1"],
+        ["chr2", "5", "50", "AAAA-1", "1"],
+    ]
+
+    os.makedirs(tmp_path.joinpath("not_skipped"))
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = path_to_fragments,
+        path_to_output_folder = str(tmp_path.joinpath("not_skipped")),
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+        chromsizes = {},
+        comment_char = None,
+        verbose = False,
+    )
+    assert summary.contig_order == ["#chr1", "chr1", "chr2"]
+    assert read_fragments(tmp_path.joinpath("not_skipped", "type_1.fragments.tsv.gz"))[0] == [
+        "#chr1", "0", "1", "header", "0"
+    ]
+
+
+def test_estimate_split_sizes(tmp_path):
+    cell_type_to_cell_barcodes = {
+        **CELL_TYPE_TO_CELL_BARCODES,
+        "empty": ["NOT_IN_FRAGMENTS-1"],
+    }
+    estimates = _rust_scatac_fragment_tools.estimate_split_sizes(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+    )
+    fragments = read_fragments(PATH_TO_A_FRAGMENTS)
+    assert set(estimates) == set(cell_type_to_cell_barcodes)
+    for cell_type, cell_barcodes in cell_type_to_cell_barcodes.items():
+        number_of_fragments = sum(fragment[3] in cell_barcodes for fragment in fragments)
+        assert estimates[cell_type].number_of_fragments == number_of_fragments
+    assert estimates["empty"].estimated_bytes == 0
+
+    # the byte estimates are only approximate, but should be in the right order of magnitude
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+    )
+    for cell_type in CELL_TYPE_TO_CELL_BARCODES:
+        file_size = os.path.getsize(tmp_path.joinpath(f"{cell_type}.fragments.tsv.gz"))
+        assert file_size / 10 < estimates[cell_type].estimated_bytes < file_size * 10
+
+    estimates = _rust_scatac_fragment_tools.estimate_split_sizes(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        bytes_per_fragment = 4.0,
+    )
+    for estimate in estimates.values():
+        assert estimate.estimated_bytes == estimate.number_of_fragments * 4
+
+    with pytest.raises(ValueError):
+        _rust_scatac_fragment_tools.estimate_split_sizes(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            bytes_per_fragment = -1.0,
+        )
+
+
+@pytest.mark.parametrize(
+    "duplicate_handling, expected",
+    [
+        (
+            "keep",
+            [
+                ["chr1", "10", "20", "AAAA-1", "1"],
+                ["chr1", "10", "20", "AAAA-1", "2"],
+                ["chr1", "10", "30", "AAAA-1"],
+                ["chr1", "10", "30", "AAAA-1", "4"],
+                ["chr2", "10", "20", "AAAA-1", "5"],
+            ],
+        ),
+        (
+            "collapse_sum_score",
+            [
+                ["chr1", "10", "20", "AAAA-1", "3"],
+                ["chr1", "10", "30", "AAAA-1", "4"],
+                ["chr2", "10", "20", "AAAA-1", "5"],
+            ],
+        ),
+        (
+            "collapse_count",
+            [
+                ["chr1", "10", "20", "AAAA-1", "2"],
+                ["chr1", "10", "30", "AAAA-1", "2"],
+                ["chr2", "10", "20", "AAAA-1", "1"],
+            ],
+        ),
+    ],
+)
+def test_split_with_duplicate_handling(tmp_path, duplicate_handling, expected):
+    # The duplicates of AAAA-1 are separated by a fragment of BBBB-1 in the fragments file,
+    # but are consecutive among the fragments of type_1.
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = str(TEST_DIRECTORY.joinpath("duplicates.fragments.tsv.gz")),
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = {"type_1": ["AAAA-1"], "type_2": ["BBBB-1"]},
+        chromsizes = {"chr1": 1000, "chr2": 1000},
+        verbose = False,
+        duplicate_handling = duplicate_handling,
+    )
+    assert read_fragments(tmp_path.joinpath("type_1.fragments.tsv.gz")) == expected
+    assert len(read_fragments(tmp_path.joinpath("type_2.fragments.tsv.gz"))) == 1
+
+
+def test_split_with_invalid_duplicate_handling(tmp_path):
+    with pytest.raises(ValueError, match = "Invalid duplicate handling"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = str(TEST_DIRECTORY.joinpath("duplicates.fragments.tsv.gz")),
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {"type_1": ["AAAA-1"]},
+            chromsizes = {"chr1": 1000, "chr2": 1000},
+            verbose = False,
+            duplicate_handling = "collapse",
+        )
+
+
+def test_split_and_merge_bgz_files(tmp_path):
+    # Files are recognized by their content, not by their extension.
+    path_to_fragments = str(tmp_path.joinpath("a.fragments.tsv.bgz"))
+    _rust_scatac_fragment_tools.rebgzip(
+        path_to_input_file = PATH_TO_A_FRAGMENTS,
+        path_to_output_file = path_to_fragments,
+        number_of_threads = 1,
+        create_index = True,
+    )
+    assert os.path.exists(path_to_fragments + ".tbi")
+
+    for output_folder, output_extension in [("gz", None), ("bgz", "fragments.tsv.bgz")]:
+        os.makedirs(tmp_path.joinpath(output_folder))
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_fragments,
+            path_to_output_folder = str(tmp_path.joinpath(output_folder)),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            output_extension = output_extension,
+        )
+    assert sorted(os.listdir(tmp_path.joinpath("bgz"))) == sorted(
+        f"{cell_type}.fragments.tsv.bgz" for cell_type in CELL_TYPE_TO_CELL_BARCODES
+    )
+    for cell_type in CELL_TYPE_TO_CELL_BARCODES:
+        assert read_fragments(tmp_path.joinpath("bgz", f"{cell_type}.fragments.tsv.bgz")) == read_fragments(
+            tmp_path.joinpath("gz", f"{cell_type}.fragments.tsv.gz")
+        )
+
+    path_to_merged_file = str(tmp_path.joinpath("merged.fragments.tsv.bgz"))
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [
+            str(tmp_path.joinpath("bgz", file_name)) for file_name in os.listdir(tmp_path.joinpath("bgz"))
+        ],
+        path_to_output_file = path_to_merged_file,
+        number_of_threads = 1,
+        verbose = False,
+    )
+    assert sorted(read_fragments(path_to_merged_file)) == sorted(
+        fragment
+        for fragment in read_fragments(PATH_TO_A_FRAGMENTS)
+        if any(fragment[3] in cell_barcodes for cell_barcodes in CELL_TYPE_TO_CELL_BARCODES.values())
+    )
+
+    with pytest.raises(ValueError, match = "Invalid output extension"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_fragments,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            output_extension = "",
+        )
+
+
+def test_split_with_barcode_rename(tmp_path):
+    def split_renamed(output_folder, **kwargs):
+        os.makedirs(tmp_path.joinpath(output_folder))
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = str(TEST_DIRECTORY.joinpath("duplicates.fragments.tsv.gz")),
+            path_to_output_folder = str(tmp_path.joinpath(output_folder)),
+            cell_type_to_cell_barcodes = {"type_1": ["AAAA-1", "BBBB-1"]},
+            chromsizes = {"chr1": 1000, "chr2": 1000},
+            verbose = False,
+            barcode_rename = {"AAAA-1": "sample_1_AAAA"},
+            **kwargs,
+        )
+        return read_fragments(tmp_path.joinpath(output_folder, "type_1.fragments.tsv.gz"))
+
+    # cell types are looked up with the original barcodes, barcodes without a new name are kept
+    assert split_renamed("kept") == [
+        ["chr1", "10", "20", "sample_1_AAAA", "1"],
+        ["chr1", "10", "20", "BBBB-1", "3"],
+        ["chr1", "10", "20", "sample_1_AAAA", "2"],
+        ["chr1", "10", "30", "sample_1_AAAA"],
+        ["chr1", "10", "30", "sample_1_AAAA", "4"],
+        ["chr2", "10", "20", "sample_1_AAAA", "5"],
+    ]
+    assert split_renamed("dropped", drop_unrenamed_barcodes = True) == [
+        ["chr1", "10", "20", "sample_1_AAAA", "1"],
+        ["chr1", "10", "20", "sample_1_AAAA", "2"],
+        ["chr1", "10", "30", "sample_1_AAAA"],
+        ["chr1", "10", "30", "sample_1_AAAA", "4"],
+        ["chr2", "10", "20", "sample_1_AAAA", "5"],
+    ]
+    # parsed fragments are renamed as well
+    assert split_renamed(
+        "collapsed", drop_unrenamed_barcodes = True, duplicate_handling = "collapse_count"
+    ) == [
+        ["chr1", "10", "20", "sample_1_AAAA", "2"],
+        ["chr1", "10", "30", "sample_1_AAAA", "2"],
+        ["chr2", "10", "20", "sample_1_AAAA", "1"],
+    ]
+
+
+def test_split_with_barcode_rename_of_transformed_barcodes(tmp_path):
+    def split_renamed(output_folder, **kwargs):
+        os.makedirs(tmp_path.joinpath(output_folder))
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = str(TEST_DIRECTORY.joinpath("duplicates.fragments.tsv.gz")),
+            path_to_output_folder = str(tmp_path.joinpath(output_folder)),
+            cell_type_to_cell_barcodes = {"type_1": ["AAAA", "BBBB"]},
+            chromsizes = {"chr1": 1000, "chr2": 1000},
+            verbose = False,
+            strip_barcode_suffix = "-1",
+            barcode_rename = {"AAAA": "sample_1_AAAA"},
+            drop_unrenamed_barcodes = True,
+            shift = (4, -5),
+            **kwargs,
+        )
+        return read_fragments(tmp_path.joinpath(output_folder, "type_1.fragments.tsv.gz"))
+
+    # shifted fragments are parsed, their cell barcodes are renamed after they are transformed
+    assert split_renamed("shifted") == [
+        ["chr1", "14", "15", "sample_1_AAAA", "1"],
+        ["chr1", "14", "15", "sample_1_AAAA", "2"],
+        ["chr1", "14", "25", "sample_1_AAAA"],
+        ["chr1", "14", "25", "sample_1_AAAA", "4"],
+        ["chr2", "14", "15", "sample_1_AAAA", "5"],
+    ]
+    assert split_renamed("collapsed", duplicate_handling = "collapse_count") == [
+        ["chr1", "14", "15", "sample_1_AAAA", "2"],
+        ["chr1", "14", "25", "sample_1_AAAA", "2"],
+        ["chr2", "14", "15", "sample_1_AAAA", "1"],
+    ]
+
+
+def test_split_by_regex_captured_sample(tmp_path, capfd):
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = str(TEST_DIRECTORY.joinpath("multi_sample.fragments.tsv.gz")),
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = {},
+        chromsizes = {},
+        verbose = False,
+        split_regex = "-(sample[A-Z])$",
+    )
+    assert sorted(os.listdir(tmp_path)) == ["sampleA.fragments.tsv.gz", "sampleB.fragments.tsv.gz"]
+    assert read_fragments(tmp_path.joinpath("sampleA.fragments.tsv.gz")) == [
+        ["chr1", "10", "20", "AAAA-sampleA", "1"],
+        ["chr1", "60", "90", "CCCC-sampleA", "2"],
+    ]
+    assert read_fragments(tmp_path.joinpath("sampleB.fragments.tsv.gz")) == [
+        ["chr1", "15", "40", "CCCC-sampleB", "1"],
+        ["chr2", "10", "20", "AAAA-sampleB", "1"],
+    ]
+    assert summary.distinct_barcodes == {"sampleA": 2, "sampleB": 2}
+    assert "1 cell barcode(s)" in capfd.readouterr().out
+
+
+@pytest.mark.parametrize(
+    "split_regex, cell_type_to_cell_barcodes, message",
+    [
+        ("-(sample[A-Z]$", {}, "Invalid split_regex"),
+        ("-sample[A-Z]$", {}, "at least one capture group"),
+        ("-(sample[A-Z])$", {"type_1": ["AAAA-sampleA"]}, "Cell types can not be given"),
+    ],
+)
+def test_split_with_invalid_split_regex(tmp_path, split_regex, cell_type_to_cell_barcodes, message):
+    with pytest.raises(ValueError, match = message):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = str(TEST_DIRECTORY.joinpath("multi_sample.fragments.tsv.gz")),
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+            chromsizes = {},
+            verbose = False,
+            split_regex = split_regex,
+        )
+
+
+def test_split_with_writer_pool_per_writer(tmp_path):
+    for writer_pool_strategy in ["shared", "per_writer"]:
+        os.makedirs(tmp_path.joinpath(writer_pool_strategy))
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path.joinpath(writer_pool_strategy)),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            number_of_threads = 2,
+            writer_pool_strategy = writer_pool_strategy,
+        )
+    assert sorted(os.listdir(tmp_path.joinpath("per_writer"))) == sorted(os.listdir(tmp_path.joinpath("shared")))
+    for file_name in os.listdir(tmp_path.joinpath("shared")):
+        assert read_fragments(tmp_path.joinpath("per_writer", file_name)) == read_fragments(
+            tmp_path.joinpath("shared", file_name)
+        )
+
+    with pytest.raises(ValueError, match = "Invalid writer pool strategy"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            writer_pool_strategy = "per_cell_type",
+        )
+
+
+def read_bgzf_block_sizes(path_to_fragment_file):
+    """Returns the number of uncompressed bytes of each BGZF block (except the empty EOF block)."""
+    with open(path_to_fragment_file, "rb") as f:
+        data = f.read()
+    block_sizes = []
+    offset = 0
+    while offset < len(data):
+        # BSIZE (total block size - 1) is stored after the 12 byte header and 6 byte extra subfield header
+        block_end = offset + int.from_bytes(data[offset + 16:offset + 18], "little") + 1
+        block_sizes.append(int.from_bytes(data[block_end - 4:block_end], "little"))
+        offset = block_end
+    return [block_size for block_size in block_sizes if block_size > 0]
+
+
+def test_split_browser_optimized(tmp_path):
+    with open(tmp_path.joinpath("fragments.tsv"), "w") as f:
+        for contig in ["chr1", "chr2"]:
+            for i in range(2000):
+                f.write(f"{contig}\t{10 * i}\t{10 * i + 150}\tBARCODE-{i % 2}\t1\n")
+    path_to_fragments = str(tmp_path.joinpath("fragments.tsv.gz"))
+    _rust_scatac_fragment_tools.rebgzip(
+        path_to_input_file = str(tmp_path.joinpath("fragments.tsv")),
+        path_to_output_file = path_to_fragments,
+        create_index = True,
+    )
+    cell_type_to_cell_barcodes = {"type_1": ["BARCODE-0"], "type_2": ["BARCODE-1"]}
+    for browser_optimized in [False, True]:
+        os.makedirs(tmp_path.joinpath(str(browser_optimized)))
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_fragments,
+            path_to_output_folder = str(tmp_path.joinpath(str(browser_optimized))),
+            cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+            chromsizes = {},
+            verbose = False,
+            browser_optimized = browser_optimized,
+        )
+
+    assert sorted(os.listdir(tmp_path.joinpath("True"))) == [
+        "type_1.fragments.tsv.gz",
+        "type_1.fragments.tsv.gz.tbi",
+        "type_2.fragments.tsv.gz",
+        "type_2.fragments.tsv.gz.tbi",
+    ]
+    for file_name in ["type_1.fragments.tsv.gz", "type_2.fragments.tsv.gz"]:
+        path_to_output = tmp_path.joinpath("True", file_name)
+        assert read_fragments(path_to_output) == read_fragments(tmp_path.joinpath("False", file_name))
+        # blocks only end early at the end of a contig
+        block_sizes = read_bgzf_block_sizes(path_to_output)
+        assert max(block_sizes) == 16384
+        assert len([block_size for block_size in block_sizes if block_size < 16384]) <= 2
+        stats = _rust_scatac_fragment_tools.fragment_file_stats(str(path_to_output))
+        assert stats.indexed
+        assert stats.records_per_contig == {"chr1": 1000, "chr2": 1000}
+
+    with pytest.raises(ValueError, match = "browser_optimized can only be used with bgzf output"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_fragments,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+            chromsizes = {},
+            verbose = False,
+            output_codec = "parquet",
+            browser_optimized = True,
+        )
+
+
+
+def test_split_with_create_index(tmp_path):
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = {**CELL_TYPE_TO_CELL_BARCODES, "type_6": ["NOT_IN_FILE-1"]},
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        create_index = True,
+    )
+    file_names = sorted(os.listdir(tmp_path))
+    fragment_file_names = [file_name for file_name in file_names if file_name.endswith(".fragments.tsv.gz")]
+    # only the written files are indexed
+    assert "type_6.fragments.tsv.gz" not in fragment_file_names
+    assert file_names == sorted(
+        fragment_file_names + [f"{file_name}.tbi" for file_name in fragment_file_names]
+    )
+    for file_name in fragment_file_names:
+        fragments = read_fragments(os.path.join(tmp_path, file_name))
+        stats = _rust_scatac_fragment_tools.fragment_file_stats(os.path.join(tmp_path, file_name))
+        assert stats.indexed
+        assert stats.records_per_contig == {
+            contig: len([fragment for fragment in fragments if fragment[0] == contig])
+            for contig in {fragment[0] for fragment in fragments}
+        }
+
+    with pytest.raises(ValueError, match = "Tabix indexes can only be created for bgzf output"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            output_codec = "parquet",
+            create_index = True,
+        )
+
+
+def test_split_with_barcode_transform(tmp_path):
+    os.makedirs(tmp_path.joinpath("original"))
+    os.makedirs(tmp_path.joinpath("transformed"))
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path.joinpath("original")),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+    )
+    # the annotation uses other cell barcodes than the fragments file: sample1:TTAGCTTAGGAGAACA for TTAGCTTAGGAGAACA-1
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path.joinpath("transformed")),
+        cell_type_to_cell_barcodes = {
+            cell_type: [f"sample1:{cell_barcode.removesuffix('-1')}" for cell_barcode in cell_barcodes]
+            for cell_type, cell_barcodes in CELL_TYPE_TO_CELL_BARCODES.items()
+        },
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        strip_barcode_suffix = "-1",
+        add_sample_prefix = "sample1:",
+    )
+    file_names = sorted(os.listdir(tmp_path.joinpath("original")))
+    assert len(file_names) > 0
+    assert sorted(os.listdir(tmp_path.joinpath("transformed"))) == file_names
+    # the fragments keep their original cell barcode
+    for file_name in file_names:
+        assert read_fragments(tmp_path.joinpath("transformed", file_name)) == read_fragments(
+            tmp_path.joinpath("original", file_name)
+        )
+    assert sum(summary.distinct_barcodes.values()) > 0
+
+    # a prefix which the cell barcodes do not have is not stripped
+    os.makedirs(tmp_path.joinpath("prefix"))
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path.joinpath("prefix")),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        strip_barcode_prefix = "sample1:",
+    )
+    assert sorted(os.listdir(tmp_path.joinpath("prefix"))) == file_names
+
+
+def test_split_summary_counts_fragments_per_cell_type(tmp_path):
+    cell_type_to_cell_barcodes = {
+        **CELL_TYPE_TO_CELL_BARCODES,
+        # a cell barcode of type_1 which is also in type_6, and a cell type without fragments
+        "type_6": ["TTAGCTTAGGAGAACA-1"],
+        "type_7": ["NOT_IN_FILE-1"],
+    }
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+    )
+    fragments = read_fragments(PATH_TO_A_FRAGMENTS)
+    assert summary.fragments_per_cell_type == {
+        cell_type: len([fragment for fragment in fragments if fragment[3] in cell_barcodes])
+        for cell_type, cell_barcodes in cell_type_to_cell_barcodes.items()
+    }
+    assert summary.fragments_per_cell_type["type_6"] > 0
+    assert summary.fragments_per_cell_type["type_7"] == 0
+    for cell_type, number_of_fragments in summary.fragments_per_cell_type.items():
+        if number_of_fragments > 0:
+            assert len(read_fragments(tmp_path.joinpath(f"{cell_type}.fragments.tsv.gz"))) == number_of_fragments
+
+
+def test_split_returns_output_files(tmp_path):
+    cell_type_to_cell_barcodes = {
+        "type 1/a": CELL_TYPE_TO_CELL_BARCODES["type_1"],
+        "type_2": CELL_TYPE_TO_CELL_BARCODES["type_2"],
+        # a cell type without fragments has no file
+        "type_3": ["NOT_IN_FILE-1"],
+    }
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        create_index = True,
+    )
+    assert summary.output_files == [
+        ("type 1/a", os.path.join(str(tmp_path), "type_1_a.fragments.tsv.gz")),
+        ("type_2", os.path.join(str(tmp_path), "type_2.fragments.tsv.gz")),
+    ]
+    for cell_type, path_to_output_file in summary.output_files:
+        assert len(read_fragments(path_to_output_file)) == summary.fragments_per_cell_type[cell_type]
+
+
+# shift=(0, 0) writes the parsed fragments, otherwise the lines are written as read
+@pytest.mark.parametrize("kwargs", [dict(), dict(shift = (0, 0))])
+def test_split_with_length_histogram(tmp_path, kwargs):
+    cell_type_to_cell_barcodes = {
+        **CELL_TYPE_TO_CELL_BARCODES,
+        "type_6": ["NOT_IN_FILE-1"],
+    }
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        length_histogram_bin_size = 50,
+        length_histogram_max_length = 200,
+        **kwargs,
+    )
+    expected_length_histograms = {cell_type: [0] * 5 for cell_type in cell_type_to_cell_barcodes}
+    for fragment in read_fragments(PATH_TO_A_FRAGMENTS):
+        length = int(fragment[2]) - int(fragment[1])
+        for cell_type, cell_barcodes in cell_type_to_cell_barcodes.items():
+            if fragment[3] in cell_barcodes:
+                expected_length_histograms[cell_type][min(length, 200) // 50] += 1
+    assert summary.length_histograms == expected_length_histograms
+    assert sum(summary.length_histograms["type_1"]) == summary.fragments_per_cell_type["type_1"]
+
+
+def test_split_without_length_histogram_returns_none(tmp_path):
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+    )
+    assert summary.length_histograms is None
+
+
+@pytest.mark.parametrize("bin_size, max_length", [(0, 1000), (10, 0), (30, 1000)])
+def test_split_with_invalid_length_histogram(tmp_path, bin_size, max_length):
+    with pytest.raises(ValueError, match = "Invalid length histogram"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            length_histogram_bin_size = bin_size,
+            length_histogram_max_length = max_length,
+        )
+
+
+def test_split_with_barcode_tsv(tmp_path):
+    path_to_barcode_tsv = os.path.join(tmp_path, "barcodes.tsv.gz")
+    with gzip.open(path_to_barcode_tsv, "wt") as f:
+        f.write("# cell_barcode\tcell_type\n")
+        for cell_type, cell_barcodes in CELL_TYPE_TO_CELL_BARCODES.items():
+            for cell_barcode in cell_barcodes:
+                f.write(f"{cell_barcode}\t{cell_type}\n")
+        # a repeated line, and a cell barcode with a second cell type
+        f.write(f"{CELL_TYPE_TO_CELL_BARCODES['type_1'][0]}\ttype_1\n")
+        f.write(f"{CELL_TYPE_TO_CELL_BARCODES['type_1'][0]}\ttype_2\n")
+    cell_type_to_cell_barcodes = {
+        **CELL_TYPE_TO_CELL_BARCODES,
+        "type_2": CELL_TYPE_TO_CELL_BARCODES["type_2"] + [CELL_TYPE_TO_CELL_BARCODES["type_1"][0]],
+    }
+
+    summaries = {}
+    for output_folder, kwargs in [
+        ("dict", dict(cell_type_to_cell_barcodes = cell_type_to_cell_barcodes)),
+        ("tsv", dict(cell_type_to_cell_barcodes = {}, path_to_barcode_tsv = path_to_barcode_tsv)),
+    ]:
+        os.makedirs(tmp_path.joinpath(output_folder))
+        summaries[output_folder] = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path.joinpath(output_folder)),
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            **kwargs,
+        )
+    assert summaries["tsv"].fragments_per_cell_type == summaries["dict"].fragments_per_cell_type
+    assert sorted(os.listdir(tmp_path.joinpath("tsv"))) == sorted(os.listdir(tmp_path.joinpath("dict")))
+    for file_name in os.listdir(tmp_path.joinpath("dict")):
+        assert read_fragments(tmp_path.joinpath("tsv", file_name)) == read_fragments(
+            tmp_path.joinpath("dict", file_name)
+        )
+
+
+@pytest.mark.parametrize(
+    "content, cell_type_to_cell_barcodes, message",
+    [
+        ("TTAGCTTAGGAGAACA-1\n", {}, "Invalid line 1 in barcode file"),
+        ("TTAGCTTAGGAGAACA-1\ttype_1\textra\n", {}, "Invalid line 1 in barcode file"),
+        ("TTAGCTTAGGAGAACA-1\ttype_1\n", CELL_TYPE_TO_CELL_BARCODES, "cell_type_to_cell_barcodes should be empty"),
+    ],
+)
+def test_split_with_invalid_barcode_tsv(tmp_path, content, cell_type_to_cell_barcodes, message):
+    path_to_barcode_tsv = os.path.join(tmp_path, "barcodes.tsv")
+    with open(path_to_barcode_tsv, "w") as f:
+        f.write(content)
+    with pytest.raises(ValueError, match = message):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            path_to_barcode_tsv = path_to_barcode_tsv,
+        )
+
+
+def test_split_with_unassigned_output(tmp_path):
+    path_to_unassigned_output = os.path.join(tmp_path, "unassigned.fragments.tsv.gz")
+    os.makedirs(tmp_path.joinpath("output"))
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path.joinpath("output")),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        path_to_unassigned_output = path_to_unassigned_output,
+    )
+    assigned_cell_barcodes = {
+        cell_barcode for cell_barcodes in CELL_TYPE_TO_CELL_BARCODES.values() for cell_barcode in cell_barcodes
+    }
+    expected_unassigned_fragments = [
+        fragment for fragment in read_fragments(PATH_TO_A_FRAGMENTS) if fragment[3] not in assigned_cell_barcodes
+    ]
+    assert len(expected_unassigned_fragments) > 0
+    assert read_fragments(path_to_unassigned_output) == expected_unassigned_fragments
+    assert summary.unassigned_fragments == len(expected_unassigned_fragments)
+    # the files per cell type are unchanged
+    assert sum(summary.fragments_per_cell_type.values()) == len(read_fragments(PATH_TO_A_FRAGMENTS)) - len(
+        expected_unassigned_fragments
+    )
+
+
+def test_split_without_unassigned_fragments(tmp_path):
+    path_to_unassigned_output = os.path.join(tmp_path, "unassigned.fragments.tsv.gz")
+    os.makedirs(tmp_path.joinpath("output"))
+    cell_barcodes = {fragment[3] for fragment in read_fragments(PATH_TO_A_FRAGMENTS)}
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path.joinpath("output")),
+        cell_type_to_cell_barcodes = {"all": sorted(cell_barcodes)},
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        path_to_unassigned_output = path_to_unassigned_output,
+    )
+    assert summary.unassigned_fragments == 0
+    assert not os.path.exists(path_to_unassigned_output)
+
+
+def test_split_calls_progress_callback_after_each_contig(tmp_path):
+    progress = []
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        progress_callback = lambda cell_type, contig, fragments_written: progress.append(
+            (cell_type, contig, fragments_written)
+        ),
+    )
+    fragments = read_fragments(PATH_TO_A_FRAGMENTS)
+    # one call per cell type, in sorted order, after each contig
+    assert progress == [
+        (
+            cell_type,
+            contig,
+            len([
+                fragment
+                for fragment in fragments
+                if fragment[0] == contig and fragment[3] in CELL_TYPE_TO_CELL_BARCODES[cell_type]
+            ]),
+        )
+        for contig in summary.contig_order
+        for cell_type in sorted(CELL_TYPE_TO_CELL_BARCODES)
+    ]
+
+    def failing_progress_callback(cell_type, contig, fragments_written):
+        raise RuntimeError("progress bar closed")
+
+    with pytest.raises(RuntimeError, match = "progress bar closed"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            progress_callback = failing_progress_callback,
+        )
+
+
+def test_split_updates_counts_file_after_each_contig(tmp_path):
+    path_to_counts_file = tmp_path.joinpath("counts.tsv")
+    cell_barcode_to_cell_type = {
+        cell_barcode: cell_type
+        for cell_type, cell_barcodes in CELL_TYPE_TO_CELL_BARCODES.items()
+        for cell_barcode in cell_barcodes
+    }
+    expected_counts_per_contig = {}
+    for chrom, _, _, cell_barcode, _ in read_fragments(PATH_TO_A_FRAGMENTS):
+        if cell_barcode in cell_barcode_to_cell_type:
+            counts = expected_counts_per_contig.setdefault(chrom, {})
+            cell_type = cell_barcode_to_cell_type[cell_barcode]
+            counts[cell_type] = counts.get(cell_type, 0) + 1
+
+    def read_counts_file():
+        with open(path_to_counts_file) as f:
+            assert f.readline() == "cell_type\tfragments\n"
+            return {cell_type: int(count) for cell_type, count in (line.rstrip("\n").split("\t") for line in f)}
+
+    def expected_counts(contigs):
+        return {
+            cell_type: sum(expected_counts_per_contig[contig].get(cell_type, 0) for contig in contigs)
+            for cell_type in CELL_TYPE_TO_CELL_BARCODES
+        }
+
+    # the filter is called for each fragment, so it sees the counts file as it was after the previous contig
+    seen_contigs = []
+    def fragment_filter(chrom, start, end, cell_barcode, score):
+        if not seen_contigs or seen_contigs[-1] != chrom:
+            if seen_contigs:
+                assert read_counts_file() == expected_counts(seen_contigs)
+            else:
+                assert not path_to_counts_file.exists()
+            seen_contigs.append(chrom)
+        return True
+
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        fragment_filter = fragment_filter,
+        path_to_counts_file = str(path_to_counts_file),
+    )
+    assert seen_contigs == ["chr1", "chr2"]
+    assert read_counts_file() == expected_counts(seen_contigs)
+    assert not tmp_path.joinpath("counts.tsv.tmp").exists()
+
+
+def test_split_truncates_long_cell_types(tmp_path):
+    # two cell types of 400 characters, which only differ at the end
+    long_cell_type_1 = "level_1 " * 49 + "type_1.."
+    long_cell_type_2 = "level_1 " * 49 + "type_2.."
+    assert len(long_cell_type_1) == len(long_cell_type_2) == 400
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = {
+            long_cell_type_1: CELL_TYPE_TO_CELL_BARCODES["type_1"],
+            long_cell_type_2: CELL_TYPE_TO_CELL_BARCODES["type_2"],
+            "type_3": CELL_TYPE_TO_CELL_BARCODES["type_3"],
+        },
+        chromsizes = CHROMSIZES,
+        verbose = False,
+    )
+    assert set(summary.truncated_file_names) == {long_cell_type_1, long_cell_type_2}
+    file_name_1 = summary.truncated_file_names[long_cell_type_1]
+    file_name_2 = summary.truncated_file_names[long_cell_type_2]
+    assert file_name_1 != file_name_2
+    for file_name in [file_name_1, file_name_2]:
+        assert file_name.startswith(long_cell_type_1.replace(" ", "_")[:200] + "_")
+        assert len(file_name) == 209
+    assert sorted(os.listdir(tmp_path)) == sorted(
+        [f"{file_name_1}.fragments.tsv.gz", f"{file_name_2}.fragments.tsv.gz", "type_3.fragments.tsv.gz"]
+    )
+    assert all(len(file_name.encode()) <= 255 for file_name in os.listdir(tmp_path))
+    assert read_fragments(tmp_path.joinpath(f"{file_name_1}.fragments.tsv.gz")) == [
+        fragment
+        for fragment in read_fragments(PATH_TO_A_FRAGMENTS)
+        if fragment[3] in CELL_TYPE_TO_CELL_BARCODES["type_1"]
+    ]
+
+
+def test_split_with_colliding_file_names(tmp_path):
+    cell_type_to_cell_barcodes = {
+        "type 1": CELL_TYPE_TO_CELL_BARCODES["type_1"],
+        "type/1": CELL_TYPE_TO_CELL_BARCODES["type_2"],
+        "type_1": CELL_TYPE_TO_CELL_BARCODES["type_3"],
+        "type_4": CELL_TYPE_TO_CELL_BARCODES["type_4"],
+    }
+    with pytest.raises(ValueError, match = r'"type 1", "type/1", "type_1" have file name "type_1"'):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+        )
+    # no file is written
+    assert os.listdir(tmp_path) == []
+
+
+def test_split_preserves_strand_column(tmp_path):
+    path_to_fragments = str(TEST_DIRECTORY.joinpath("stranded.fragments.tsv.gz"))
+    fragments = read_fragments(path_to_fragments)
+    # fragments are written unchanged, or parsed when collapsing duplicates
+    for duplicate_handling in ["keep", "collapse_sum_score"]:
+        os.makedirs(tmp_path.joinpath(duplicate_handling))
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_fragments,
+            path_to_output_folder = str(tmp_path.joinpath(duplicate_handling)),
+            cell_type_to_cell_barcodes = {"type_a": ["AAAA-1"], "type_c": ["CCCC-1"]},
+            chromsizes = {},
+            verbose = False,
+            strand_column = 5,
+            duplicate_handling = duplicate_handling,
+        )
+    assert read_fragments(tmp_path.joinpath("keep", "type_a.fragments.tsv.gz")) == [
+        fragment for fragment in fragments if fragment[3] == "AAAA-1"
+    ]
+    assert read_fragments(tmp_path.joinpath("keep", "type_c.fragments.tsv.gz")) == [
+        fragment for fragment in fragments if fragment[3] == "CCCC-1"
+    ]
+    assert read_fragments(tmp_path.joinpath("collapse_sum_score", "type_c.fragments.tsv.gz")) == [
+        ["chr1", "150", "260", "CCCC-1", "3", "-"],
+        ["chr2", "50", "120", "CCCC-1", "3", "-"],
+    ]
+
+    # merging the files per cell type gives the original fragments back
+    path_to_merged = str(tmp_path.joinpath("merged.fragments.tsv.gz"))
+    _rust_scatac_fragment_tools.merge_fragment_files(
+        path_to_fragment_files = [
+            str(tmp_path.joinpath("keep", "type_a.fragments.tsv.gz")),
+            str(tmp_path.joinpath("keep", "type_c.fragments.tsv.gz")),
+        ],
+        path_to_output_file = path_to_merged,
+        number_of_threads = 1,
+        verbose = False,
+        strand_column = 5,
+    )
+    assert read_fragments(path_to_merged) == fragments
+
+
+def test_split_with_invalid_strand(tmp_path):
+    with open(tmp_path.joinpath("fragments.tsv"), "w") as f:
+        f.write("chr1\t100\t200\tAAAA-1\t1\t+\n")
+        f.write("chr1\t150\t260\tAAAA-1\t1\tminus\n")
+    path_to_fragments = str(tmp_path.joinpath("fragments.tsv.gz"))
+    _rust_scatac_fragment_tools.rebgzip(
+        path_to_input_file = str(tmp_path.joinpath("fragments.tsv")),
+        path_to_output_file = path_to_fragments,
+        create_index = True,
+    )
+    with pytest.raises(_rust_scatac_fragment_tools.InvalidFragmentFileError, match = "Invalid strand \"minus\""):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_fragments,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {"type_a": ["AAAA-1"]},
+            chromsizes = {},
+            verbose = False,
+            strand_column = 5,
+            duplicate_handling = "collapse_count",
+        )
+
+    # the strand can not be read from the score column
+    with pytest.raises(ValueError, match = "Columns should be distinct"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_fragments,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {"type_a": ["AAAA-1"]},
+            chromsizes = {},
+            verbose = False,
+            strand_column = 4,
+        )
+
+
+def test_split_with_parallel_cell_barcodes_and_cell_types(tmp_path):
+    # like the obs table of an AnnData object, in which a cell barcode can be repeated
+    cell_barcodes = []
+    cell_types = []
+    for cell_type, barcodes in CELL_TYPE_TO_CELL_BARCODES.items():
+        cell_barcodes.extend(barcodes)
+        cell_types.extend([cell_type] * len(barcodes))
+    cell_barcodes.append(CELL_TYPE_TO_CELL_BARCODES["type_1"][0])
+    cell_types.append("type_1")
+    cell_barcodes.append(CELL_TYPE_TO_CELL_BARCODES["type_1"][0])
+    cell_types.append("type_2")
+
+    os.makedirs(tmp_path.joinpath("lists"))
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path.joinpath("lists")),
+        cell_type_to_cell_barcodes = {},
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        cell_barcodes = cell_barcodes,
+        cell_types = cell_types,
+    )
+    os.makedirs(tmp_path.joinpath("dict"))
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path.joinpath("dict")),
+        cell_type_to_cell_barcodes = {
+            **CELL_TYPE_TO_CELL_BARCODES,
+            "type_2": CELL_TYPE_TO_CELL_BARCODES["type_2"] + [CELL_TYPE_TO_CELL_BARCODES["type_1"][0]],
+        },
+        chromsizes = CHROMSIZES,
+        verbose = False,
+    )
+    assert sorted(os.listdir(tmp_path.joinpath("lists"))) == sorted(os.listdir(tmp_path.joinpath("dict")))
+    for file_name in os.listdir(tmp_path.joinpath("dict")):
+        # the repeated pair of cell barcode and type_1 does not write its fragments twice
+        assert read_fragments(tmp_path.joinpath("lists", file_name)) == read_fragments(
+            tmp_path.joinpath("dict", file_name)
+        )
+
+    with pytest.raises(ValueError, match = "should have the same length, got 16 and 15"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {},
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            cell_barcodes = cell_barcodes,
+            cell_types = cell_types[:-1],
+        )
+    with pytest.raises(ValueError, match = "should be given together"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {},
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            cell_barcodes = cell_barcodes,
+        )
+
+
+@pytest.mark.parametrize(
+    "zero_length, duplicate_handling, expected_zero_length_fragments",
+    [("keep", "keep", 0), ("drop", "keep", 2), ("drop", "collapse_count", 2)],
+)
+def test_split_with_zero_length_fragments(tmp_path, zero_length, duplicate_handling, expected_zero_length_fragments):
+    path_to_fragments = str(TEST_DIRECTORY.joinpath("zero_length.fragments.tsv.gz"))
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = path_to_fragments,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = {"type_a": ["AAAA-1"], "type_c": ["CCCC-1"]},
+        chromsizes = {},
+        verbose = False,
+        zero_length = zero_length,
+        duplicate_handling = duplicate_handling,
+    )
+    assert summary.zero_length_fragments == expected_zero_length_fragments
+    written = read_fragments(tmp_path.joinpath("type_a.fragments.tsv.gz")) + read_fragments(
+        tmp_path.joinpath("type_c.fragments.tsv.gz")
+    )
+    assert len(written) == 4 - expected_zero_length_fragments
+    if zero_length == "drop":
+        assert all(fragment[1] != fragment[2] for fragment in written)
+
+
+def test_split_with_zero_length_fragments_error(tmp_path):
+    with pytest.raises(_rust_scatac_fragment_tools.InvalidFragmentFileError, match = "Zero-length fragment") as e:
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = str(TEST_DIRECTORY.joinpath("zero_length.fragments.tsv.gz")),
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {"type_a": ["AAAA-1"]},
+            chromsizes = {},
+            verbose = False,
+            zero_length = "error",
+        )
+    assert e.value.kind == "invalid_coordinates"
+
+    with pytest.raises(ValueError, match = "Invalid zero-length handling"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = str(TEST_DIRECTORY.joinpath("zero_length.fragments.tsv.gz")),
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = {"type_a": ["AAAA-1"]},
+            chromsizes = {},
+            verbose = False,
+            zero_length = "skip",
+        )
+
+
+def test_split_with_blacklist(tmp_path):
+    path_to_blacklist = os.path.join(tmp_path, "blacklist.bed")
+    with open(path_to_blacklist, "w") as f:
+        f.write("chr1\t10079\t10080\n")
+        # ends where the only fragment of type_3 on chr2 (chr2:10115-10147) starts
+        f.write("chr2\t10000\t10115\n")
+    output_folder = os.path.join(tmp_path, "output")
+    os.makedirs(output_folder)
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = output_folder,
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        path_to_blacklist = path_to_blacklist,
+    )
+    # all three fragments of type_1 overlap chr1:10079-10080, so no file is written for it
+    assert summary.blacklisted_fragments == 3
+    assert not os.path.exists(os.path.join(output_folder, "type_1.fragments.tsv.gz"))
+    type_3_fragments = read_fragments(os.path.join(output_folder, "type_3.fragments.tsv.gz"))
+    assert ["chr2", "10115", "10147", "GTGACATCATTGTTCT-1", "1"] in type_3_fragments
+
+
+def test_split_with_missing_blacklist(tmp_path):
+    with pytest.raises(ValueError, match = "blacklist"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            path_to_blacklist = os.path.join(tmp_path, "missing.bed"),
+        )
+
+
+def test_split_by_barcode(tmp_path):
+    cell_barcodes = CELL_TYPE_TO_CELL_BARCODES["type_1"] + CELL_TYPE_TO_CELL_BARCODES["type_2"]
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        # the cell types are ignored, the cell barcodes are a whitelist
+        cell_type_to_cell_barcodes = {"type_1": cell_barcodes},
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        group_by = "barcode",
+    )
+    all_fragments = read_fragments(PATH_TO_A_FRAGMENTS)
+    written_barcodes = sorted(
+        file_name.removesuffix(".fragments.tsv.gz") for file_name in os.listdir(tmp_path)
+    )
+    assert written_barcodes == sorted(
+        {fragment[3] for fragment in all_fragments if fragment[3] in cell_barcodes}
+    )
+    for cell_barcode in written_barcodes:
+        assert read_fragments(os.path.join(tmp_path, f"{cell_barcode}.fragments.tsv.gz")) == [
+            fragment for fragment in all_fragments if fragment[3] == cell_barcode
+        ]
+        assert summary.distinct_barcodes[cell_barcode] == 1
+
+
+def test_split_by_barcode_in_passes(tmp_path):
+    def split_by_barcode(output_folder, **kwargs):
+        os.makedirs(tmp_path.joinpath(output_folder))
+        return _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path.joinpath(output_folder)),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            group_by = "barcode",
+            compute_checksums = True,
+            **kwargs,
+        )
+
+    summary = split_by_barcode("one_pass")
+    # each pass writes the files of at most 2 cell barcodes
+    passes_summary = split_by_barcode("passes", max_open_files = 2)
+    file_names = sorted(os.listdir(tmp_path.joinpath("one_pass")))
+    assert len(file_names) > 2
+    assert sorted(os.listdir(tmp_path.joinpath("passes"))) == file_names
+    for file_name in file_names:
+        assert read_fragments(tmp_path.joinpath("passes", file_name)) == read_fragments(
+            tmp_path.joinpath("one_pass", file_name)
+        )
+    assert [os.path.basename(path) for _, path in passes_summary.output_files] == [
+        os.path.basename(path) for _, path in summary.output_files
+    ]
+    assert passes_summary.checksums == summary.checksums
+    assert passes_summary.distinct_barcodes == summary.distinct_barcodes
+    assert passes_summary.fragments_per_cell_type == summary.fragments_per_cell_type
+    assert passes_summary.written_fragments_per_contig == summary.written_fragments_per_contig
+
+
+def test_split_with_too_many_files(tmp_path):
+    # when grouping by cell type, the cell types are not split in passes
+    with pytest.raises(ValueError, match = "more than max_open_files \\(1\\)"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            max_open_files = 1,
+        )
+    # a counts file is written for all cell barcodes at the same time
+    with pytest.raises(ValueError, match = "path_to_counts_file can not be used"):
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = PATH_TO_A_FRAGMENTS,
+            path_to_output_folder = str(tmp_path),
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            chromsizes = CHROMSIZES,
+            verbose = False,
+            group_by = "barcode",
+            max_open_files = 2,
+            path_to_counts_file = os.path.join(tmp_path, "counts.tsv"),
+        )
+    assert os.listdir(tmp_path) == []
+
+
+def test_split_reports_timing_per_contig(tmp_path, capfd):
+    summary = _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = True,
+    )
+    assert sorted(summary.seconds_per_contig) == summary.contig_order
+    assert sorted(summary.fragments_per_second_per_contig) == summary.contig_order
+    assert all(seconds >= 0 for seconds in summary.seconds_per_contig.values())
+    assert all(
+        fragments_per_second >= 0 for fragments_per_second in summary.fragments_per_second_per_contig.values()
+    )
+    output = capfd.readouterr().out
+    for contig in summary.contig_order:
+        assert f"fragment(s) of contig {contig} in " in output
+
+
+def test_split_with_score_pairs(tmp_path):
+    with open(tmp_path.joinpath("fragments.tsv"), "w") as f:
+        f.write("chr1\t100\t200\tAAAA-1\t3,5\n")
+        f.write("chr1\t150\t260\tAAAA-1\t1,9\n")
+        f.write("chr1\t300\t400\tAAAA-1\t4\n")
+    path_to_fragments = str(tmp_path.joinpath("fragments.tsv.gz"))
+    _rust_scatac_fragment_tools.rebgzip(
+        path_to_input_file = str(tmp_path.joinpath("fragments.tsv")),
+        path_to_output_file = path_to_fragments,
+        create_index = True,
+    )
+
+    def split_score_pairs(output_folder, **kwargs):
+        os.makedirs(tmp_path.joinpath(output_folder))
+        _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+            path_to_fragments = path_to_fragments,
+            path_to_output_folder = str(tmp_path.joinpath(output_folder)),
+            cell_type_to_cell_barcodes = {"type_a": ["AAAA-1"]},
+            chromsizes = {},
+            verbose = False,
+            **kwargs,
+        )
+        return read_fragments(tmp_path.joinpath(output_folder, "type_a.fragments.tsv.gz"))
+
+    # pairs are written unchanged, also when the fragments are parsed to filter on their score
+    assert split_score_pairs("unfiltered") == read_fragments(path_to_fragments)
+    assert split_score_pairs("first", score_pair = "first", score_predicate = "3..") == [
+        ["chr1", "100", "200", "AAAA-1", "3,5"],
+        ["chr1", "300", "400", "AAAA-1", "4"],
+    ]
+    assert split_score_pairs("second", score_pair = "second", score_predicate = "6..") == [
+        ["chr1", "150", "260", "AAAA-1", "1,9"],
+    ]
+
+    # without score_pair, a pair is not a valid score
+    with pytest.raises(_rust_scatac_fragment_tools.InvalidFragmentFileError, match = "Invalid number \"3,5\""):
+        split_score_pairs("without_score_pair", score_predicate = "3..")
+    with pytest.raises(ValueError, match = "Invalid score pair value"):
+        split_score_pairs("invalid_score_pair", score_pair = "total")
+
+
+@pytest.mark.parametrize(
+    "expected_cell_types, warns",
+    [(5, False), (4, False), (8, False), (2, True), (12, True)],
+)
+def test_split_warns_about_unexpected_number_of_cell_types(tmp_path, capfd, expected_cell_types, warns):
+    # the annotation has 5 cell types
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = PATH_TO_A_FRAGMENTS,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        chromsizes = CHROMSIZES,
+        verbose = False,
+        expected_cell_types = expected_cell_types,
+    )
+    output = capfd.readouterr().out
+    assert (
+        f"splitting into 5 cell types, while {expected_cell_types} were expected" in output
+    ) == warns
@@ -0,0 +1,102 @@
+import gzip
+import os
+
+import pytest
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+CELL_TYPE_TO_CELL_BARCODES = {
+    "type_1": ["AAAA-1", "CCCC-1"],
+    "type_2": ["GGGG-1", "CCCC-1"],
+}
+
+FRAGMENTS = [
+    ("chr2", 50, 150, "AAAA-1", 1),
+    ("chr1", 300, 400, "GGGG-1", None),
+    ("chr1", 100, 200, "CCCC-1", 3),
+    ("chr1", 100, 180, "TTTT-1", 1),
+]
+
+
+def test_split_list_of_tuples_in_memory():
+    cell_type_to_fragments = _rust_scatac_fragment_tools.split_fragments_in_memory(
+        fragments = FRAGMENTS,
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+    )
+    assert cell_type_to_fragments == {
+        "type_1": [
+            ("chr1", 100, 200, "CCCC-1", 3),
+            ("chr2", 50, 150, "AAAA-1", 1),
+        ],
+        "type_2": [
+            ("chr1", 100, 200, "CCCC-1", 3),
+            ("chr1", 300, 400, "GGGG-1", None),
+        ],
+    }
+
+
+def test_split_bytes_in_memory_to_files(tmp_path):
+    fragments_tsv = "".join(
+        "\t".join(str(field) for field in fragment if field is not None) + "\n"
+        for fragment in FRAGMENTS
+    ).encode()
+    result = _rust_scatac_fragment_tools.split_fragments_in_memory(
+        fragments = fragments_tsv,
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        path_to_output_folder = str(tmp_path),
+    )
+    assert result is None
+    assert sorted(os.listdir(tmp_path)) == [
+        "type_1.fragments.tsv.gz",
+        "type_2.fragments.tsv.gz",
+    ]
+    with gzip.open(os.path.join(tmp_path, "type_2.fragments.tsv.gz"), "rt") as f:
+        assert f.read() == "chr1\t100\t200\tCCCC-1\t3\nchr1\t300\t400\tGGGG-1\n"
+
+
+def test_split_bytes_with_quoted_barcodes_in_memory():
+    cell_type_to_fragments = _rust_scatac_fragment_tools.split_fragments_in_memory(
+        fragments = b'chr1\t100\t200\t"CCCC-1"\t3\nchr1\t300\t400\t\'GGGG-1\'\n',
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        strip_quotes = True,
+    )
+    assert cell_type_to_fragments == {
+        "type_1": [("chr1", 100, 200, "CCCC-1", 3)],
+        "type_2": [
+            ("chr1", 100, 200, "CCCC-1", 3),
+            ("chr1", 300, 400, "GGGG-1", None),
+        ],
+    }
+
+
+def test_split_bytes_with_two_character_delimiter_in_memory():
+    cell_type_to_fragments = _rust_scatac_fragment_tools.split_fragments_in_memory(
+        fragments = b"chr1||100||200||CCCC-1||3\nchr2||50||150||AAAA-1||1\n",
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        delimiter = "||",
+    )
+    assert cell_type_to_fragments["type_1"] == [
+        ("chr1", 100, 200, "CCCC-1", 3),
+        ("chr2", 50, 150, "AAAA-1", 1),
+    ]
+
+
+def test_split_bytes_with_ambiguous_delimiter_in_memory():
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "unambiguously",
+    ):
+        _rust_scatac_fragment_tools.split_fragments_in_memory(
+            fragments = b"chr1||100||200|||CCCC-1\n",
+            cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+            delimiter = "||",
+        )
+
+
+def test_split_bytes_with_barcode_tag_in_memory():
+    cell_type_to_fragments = _rust_scatac_fragment_tools.split_fragments_in_memory(
+        fragments = b"chr1\t100\t200\tCB:Z:CCCC-1\t3\n",
+        cell_type_to_cell_barcodes = CELL_TYPE_TO_CELL_BARCODES,
+        barcode_tag = "CB",
+    )
+    assert cell_type_to_fragments["type_1"] == [("chr1", 100, 200, "CCCC-1", 3)]
@@ -0,0 +1,75 @@
+import gzip
+import os
+import pathlib
+
+import pytest
+
+from scatac_fragment_tools.library.split.split_fragments_by_cell_type import (
+    split_fragment_files_by_cell_type_with_shared_annotation,
+)
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+
+FRAGMENT_FILES = [
+    str(TEST_DIRECTORY.joinpath("a.fragments.tsv.gz")),
+    str(TEST_DIRECTORY.joinpath("b.fragments.tsv.gz")),
+]
+
+CHROMSIZES = {"chr1": 248956422, "chr2": 242193529}
+
+NUMBER_OF_CELL_TYPES = 100
+
+
+def many_cell_types_annotation():
+    # Every cell barcode maps to several of NUMBER_OF_CELL_TYPES small cell types.
+    cell_barcodes = set()
+    for path_to_fragments in FRAGMENT_FILES:
+        with gzip.open(path_to_fragments, "rt") as f:
+            cell_barcodes.update(line.split("\t")[3] for line in f)
+    return {
+        cell_barcode: [f"type_{(i + j * 7) % NUMBER_OF_CELL_TYPES}" for j in range(10)]
+        for i, cell_barcode in enumerate(sorted(cell_barcodes))
+    }
+
+
+def read_output_folder(path_to_output_folder):
+    output = {}
+    for file_name in sorted(os.listdir(path_to_output_folder)):
+        with gzip.open(os.path.join(path_to_output_folder, file_name), "rt") as f:
+            output[file_name] = f.read()
+    return output
+
+
+def test_parallel_cell_types_match_serial(tmp_path):
+    cell_barcode_to_cell_type = many_cell_types_annotation()
+    outputs = []
+    for name, n_cpu, max_parallel_cell_types in [
+        ("serial", 1, None),
+        ("parallel", 8, None),
+        ("bounded", 8, 2),
+    ]:
+        split_fragment_files_by_cell_type_with_shared_annotation(
+            fragment_files = FRAGMENT_FILES,
+            path_to_temp_folder = os.path.join(tmp_path, f"{name}_temp"),
+            path_to_output_folder = os.path.join(tmp_path, name),
+            cell_barcode_to_cell_type = cell_barcode_to_cell_type,
+            chromsizes = CHROMSIZES,
+            n_cpu = n_cpu,
+            max_parallel_cell_types = max_parallel_cell_types,
+        )
+        outputs.append(read_output_folder(os.path.join(tmp_path, name)))
+    assert len(outputs[0]) == NUMBER_OF_CELL_TYPES
+    assert outputs[1] == outputs[0]
+    assert outputs[2] == outputs[0]
+
+
+def test_invalid_max_parallel_cell_types(tmp_path):
+    with pytest.raises(ValueError, match = "max_parallel_cell_types"):
+        split_fragment_files_by_cell_type_with_shared_annotation(
+            fragment_files = FRAGMENT_FILES,
+            path_to_temp_folder = os.path.join(tmp_path, "temp"),
+            path_to_output_folder = os.path.join(tmp_path, "output"),
+            cell_barcode_to_cell_type = {},
+            chromsizes = CHROMSIZES,
+            max_parallel_cell_types = 0,
+        )
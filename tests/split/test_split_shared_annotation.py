@@ -0,0 +1,97 @@
+import gzip
+import os
+import pathlib
+
+from scatac_fragment_tools.library.split.split_fragments_by_cell_type import (
+    split_fragment_files_by_cell_type,
+    split_fragment_files_by_cell_type_with_shared_annotation,
+)
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+
+FRAGMENT_FILES = [
+    str(TEST_DIRECTORY.joinpath("a.fragments.tsv.gz")),
+    str(TEST_DIRECTORY.joinpath("b.fragments.tsv.gz")),
+]
+
+CHROMSIZES = {"chr1": 248956422, "chr2": 242193529}
+
+
+def read_cell_type_annotation():
+    cell_barcode_to_cell_type = {}
+    with open(TEST_DIRECTORY.joinpath("cell_type_annotation.tsv")) as f:
+        f.readline()
+        for line in f:
+            _, cell_type, cell_barcode = line.strip().split("\t")
+            cell_barcode_to_cell_type[cell_barcode] = cell_type
+    return cell_barcode_to_cell_type
+
+
+def read_output_folder(path_to_output_folder):
+    output = {}
+    for file_name in sorted(os.listdir(path_to_output_folder)):
+        with gzip.open(os.path.join(path_to_output_folder, file_name), "rt") as f:
+            output[file_name] = f.read()
+    return output
+
+
+def test_shared_annotation_matches_nested_annotation(tmp_path):
+    cell_barcode_to_cell_type = read_cell_type_annotation()
+    cell_type_to_cell_barcodes = {}
+    for cell_barcode, cell_type in cell_barcode_to_cell_type.items():
+        cell_type_to_cell_barcodes.setdefault(cell_type, []).append(cell_barcode)
+
+    nested_output_folder = os.path.join(tmp_path, "nested")
+    split_fragment_files_by_cell_type(
+        sample_to_fragment_file = {"A": FRAGMENT_FILES[0], "B": FRAGMENT_FILES[1]},
+        path_to_temp_folder = os.path.join(tmp_path, "nested_temp"),
+        path_to_output_folder = nested_output_folder,
+        sample_to_cell_type_to_cell_barcodes = {
+            "A": cell_type_to_cell_barcodes,
+            "B": cell_type_to_cell_barcodes,
+        },
+        chromsizes = CHROMSIZES,
+    )
+
+    shared_output_folder = os.path.join(tmp_path, "shared")
+    split_fragment_files_by_cell_type_with_shared_annotation(
+        fragment_files = FRAGMENT_FILES,
+        path_to_temp_folder = os.path.join(tmp_path, "shared_temp"),
+        path_to_output_folder = shared_output_folder,
+        cell_barcode_to_cell_type = cell_barcode_to_cell_type,
+        chromsizes = CHROMSIZES,
+    )
+
+    nested_output = read_output_folder(nested_output_folder)
+    assert len(nested_output) == 5
+    assert read_output_folder(shared_output_folder) == nested_output
+
+
+def test_shared_annotation_with_source_column(tmp_path):
+    cell_barcode_to_cell_type = read_cell_type_annotation()
+    for output_folder, add_source_column in [("without_source", False), ("with_source", True)]:
+        split_fragment_files_by_cell_type_with_shared_annotation(
+            fragment_files = FRAGMENT_FILES,
+            path_to_temp_folder = os.path.join(tmp_path, f"{output_folder}_temp"),
+            path_to_output_folder = os.path.join(tmp_path, output_folder),
+            cell_barcode_to_cell_type = cell_barcode_to_cell_type,
+            chromsizes = CHROMSIZES,
+            add_source_column = add_source_column,
+        )
+    output_without_source = read_output_folder(os.path.join(tmp_path, "without_source"))
+    output_with_source = read_output_folder(os.path.join(tmp_path, "with_source"))
+    assert output_with_source.keys() == output_without_source.keys()
+
+    source_to_lines = {}
+    for fragment_file in FRAGMENT_FILES:
+        with gzip.open(fragment_file, "rt") as f:
+            source_to_lines[os.path.basename(fragment_file)] = set(f.read().splitlines())
+    sources = set()
+    for file_name, content in output_with_source.items():
+        lines = [line.rsplit("\t", 1) for line in content.splitlines()]
+        # Each fragment is tagged with the file it comes from, the order is not changed.
+        for line, source in lines:
+            assert line in source_to_lines[source]
+            sources.add(source)
+        assert [line for line, _ in lines] == output_without_source[file_name].splitlines()
+    assert sources == {"a.fragments.tsv.gz", "b.fragments.tsv.gz"}
@@ -0,0 +1,118 @@
+import os
+import pathlib
+
+import pytest
+
+from scatac_fragment_tools.library.split.split_fragments_by_cell_type import (
+    split_fragment_files_by_cell_type,
+)
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+
+CHROMSIZES = {"chr1": 248956422, "chr2": 242193529}
+
+
+def list_temp_files(path_to_temp_folder):
+    return sorted(
+        os.path.relpath(os.path.join(root, file_name), path_to_temp_folder)
+        for root, _, file_names in os.walk(path_to_temp_folder)
+        for file_name in file_names
+    )
+
+
+def split(tmp_path, temp_cleanup, path_to_b_fragments = None, **kwargs):
+    split_fragment_files_by_cell_type(
+        sample_to_fragment_file = {
+            "A": str(TEST_DIRECTORY.joinpath("a.fragments.tsv.gz")),
+            "B": path_to_b_fragments or str(TEST_DIRECTORY.joinpath("b.fragments.tsv.gz")),
+        },
+        path_to_temp_folder = os.path.join(tmp_path, "temp"),
+        path_to_output_folder = os.path.join(tmp_path, "output"),
+        sample_to_cell_type_to_cell_barcodes = {
+            "A": {"type_1": ["TTAGCTTAGGAGAACA-1"]},
+            "B": {"type_1": ["ATTACCTGTGTGCTTA-1"]},
+        },
+        chromsizes = CHROMSIZES,
+        temp_cleanup = temp_cleanup,
+        **kwargs,
+    )
+
+
+@pytest.mark.parametrize(
+    "temp_cleanup, expected_temp_files",
+    [
+        ("always", []),
+        ("on_success", []),
+        ("never", ["A/type_1.fragments.tsv.gz", "B/type_1.fragments.tsv.gz"]),
+    ],
+)
+def test_split_temp_cleanup(tmp_path, temp_cleanup, expected_temp_files):
+    split(tmp_path, temp_cleanup)
+    assert list_temp_files(os.path.join(tmp_path, "temp")) == expected_temp_files
+    assert os.listdir(os.path.join(tmp_path, "output")) == ["type_1.fragments.tsv.gz"]
+
+
+@pytest.mark.parametrize(
+    "temp_cleanup, expected_temp_files",
+    [
+        ("always", []),
+        ("on_success", ["A/type_1.fragments.tsv.gz"]),
+        ("never", ["A/type_1.fragments.tsv.gz"]),
+    ],
+)
+def test_split_temp_cleanup_when_split_fails(tmp_path, temp_cleanup, expected_temp_files):
+    # sample A is split before splitting sample B fails
+    with pytest.raises(ValueError, match = "missing_b.fragments.tsv.gz"):
+        split(
+            tmp_path,
+            temp_cleanup,
+            path_to_b_fragments = os.path.join(tmp_path, "missing_b.fragments.tsv.gz"),
+            error_policy = "collect",
+        )
+    assert list_temp_files(os.path.join(tmp_path, "temp")) == expected_temp_files
+
+
+def test_split_temp_cleanup_with_long_cell_type(tmp_path):
+    # the file names of long cell types are truncated and get a hash suffix
+    long_cell_type = "type_" + "x" * 295
+    split_fragment_files_by_cell_type(
+        sample_to_fragment_file = {"A": str(TEST_DIRECTORY.joinpath("a.fragments.tsv.gz"))},
+        path_to_temp_folder = os.path.join(tmp_path, "temp"),
+        path_to_output_folder = os.path.join(tmp_path, "output"),
+        sample_to_cell_type_to_cell_barcodes = {"A": {long_cell_type: ["TTAGCTTAGGAGAACA-1"]}},
+        chromsizes = CHROMSIZES,
+        temp_cleanup = "on_success",
+    )
+    assert list_temp_files(os.path.join(tmp_path, "temp")) == []
+    assert len(os.listdir(os.path.join(tmp_path, "output"))) == 1
+
+
+def test_split_clear_temp_folder_without_temp_cleanup(tmp_path):
+    split(tmp_path, None, clear_temp_folder = True)
+    assert list_temp_files(os.path.join(tmp_path, "temp")) == []
+
+
+def test_split_with_temp_prefix(tmp_path):
+    split(tmp_path, "never", temp_prefix = "run_1.")
+    assert list_temp_files(os.path.join(tmp_path, "temp")) == [
+        "run_1.A/type_1.fragments.tsv.gz",
+        "run_1.B/type_1.fragments.tsv.gz",
+    ]
+    # another run sharing the temporary folder does not remove the files of the first run
+    split(tmp_path, "on_success", temp_prefix = "run_2.")
+    assert list_temp_files(os.path.join(tmp_path, "temp")) == [
+        "run_1.A/type_1.fragments.tsv.gz",
+        "run_1.B/type_1.fragments.tsv.gz",
+    ]
+
+
+@pytest.mark.parametrize(
+    "kwargs, match",
+    [
+        ({"temp_cleanup": "sometimes"}, "temp_cleanup must be one of"),
+        ({"temp_cleanup": "never", "temp_prefix": "runs/run_1."}, "temp_prefix should be a file name prefix"),
+    ],
+)
+def test_split_with_invalid_temp_options(tmp_path, kwargs, match):
+    with pytest.raises(ValueError, match = match):
+        split(tmp_path, **kwargs)
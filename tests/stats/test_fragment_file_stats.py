@@ -0,0 +1,68 @@
+import collections
+import gzip
+import pathlib
+import shutil
+
+import pytest
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+
+SPLIT_TEST_DIRECTORY = TEST_DIRECTORY.parent.joinpath("split")
+
+
+def read_contigs(path_to_fragments):
+    with gzip.open(path_to_fragments, "rt") as f:
+        return [line.split("\t")[0] for line in f if not line.startswith("#")]
+
+
+@pytest.mark.parametrize(
+    "file_name",
+    ["a.fragments.tsv.gz", "contig_order.fragments.tsv.gz", "multi_sample.fragments.tsv.gz"],
+)
+def test_fragment_file_stats_from_index(file_name):
+    path_to_fragments = str(SPLIT_TEST_DIRECTORY.joinpath(file_name))
+    stats = _rust_scatac_fragment_tools.fragment_file_stats(path_to_fragments = path_to_fragments)
+    contigs = read_contigs(path_to_fragments)
+    # tabix lists the contigs in the index header in the order in which they appear in the file
+    assert stats.contigs == list(dict.fromkeys(contigs))
+    assert stats.records_per_contig == dict(collections.Counter(contigs))
+    assert stats.indexed
+    assert stats.sorted
+
+
+def test_fragment_file_stats_without_index(tmp_path):
+    path_to_fragments = str(tmp_path.joinpath("a.fragments.tsv.gz"))
+    shutil.copy(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz"), path_to_fragments)
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError, match = "allow a scan"
+    ) as exception_info:
+        _rust_scatac_fragment_tools.fragment_file_stats(path_to_fragments = path_to_fragments)
+    assert exception_info.value.kind == "index"
+
+    stats = _rust_scatac_fragment_tools.fragment_file_stats(
+        path_to_fragments = path_to_fragments,
+        allow_scan = True,
+    )
+    indexed_stats = _rust_scatac_fragment_tools.fragment_file_stats(
+        path_to_fragments = str(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz"))
+    )
+    assert stats.contigs == indexed_stats.contigs
+    assert stats.records_per_contig == indexed_stats.records_per_contig
+    assert not stats.indexed
+    assert stats.sorted
+
+
+def test_fragment_file_stats_of_unsorted_file(tmp_path):
+    path_to_fragments = tmp_path.joinpath("fragments.tsv")
+    path_to_fragments.write_text(
+        "chr1\t10\t20\tAAAA-1\t1\nchr2\t10\t20\tAAAA-1\t1\nchr1\t30\t40\tAAAA-1\t1\n"
+    )
+    stats = _rust_scatac_fragment_tools.fragment_file_stats(
+        path_to_fragments = str(path_to_fragments),
+        allow_scan = True,
+    )
+    assert stats.contigs == ["chr1", "chr2"]
+    assert stats.records_per_contig == {"chr1": 2, "chr2": 1}
+    assert not stats.sorted
@@ -0,0 +1,188 @@
+import gzip
+import os
+import pathlib
+
+import pytest
+from conftest import write_fragments
+
+from scatac_fragment_tools import _rust_scatac_fragment_tools
+
+TEST_DIRECTORY = pathlib.Path(__file__).parent.absolute()
+
+SPLIT_TEST_DIRECTORY = TEST_DIRECTORY.parent.joinpath("split")
+
+
+@pytest.mark.parametrize(
+    "file_name",
+    [
+        "a.fragments.tsv.gz",
+        "b.fragments.tsv.gz",
+        "contig_order.fragments.tsv.gz",
+        "scores.fragments.tsv.gz",
+    ],
+)
+def test_parallel_and_serial_validation_agree(file_name):
+    path_to_fragments = str(SPLIT_TEST_DIRECTORY.joinpath(file_name))
+    serial_report = _rust_scatac_fragment_tools.validate_fragment_file(
+        path_to_fragments = path_to_fragments,
+        number_of_threads = 1,
+    )
+    parallel_report = _rust_scatac_fragment_tools.validate_fragment_file(
+        path_to_fragments = path_to_fragments,
+        number_of_threads = 4,
+    )
+    assert serial_report.number_of_fragments > 0
+    assert parallel_report.number_of_fragments == serial_report.number_of_fragments
+    assert parallel_report.contig_order == serial_report.contig_order
+    assert parallel_report.fragments_per_contig == serial_report.fragments_per_contig
+
+
+def test_parallel_validation_reports_fragment_beyond_contig():
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "after the end of contig chr2 .* of contig chr2 in",
+    ):
+        _rust_scatac_fragment_tools.validate_fragment_file(
+            path_to_fragments = str(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz")),
+            chromsizes = {"chr1": 248956422, "chr2": 10},
+            number_of_threads = 2,
+        )
+
+
+def test_validation_accepts_equal_starts_with_unsorted_ends(tmp_path):
+    path_to_fragments = write_fragments(
+        tmp_path.joinpath("fragments.tsv"),
+        ["chr1\t10\t50\tAAAA-1\t1", "chr1\t10\t20\tAAAA-1\t1", "chr1\t12\t15\tAAAA-1\t1"],
+    )
+    report = _rust_scatac_fragment_tools.validate_fragment_file(path_to_fragments = path_to_fragments)
+    assert report.number_of_fragments == 3
+
+
+def test_validation_with_unsorted_tolerance(tmp_path, capfd):
+    # the second and third fragment start 2 and 1 bp before the first one
+    path_to_fragments = write_fragments(
+        tmp_path.joinpath("fragments.tsv"),
+        [
+            "chr1\t100\t150\tAAAA-1\t1",
+            "chr1\t98\t150\tAAAA-1\t1",
+            "chr1\t99\t150\tAAAA-1\t1",
+            "chr2\t10\t20\tAAAA-1\t1",
+        ],
+    )
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "not sorted by start position: 98 comes after 100 \\(line 2",
+    ):
+        _rust_scatac_fragment_tools.validate_fragment_file(path_to_fragments = path_to_fragments)
+    with pytest.raises(
+        _rust_scatac_fragment_tools.InvalidFragmentFileError,
+        match = "98 comes after 100",
+    ):
+        _rust_scatac_fragment_tools.validate_fragment_file(
+            path_to_fragments = path_to_fragments,
+            unsorted_tolerance = 1,
+        )
+    capfd.readouterr()
+
+    report = _rust_scatac_fragment_tools.validate_fragment_file(
+        path_to_fragments = path_to_fragments,
+        unsorted_tolerance = 2,
+    )
+    assert report.number_of_fragments == 4
+    assert "Warning: 2 fragment(s)" in capfd.readouterr().out
+
+
+def test_verify_split_completeness(tmp_path):
+    path_to_fragments = str(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz"))
+    # TTAGCTTAGGAGAACA-1 maps to two cell types, so its fragments are expected twice
+    cell_type_to_cell_barcodes = {
+        "type_1": ["TTAGCTTAGGAGAACA-1", "ATATTCCTCTTGTACT-1"],
+        "type_2": ["TTAGCTTAGGAGAACA-1", "TGTGACAGTACAACGG-1"],
+    }
+    _rust_scatac_fragment_tools.split_fragments_by_cell_barcode(
+        path_to_fragments = path_to_fragments,
+        path_to_output_folder = str(tmp_path),
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+        chromsizes = {},
+        verbose = False,
+    )
+    paths_to_output_files = [
+        os.path.join(tmp_path, f"{cell_type}.fragments.tsv.gz") for cell_type in cell_type_to_cell_barcodes
+    ]
+    report = _rust_scatac_fragment_tools.verify_split_completeness(
+        path_to_fragments = path_to_fragments,
+        paths_to_output_files = paths_to_output_files,
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+    )
+    assert report.expected_fragments > 0
+    assert report.missing_fragments == 0
+    assert report.extra_fragments == 0
+
+    # move the first fragment of type_1 to the end of type_2 and add a fragment which is not in the input
+    with gzip.open(paths_to_output_files[0], "rt") as f:
+        type_1_lines = f.readlines()
+    with gzip.open(paths_to_output_files[1], "rt") as f:
+        type_2_lines = f.readlines()
+    with gzip.open(paths_to_output_files[0], "wt") as f:
+        f.writelines(type_1_lines[1:])
+    extra_line = "chr1\t1\t2\tATATTCCTCTTGTACT-1\t1\n"
+    with gzip.open(paths_to_output_files[1], "wt") as f:
+        f.writelines(type_2_lines + [type_1_lines[0], extra_line])
+    report = _rust_scatac_fragment_tools.verify_split_completeness(
+        path_to_fragments = path_to_fragments,
+        paths_to_output_files = paths_to_output_files,
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+    )
+    # only the union of the output files is compared, so the moved fragment is not a discrepancy
+    assert report.missing_fragments == 0
+    assert report.extra_fragments == 1
+    assert report.extra_examples == [extra_line.rstrip("\n")]
+
+    # drop the last fragment of type_2
+    with gzip.open(paths_to_output_files[1], "wt") as f:
+        f.writelines(type_2_lines[:-1])
+    report = _rust_scatac_fragment_tools.verify_split_completeness(
+        path_to_fragments = path_to_fragments,
+        paths_to_output_files = paths_to_output_files,
+        cell_type_to_cell_barcodes = cell_type_to_cell_barcodes,
+    )
+    assert report.missing_fragments == 2
+    assert report.extra_fragments == 0
+    assert sorted(report.missing_examples) == sorted(
+        [type_1_lines[0].rstrip("\n"), type_2_lines[-1].rstrip("\n")]
+    )
+
+
+@pytest.mark.parametrize("sample_stride", [1, 7, 1000])
+def test_quick_check_agrees_with_validation(sample_stride):
+    path_to_fragments = str(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz"))
+    report = _rust_scatac_fragment_tools.validate_fragment_file(path_to_fragments = path_to_fragments)
+    assert _rust_scatac_fragment_tools.quick_check(path_to_fragments, sample_stride = sample_stride) == (
+        report.number_of_fragments,
+        True,
+        True,
+        True,
+    )
+
+
+def test_quick_check_flags_unsorted_file(tmp_path):
+    with gzip.open(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz"), "rt") as f:
+        lines = f.readlines()
+    # plain gzip instead of BGZF, without index
+    path_to_fragments = str(tmp_path.joinpath("reversed.fragments.tsv.gz"))
+    with gzip.open(path_to_fragments, "wt") as f:
+        f.writelines(reversed(lines))
+    assert _rust_scatac_fragment_tools.quick_check(path_to_fragments, sample_stride = 5) == (
+        len(lines),
+        False,
+        False,
+        False,
+    )
+
+
+def test_quick_check_with_invalid_sample_stride():
+    with pytest.raises(ValueError, match = "sample_stride should be at least 1"):
+        _rust_scatac_fragment_tools.quick_check(
+            str(SPLIT_TEST_DIRECTORY.joinpath("a.fragments.tsv.gz")),
+            sample_stride = 0,
+        )